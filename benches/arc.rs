@@ -0,0 +1,129 @@
+// clone, drop, upgrade and contention costs, for this crate's Arc and std's side by
+// side. run with `cargo bench`, optionally followed by a filter on the name:
+//
+//     cargo bench --bench arc -- upgrade
+//
+// each scenario is timed over enough iterations to take a fraction of a second, and
+// reported as the best of a few runs, in nanoseconds per operation
+
+use provenant::shared::{SharedPtr, SharedWeak};
+use std::hint::black_box;
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const RUNS: usize = 5;
+const THREADS: usize = 4;
+
+// runs a scenario some number of times, and gets how long that took
+type Scenario = fn(u64) -> Duration;
+
+fn main() {
+    let filter = std::env::args().skip(1).find(|arg| !arg.starts_with('-'));
+    let filter = filter.as_deref().unwrap_or("");
+
+    suite::<provenant::Arc<u64>>("provenant", filter);
+    suite::<std::sync::Arc<u64>>("std", filter);
+}
+
+fn suite<P>(flavor: &str, filter: &str)
+where
+    P: SharedPtr<Target = u64> + Send + Sync + 'static,
+    P::Weak: Send + Sync,
+{
+    let scenarios: Vec<(&str, Scenario)> = vec![
+        ("clone_drop", clone_drop::<P>),
+        ("upgrade_live", upgrade_live::<P>),
+        ("upgrade_dead", upgrade_dead::<P>),
+        ("contended_clone_drop", contended_clone_drop::<P>),
+        ("contended_upgrade_drop", contended_upgrade_drop::<P>),
+    ];
+    for (name, scenario) in scenarios {
+        let name = format!("{}/{}", flavor, name);
+        if name.contains(filter) {
+            report(&name, scenario);
+        }
+    }
+}
+
+fn report(name: &str, scenario: Scenario) {
+    // grows the count until a run is long enough to time reliably
+    let mut iters = 1_000;
+    while scenario(iters) < Duration::from_millis(50) {
+        iters *= 2;
+    }
+    let best = (0..RUNS).map(|_| scenario(iters)).min().unwrap();
+    let per_op = best.as_secs_f64() * 1e9 / iters as f64;
+    println!("{:<40} {:>10.2} ns/op", name, per_op);
+}
+
+fn clone_drop<P: SharedPtr<Target = u64>>(iters: u64) -> Duration {
+    let arc = P::new(7);
+    let start = Instant::now();
+    for _ in 0..iters {
+        drop(black_box(arc.clone()));
+    }
+    start.elapsed()
+}
+
+fn upgrade_live<P: SharedPtr<Target = u64>>(iters: u64) -> Duration {
+    let arc = P::new(7);
+    let weak = P::downgrade(&arc);
+    let start = Instant::now();
+    for _ in 0..iters {
+        drop(black_box(weak.upgrade()));
+    }
+    start.elapsed()
+}
+
+fn upgrade_dead<P: SharedPtr<Target = u64>>(iters: u64) -> Duration {
+    let weak = P::downgrade(&P::new(7));
+    let start = Instant::now();
+    for _ in 0..iters {
+        assert!(black_box(weak.upgrade()).is_none());
+    }
+    start.elapsed()
+}
+
+// every thread cloning and dropping the same allocation
+fn contended_clone_drop<P>(iters: u64) -> Duration
+where
+    P: SharedPtr<Target = u64> + Send + Sync,
+{
+    let arc = P::new(7);
+    contended(iters, || drop(black_box(arc.clone())))
+}
+
+// every thread upgrading the same weak pointer and dropping what it gets, so the
+// upgrades race with the drops that take the count back down
+fn contended_upgrade_drop<P>(iters: u64) -> Duration
+where
+    P: SharedPtr<Target = u64>,
+    P::Weak: Send + Sync,
+{
+    let arc = P::new(7);
+    let weak = P::downgrade(&arc);
+    contended(iters, || drop(black_box(weak.upgrade())))
+}
+
+// splits `iters` calls of `op` across THREADS threads, timed from when they're all
+// ready to when the last one finishes
+fn contended(iters: u64, op: impl Fn() + Sync) -> Duration {
+    let per_thread = iters / THREADS as u64;
+    let barrier = Barrier::new(THREADS + 1);
+    thread::scope(|scope| {
+        for _ in 0..THREADS {
+            scope.spawn(|| {
+                barrier.wait();
+                for _ in 0..per_thread {
+                    op();
+                }
+                barrier.wait();
+            });
+        }
+        barrier.wait();
+        let start = Instant::now();
+        barrier.wait();
+        start.elapsed()
+    })
+}
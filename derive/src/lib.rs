@@ -0,0 +1,115 @@
+//! Derive macros for provenant. Use them through provenant's `derive` feature.
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2};
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields, Index, Member};
+
+/// Generates field projections for a struct.
+///
+/// For `struct Config { name: String }`, this generates a `ConfigArcProject` trait
+/// with `arc.project_name() -> ArcRef<String>` for `Arc<Config>`, and a
+/// `ConfigWeakProject` trait with `weak.project_name() -> WeakRef<String>` for
+/// `Weak<Config>`. Tuple struct fields are named by index, like `project_0`. The
+/// traits take the same generic parameters as the struct.
+#[proc_macro_derive(ArcProject)]
+pub fn derive_arc_project(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => &data.fields,
+        _ => {
+            return Err(Error::new(
+                Span::call_site(),
+                "ArcProject can only be derived for structs",
+            ))
+        }
+    };
+
+    let members: Vec<(Member, &syn::Type)> = match fields {
+        Fields::Named(named) => named
+            .named
+            .iter()
+            .map(|f| (Member::Named(f.ident.clone().unwrap()), &f.ty))
+            .collect(),
+        Fields::Unnamed(unnamed) => unnamed
+            .unnamed
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (Member::Unnamed(Index::from(i)), &f.ty))
+            .collect(),
+        Fields::Unit => Vec::new(),
+    };
+
+    let vis = &input.vis;
+    let name = &input.ident;
+    let arc_trait = format_ident!("{}ArcProject", name);
+    let weak_trait = format_ident!("{}WeakProject", name);
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let mut predicates = where_clause
+        .map(|w| w.predicates.iter().cloned().collect::<Vec<_>>())
+        .unwrap_or_default();
+    predicates.push(syn::parse_quote!(#name #ty_generics: ::core::marker::Send + ::core::marker::Sync + 'static));
+
+    let methods: Vec<_> = members
+        .iter()
+        .map(|(member, _)| match member {
+            Member::Named(ident) => format_ident!("project_{}", ident),
+            Member::Unnamed(index) => format_ident!("project_{}", index.index),
+        })
+        .collect();
+    let types: Vec<_> = members.iter().map(|(_, ty)| *ty).collect();
+    let fields: Vec<_> = members.iter().map(|(member, _)| member).collect();
+
+    let arc_docs = fields
+        .iter()
+        .map(|field| format!("Gets a strong handle to `{}`", quote!(#field)));
+    let weak_docs = fields
+        .iter()
+        .map(|field| format!("Gets a weak handle to `{}`", quote!(#field)));
+
+    Ok(quote! {
+        #[doc = concat!("Field projections for `Arc<", stringify!(#name), ">`")]
+        #vis trait #arc_trait #impl_generics #where_clause {
+            #(
+                #[doc = #arc_docs]
+                fn #methods(&self) -> ::provenant::project::ArcRef<#types>;
+            )*
+        }
+
+        #[doc = concat!("Field projections for `Weak<", stringify!(#name), ">`")]
+        #vis trait #weak_trait #impl_generics #where_clause {
+            #(
+                #[doc = #weak_docs]
+                fn #methods(&self) -> ::provenant::project::WeakRef<#types>;
+            )*
+        }
+
+        impl #impl_generics #arc_trait #ty_generics for ::provenant::Arc<#name #ty_generics>
+        where #(#predicates,)*
+        {
+            #(
+                fn #methods(&self) -> ::provenant::project::ArcRef<#types> {
+                    ::provenant::Arc::map(::core::clone::Clone::clone(self), |value| &value.#fields)
+                }
+            )*
+        }
+
+        impl #impl_generics #weak_trait #ty_generics for ::provenant::Weak<#name #ty_generics>
+        where #(#predicates,)*
+        {
+            #(
+                fn #methods(&self) -> ::provenant::project::WeakRef<#types> {
+                    // the offset comes from the struct definition itself
+                    unsafe { self.project(::core::mem::offset_of!(#name #ty_generics, #fields)) }
+                }
+            )*
+        }
+    })
+}
@@ -0,0 +1,115 @@
+//! Arcs whose values are more aligned than their types ask for.
+//!
+//! `#[repr(align)]` types already get their alignment from [`Arc::new`]. For
+//! buffers whose alignment is a runtime property, like SIMD lanes or DMA pages,
+//! [`Arc::new_aligned`] places the value at a chosen alignment instead.
+//!
+//! [`Arc::new_padded`] uses that to keep the value off the cache line holding the
+//! counts, so threads reading it aren't slowed down by others cloning and dropping.
+
+use crate::{events, random_provenance, Arc, Inner};
+use alloc::alloc::Layout;
+use core::mem::{self, offset_of};
+
+// what new_padded aligns to. x86_64 prefetches lines in pairs, and Apple's aarch64
+// cores have 128 byte lines, so those get two lines' worth
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const CACHE_LINE: usize = 128;
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const CACHE_LINE: usize = 64;
+
+// how far into the allocation the Inner goes, so that its data lands on `align`.
+// there's always room for a word before the Inner, which remembers `align`
+fn padding<T>(align: usize) -> usize {
+    let data = offset_of!(Inner<T>, data);
+    let mut pad = (align - data % align) % align;
+    while pad < mem::size_of::<usize>() {
+        pad += align;
+    }
+    pad
+}
+
+fn allocation<T>(align: usize) -> (Layout, usize) {
+    let inner = Layout::new::<Inner<T>>();
+    let align = align.max(inner.align());
+    let pad = padding::<T>(align);
+    let layout = Layout::from_size_align(pad + inner.size(), align).expect("alignment too large");
+    // so nothing else is allocated in the value's last line
+    (layout.pad_to_align(), pad)
+}
+
+unsafe fn release_aligned<T>(ptr: *mut u8, _layout: Layout) {
+    let align = (ptr as *const usize).sub(1).read();
+    let (layout, pad) = allocation::<T>(align);
+    alloc::alloc::dealloc(ptr.sub(pad), layout);
+}
+
+impl<T> Arc<T> {
+    /// Like [`Arc::new`], but the value's address is a multiple of `align`.
+    ///
+    /// Panics if `align` isn't a power of two.
+    pub fn new_aligned(val: T, align: usize) -> Self {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+
+        let (layout, pad) = allocation::<T>(align);
+        unsafe {
+            let base = alloc::alloc::alloc(layout);
+            if base.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+
+            let ptr = base.add(pad) as *mut Inner<T>;
+            (ptr as *mut usize).sub(1).write(layout.align());
+            ptr.write(Inner::new(val, random_provenance(), release_aligned::<T>));
+
+            events::allocated(ptr);
+            Arc { ptr }
+        }
+    }
+
+    /// Like [`Arc::new`], but the value starts on a cache line of its own, away from
+    /// the provenance id and count, which stay on the line before.
+    ///
+    /// For values read from many threads while the Arc is cloned and dropped a lot.
+    /// It costs up to a couple of cache lines of padding per allocation.
+    pub fn new_padded(val: T) -> Self {
+        Self::new_aligned(val, CACHE_LINE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aligned() {
+        for align in [1, 8, 64, 4096] {
+            let arc = Arc::new_aligned([1u8; 3], align);
+            assert_eq!(0, &*arc as *const [u8; 3] as usize % align);
+
+            let weak = Arc::downgrade(&arc);
+            assert_eq!([1; 3], *weak.upgrade().unwrap());
+            drop(arc);
+            assert!(weak.upgrade().is_none());
+        }
+    }
+
+    #[test]
+    fn padded() {
+        let arc = Arc::new_padded(1u64);
+        let data = &*arc as *const u64 as usize;
+        assert_eq!(0, data % CACHE_LINE);
+        assert_ne!(arc.addr() / CACHE_LINE, data / CACHE_LINE);
+        assert_eq!(1, *arc);
+    }
+
+    #[test]
+    fn repr_align() {
+        #[repr(align(256))]
+        struct Page(u8);
+
+        let arc = Arc::new(Page(1));
+        assert_eq!(0, &*arc as *const Page as usize % 256);
+        assert_eq!(1, arc.0);
+    }
+}
@@ -0,0 +1,154 @@
+//! Arcs allocated from somewhere other than the global allocator.
+//!
+//! std's `Allocator` trait is still unstable, so allocators implement
+//! [`ArcAllocator`] instead. [`Arc::new_in`] moves the allocator into the
+//! allocation, just in front of the value, and the last Arc to go uses it to free
+//! the memory. The allocator is then dropped, so a handle to an arena, like
+//! `&'static Bump`, is what's usually passed.
+//!
+//! Only the allocation is tied to the allocator. A copy made by
+//! [`Arc::make_mut`] comes from the global allocator, like one made by `clone`
+//! on the value would.
+
+use crate::fallible::AllocError;
+use crate::{events, random_provenance, Arc, Inner};
+use alloc::alloc::Layout;
+use core::ptr::{self, NonNull};
+
+/// Something [`Arc::new_in`] can allocate from.
+///
+/// # Safety
+///
+/// Memory returned by `allocate` must fit `layout` and stay valid until it's
+/// passed to `deallocate` with the same layout.
+pub unsafe trait ArcAllocator {
+    /// Gets memory for `layout`, or None if there isn't any
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>>;
+
+    /// Gives back memory from [`allocate`](ArcAllocator::allocate)
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `allocate` on this allocator with `layout`, and
+    /// not have been given back already.
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout);
+}
+
+/// The global allocator, which [`Arc::new`] uses
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Global;
+
+unsafe impl ArcAllocator for Global {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc::alloc::alloc(layout) })
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        alloc::alloc::dealloc(ptr.as_ptr(), layout)
+    }
+}
+
+unsafe impl<A: ArcAllocator + ?Sized> ArcAllocator for &A {
+    fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+        (**self).allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (**self).deallocate(ptr, layout)
+    }
+}
+
+// the whole allocation, for an Inner of `inner`'s layout, and where the Inner starts
+fn with_allocator<A>(inner: Layout) -> (Layout, usize) {
+    let (layout, offset) = Layout::new::<A>()
+        .extend(inner)
+        .expect("allocation too large");
+    (layout.pad_to_align(), offset)
+}
+
+// the release for Inners allocated with new_in. the allocator sits in front
+unsafe fn release_in<A: ArcAllocator>(ptr: *mut u8, layout: Layout) {
+    let (whole, offset) = with_allocator::<A>(layout);
+    let base = ptr.sub(offset);
+    let allocator = ptr::read(base as *const A);
+    allocator.deallocate(NonNull::new_unchecked(base), whole);
+}
+
+impl<T> Arc<T> {
+    /// Like [`Arc::new`], but gets the memory from `allocator`, which frees it once
+    /// the last Arc is dropped
+    pub fn new_in<A: ArcAllocator + Send + 'static>(val: T, allocator: A) -> Self {
+        let (layout, _) = with_allocator::<A>(Layout::new::<Inner<T>>());
+        match Arc::try_new_in(val, allocator) {
+            Ok(arc) => arc,
+            Err(_) => alloc::alloc::handle_alloc_error(layout),
+        }
+    }
+
+    /// Like [`Arc::new_in`], but returns an error if `allocator` is out of memory
+    pub fn try_new_in<A: ArcAllocator + Send + 'static>(
+        val: T,
+        allocator: A,
+    ) -> Result<Self, AllocError> {
+        let (layout, offset) = with_allocator::<A>(Layout::new::<Inner<T>>());
+        let base = allocator.allocate(layout).ok_or(AllocError)?.as_ptr();
+
+        unsafe {
+            (base as *mut A).write(allocator);
+            let ptr = base.add(offset) as *mut Inner<T>;
+            ptr.write(Inner::new(val, random_provenance(), release_in::<A>));
+            events::allocated(ptr);
+            Ok(Arc { ptr })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    // counts what's outstanding, and hands the rest to the global allocator
+    struct Counting(AtomicUsize);
+
+    unsafe impl ArcAllocator for Counting {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn freed_through_allocator() {
+        static COUNTING: Counting = Counting(AtomicUsize::new(0));
+
+        let arc = Arc::new_in(String::from("arena"), &COUNTING);
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+        assert_eq!(1, COUNTING.0.load(Ordering::Relaxed));
+
+        drop(arc);
+        assert_eq!("arena", *weak.upgrade().unwrap());
+        drop(other);
+        assert_eq!(0, COUNTING.0.load(Ordering::Relaxed));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn out_of_memory() {
+        struct Empty;
+        unsafe impl ArcAllocator for Empty {
+            fn allocate(&self, _layout: Layout) -> Option<NonNull<u8>> {
+                None
+            }
+            unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {}
+        }
+
+        assert!(Arc::try_new_in(5, Empty).is_err());
+    }
+}
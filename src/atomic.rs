@@ -0,0 +1,347 @@
+//! Shared slots holding an Arc or a weak pointer, which can be swapped while others
+//! read them.
+//!
+//! [`AtomicArc`] is for values that are read often and replaced now and then,
+//! like configuration that's reloaded: readers [`load`](AtomicArc::load) the
+//! current snapshot and keep it for as long as they like, while a writer
+//! [`store`](AtomicArc::store)s a new one.
+//!
+//! ```
+//! use provenant::atomic::AtomicArc;
+//! use provenant::Arc;
+//!
+//! let config = AtomicArc::new(Arc::new(String::from("v1")));
+//! let snapshot = config.load();
+//! config.store(Arc::new(String::from("v2")));
+//! assert_eq!("v1", *snapshot);
+//! assert_eq!("v2", *config.load());
+//! ```
+//!
+//! Readers and writers take turns on a lock in the slot, held just long enough to
+//! clone or swap the Arc, and waited on like a provenance lock. The provenance lock
+//! itself can't be used: it lives in the allocation, which a writer could free
+//! between a reader finding it and locking it. Old values are dropped after the
+//! lock is released.
+//!
+//! [`AtomicWeak`] holds a weak pointer, for slots where the latest one registered
+//! wins. Weak pointers are `Copy`, so readers never need a lock: they copy the two
+//! words out and check that no writer changed them meanwhile, like a seqlock.
+
+use crate::contention;
+use crate::primitives::{fence, AtomicProvenance, AtomicUsize};
+use crate::{Arc, Inner, Weak};
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// A slot holding an Arc, which can be loaded and replaced from any thread
+pub struct AtomicArc<T: ?Sized> {
+    // 1 while someone is looking at arc
+    lock: AtomicUsize,
+    arc: UnsafeCell<Arc<T>>,
+}
+
+// the same as Mutex<Arc<T>>
+unsafe impl<T: ?Sized + Send + Sync> Send for AtomicArc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for AtomicArc<T> {}
+
+impl<T: ?Sized> AtomicArc<T> {
+    /// Creates a slot holding `arc`
+    pub fn new(arc: Arc<T>) -> Self {
+        AtomicArc {
+            lock: AtomicUsize::new(0),
+            arc: UnsafeCell::new(arc),
+        }
+    }
+
+    /// Gets the Arc that's in the slot
+    pub fn load(&self) -> Arc<T> {
+        self.locked(|arc| arc.clone())
+    }
+
+    /// Puts `arc` in the slot, dropping the one that was there
+    pub fn store(&self, arc: Arc<T>) {
+        drop(self.swap(arc));
+    }
+
+    /// Puts `arc` in the slot, returning the one that was there
+    pub fn swap(&self, arc: Arc<T>) -> Arc<T> {
+        self.locked(|slot| mem::replace(slot, arc))
+    }
+
+    /// Puts `new` in the slot if what's there points to the same allocation as
+    /// `current`. Returns what was there, which is `current` if it was swapped.
+    /// Otherwise `new` is dropped
+    pub fn compare_and_swap(&self, current: &Arc<T>, new: Arc<T>) -> Arc<T> {
+        let swapped = self.locked(|slot| {
+            if Arc::ptr_eq(slot, current) {
+                Ok(mem::replace(slot, new))
+            } else {
+                Err((slot.clone(), new))
+            }
+        });
+        match swapped {
+            Ok(old) => old,
+            Err((there, new)) => {
+                drop(new);
+                there
+            }
+        }
+    }
+
+    /// Takes the Arc out of the slot
+    pub fn into_inner(self) -> Arc<T> {
+        self.arc.into_inner()
+    }
+
+    // runs f on the Arc with the lock held. f mustn't drop anything, since a Drop
+    // impl could come back to this slot
+    fn locked<R>(&self, f: impl FnOnce(&mut Arc<T>) -> R) -> R {
+        let mut attempt = 0;
+        while self
+            .lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            contention::wait(self.addr(), attempt, || {
+                self.lock.load(Ordering::Relaxed) == 1
+            });
+            attempt = attempt.saturating_add(1);
+        }
+
+        let out = f(unsafe { &mut *self.arc.get() });
+        self.lock.store(0, Ordering::Release);
+        contention::released(self.addr());
+        out
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+}
+
+impl<T: ?Sized> From<Arc<T>> for AtomicArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        AtomicArc::new(arc)
+    }
+}
+
+impl<T: Default> Default for AtomicArc<T> {
+    fn default() -> Self {
+        AtomicArc::new(Arc::default())
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for AtomicArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicArc").field(&self.load()).finish()
+    }
+}
+
+/// A slot holding a weak pointer, which can be loaded without locking
+pub struct AtomicWeak<T> {
+    // odd while a writer is changing the fields
+    seq: AtomicUsize,
+    ptr: AtomicPtr<Inner<T>>,
+    provenance: AtomicProvenance,
+}
+
+unsafe impl<T: Send + Sync> Send for AtomicWeak<T> {}
+unsafe impl<T: Send + Sync> Sync for AtomicWeak<T> {}
+
+impl<T> AtomicWeak<T> {
+    /// Creates a slot holding `weak`
+    pub fn new(weak: Weak<T>) -> Self {
+        AtomicWeak {
+            seq: AtomicUsize::new(0),
+            ptr: AtomicPtr::new(weak.ptr as *mut Inner<T>),
+            provenance: AtomicProvenance::new(weak.provenance),
+        }
+    }
+
+    /// Gets the weak pointer that's in the slot
+    pub fn load(&self) -> Weak<T> {
+        let mut attempt = 0;
+        loop {
+            let seq = self.seq.load(Ordering::Acquire);
+            if seq & 1 == 0 {
+                let weak = self.read();
+                // the fields are read before seq is checked again
+                fence(Ordering::Acquire);
+                if self.seq.load(Ordering::Relaxed) == seq {
+                    return weak;
+                }
+            }
+            contention::wait(self.addr(), attempt, || {
+                self.seq.load(Ordering::Relaxed) & 1 == 1
+            });
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    /// Puts `weak` in the slot
+    pub fn store(&self, weak: Weak<T>) {
+        self.swap(weak);
+    }
+
+    /// Puts `weak` in the slot, returning the one that was there
+    pub fn swap(&self, weak: Weak<T>) -> Weak<T> {
+        let mut attempt = 0;
+        let seq = loop {
+            let seq = self.seq.load(Ordering::Relaxed);
+            if seq & 1 == 0
+                && self
+                    .seq
+                    .compare_exchange_weak(seq, seq + 1, Ordering::Acquire, Ordering::Relaxed)
+                    .is_ok()
+            {
+                break seq;
+            }
+            contention::wait(self.addr(), attempt, || {
+                self.seq.load(Ordering::Relaxed) & 1 == 1
+            });
+            attempt = attempt.saturating_add(1);
+        };
+        // readers that see the new fields also see seq as odd
+        fence(Ordering::Release);
+
+        let old = self.read();
+        self.ptr.store(weak.ptr as *mut Inner<T>, Ordering::Relaxed);
+        self.provenance.store(weak.provenance, Ordering::Relaxed);
+
+        self.seq.store(seq.wrapping_add(2), Ordering::Release);
+        contention::released(self.addr());
+        old
+    }
+
+    /// Empties the slot, returning the weak pointer that was there. What's left
+    /// never upgrades, like [`Weak::new`]
+    pub fn take(&self) -> Weak<T> {
+        self.swap(Weak::new())
+    }
+
+    // may be torn, unless seq says otherwise
+    fn read(&self) -> Weak<T> {
+        Weak {
+            ptr: self.ptr.load(Ordering::Relaxed),
+            provenance: self.provenance.load(Ordering::Relaxed),
+        }
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Self as *const u8 as usize
+    }
+}
+
+impl<T> From<Weak<T>> for AtomicWeak<T> {
+    fn from(weak: Weak<T>) -> Self {
+        AtomicWeak::new(weak)
+    }
+}
+
+impl<T> Default for AtomicWeak<T> {
+    fn default() -> Self {
+        AtomicWeak::new(Weak::new())
+    }
+}
+
+impl<T> fmt::Debug for AtomicWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("AtomicWeak").field(&self.load()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ptr;
+    use std::thread;
+
+    #[test]
+    fn compare_and_swap() {
+        let first = Arc::new(1);
+        let slot = AtomicArc::new(first.clone());
+
+        let old = slot.compare_and_swap(&Arc::new(1), Arc::new(2));
+        assert!(Arc::ptr_eq(&first, &old));
+        assert_eq!(1, *slot.load());
+
+        let old = slot.compare_and_swap(&first, Arc::new(3));
+        assert!(Arc::ptr_eq(&first, &old));
+        assert_eq!(3, *slot.into_inner());
+    }
+
+    #[test]
+    fn drop_loads_slot() {
+        struct LoadsOnDrop(*const AtomicArc<LoadsOnDrop>);
+        impl Drop for LoadsOnDrop {
+            fn drop(&mut self) {
+                if let Some(slot) = unsafe { self.0.as_ref() } {
+                    drop(slot.load());
+                }
+            }
+        }
+
+        let slot = AtomicArc::new(Arc::new(LoadsOnDrop(ptr::null())));
+        let new = Arc::new(LoadsOnDrop(&slot));
+        // the rejected Arc is dropped after the lock is released
+        drop(slot.compare_and_swap(&Arc::new(LoadsOnDrop(ptr::null())), new));
+    }
+
+    #[test]
+    fn readers_and_writer() {
+        let slot = Arc::new(AtomicArc::new(Arc::new(0)));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let slot = slot.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..1000 {
+                        let now = *slot.load();
+                        assert!(now >= last);
+                        last = now;
+                    }
+                })
+            })
+            .collect();
+
+        for i in 1..=1000 {
+            slot.store(Arc::new(i));
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(1000, *slot.load());
+    }
+
+    #[test]
+    fn latest_observer_wins() {
+        let observers: Vec<_> = (0..4).map(Arc::new).collect();
+        let slot = Arc::new(AtomicWeak::default());
+        assert!(slot.load().upgrade().is_none());
+
+        let writers: Vec<_> = observers
+            .iter()
+            .map(|observer| {
+                let (slot, weak) = (slot.clone(), Arc::downgrade(observer));
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        slot.store(weak);
+                        // never a mix of two of them
+                        assert!(slot.load().upgrade().is_some());
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        let last = slot.take().upgrade().unwrap();
+        assert!(observers
+            .iter()
+            .any(|observer| Arc::ptr_eq(observer, &last)));
+        assert!(slot.load().upgrade().is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Borrowing an Arc without touching its reference count.
+//!
+//! An [`ArcBorrow`] is what `&Arc<T>` would be if it pointed straight at the
+//! allocation: one pointer, `Copy`, and proof that some Arc keeps the value alive
+//! for `'a`. Reading through it costs nothing, and it turns into a real [`Arc`]
+//! only when one is needed:
+//!
+//! ```
+//! use provenant::borrow::ArcBorrow;
+//! use provenant::Arc;
+//!
+//! fn keep_if_long(name: ArcBorrow<'_, String>, kept: &mut Vec<Arc<String>>) {
+//!     if name.len() > 3 {
+//!         kept.push(name.clone_arc());
+//!     }
+//! }
+//!
+//! let mut kept = vec![];
+//! let name = Arc::new(String::from("provenant"));
+//! keep_if_long(Arc::borrow_arc(&name), &mut kept);
+//! assert_eq!(2, Arc::strong_count(&name));
+//! ```
+
+use crate::tag::untagged;
+use crate::{Arc, Inner, Weak};
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::ManuallyDrop;
+use core::ops::Deref;
+
+/// A borrow of the value in an [`Arc`], which keeps it alive for `'a`
+pub struct ArcBorrow<'a, T: ?Sized> {
+    ptr: *const Inner<T>,
+    _marker: PhantomData<&'a Arc<T>>,
+}
+
+// the same as sharing &Arc<T>
+unsafe impl<T: ?Sized + Send + Sync> Send for ArcBorrow<'_, T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for ArcBorrow<'_, T> {}
+
+impl<T: ?Sized> Copy for ArcBorrow<'_, T> {}
+
+impl<T: ?Sized> Clone for ArcBorrow<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Borrows this without changing the reference count
+    pub fn borrow_arc(this: &Self) -> ArcBorrow<'_, T> {
+        ArcBorrow {
+            ptr: this.ptr,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<'a, T: ?Sized> ArcBorrow<'a, T> {
+    // for a pointer that an Arc living for 'a owns, tag and all
+    pub(crate) unsafe fn from_ptr(ptr: *const Inner<T>) -> Self {
+        ArcBorrow {
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Gets the value, for as long as the Arc it came from is borrowed
+    pub fn get(self) -> &'a T {
+        unsafe { &(*untagged(self.ptr)).data }
+    }
+
+    /// Gets an Arc of its own, adding to the reference count
+    pub fn clone_arc(self) -> Arc<T> {
+        self.with_arc(Arc::clone)
+    }
+
+    /// Gets a weak pointer, like [`Arc::downgrade`]
+    pub fn downgrade(self) -> Weak<T> {
+        self.with_arc(Arc::downgrade)
+    }
+
+    /// Runs `f` with an Arc that's borrowed from the original, for the Arc methods
+    /// that take `&Arc<T>`
+    pub fn with_arc<R>(self, f: impl FnOnce(&Arc<T>) -> R) -> R {
+        // never dropped, so it never gives back the reference it doesn't own
+        let arc = ManuallyDrop::new(Arc { ptr: self.ptr });
+        f(&arc)
+    }
+}
+
+impl<T: ?Sized> Deref for ArcBorrow<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.get()
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ArcBorrow<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.get(), f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn borrow_without_counting() {
+        let arc = Arc::with_tag(Arc::new(String::from("borrowed")), 3);
+        let borrow = Arc::borrow_arc(&arc);
+        let copy = borrow;
+        assert_eq!("borrowed", *copy);
+        assert_eq!(1, Arc::strong_count(&arc));
+
+        let owned = borrow.clone_arc();
+        assert_eq!(3, Arc::tag(&owned));
+        assert_eq!(2, borrow.with_arc(Arc::strong_count));
+        assert!(borrow.downgrade().upgrade().is_some());
+    }
+}
@@ -0,0 +1,140 @@
+//! Lock-free mutation of shared values through a branded token, GhostCell style.
+//!
+//! A [`Token`] is created by [`Token::with`], which gives it a brand: a lifetime that
+//! no other token shares. [`Branded`] values made with that brand can only be read
+//! through a `&Token` and written through a `&mut Token`. Since there's one token
+//! per brand, the borrow checker enforces that at most one branded value is being
+//! written at a time, across every value with the brand, with no runtime cost.
+//!
+//! Putting branded values in [`Arc`]s lets graph nodes point at each other freely,
+//! with weak pointers still checking liveness, while an algorithm holding the token
+//! mutates them without per-node locks.
+//!
+//! [`Arc`]: crate::Arc
+//!
+//! ```
+//! use provenant::brand::{Branded, Token};
+//! use provenant::Arc;
+//!
+//! Token::with(|mut token| {
+//!     let a = Arc::new(Branded::new(1));
+//!     let b = Arc::clone(&a);
+//!
+//!     *b.borrow_mut(&mut token) += 1;
+//!     assert_eq!(2, *a.borrow(&token));
+//! });
+//! ```
+//!
+//! Values can't be used with another brand's token:
+//!
+//! ```compile_fail
+//! use provenant::brand::{Branded, Token};
+//!
+//! Token::with(|token_a| {
+//!     let value = Branded::new(1);
+//!     value.borrow(&token_a);
+//!     Token::with(|token_b| {
+//!         value.borrow(&token_b);
+//!     });
+//! });
+//! ```
+
+use core::cell::UnsafeCell;
+use core::marker::PhantomData;
+
+// invariant in 'id, so brands can't be shortened or lengthened into each other
+type Brand<'id> = PhantomData<fn(&'id ()) -> &'id ()>;
+
+/// The only key to the [`Branded`] values with its brand
+pub struct Token<'id> {
+    _brand: Brand<'id>,
+}
+
+impl Token<'_> {
+    /// Runs `f` with a token that has a brand of its own
+    pub fn with<R, F>(f: F) -> R
+    where
+        F: for<'id> FnOnce(Token<'id>) -> R,
+    {
+        f(Token {
+            _brand: PhantomData,
+        })
+    }
+}
+
+/// A value that's accessed through the [`Token`] for its brand
+pub struct Branded<'id, T: ?Sized> {
+    _brand: Brand<'id>,
+    value: UnsafeCell<T>,
+}
+
+// sharing a Branded lets any thread with the token get &T or &mut T,
+// so it needs the same bounds as sharing a RwLock
+unsafe impl<T: ?Sized + Send + Sync> Sync for Branded<'_, T> {}
+
+impl<'id, T> Branded<'id, T> {
+    /// Wraps a value with a brand
+    pub fn new(value: T) -> Self {
+        Branded {
+            _brand: PhantomData,
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Unwraps the value
+    pub fn into_inner(self) -> T {
+        self.value.into_inner()
+    }
+}
+
+impl<'id, T: ?Sized> Branded<'id, T> {
+    /// Reads the value
+    pub fn borrow<'a>(&'a self, _token: &'a Token<'id>) -> &'a T {
+        // the shared borrow of the token rules out writes to any value with this brand
+        unsafe { &*self.value.get() }
+    }
+
+    /// Writes the value
+    #[allow(clippy::mut_from_ref)]
+    pub fn borrow_mut<'a>(&'a self, _token: &'a mut Token<'id>) -> &'a mut T {
+        // the unique borrow of the token rules out any other access with this brand
+        unsafe { &mut *self.value.get() }
+    }
+
+    /// Writes the value without the token, since `&mut self` is already unique
+    pub fn get_mut(&mut self) -> &mut T {
+        self.value.get_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Arc, Weak};
+
+    struct Node<'id> {
+        value: Branded<'id, u32>,
+        next: Option<Weak<Node<'id>>>,
+    }
+
+    #[test]
+    fn mutate_graph() {
+        Token::with(|mut token| {
+            let last = Arc::new(Node {
+                value: Branded::new(1),
+                next: None,
+            });
+            let first = Arc::new(Node {
+                value: Branded::new(10),
+                next: Some(Arc::downgrade(&last)),
+            });
+
+            let next = first.next.unwrap().upgrade().unwrap();
+            *next.value.borrow_mut(&mut token) += *first.value.borrow(&token);
+            assert_eq!(11, *last.value.borrow(&token));
+
+            drop((next, last));
+            assert!(first.next.unwrap().upgrade().is_none());
+        });
+    }
+}
@@ -0,0 +1,214 @@
+//! Hierarchical cancellation.
+//!
+//! A [`CancelScope`] owns a small allocation, and its [`CancelToken`]s are weak
+//! pointers to it. Cancelling the scope gives the allocation a new provenance, and
+//! dropping it frees the allocation, so either way every token stops upgrading at
+//! once, without the scope keeping a list of them.
+//!
+//! Scopes can be nested with [`CancelToken::child`]. A child scope only holds a
+//! token for its parent, so a token is cancelled when any scope above it is, which
+//! it finds out by walking up the chain.
+//!
+//! A token for a scope without a parent only has to look at the provenance, so
+//! checking it doesn't touch the reference count. Tokens for nested scopes upgrade
+//! and check a flag, which is all cancelling one sets. Many threads can poll one token
+//! without contending on anything but a read-only cache line.
+
+use crate::{Arc, Weak};
+use core::fmt;
+use core::sync::atomic::{fence, AtomicBool, Ordering};
+
+struct Node {
+    cancelled: AtomicBool,
+    parent: Option<CancelToken>,
+}
+
+/// Owns a cancellation scope. Dropping it cancels the scope.
+pub struct CancelScope {
+    node: Arc<Node>,
+}
+
+impl Default for CancelScope {
+    fn default() -> Self {
+        CancelScope::new()
+    }
+}
+
+impl CancelScope {
+    /// Creates a scope with no parent
+    pub fn new() -> Self {
+        CancelScope::with_parent(None)
+    }
+
+    fn with_parent(parent: Option<CancelToken>) -> Self {
+        CancelScope {
+            node: Arc::new(Node {
+                cancelled: AtomicBool::new(false),
+                parent,
+            }),
+        }
+    }
+
+    /// Hands out a token that's cancelled along with this scope
+    pub fn token(&self) -> CancelToken {
+        let weak = Arc::downgrade(&self.node);
+        // if that has the provenance cancel gave it, this sees the flag cancel set
+        fence(Ordering::Acquire);
+        if self.node.cancelled.load(Ordering::SeqCst) {
+            // the provenance is a fresh one from cancelling, so the token gets none
+            return CancelToken {
+                weak: Weak::new(),
+                nested: false,
+            };
+        }
+        CancelToken {
+            weak,
+            nested: self.node.parent.is_some(),
+        }
+    }
+
+    /// Creates a scope that's cancelled along with this one, or on its own
+    pub fn child(&self) -> CancelScope {
+        self.token().child()
+    }
+
+    /// Cancels this scope, and every scope below it, and all of their tokens
+    pub fn cancel(&self) {
+        self.node.cancelled.store(true, Ordering::SeqCst);
+        // tokens for a nested scope upgrade, and see the flag. their Arcs could be
+        // dropped while this rekeys, and leak the allocation, so only a scope without
+        // a parent rekeys. nothing else ever holds a strong reference to its node
+        if self.node.parent.is_none() {
+            self.node.inner().rekey();
+        }
+    }
+
+    /// Whether this scope, or one above it, has been cancelled
+    pub fn is_cancelled(&self) -> bool {
+        self.node.cancelled.load(Ordering::SeqCst)
+            || self.node.parent.is_some_and(|parent| parent.is_cancelled())
+    }
+}
+
+impl fmt::Debug for CancelScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelScope")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// Finds out whether a [`CancelScope`] has been cancelled or dropped
+#[derive(Clone, Copy)]
+pub struct CancelToken {
+    weak: Weak<Node>,
+    // whether the scope has a parent, which can only be found by upgrading
+    nested: bool,
+}
+
+impl CancelToken {
+    /// Whether the scope this came from, or one above it, has been cancelled or dropped
+    pub fn is_cancelled(&self) -> bool {
+        let mut token = *self;
+        loop {
+            // cancelling rekeys and dropping frees, so either way it's not alive
+            if !token.nested {
+                return !token.weak.is_probably_alive();
+            }
+            let node = match token.weak.upgrade() {
+                Some(node) => node,
+                None => return true,
+            };
+            if node.cancelled.load(Ordering::SeqCst) {
+                return true;
+            }
+            match node.parent {
+                Some(parent) => token = parent,
+                None => return false,
+            }
+        }
+    }
+
+    /// Creates a scope that's cancelled when this token is, or on its own
+    pub fn child(&self) -> CancelScope {
+        CancelScope::with_parent(Some(*self))
+    }
+}
+
+impl fmt::Debug for CancelToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancelToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cancel_and_drop() {
+        let scope = CancelScope::new();
+        let token = scope.token();
+        assert!(!token.is_cancelled());
+
+        scope.cancel();
+        assert!(token.is_cancelled());
+        assert!(scope.token().is_cancelled());
+
+        let scope = CancelScope::new();
+        let token = scope.token();
+        drop(scope);
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn checked_without_counting() {
+        let scope = CancelScope::new();
+        let token = scope.token();
+        assert!(!token.is_cancelled());
+        assert_eq!(1, Arc::strong_count(&scope.node));
+
+        scope.cancel();
+        assert!(token.is_cancelled());
+    }
+
+    #[test]
+    fn nested() {
+        let root = CancelScope::new();
+        let child = root.child();
+        let grandchild = child.token().child();
+        let token = grandchild.token();
+
+        let sibling = root.child();
+        sibling.cancel();
+        assert!(!token.is_cancelled());
+
+        drop(child);
+        assert!(token.is_cancelled());
+        assert!(grandchild.is_cancelled());
+        assert!(!root.token().is_cancelled());
+    }
+
+    #[test]
+    fn nested_cancelled_while_checked() {
+        let root = CancelScope::new();
+        for _ in 0..1000 {
+            let child = root.child();
+            let token = child.token();
+            let checkers: Vec<_> = (0..2)
+                .map(|_| std::thread::spawn(move || while !token.is_cancelled() {}))
+                .collect();
+
+            child.cancel();
+            let weak = Arc::downgrade(&child.node);
+            drop(child);
+            for checker in checkers {
+                checker.join().unwrap();
+            }
+            // freed by whichever Arc went last, not leaked
+            assert!(!weak.is_probably_alive());
+        }
+    }
+}
@@ -0,0 +1,356 @@
+//! Trait object Arcs, and casting between them.
+//!
+//! [`unsize!`](crate::unsize) turns an `Arc<T>` or `Weak<T>` into one to a trait
+//! object (or anything else `T` coerces to), which std does implicitly but needs an
+//! unstable trait for. On a nightly compiler, the `nightly` feature implements it,
+//! and the coercion happens implicitly here too.
+//!
+//! `Arc<dyn Any>`, and its `Send` and `Sync` variants, go back to the concrete type
+//! with [`downcast`](Arc::downcast), like std's.
+//!
+//! Going from one trait object to another needs to know the concrete type, so casts
+//! are registered up front with [`register_casts!`](crate::register_casts), then
+//! [`Arc::cast`] and [`Weak::cast`] look them up. The source trait has to extend
+//! [`CastFrom`], which every `'static` type implements. The registry needs `std`:
+//!
+//! ```
+//! # #[cfg(feature = "std")] {
+//! use provenant::cast::CastFrom;
+//! use provenant::{register_casts, unsize, Arc};
+//!
+//! trait Component: CastFrom {}
+//! trait Render {
+//!     fn render(&self) -> String;
+//! }
+//!
+//! struct Sprite;
+//! impl Component for Sprite {}
+//! impl Render for Sprite {
+//!     fn render(&self) -> String {
+//!         "sprite".into()
+//!     }
+//! }
+//!
+//! register_casts!(Sprite => dyn Component, dyn Render);
+//!
+//! let component: Arc<dyn Component> = unsize!(Arc::new(Sprite) => dyn Component);
+//! let render: Arc<dyn Render> = Arc::cast(component).ok().unwrap();
+//! assert_eq!("sprite", render.render());
+//! # }
+//! ```
+
+use crate::tag::{tag_of, untagged, with_tag};
+use crate::{Arc, Inner, Weak};
+use core::any::{Any, TypeId};
+use core::mem;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::{Arc as StdArc, OnceLock, PoisonError, RwLock};
+
+/// Gives trait objects their concrete type. Implemented for every `'static` type.
+pub trait CastFrom: Any {
+    /// The [`TypeId`] of the concrete type behind this value
+    fn concrete_type(&self) -> TypeId;
+}
+
+impl<T: Any> CastFrom for T {
+    fn concrete_type(&self) -> TypeId {
+        TypeId::of::<T>()
+    }
+}
+
+// points a data pointer's metadata at the Inner it lives in
+fn rebase<U: ?Sized>(data: *const U, inner: *const u8) -> *const Inner<U> {
+    let offset = inner as isize - data as *const u8 as isize;
+    data.wrapping_byte_offset(offset) as *const Inner<U>
+}
+
+impl<T> Arc<T> {
+    /// Turns this into an Arc to something `T` coerces to, usually a trait object.
+    /// [`unsize!`](crate::unsize) calls this safely.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return its argument, coerced.
+    pub unsafe fn unsize<U: ?Sized>(this: Self, coerce: fn(*const T) -> *const U) -> Arc<U> {
+        let inner = untagged(this.ptr);
+        let data = coerce(core::ptr::addr_of!((*inner).data));
+        let ptr = with_tag(rebase(data, inner as *const u8), tag_of(this.ptr));
+        mem::forget(this);
+        Arc { ptr }
+    }
+}
+
+impl<T> Weak<T> {
+    /// Turns this into a weak pointer to something `T` coerces to, usually a trait
+    /// object. [`unsize!`](crate::unsize) calls this safely.
+    ///
+    /// # Safety
+    ///
+    /// `coerce` must return its argument, coerced. It's given a pointer to the
+    /// value's old location, which may have been freed.
+    pub unsafe fn unsize<U: ?Sized>(&self, coerce: fn(*const T) -> *const U) -> Weak<U> {
+        let inner = untagged(self.ptr);
+        let data = inner.wrapping_byte_add(mem::offset_of!(Inner<T>, data)) as *const T;
+        Weak {
+            ptr: with_tag(rebase(coerce(data), inner as *const u8), tag_of(self.ptr)),
+            provenance: self.provenance,
+        }
+    }
+}
+
+/// Turns an `Arc<T>` or `Weak<T>` into one to a type `T` coerces to, like a trait object.
+///
+/// ```
+/// use provenant::{unsize, Arc};
+/// use std::fmt::Debug;
+///
+/// let arc: Arc<dyn Debug> = unsize!(Arc::new(5) => dyn Debug);
+/// assert_eq!("5", format!("{:?}", &*arc));
+/// ```
+#[macro_export]
+macro_rules! unsize {
+    ($ptr:expr => $ty:ty) => {{
+        let ptr = $ptr;
+        // the closure only coerces, which is what unsize needs
+        unsafe { $crate::cast::Unsize::unsize(ptr, |ptr| -> *const $ty { ptr }) }
+    }};
+}
+
+#[doc(hidden)]
+pub trait Unsize<T> {
+    type Output<U: ?Sized>;
+    unsafe fn unsize<U: ?Sized>(self, coerce: fn(*const T) -> *const U) -> Self::Output<U>;
+}
+
+impl<T> Unsize<T> for Arc<T> {
+    type Output<U: ?Sized> = Arc<U>;
+    unsafe fn unsize<U: ?Sized>(self, coerce: fn(*const T) -> *const U) -> Arc<U> {
+        Arc::unsize(self, coerce)
+    }
+}
+
+impl<T> Unsize<T> for Weak<T> {
+    type Output<U: ?Sized> = Weak<U>;
+    unsafe fn unsize<U: ?Sized>(self, coerce: fn(*const T) -> *const U) -> Weak<U> {
+        Weak::unsize(&self, coerce)
+    }
+}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + core::marker::Unsize<U>, U: ?Sized> core::ops::CoerceUnsized<Arc<U>> for Arc<T> {}
+
+#[cfg(feature = "nightly")]
+impl<T: ?Sized + core::marker::Unsize<U>, U: ?Sized> core::ops::CoerceUnsized<Weak<U>> for Weak<T> {}
+
+macro_rules! downcast {
+    ($($any:ty),+) => {$(
+        impl Arc<$any> {
+            /// Gets an Arc to the concrete type, or gives this back if the value
+            /// isn't a `T`. Keeps the tag.
+            ///
+            /// Takes `self`, like std's, since `Arc::downcast(arc)` would be ambiguous
+            /// between the `Any` variants.
+            pub fn downcast<T: Any>(self) -> Result<Arc<T>, Self> {
+                if !(*self).is::<T>() {
+                    return Err(self);
+                }
+                let ptr = self.ptr as *const Inner<T>;
+                mem::forget(self);
+                Ok(Arc { ptr })
+            }
+        }
+
+        impl Weak<$any> {
+            /// Gets a weak pointer to the concrete type. The value has to be alive to
+            /// find out its type, so this returns None if it's dead, as well as if
+            /// it isn't a `T`.
+            pub fn downcast<T: Any>(&self) -> Option<Weak<T>> {
+                let arc = self.upgrade()?.downcast::<T>().ok()?;
+                Some(Arc::downgrade(&arc))
+            }
+        }
+    )+};
+}
+
+downcast!(dyn Any, dyn Any + Send, dyn Any + Send + Sync);
+
+// the registry needs std. unsizing doesn't
+
+// each holds a Caster<U>, keyed by the concrete type and U
+#[cfg(feature = "std")]
+type Casters = HashMap<(TypeId, TypeId), Box<dyn Any + Send + Sync>>;
+
+// goes from the address of a concrete value to a U pointing at it
+#[cfg(feature = "std")]
+type Caster<U> = StdArc<dyn Fn(*const ()) -> *const U + Send + Sync>;
+
+#[cfg(feature = "std")]
+fn casters() -> &'static RwLock<Casters> {
+    static CASTERS: OnceLock<RwLock<Casters>> = OnceLock::new();
+    CASTERS.get_or_init(Default::default)
+}
+
+/// Lets values of type `T` be cast to `U` by [`Arc::cast`] and [`Weak::cast`].
+/// [`register_casts!`](crate::register_casts) calls this safely.
+///
+/// # Safety
+///
+/// `coerce` must return its argument, coerced.
+#[cfg(feature = "std")]
+pub unsafe fn register<T: 'static, U: ?Sized + 'static>(coerce: fn(*const T) -> *const U) {
+    let caster: Caster<U> = StdArc::new(move |data| coerce(data as *const T));
+    casters()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert((TypeId::of::<T>(), TypeId::of::<U>()), Box::new(caster));
+}
+
+/// Registers the casts from a concrete type to each of a list of trait objects.
+///
+/// ```
+/// use provenant::register_casts;
+/// use std::fmt::{Debug, Display};
+///
+/// register_casts!(u32 => dyn Debug, dyn Display);
+/// ```
+#[cfg(feature = "std")]
+#[macro_export]
+macro_rules! register_casts {
+    ($concrete:ty => $($target:ty),+ $(,)?) => {
+        $(
+            // the closure only coerces, which is what register needs
+            unsafe { $crate::cast::register::<$concrete, $target>(|ptr| -> *const $target { ptr }) };
+        )+
+    };
+}
+
+#[cfg(feature = "std")]
+fn caster<U: ?Sized + 'static>(concrete: TypeId) -> Option<Caster<U>> {
+    let casters = casters().read().unwrap_or_else(PoisonError::into_inner);
+    let caster = casters.get(&(concrete, TypeId::of::<U>()))?;
+    caster.downcast_ref::<Caster<U>>().cloned()
+}
+
+#[cfg(feature = "std")]
+impl<S: ?Sized + CastFrom> Arc<S> {
+    /// Casts to another type the value has a registered cast to, such as another
+    /// trait it implements. Gives this back if there isn't one.
+    pub fn cast<U: ?Sized + 'static>(this: Self) -> Result<Arc<U>, Self> {
+        let caster = match caster::<U>((*this).concrete_type()) {
+            Some(caster) => caster,
+            None => return Err(this),
+        };
+
+        let inner = untagged(this.ptr);
+        let data = caster(unsafe { core::ptr::addr_of!((*inner).data) } as *const ());
+        let ptr = with_tag(rebase(data, inner as *const u8), tag_of(this.ptr));
+        mem::forget(this);
+        Ok(Arc { ptr })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: ?Sized + CastFrom> Weak<S> {
+    /// Casts to another type the value has a registered cast to, such as another
+    /// trait it implements. The value has to be alive to find out its type, so this
+    /// returns None if it's dead, as well as if there's no cast.
+    pub fn cast<U: ?Sized + 'static>(&self) -> Option<Weak<U>> {
+        let arc = Arc::cast::<U>(self.upgrade()?).ok()?;
+        Some(Arc::downgrade(&arc))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    trait Shape: CastFrom {
+        fn area(&self) -> u32;
+    }
+
+    // only the registry casts to it
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    trait Named {
+        fn name(&self) -> &str;
+    }
+
+    struct Square(u32);
+
+    impl Shape for Square {
+        fn area(&self) -> u32 {
+            self.0 * self.0
+        }
+    }
+
+    impl Named for Square {
+        fn name(&self) -> &str {
+            "square"
+        }
+    }
+
+    #[test]
+    fn unsized_handles() {
+        let arc = Arc::with_tag(Arc::new(Square(3)), 2);
+        let weak = Arc::downgrade(&arc);
+
+        let shape: Arc<dyn Shape> = crate::unsize!(arc => dyn Shape);
+        assert_eq!(9, shape.area());
+        assert_eq!(2, Arc::tag(&shape));
+
+        let weak: Weak<dyn Shape> = crate::unsize!(weak => dyn Shape);
+        assert_eq!(9, weak.upgrade().unwrap().area());
+        drop(shape);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn downcast() {
+        let any: Arc<dyn Any + Send + Sync> =
+            crate::unsize!(Arc::new(Square(5)) => dyn Any + Send + Sync);
+        let weak = Arc::downgrade(&any);
+        assert!(weak.downcast::<u32>().is_none());
+
+        let any = any.downcast::<u32>().err().unwrap();
+        let square = any.downcast::<Square>().ok().unwrap();
+        assert_eq!(25, square.area());
+        assert_eq!(
+            25,
+            weak.downcast::<Square>().unwrap().upgrade().unwrap().area()
+        );
+
+        drop(square);
+        assert!(weak.downcast::<Square>().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn casts() {
+        let shape: Arc<dyn Shape> = crate::unsize!(Arc::new(Square(2)) => dyn Shape);
+        let shape = Arc::cast::<dyn Named>(shape).err().unwrap();
+
+        crate::register_casts!(Square => dyn Named);
+        let weak = Arc::downgrade(&shape);
+        let named: Arc<dyn Named> = Arc::cast(shape).ok().unwrap();
+        assert_eq!("square", named.name());
+
+        assert_eq!(
+            "square",
+            weak.cast::<dyn Named>().unwrap().upgrade().unwrap().name()
+        );
+        drop(named);
+        assert!(weak.cast::<dyn Named>().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "nightly")]
+    fn implicit() {
+        let arc = Arc::new(Square(4));
+        let weak = Arc::downgrade(&arc);
+        let weak: Weak<dyn Shape> = weak;
+        let shape: Arc<dyn Shape> = arc;
+        assert_eq!(16, shape.area());
+        assert_eq!(16, weak.upgrade().unwrap().area());
+    }
+}
@@ -0,0 +1,163 @@
+//! Comparison impls.
+
+use crate::{Arc, Provenance, Weak};
+use core::cmp::Ordering;
+use core::hash::{Hash, Hasher};
+
+// weak pointers are compared by identity: which allocation, and which of the
+// values that have lived there. tags belong to the handle, so they're ignored
+impl<T: ?Sized> Weak<T> {
+    fn identity(&self) -> (usize, Provenance) {
+        (self.addr(), self.provenance)
+    }
+
+    /// Returns true if both point at the same allocation with the same provenance id,
+    /// like `==`. A weak pointer to a value that's gone never equals one to whatever
+    /// was allocated in its place
+    pub fn ptr_eq(&self, other: &Self) -> bool {
+        self.identity() == other.identity()
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Returns true if both point to the same allocation, whatever their tags
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.addr() == other.addr()
+    }
+}
+
+/// Equal when they point at the same allocation with the same provenance id.
+/// Never upgrades.
+impl<T: ?Sized> PartialEq for Weak<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr_eq(other)
+    }
+}
+
+impl<T: ?Sized> Eq for Weak<T> {}
+
+/// Ordered by address, then provenance id. The order means nothing beyond being
+/// total and stable, so weak pointers can key ordered collections.
+impl<T: ?Sized> PartialOrd for Weak<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> Ord for Weak<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.identity().cmp(&other.identity())
+    }
+}
+
+/// Hashes the address and provenance id, like `==` compares them
+impl<T: ?Sized> Hash for Weak<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.identity().hash(state)
+    }
+}
+
+// Arcs compare by their values, like std's
+impl<T: ?Sized + PartialEq> PartialEq for Arc<T> {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl<T: ?Sized + Eq> Eq for Arc<T> {}
+
+impl<T: ?Sized + PartialOrd> PartialOrd for Arc<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        (**self).partial_cmp(&**other)
+    }
+
+    fn lt(&self, other: &Self) -> bool {
+        **self < **other
+    }
+
+    fn le(&self, other: &Self) -> bool {
+        **self <= **other
+    }
+
+    fn gt(&self, other: &Self) -> bool {
+        **self > **other
+    }
+
+    fn ge(&self, other: &Self) -> bool {
+        **self >= **other
+    }
+}
+
+impl<T: ?Sized + Ord> Ord for Arc<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (**self).cmp(&**other)
+    }
+}
+
+impl<T: ?Sized + Hash> Hash for Arc<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (**self).hash(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+
+    #[test]
+    fn identity() {
+        let a = Arc::new(1);
+        let b = Arc::new(1);
+        let weak = Arc::downgrade(&a);
+
+        assert_eq!(weak, Arc::downgrade(&Arc::with_tag(a.clone(), 1)));
+        assert_ne!(weak, Arc::downgrade(&b));
+
+        let mut sorted = vec![Arc::downgrade(&b), weak, Arc::downgrade(&b)];
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(2, sorted.len());
+    }
+
+    #[test]
+    fn hash_set() {
+        use std::collections::HashSet;
+
+        let mut a = Arc::new(1);
+        let before = Arc::downgrade(&a);
+        let mut registered: HashSet<_> = vec![before, before, Arc::downgrade(&a)]
+            .into_iter()
+            .collect();
+        assert_eq!(1, registered.len());
+
+        // same address, different provenance
+        assert!(Arc::get_mut(&mut a).is_some());
+        assert!(registered.insert(Arc::downgrade(&a)));
+        assert!(registered.contains(&before));
+        assert_eq!(2, registered.len());
+    }
+
+    #[test]
+    fn ptr_eq() {
+        let mut a = Arc::new(1);
+        let b = Arc::new(1);
+        assert!(Arc::ptr_eq(&a, &Arc::with_tag(a.clone(), 1)));
+        assert!(!Arc::ptr_eq(&a, &b));
+
+        // same allocation, but the value it held is gone
+        let weak = Arc::downgrade(&a);
+        assert!(Arc::get_mut(&mut a).is_some());
+        assert!(!weak.ptr_eq(&Arc::downgrade(&a)));
+    }
+
+    #[test]
+    fn by_value() {
+        let a = Arc::new(String::from("a"));
+        assert!(a == Arc::new(String::from("a")));
+        assert!(a < Arc::new(String::from("b")));
+
+        let mut set = std::collections::HashSet::new();
+        set.insert(a);
+        assert!(set.contains(&Arc::new(String::from("a"))));
+    }
+}
@@ -0,0 +1,1232 @@
+//! Collections of weak pointers.
+
+use crate::sweep::Sweep;
+use crate::{Arc, Weak};
+use std::borrow::Borrow;
+use std::cell::{Cell, UnsafeCell};
+use std::collections::hash_map::RandomState;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::RangeBounds;
+use std::ptr::NonNull;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+
+/// An ordered map whose values are held weakly.
+///
+/// Entries whose values have died are dropped by [`range`](WeakBTreeMap::range)
+/// and [`iter`](WeakBTreeMap::iter) as they pass them, and by
+/// [`prune`](WeakBTreeMap::prune). Registering a `Mutex<WeakBTreeMap>` with a
+/// [`Sweeper`](crate::sweep::Sweeper) prunes it in the background.
+pub struct WeakBTreeMap<K, V: ?Sized> {
+    map: BTreeMap<K, Weak<V>>,
+}
+
+impl<K, V: ?Sized> Default for WeakBTreeMap<K, V> {
+    fn default() -> Self {
+        WeakBTreeMap {
+            map: BTreeMap::new(),
+        }
+    }
+}
+
+impl<K: Ord, V: ?Sized> WeakBTreeMap<K, V> {
+    /// Creates an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `key` to a weak pointer to `value`, returning what it replaced
+    pub fn insert(&mut self, key: K, value: &Arc<V>) -> Option<Weak<V>> {
+        self.map.insert(key, Arc::downgrade(value))
+    }
+
+    /// Gets the value for `key`, if it's alive
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.map.get(key)?.upgrade()
+    }
+
+    /// Removes the entry for `key`, returning its value if it's alive
+    pub fn remove(&mut self, key: &K) -> Option<Arc<V>> {
+        self.map.remove(key)?.upgrade()
+    }
+
+    /// How many entries there are, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether there are no entries, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Drops every entry whose value has died, returning how many there were
+    pub fn prune(&mut self) -> usize {
+        let before = self.map.len();
+        self.map.retain(|_, weak| weak.is_probably_alive());
+        before - self.map.len()
+    }
+
+    /// Gets the live entries with keys in `range`, in order, dropping the dead ones
+    pub fn range<R>(&mut self, range: R) -> Vec<(&K, Arc<V>)>
+    where
+        K: Clone,
+        R: RangeBounds<K> + Clone,
+    {
+        let dead: Vec<K> = self
+            .map
+            .range(range.clone())
+            .filter(|(_, weak)| !weak.is_probably_alive())
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in &dead {
+            self.map.remove(key);
+        }
+
+        self.map
+            .range(range)
+            .filter_map(|(key, weak)| Some((key, weak.upgrade()?)))
+            .collect()
+    }
+
+    /// Gets every live entry, in order, dropping the dead ones
+    pub fn iter(&mut self) -> Vec<(&K, Arc<V>)> {
+        self.prune();
+        self.map
+            .iter()
+            .filter_map(|(key, weak)| Some((key, weak.upgrade()?)))
+            .collect()
+    }
+}
+
+impl<K: fmt::Debug, V: ?Sized> fmt::Debug for WeakBTreeMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K: Ord + Send, V: ?Sized + Send + Sync> Sweep for Mutex<WeakBTreeMap<K, V>> {
+    fn sweep(&self) -> usize {
+        self.lock().unwrap_or_else(PoisonError::into_inner).prune()
+    }
+}
+
+/// A hash map whose values are held weakly, for caches that don't keep what they
+/// hold alive.
+///
+/// Dead entries are pruned by [`prune`](WeakValueHashMap::prune), and by
+/// [`insert`](WeakValueHashMap::insert) whenever the map has doubled since it was
+/// last pruned, so the work is spread out and the map stays in proportion to what's
+/// alive. Like [`WeakBTreeMap`], a
+/// `Mutex<WeakValueHashMap>` can be swept in the background.
+///
+/// ```
+/// use provenant::collections::WeakValueHashMap;
+///
+/// let mut textures = WeakValueHashMap::new();
+/// let grass = textures.get_or_insert_with("grass.png", || vec![0u8; 16]);
+/// assert!(textures.get("grass.png").is_some());
+///
+/// drop(grass);
+/// assert!(textures.get("grass.png").is_none());
+/// ```
+pub struct WeakValueHashMap<K, V: ?Sized> {
+    map: HashMap<K, Weak<V>>,
+    // the length that sets off the next prune
+    prune_at: usize,
+}
+
+// the fewest entries worth pruning for
+const MIN_PRUNE: usize = 8;
+
+impl<K, V: ?Sized> Default for WeakValueHashMap<K, V> {
+    fn default() -> Self {
+        WeakValueHashMap {
+            map: HashMap::new(),
+            prune_at: MIN_PRUNE,
+        }
+    }
+}
+
+impl<K: Eq + Hash, V: ?Sized> WeakValueHashMap<K, V> {
+    /// Creates an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `key` to a weak pointer to `value`, returning what it replaced
+    pub fn insert(&mut self, key: K, value: &Arc<V>) -> Option<Weak<V>> {
+        if self.map.len() >= self.prune_at {
+            self.prune();
+            self.prune_at = (self.map.len() * 2).max(MIN_PRUNE);
+        }
+        self.map.insert(key, Arc::downgrade(value))
+    }
+
+    /// Gets the value for `key`, if it's alive
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.map.get(key)?.upgrade()
+    }
+
+    /// Gets the value for `key` if it's alive, or else makes it with `f` and inserts
+    /// it
+    pub fn get_or_insert_with(&mut self, key: K, f: impl FnOnce() -> V) -> Arc<V>
+    where
+        V: Sized,
+    {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = Arc::new(f());
+        self.insert(key, &value);
+        value
+    }
+
+    /// Removes the entry for `key`, returning its value if it's alive
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        self.map.remove(key)?.upgrade()
+    }
+
+    /// How many entries there are, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether there are no entries, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Drops every entry whose value has died, returning how many there were
+    pub fn prune(&mut self) -> usize {
+        let before = self.map.len();
+        self.map.retain(|_, weak| weak.is_probably_alive());
+        before - self.map.len()
+    }
+
+    /// Gets every live entry, in no particular order, dropping the dead ones
+    pub fn iter(&mut self) -> Vec<(&K, Arc<V>)> {
+        self.prune();
+        self.map
+            .iter()
+            .filter_map(|(key, weak)| Some((key, weak.upgrade()?)))
+            .collect()
+    }
+}
+
+impl<K: fmt::Debug, V: ?Sized> fmt::Debug for WeakValueHashMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K: Eq + Hash + Send, V: ?Sized + Send + Sync> Sweep for Mutex<WeakValueHashMap<K, V>> {
+    fn sweep(&self) -> usize {
+        self.lock().unwrap_or_else(PoisonError::into_inner).prune()
+    }
+}
+
+/// A hash map whose keys are held weakly, for attaching data to shared values
+/// without keeping them alive.
+///
+/// Keys are told apart by identity, like `==` on [`Weak`], not by value. An entry
+/// whose key has died can't be looked up any more, and is pruned the same way as
+/// in a [`WeakValueHashMap`], dropping its value.
+///
+/// ```
+/// use provenant::collections::WeakKeyHashMap;
+/// use provenant::Arc;
+///
+/// let mut labels = WeakKeyHashMap::new();
+/// let enemy = Arc::new([0.0f32; 3]);
+/// labels.insert(&enemy, "enemy");
+/// assert_eq!(Some(&"enemy"), labels.get(&enemy));
+///
+/// drop(enemy);
+/// assert_eq!(1, labels.prune());
+/// ```
+pub struct WeakKeyHashMap<K: ?Sized, V> {
+    map: HashMap<Weak<K>, V>,
+    // the length that sets off the next prune
+    prune_at: usize,
+}
+
+impl<K: ?Sized, V> Default for WeakKeyHashMap<K, V> {
+    fn default() -> Self {
+        WeakKeyHashMap {
+            map: HashMap::new(),
+            prune_at: MIN_PRUNE,
+        }
+    }
+}
+
+impl<K: ?Sized, V> WeakKeyHashMap<K, V> {
+    /// Creates an empty map
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maps `key` to `value`, returning the value it replaced
+    pub fn insert(&mut self, key: &Arc<K>, value: V) -> Option<V> {
+        if self.map.len() >= self.prune_at {
+            self.prune();
+            self.prune_at = (self.map.len() * 2).max(MIN_PRUNE);
+        }
+        self.map.insert(Arc::downgrade(key), value)
+    }
+
+    /// Gets the value for `key`
+    pub fn get(&self, key: &Arc<K>) -> Option<&V> {
+        self.map.get(&Arc::downgrade(key))
+    }
+
+    /// Gets the value for `key`, to change it
+    pub fn get_mut(&mut self, key: &Arc<K>) -> Option<&mut V> {
+        self.map.get_mut(&Arc::downgrade(key))
+    }
+
+    /// Removes the entry for `key`, returning its value
+    pub fn remove(&mut self, key: &Arc<K>) -> Option<V> {
+        self.map.remove(&Arc::downgrade(key))
+    }
+
+    /// How many entries there are, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Whether there are no entries, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    /// Drops every entry whose key has died, returning how many there were
+    pub fn prune(&mut self) -> usize {
+        let before = self.map.len();
+        self.map.retain(|weak, _| weak.is_probably_alive());
+        before - self.map.len()
+    }
+
+    /// Gets every entry whose key is alive, in no particular order, dropping the
+    /// dead ones
+    pub fn iter(&mut self) -> Vec<(Arc<K>, &V)> {
+        self.prune();
+        self.map
+            .iter()
+            .filter_map(|(weak, value)| Some((weak.upgrade()?, value)))
+            .collect()
+    }
+}
+
+impl<K: ?Sized, V: fmt::Debug> fmt::Debug for WeakKeyHashMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.map.iter()).finish()
+    }
+}
+
+impl<K: ?Sized + Send + Sync, V: Send> Sweep for Mutex<WeakKeyHashMap<K, V>> {
+    fn sweep(&self) -> usize {
+        self.lock().unwrap_or_else(PoisonError::into_inner).prune()
+    }
+}
+
+/// A list of weak pointers, for listener lists and the like.
+///
+/// Dead entries are pruned by [`retain_live`](WeakVec::retain_live), by
+/// [`iter_upgraded`](WeakVec::iter_upgraded), and by [`push`](WeakVec::push) the
+/// same way as [`WeakValueHashMap::insert`], so the list stays in proportion to
+/// what's alive even if it's never iterated. Order is kept.
+///
+/// `iter_upgraded` hands back strong references without borrowing the list, so
+/// what's done with them can push to it or prune it:
+///
+/// ```
+/// use provenant::collections::WeakVec;
+/// use provenant::Arc;
+///
+/// let mut listeners = WeakVec::new();
+/// let window = Arc::new(String::from("window"));
+/// listeners.push(&window);
+/// listeners.push(&Arc::new(String::from("closed already")));
+///
+/// for listener in listeners.iter_upgraded() {
+///     listeners.push(&listener);
+/// }
+/// assert_eq!(2, listeners.len());
+/// ```
+pub struct WeakVec<T: ?Sized> {
+    weaks: Vec<Weak<T>>,
+    // the length that sets off the next prune
+    prune_at: usize,
+}
+
+impl<T: ?Sized> Default for WeakVec<T> {
+    fn default() -> Self {
+        WeakVec {
+            weaks: Vec::new(),
+            prune_at: MIN_PRUNE,
+        }
+    }
+}
+
+impl<T: ?Sized> WeakVec<T> {
+    /// Creates an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a weak pointer to `value` at the end
+    pub fn push(&mut self, value: &Arc<T>) {
+        if self.weaks.len() >= self.prune_at {
+            self.retain_live();
+            self.prune_at = (self.weaks.len() * 2).max(MIN_PRUNE);
+        }
+        self.weaks.push(Arc::downgrade(value));
+    }
+
+    /// How many entries there are, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.weaks.len()
+    }
+
+    /// Whether there are no entries, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.weaks.is_empty()
+    }
+
+    /// Drops every entry whose value has died, returning how many there were
+    pub fn retain_live(&mut self) -> usize {
+        let before = self.weaks.len();
+        self.weaks.retain(|weak| weak.is_probably_alive());
+        before - self.weaks.len()
+    }
+
+    /// Upgrades every live entry, in order, dropping the dead ones on the way
+    pub fn iter_upgraded(&mut self) -> impl Iterator<Item = Arc<T>> {
+        let mut live = Vec::with_capacity(self.weaks.len());
+        self.weaks.retain(|weak| match weak.upgrade() {
+            Some(arc) => {
+                live.push(arc);
+                true
+            }
+            None => false,
+        });
+        live.into_iter()
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for WeakVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.weaks.iter()).finish()
+    }
+}
+
+impl<T: ?Sized + Send + Sync> Sweep for Mutex<WeakVec<T>> {
+    fn sweep(&self) -> usize {
+        self.lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain_live()
+    }
+}
+
+/// A doubly linked list, whose nodes link forward with Arcs and back with weak
+/// pointers.
+///
+/// Each node is kept alive by the one before it, and the first by the list, so
+/// there's no cycle to leak, and nothing to count going backwards: moving back
+/// upgrades a weak pointer. Besides the ends, changes go through a [`CursorMut`]:
+///
+/// ```
+/// use provenant::collections::LinkedList;
+///
+/// let mut list: LinkedList<u32> = (1..=3).collect();
+/// let mut cursor = list.cursor_front_mut();
+/// cursor.move_next();
+/// assert_eq!(Some(&mut 2), cursor.current());
+///
+/// cursor.insert_after(4);
+/// assert_eq!(Some(2), cursor.remove_current());
+/// cursor.move_prev();
+/// cursor.insert_before(0);
+/// assert_eq!(vec![0, 1, 4, 3], list.iter().copied().collect::<Vec<_>>());
+/// ```
+pub struct LinkedList<T> {
+    head: Option<Arc<Node<T>>>,
+    tail: Weak<Node<T>>,
+    len: usize,
+}
+
+// nodes are never seen outside the list, so what's in them is only changed while
+// it's borrowed mutably. that's what makes the UnsafeCells sound, and the value
+// can't go through Arc::get_mut, which would detach the weak pointers to it
+struct Node<T> {
+    value: UnsafeCell<T>,
+    next: UnsafeCell<Option<Arc<Node<T>>>>,
+    // Weak::new() for the first node
+    prev: Cell<Weak<Node<T>>>,
+}
+
+unsafe impl<T: Send> Send for LinkedList<T> {}
+unsafe impl<T: Sync> Sync for LinkedList<T> {}
+
+// the link that owns the node after `prev`, or the first node. nothing else can be
+// borrowed from it, which holding the list mutably makes sure of
+unsafe fn slot<'a, T>(
+    head: &'a mut Option<Arc<Node<T>>>,
+    prev: Option<&'a Node<T>>,
+) -> &'a mut Option<Arc<Node<T>>> {
+    match prev {
+        Some(prev) => &mut *prev.next.get(),
+        None => head,
+    }
+}
+
+// the last Arc to a node that's been unlinked
+fn into_value<T>(node: Option<Arc<Node<T>>>) -> T {
+    match node.map(Arc::try_unwrap) {
+        Some(Ok(node)) => node.value.into_inner(),
+        _ => unreachable!("a node is only owned by the link to it"),
+    }
+}
+
+impl<T> Default for LinkedList<T> {
+    fn default() -> Self {
+        LinkedList {
+            head: None,
+            tail: Weak::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<T> LinkedList<T> {
+    /// Creates an empty list
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many values there are
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there are no values
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Gets the first value
+    pub fn front(&self) -> Option<&T> {
+        let head = self.head.as_ref()?;
+        Some(unsafe { &*head.value.get() })
+    }
+
+    /// Gets the last value
+    pub fn back(&self) -> Option<&T> {
+        // kept alive by the node before it, for as long as the list is borrowed
+        let value = self.tail.upgrade()?.value.get();
+        Some(unsafe { &*value })
+    }
+
+    /// Adds `value` at the start
+    pub fn push_front(&mut self, value: T) {
+        self.insert_after(None, value);
+    }
+
+    /// Adds `value` at the end
+    pub fn push_back(&mut self, value: T) {
+        let tail = self.tail.upgrade();
+        self.insert_after(tail.as_deref(), value);
+    }
+
+    /// Removes the first value
+    pub fn pop_front(&mut self) -> Option<T> {
+        let head: *const Node<T> = self.head.as_deref()?;
+        Some(unsafe { self.remove(head) })
+    }
+
+    /// Removes the last value
+    pub fn pop_back(&mut self) -> Option<T> {
+        let tail = Arc::as_ptr(&self.tail.upgrade()?);
+        Some(unsafe { self.remove(tail) })
+    }
+
+    /// Iterates over the values, first to last
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            next: self.head.as_deref(),
+            len: self.len,
+        }
+    }
+
+    /// Gets a cursor at the first value, or at the ghost position if it's empty
+    pub fn cursor_front_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.head.as_deref().map(NonNull::from);
+        CursorMut {
+            list: self,
+            current,
+        }
+    }
+
+    /// Gets a cursor at the last value, or at the ghost position if it's empty
+    pub fn cursor_back_mut(&mut self) -> CursorMut<'_, T> {
+        let current = self.tail.upgrade().map(|tail| NonNull::from(&*tail));
+        CursorMut {
+            list: self,
+            current,
+        }
+    }
+
+    // puts `value` in a new node after `prev`, or first
+    fn insert_after(&mut self, prev: Option<&Node<T>>, value: T) {
+        let slot = unsafe { slot(&mut self.head, prev) };
+        let next = slot.take();
+        // whatever pointed back at prev before
+        let back = match &next {
+            Some(next) => next.prev.get(),
+            None => self.tail,
+        };
+        let node = Arc::new(Node {
+            value: UnsafeCell::new(value),
+            next: UnsafeCell::new(None),
+            prev: Cell::new(back),
+        });
+        match &next {
+            Some(next) => next.prev.set(Arc::downgrade(&node)),
+            None => self.tail = Arc::downgrade(&node),
+        }
+        unsafe { *node.next.get() = next };
+        *slot = Some(node);
+        self.len += 1;
+    }
+
+    // unlinks `node`, which has to be in this list, and gets its value. it's a
+    // pointer rather than a reference since it's freed before this returns
+    unsafe fn remove(&mut self, node: *const Node<T>) -> T {
+        let (prev, next) = {
+            let node = &*node;
+            let next = (*node.next.get()).take();
+            match &next {
+                Some(next) => next.prev.set(node.prev.get()),
+                None => self.tail = node.prev.get(),
+            }
+            (node.prev.get().upgrade(), next)
+        };
+        let slot = slot(&mut self.head, prev.as_deref());
+        let owned = mem::replace(slot, next);
+        drop(prev);
+        self.len -= 1;
+        into_value(owned)
+    }
+}
+
+impl<T> Drop for LinkedList<T> {
+    // one node at a time, since dropping the first would otherwise recurse down the
+    // whole list
+    fn drop(&mut self) {
+        let mut next = self.head.take();
+        while let Some(node) = next {
+            next = unsafe { (*node.next.get()).take() };
+        }
+    }
+}
+
+impl<T> FromIterator<T> for LinkedList<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut list = LinkedList::new();
+        list.extend(iter);
+        list
+    }
+}
+
+impl<T> Extend<T> for LinkedList<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push_back(value);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for LinkedList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over a [`LinkedList`]'s values, from [`LinkedList::iter`]
+pub struct Iter<'a, T> {
+    next: Option<&'a Node<T>>,
+    len: usize,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        let node = self.next?;
+        // the list is borrowed for 'a, so none of this changes
+        unsafe {
+            self.next = (*node.next.get()).as_deref();
+            self.len -= 1;
+            Some(&*node.value.get())
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+/// A position in a [`LinkedList`] that can change the list around it.
+///
+/// It's either at a value or at the ghost position, which is past the last value
+/// and before the first, so moving on from the end wraps around through it.
+pub struct CursorMut<'a, T> {
+    list: &'a mut LinkedList<T>,
+    // None at the ghost position. the list keeps it alive, and only the cursor can
+    // remove it while it's borrowed
+    current: Option<NonNull<Node<T>>>,
+}
+
+impl<T> CursorMut<'_, T> {
+    /// Gets the value the cursor is at
+    pub fn current(&mut self) -> Option<&mut T> {
+        let node = self.node()?;
+        Some(unsafe { &mut *node.value.get() })
+    }
+
+    /// Moves to the next value, or from the last to the ghost position
+    pub fn move_next(&mut self) {
+        let next = match self.node() {
+            Some(node) => unsafe { (*node.next.get()).as_deref() },
+            None => self.list.head.as_deref(),
+        };
+        self.current = next.map(NonNull::from);
+    }
+
+    /// Moves to the previous value, or from the first to the ghost position
+    pub fn move_prev(&mut self) {
+        let prev = match self.node() {
+            Some(node) => node.prev.get().upgrade(),
+            None => self.list.tail.upgrade(),
+        };
+        self.current = prev.map(|prev| NonNull::from(&*prev));
+    }
+
+    /// Adds `value` after the cursor, or at the start from the ghost position
+    pub fn insert_after(&mut self, value: T) {
+        let node = self.current.map(|node| unsafe { &*node.as_ptr() });
+        self.list.insert_after(node, value);
+    }
+
+    /// Adds `value` before the cursor, or at the end from the ghost position
+    pub fn insert_before(&mut self, value: T) {
+        let prev = match self.node() {
+            Some(node) => node.prev.get().upgrade(),
+            None => self.list.tail.upgrade(),
+        };
+        self.list.insert_after(prev.as_deref(), value);
+    }
+
+    /// Removes the value the cursor is at and moves to the next one
+    pub fn remove_current(&mut self) -> Option<T> {
+        let node = self.current?.as_ptr();
+        self.move_next();
+        Some(unsafe { self.list.remove(node) })
+    }
+
+    fn node(&self) -> Option<&Node<T>> {
+        self.current.map(|node| unsafe { &*node.as_ptr() })
+    }
+}
+
+/// Deduplicates values into shared Arcs, without keeping them alive.
+///
+/// Interning a value that's equal to one already interned, and still alive, gets
+/// another Arc to the same allocation. Only weak pointers are kept, so a value is
+/// freed as soon as nothing outside the interner uses it. Entries for dead values
+/// are pruned like in a [`WeakValueHashMap`].
+///
+/// ```
+/// use provenant::collections::Interner;
+/// use provenant::Arc;
+///
+/// let names: Interner<str> = Interner::new();
+/// let a = names.intern("main");
+/// let b = names.intern(String::from("main"));
+/// assert!(Arc::ptr_eq(&a, &b));
+/// ```
+pub struct Interner<T: ?Sized> {
+    state: Mutex<InternerState<T>>,
+}
+
+struct InternerState<T: ?Sized> {
+    // by the value's hash, so the values themselves aren't kept
+    buckets: HashMap<u64, Vec<Weak<T>>>,
+    hasher: RandomState,
+    len: usize,
+    // the length that sets off the next prune
+    prune_at: usize,
+}
+
+impl<T: ?Sized> Default for Interner<T> {
+    fn default() -> Self {
+        Interner {
+            state: Mutex::new(InternerState {
+                buckets: HashMap::new(),
+                hasher: RandomState::new(),
+                len: 0,
+                prune_at: MIN_PRUNE,
+            }),
+        }
+    }
+}
+
+impl<T: ?Sized + Eq + Hash> Interner<T> {
+    /// Creates an empty interner
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets an Arc to the interned value equal to `value`, interning it if there
+    /// isn't one. Interning an Arc that isn't interned yet keeps it, without copying
+    pub fn intern<Q: Borrow<T> + Into<Arc<T>>>(&self, value: Q) -> Arc<T> {
+        let mut state = self.lock();
+        let hash = state.hasher.hash_one(value.borrow());
+        if let Some(arc) = state.find(hash, value.borrow()) {
+            return arc;
+        }
+
+        if state.len >= state.prune_at {
+            state.prune();
+            state.prune_at = (state.len * 2).max(MIN_PRUNE);
+        }
+        let arc = value.into();
+        state
+            .buckets
+            .entry(hash)
+            .or_default()
+            .push(Arc::downgrade(&arc));
+        state.len += 1;
+        arc
+    }
+
+    /// Gets an Arc to the interned value equal to `value`, if there is one
+    pub fn get(&self, value: &T) -> Option<Arc<T>> {
+        let mut state = self.lock();
+        let hash = state.hasher.hash_one(value);
+        state.find(hash, value)
+    }
+
+    /// How many values are interned, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.lock().len
+    }
+
+    /// Whether nothing is interned, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops the entries for values that have died, returning how many there were
+    pub fn prune(&self) -> usize {
+        self.lock().prune()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, InternerState<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T: ?Sized + Eq> InternerState<T> {
+    // looks through the bucket for `hash`, dropping dead entries on the way
+    fn find(&mut self, hash: u64, value: &T) -> Option<Arc<T>> {
+        let bucket = self.buckets.get_mut(&hash)?;
+        let mut found = None;
+        let before = bucket.len();
+        bucket.retain(|weak| match weak.upgrade() {
+            Some(arc) => {
+                if found.is_none() && *arc == *value {
+                    found = Some(arc);
+                }
+                true
+            }
+            None => false,
+        });
+        self.len -= before - bucket.len();
+        if bucket.is_empty() {
+            self.buckets.remove(&hash);
+        }
+        found
+    }
+
+    fn prune(&mut self) -> usize {
+        let before = self.len;
+        self.buckets.retain(|_, bucket| {
+            bucket.retain(|weak| weak.is_probably_alive());
+            !bucket.is_empty()
+        });
+        self.len = self.buckets.values().map(Vec::len).sum();
+        before - self.len
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Interner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("Interner").field("len", &state.len).finish()
+    }
+}
+
+impl<T: ?Sized + Eq + Hash + Send + Sync> Sweep for Interner<T> {
+    fn sweep(&self) -> usize {
+        self.prune()
+    }
+}
+
+/// A cache of computed values, held weakly and shared between threads.
+///
+/// [`get_or_insert_with`](MemoCache::get_or_insert_with) only computes a value if
+/// the last one computed for its key has been dropped. If several threads miss on
+/// the same key at once, one computes the value and the rest wait for it.
+/// Dead entries are pruned like in a [`WeakValueHashMap`].
+///
+/// ```
+/// use provenant::collections::MemoCache;
+///
+/// let parsed: MemoCache<&str, Vec<u32>> = MemoCache::new();
+/// let a = parsed.get_or_insert_with("1,2,3", || vec![1, 2, 3]);
+/// let b = parsed.get_or_insert_with("1,2,3", || unreachable!());
+/// assert_eq!(a, b);
+/// ```
+pub struct MemoCache<K, T> {
+    state: Mutex<MemoState<K, T>>,
+    // signalled whenever a value is done being computed
+    computed: Condvar,
+}
+
+struct MemoState<K, T> {
+    entries: HashMap<K, Memo<T>>,
+    // the length that sets off the next prune
+    prune_at: usize,
+}
+
+enum Memo<T> {
+    Ready(Weak<T>),
+    // some thread is computing it, and the others should wait
+    Computing,
+}
+
+impl<K, T> Default for MemoCache<K, T> {
+    fn default() -> Self {
+        MemoCache {
+            state: Mutex::new(MemoState {
+                entries: HashMap::new(),
+                prune_at: MIN_PRUNE,
+            }),
+            computed: Condvar::new(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, T> MemoCache<K, T> {
+    /// Creates an empty cache
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the value for `key` if it's alive, or else computes it with `f`. Waits
+    /// if another thread is already computing it.
+    ///
+    /// If `f` panics, one of the waiting threads computes the value instead.
+    pub fn get_or_insert_with(&self, key: K, f: impl FnOnce() -> T) -> Arc<T> {
+        let mut state = self.lock();
+        loop {
+            match state.entries.get(&key) {
+                Some(Memo::Ready(weak)) => match weak.upgrade() {
+                    Some(value) => return value,
+                    None => break,
+                },
+                Some(Memo::Computing) => {
+                    state = self
+                        .computed
+                        .wait(state)
+                        .unwrap_or_else(PoisonError::into_inner);
+                }
+                None => break,
+            }
+        }
+
+        if state.entries.len() >= state.prune_at {
+            state.prune();
+            state.prune_at = (state.entries.len() * 2).max(MIN_PRUNE);
+        }
+        state.entries.insert(key.clone(), Memo::Computing);
+        drop(state);
+
+        let computing = Computing {
+            cache: self,
+            key: Some(key),
+        };
+        let value = Arc::new(f());
+        let key = computing.finish();
+        self.lock()
+            .entries
+            .insert(key, Memo::Ready(Arc::downgrade(&value)));
+        self.computed.notify_all();
+        value
+    }
+
+    /// Gets the value for `key`, if it's alive and done being computed
+    pub fn get<Q>(&self, key: &Q) -> Option<Arc<T>>
+    where
+        K: Borrow<Q>,
+        Q: ?Sized + Eq + Hash,
+    {
+        match self.lock().entries.get(key)? {
+            Memo::Ready(weak) => weak.upgrade(),
+            Memo::Computing => None,
+        }
+    }
+
+    /// How many entries there are, including dead ones not yet pruned
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether there are no entries, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Drops every entry whose value has died, returning how many there were
+    pub fn prune(&self) -> usize {
+        self.lock().prune()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MemoState<K, T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<K, T> MemoState<K, T> {
+    fn prune(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|_, memo| match memo {
+            Memo::Ready(weak) => weak.is_probably_alive(),
+            Memo::Computing => true,
+        });
+        before - self.entries.len()
+    }
+}
+
+// takes the Computing entry back out if the value's computation panics, so a
+// waiting thread can have a go
+struct Computing<'a, K: Eq + Hash + Clone, T> {
+    cache: &'a MemoCache<K, T>,
+    // None once it's finished
+    key: Option<K>,
+}
+
+impl<K: Eq + Hash + Clone, T> Computing<'_, K, T> {
+    fn finish(mut self) -> K {
+        self.key.take().unwrap()
+    }
+}
+
+impl<K: Eq + Hash + Clone, T> Drop for Computing<'_, K, T> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.cache.lock().entries.remove(&key);
+            self.cache.computed.notify_all();
+        }
+    }
+}
+
+impl<K, T> fmt::Debug for MemoCache<K, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        f.debug_struct("MemoCache")
+            .field("len", &state.entries.len())
+            .finish()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, T: Send + Sync> Sweep for MemoCache<K, T> {
+    fn sweep(&self) -> usize {
+        self.prune()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_prunes() {
+        let mut tasks: Vec<Arc<&str>> = ["a", "b", "c", "d"].iter().map(|&t| Arc::new(t)).collect();
+        let mut by_deadline = WeakBTreeMap::new();
+        for (deadline, task) in tasks.iter().enumerate() {
+            by_deadline.insert(deadline as u64, task);
+        }
+
+        drop(tasks.remove(1));
+        drop(tasks.remove(2));
+
+        let due: Vec<&str> = by_deadline
+            .range(..3)
+            .into_iter()
+            .map(|(_, t)| *t)
+            .collect();
+        assert_eq!(vec!["a", "c"], due);
+        assert_eq!(3, by_deadline.len());
+        assert_eq!(1, by_deadline.prune());
+    }
+
+    #[test]
+    fn insert_prunes() {
+        let mut cache = WeakValueHashMap::new();
+        let kept = Arc::new(String::from("kept"));
+        cache.insert(String::from("kept"), &kept);
+        for i in 0..100 {
+            cache.insert(i.to_string(), &Arc::new(i.to_string()));
+        }
+
+        assert!(cache.len() <= 2 * MIN_PRUNE);
+        assert_eq!("kept", *cache.get("kept").unwrap());
+        assert!(cache.get("99").is_none());
+        assert_eq!(1, cache.iter().len());
+    }
+
+    #[test]
+    fn keyed_by_identity() {
+        let a = Arc::new(1);
+        let b = Arc::new(1);
+        let mut names = WeakKeyHashMap::new();
+        names.insert(&a, "a");
+        names.insert(&b, "b");
+
+        *names.get_mut(&b).unwrap() = "bee";
+        assert_eq!(Some(&"a"), names.get(&a));
+        assert_eq!(Some(&"bee"), names.get(&Arc::clone(&b)));
+
+        drop(a);
+        let alive = names.iter();
+        assert_eq!(1, alive.len());
+        assert!(Arc::ptr_eq(&b, &alive[0].0));
+    }
+
+    #[test]
+    fn weak_vec() {
+        let kept = Arc::new(0);
+        let mut list = WeakVec::new();
+        list.push(&kept);
+        for i in 1..100 {
+            list.push(&Arc::new(i));
+        }
+        assert!(list.len() <= 2 * MIN_PRUNE);
+
+        let other = Arc::new(100);
+        list.push(&other);
+        let live: Vec<i32> = list.iter_upgraded().map(|arc| *arc).collect();
+        assert_eq!(vec![0, 100], live);
+        assert_eq!(2, list.len());
+
+        drop(other);
+        assert_eq!(1, list.retain_live());
+    }
+
+    #[test]
+    fn linked_list() {
+        let mut list = LinkedList::new();
+        list.push_back(2);
+        list.push_front(1);
+        list.push_back(3);
+        assert_eq!((Some(&1), Some(&3)), (list.front(), list.back()));
+
+        let mut cursor = list.cursor_back_mut();
+        cursor.move_next();
+        assert_eq!(None, cursor.current());
+        cursor.move_prev();
+        cursor.move_prev();
+        assert_eq!(Some(2), cursor.remove_current());
+        assert_eq!(Some(&mut 3), cursor.current());
+        cursor.insert_before(5);
+        assert_eq!("[1, 5, 3]", format!("{:?}", list));
+
+        assert_eq!(Some(3), list.pop_back());
+        assert_eq!(Some(1), list.pop_front());
+        assert_eq!(Some(5), list.pop_back());
+        assert!(list.is_empty() && list.back().is_none());
+
+        // long enough that dropping it recursively would overflow the stack
+        let long: LinkedList<u32> = (0..1_000_000).collect();
+        assert_eq!(1_000_000, long.iter().len());
+    }
+
+    #[test]
+    fn interned_until_dropped() {
+        let interner = Interner::new();
+        let kept = Arc::new(7u64);
+        assert!(Arc::ptr_eq(&kept, &interner.intern(kept.clone())));
+        assert!(Arc::ptr_eq(&kept, &interner.intern(7)));
+
+        let dropped = interner.intern(8);
+        assert_eq!(2, interner.len());
+        drop(dropped);
+        assert!(interner.get(&8).is_none());
+        assert_eq!(1, interner.len());
+        assert_eq!(7, *interner.get(&7).unwrap());
+    }
+
+    #[test]
+    fn computed_once() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+        use std::time::Duration;
+
+        let cache = MemoCache::new();
+        let computed = AtomicUsize::new(0);
+        let values: Vec<Arc<u32>> = thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| {
+                    s.spawn(|| {
+                        cache.get_or_insert_with("slow", || {
+                            thread::sleep(Duration::from_millis(20));
+                            computed.fetch_add(1, Ordering::Relaxed);
+                            5
+                        })
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        assert_eq!(1, computed.load(Ordering::Relaxed));
+        assert!(values.iter().all(|value| Arc::ptr_eq(value, &values[0])));
+        drop(values);
+        assert_eq!(6, *cache.get_or_insert_with("slow", || 6));
+    }
+
+    #[test]
+    fn panicked() {
+        let cache = MemoCache::new();
+        let result = std::panic::catch_unwind(|| cache.get_or_insert_with(1, || panic!()));
+        assert!(result.is_err());
+        assert!(cache.is_empty());
+        assert_eq!(2, *cache.get_or_insert_with(1, || 2));
+    }
+}
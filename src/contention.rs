@@ -0,0 +1,152 @@
+// what a thread does while another one holds an allocation's provenance lock.
+//
+// the lock is only ever held for a couple of atomic operations, so spinning is the
+// default, with exponential backoff so waiters don't hammer the cache line. crate
+// features pick something else, for deployments that care more about cpu time than
+// latency:
+//
+// - `lock-yield` spins briefly, then yields to the scheduler between attempts
+// - `lock-park` spins briefly, then sleeps in the parking lot until the lock is
+//   released. the parking lot is std's Mutex and Condvar, so on Linux this is a futex
+// - `lock-std` leaves it all to std::sync, sleeping on the parking lot's Condvar
+//   straight away without spinning at all. the least cpu, and the most latency
+//
+// features are additive, so if several are enabled, the last in that list wins.
+// the strategy is the same for every type: a type parameter on Arc and Weak would
+// have to show up everywhere they're named. under `--cfg shuttle` or
+// `--cfg loom`, threads always yield to the scheduler. on wasm32 without the
+// `atomics` target feature, there's only the one thread, so a held lock is held by
+// the thread waiting on it, and this panics rather than wait forever.
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    any(feature = "lock-yield", feature = "lock-park"),
+    not(feature = "lock-std")
+))]
+const SPINS: u32 = 64;
+
+// spins twice as long as last time, up to a limit
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    not(feature = "lock-std")
+))]
+#[inline]
+fn backoff(attempt: u32) {
+    for _ in 0..1u32 << attempt.min(6) {
+        core::hint::spin_loop();
+    }
+}
+
+// called after a failed attempt to take the lock, with how many came before it.
+// `locked` rechecks whether the lock is still held
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    not(any(feature = "lock-yield", feature = "lock-park", feature = "lock-std"))
+))]
+#[inline]
+pub(crate) fn wait(_addr: usize, attempt: u32, _locked: impl Fn() -> bool) {
+    backoff(attempt);
+}
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    feature = "lock-yield",
+    not(any(feature = "lock-park", feature = "lock-std"))
+))]
+pub(crate) fn wait(_addr: usize, attempt: u32, _locked: impl Fn() -> bool) {
+    if attempt < SPINS {
+        backoff(attempt);
+    } else {
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    feature = "lock-park",
+    not(feature = "lock-std")
+))]
+pub(crate) fn wait(addr: usize, attempt: u32, locked: impl Fn() -> bool) {
+    if attempt < SPINS {
+        backoff(attempt);
+        return;
+    }
+    sleep(addr, locked);
+}
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    feature = "lock-std"
+))]
+pub(crate) fn wait(addr: usize, _attempt: u32, locked: impl Fn() -> bool) {
+    sleep(addr, locked);
+}
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    not(all(target_arch = "wasm32", not(target_feature = "atomics"))),
+    any(feature = "lock-park", feature = "lock-std")
+))]
+fn sleep(addr: usize, locked: impl Fn() -> bool) {
+    use std::time::{Duration, Instant};
+
+    // the timeout covers unlocks that don't go through released
+    let deadline = Instant::now() + Duration::from_millis(1);
+    crate::park::wait_until(addr, Some(deadline), || !locked());
+}
+
+#[cfg(all(
+    not(any(shuttle, loom)),
+    all(target_arch = "wasm32", not(target_feature = "atomics"))
+))]
+pub(crate) fn wait(_addr: usize, _attempt: u32, _locked: impl Fn() -> bool) {
+    panic!("a provenance lock is held by the only thread there is, the one waiting for it");
+}
+
+// shuttle runs one thread at a time, so spinning would never end
+#[cfg(shuttle)]
+pub(crate) fn wait(_addr: usize, _attempt: u32, _locked: impl Fn() -> bool) {
+    shuttle::thread::yield_now();
+}
+
+// same for loom, which also needs to know it's a spin loop to keep the model finite
+#[cfg(loom)]
+pub(crate) fn wait(_addr: usize, _attempt: u32, _locked: impl Fn() -> bool) {
+    loom::thread::yield_now();
+}
+
+// called after the lock is released
+#[cfg(not(any(feature = "lock-park", feature = "lock-std")))]
+#[inline]
+pub(crate) fn released(_addr: usize) {}
+
+#[cfg(any(feature = "lock-park", feature = "lock-std"))]
+pub(crate) fn released(addr: usize) {
+    crate::park::notify(addr);
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn waits_for_release() {
+        let arc = Arc::new(3);
+        let weak = Arc::downgrade(&arc);
+        assert!(arc.inner().lock(weak.provenance));
+
+        let upgrader = thread::spawn(move || *weak.upgrade().unwrap());
+        thread::sleep(Duration::from_millis(20));
+        arc.inner().unlock(weak.provenance);
+
+        assert_eq!(3, upgrader.join().unwrap());
+    }
+}
@@ -0,0 +1,159 @@
+//! Finding out who's holding on to shared values (`track-leaks` feature).
+//!
+//! Every allocation is recorded from when it's made until it's freed, along with
+//! its type and a backtrace of where it was made. [`dump_live`] lists the ones
+//! still alive, which for a process that's leaking handles points at the code
+//! that made them:
+//!
+//! ```
+//! use provenant::Arc;
+//!
+//! let leaked = std::mem::ManuallyDrop::new(Arc::new(String::from("forgotten")));
+//! for live in provenant::debug::dump_live() {
+//!     if live.addr == Arc::as_ptr(&leaked) as usize - live.data_offset {
+//!         eprintln!("{}", live);
+//!     }
+//! }
+//! ```
+//!
+//! Capturing a backtrace for every allocation is slow, so this is for tracking a
+//! leak down, not for leaving on.
+
+use crate::Inner;
+use std::any::type_name;
+use std::backtrace::Backtrace;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc as StdArc, Mutex, MutexGuard, OnceLock, PoisonError};
+
+/// An allocation that's still alive
+#[derive(Debug, Clone)]
+pub struct LiveArc {
+    /// The type of the value, from [`std::any::type_name`]
+    pub type_name: &'static str,
+    /// Where the allocation starts
+    pub addr: usize,
+    /// How far into the allocation the value is, so `addr + data_offset` is what
+    /// [`Arc::as_ptr`](crate::Arc::as_ptr) gives
+    pub data_offset: usize,
+    /// How many Arcs there were when it was listed
+    pub strong_count: usize,
+    /// Where it was made
+    pub backtrace: StdArc<Backtrace>,
+}
+
+impl fmt::Display for LiveArc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} at {:#x}, {} strong, made at:",
+            self.type_name, self.addr, self.strong_count
+        )?;
+        write!(f, "{}", self.backtrace)
+    }
+}
+
+struct Entry {
+    // the order allocations were made in
+    seq: u64,
+    type_name: &'static str,
+    data_offset: usize,
+    // only read while the entry is in the table, and the allocation is removed
+    // from it before it's freed
+    ref_count: *const AtomicUsize,
+    backtrace: StdArc<Backtrace>,
+}
+
+unsafe impl Send for Entry {}
+
+#[derive(Default)]
+struct Table {
+    live: HashMap<usize, Entry>,
+    next: u64,
+}
+
+fn table() -> MutexGuard<'static, Table> {
+    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
+    TABLE
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn allocated<T: ?Sized>(ptr: *const Inner<T>) {
+    let backtrace = StdArc::new(Backtrace::force_capture());
+    let addr = ptr as *const u8 as usize;
+    let (ref_count, data) = unsafe {
+        (
+            std::ptr::addr_of!((*ptr).ref_count),
+            std::ptr::addr_of!((*ptr).data) as *const u8 as usize,
+        )
+    };
+
+    let mut table = table();
+    let seq = table.next;
+    table.next += 1;
+    table.live.insert(
+        addr,
+        Entry {
+            seq,
+            type_name: type_name::<T>(),
+            data_offset: data - addr,
+            ref_count,
+            backtrace,
+        },
+    );
+}
+
+pub(crate) fn freed(addr: usize) {
+    table().live.remove(&addr);
+}
+
+/// Lists every allocation that's still alive, oldest first
+pub fn dump_live() -> Vec<LiveArc> {
+    let table = table();
+    let mut live: Vec<_> = table
+        .live
+        .iter()
+        .map(|(&addr, entry)| {
+            let live = LiveArc {
+                type_name: entry.type_name,
+                addr,
+                data_offset: entry.data_offset,
+                strong_count: unsafe { (*entry.ref_count).load(Ordering::Relaxed) },
+                backtrace: entry.backtrace.clone(),
+            };
+            (entry.seq, live)
+        })
+        .collect();
+    drop(table);
+
+    live.sort_by_key(|(seq, _)| *seq);
+    live.into_iter().map(|(_, live)| live).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    fn find(arc: &Arc<Vec<u8>>) -> Option<LiveArc> {
+        dump_live().into_iter().find(|live| live.addr == arc.addr())
+    }
+
+    #[test]
+    fn listed_while_alive() {
+        let arc = Arc::new(vec![1u8]);
+        let other = arc.clone();
+
+        let live = find(&arc).unwrap();
+        assert_eq!(type_name::<Vec<u8>>(), live.type_name);
+        assert_eq!(2, live.strong_count);
+        assert_eq!(Arc::as_ptr(&arc) as usize, live.addr + live.data_offset);
+        assert!(live.to_string().contains("listed_while_alive"));
+
+        drop(other);
+        assert_eq!(1, find(&arc).unwrap().strong_count);
+    }
+}
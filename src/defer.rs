@@ -0,0 +1,79 @@
+// Arc::drop_deferred, which hands the last reference to a background thread so a
+// large value is dropped off the caller's thread. there's one dropper thread for the
+// whole process, started the first time something is deferred
+
+use crate::Arc;
+use alloc::boxed::Box;
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+
+type Deferred = Box<dyn Send>;
+
+// None if the thread couldn't be started, in which case drops happen inline
+fn dropper() -> Option<&'static Mutex<Sender<Deferred>>> {
+    static DROPPER: OnceLock<Option<Mutex<Sender<Deferred>>>> = OnceLock::new();
+    DROPPER
+        .get_or_init(|| {
+            let (tx, rx) = mpsc::channel::<Deferred>();
+            thread::Builder::new()
+                .name("provenant-dropper".into())
+                .spawn(move || rx.into_iter().for_each(drop))
+                .ok()?;
+            Some(Mutex::new(tx))
+        })
+        .as_ref()
+}
+
+impl<T: Send + Sync + 'static> Arc<T> {
+    /// Drops this Arc on a background thread, so if it's the last strong
+    /// reference, the value is dropped there rather than here.
+    ///
+    /// For values that are slow to drop, like a large cache entry, released from
+    /// a thread that mustn't stall. Weak pointers stop upgrading once the dropper
+    /// thread gets to it, not straight away. If the thread can't be started, this
+    /// drops inline.
+    pub fn drop_deferred(this: Self) {
+        let dropper = match dropper() {
+            Some(dropper) => dropper,
+            None => return drop(this),
+        };
+        let deferred: Deferred = Box::new(this);
+        let sent = dropper
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .send(deferred);
+        // the dropper thread never exits, but if it did, the Arc comes back here
+        if let Err(mpsc::SendError(deferred)) = sent {
+            drop(deferred);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+    use std::sync::mpsc::{self, Sender};
+    use std::thread::{self, ThreadId};
+    use std::time::Duration;
+
+    // sends the id of the thread that drops it
+    struct DroppedOn(Sender<ThreadId>);
+
+    impl Drop for DroppedOn {
+        fn drop(&mut self) {
+            self.0.send(thread::current().id()).unwrap();
+        }
+    }
+
+    #[test]
+    fn dropped_elsewhere() {
+        let (tx, rx) = mpsc::channel();
+        let arc = Arc::new(DroppedOn(tx));
+        let weak = Arc::downgrade(&arc);
+
+        Arc::drop_deferred(arc);
+        assert!(weak.wait_dropped(Duration::from_secs(10)));
+        assert_ne!(thread::current().id(), rx.recv().unwrap());
+    }
+}
@@ -0,0 +1,401 @@
+//! Extra checking for tests and debugging.
+//!
+//! There are two parts, tracking and logging, switched on separately. Both are off
+//! unless the `diagnostics` feature is on, or the process starts with
+//! `PROVENANT_DEBUG` set, so production builds can turn them on for an incident
+//! without being rebuilt. `PROVENANT_DEBUG=track` or `=log` turns on just the one,
+//! and anything else but `0` turns on both. While a part is off, its hooks cost a
+//! relaxed load and a well-predicted branch. [`enable_tracking`] and
+//! [`enable_logging`] turn them on from code.
+//!
+//! A weak pointer upgrades whenever the memory it points at holds the provenance id
+//! it expects. Normally that means it's the same allocation, but if the memory was
+//! reused by an allocation that happened to get the same id, the upgrade gives the
+//! wrong value. That's unlikely enough to never show up in testing, which is the
+//! problem. So tracking remembers which allocation handed out the weak pointers
+//! with each (address, provenance) pair, and panics when one of them upgrades to
+//! another allocation that happens to have the same pair. If that allocation has
+//! handed out weak pointers with the pair too, the stale ones can't be told apart
+//! from its own, and aren't caught.
+//!
+//! Tracking isn't cheap. Every allocation, free and upgrade in the process takes
+//! one global lock, and besides an entry per live allocation, it remembers the last
+//! [`HELD_CAPACITY`] pairs, tens of bytes each. Reuse of a pair older than that
+//! isn't caught.
+//!
+//! Logging keeps the last [`AUDIT_CAPACITY`] failed upgrades, which
+//! [`failed_upgrades`] returns, for finding out which stale handles were being used
+//! in the run-up to a problem. It takes no locks, and its memory is fixed.
+
+use crate::{Inner, Provenance};
+use std::collections::{HashMap, VecDeque};
+use std::env;
+use std::ffi::OsStr;
+use std::fmt;
+use std::panic::Location;
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The environment variable that turns diagnostics on
+pub const ENV_VAR: &str = "PROVENANT_DEBUG";
+
+const UNKNOWN: u8 = 0;
+const OFF: u8 = 1;
+const ON: u8 = 2;
+
+static TRACKING: AtomicU8 = AtomicU8::new(UNKNOWN);
+static LOGGING: AtomicU8 = AtomicU8::new(UNKNOWN);
+
+/// Whether the collision checks are on
+#[inline]
+pub fn tracking() -> bool {
+    cfg!(feature = "diagnostics") || is_on(&TRACKING)
+}
+
+/// Whether failed upgrades are being logged
+#[inline]
+pub fn logging() -> bool {
+    cfg!(feature = "diagnostics") || is_on(&LOGGING)
+}
+
+/// Whether either part is on
+pub fn enabled() -> bool {
+    tracking() || logging()
+}
+
+#[inline]
+fn is_on(state: &AtomicU8) -> bool {
+    match state.load(Ordering::Relaxed) {
+        ON => true,
+        OFF => false,
+        _ => {
+            read_env();
+            state.load(Ordering::SeqCst) == ON
+        }
+    }
+}
+
+// which of tracking and logging a value of the environment variable turns on
+fn parse_env(value: Option<&OsStr>) -> (bool, bool) {
+    let value = match value {
+        Some(value) => value,
+        None => return (false, false),
+    };
+    match value.to_str() {
+        Some("") | Some("0") => (false, false),
+        Some("track") => (true, false),
+        Some("log") => (false, true),
+        _ => (true, true),
+    }
+}
+
+#[cold]
+fn read_env() {
+    let (track, log) = parse_env(env::var_os(ENV_VAR).as_deref());
+    // an enable_*() that got here first wins
+    for (state, on) in [(&TRACKING, track), (&LOGGING, log)] {
+        let _ = state.compare_exchange(
+            UNKNOWN,
+            if on { ON } else { OFF },
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+    }
+}
+
+/// Turns the collision checks on for the rest of the process.
+///
+/// Allocations made before this aren't known to them, so they're only checked
+/// against allocations made afterwards.
+pub fn enable_tracking() {
+    TRACKING.store(ON, Ordering::SeqCst);
+}
+
+/// Starts logging failed upgrades, for the rest of the process
+pub fn enable_logging() {
+    LOGGING.store(ON, Ordering::SeqCst);
+}
+
+/// Turns both parts on for the rest of the process
+pub fn enable() {
+    enable_tracking();
+    enable_logging();
+}
+
+/// How many (address, provenance) pairs tracking remembers
+pub const HELD_CAPACITY: usize = 1 << 16;
+
+#[derive(Default)]
+struct Registry {
+    // the allocation currently at each address
+    live: HashMap<usize, u64>,
+    // the allocation that last handed out weak pointers with each (address,
+    // provenance) pair
+    held: HashMap<(usize, Provenance), u64>,
+    // the keys of held, oldest first, so it can be kept to HELD_CAPACITY
+    order: VecDeque<(usize, Provenance)>,
+    next: u64,
+}
+
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+fn registry() -> MutexGuard<'static, Option<Registry>> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Registry {
+    fn hold(&mut self, addr: usize, provenance: Provenance, id: u64) {
+        let key = (addr, provenance);
+        if self.held.insert(key, id).is_none() {
+            self.order.push_back(key);
+            if self.order.len() > HELD_CAPACITY {
+                let oldest = self.order.pop_front().unwrap();
+                self.held.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub(crate) fn allocated<T: ?Sized>(ptr: *const Inner<T>) {
+    let addr = ptr as *const u8 as usize;
+    let mut registry = registry();
+    let registry = registry.get_or_insert_with(Registry::default);
+    let id = registry.next;
+    registry.next += 1;
+    registry.live.insert(addr, id);
+}
+
+// the allocation at `ptr` handed out a weak pointer expecting `provenance`
+pub(crate) fn downgraded<T: ?Sized>(ptr: *const Inner<T>, provenance: Provenance) {
+    let addr = ptr as *const u8 as usize;
+    if let Some(registry) = registry().as_mut() {
+        if let Some(&id) = registry.live.get(&addr) {
+            registry.hold(addr, provenance, id);
+        }
+    }
+}
+
+pub(crate) fn freed<T: ?Sized>(ptr: *const Inner<T>) {
+    let addr = ptr as *const u8 as usize;
+    if let Some(registry) = registry().as_mut() {
+        registry.live.remove(&addr);
+    }
+}
+
+// a weak pointer expecting `provenance` just upgraded to `ptr`
+pub(crate) fn matched<T: ?Sized>(ptr: *const Inner<T>, provenance: Provenance) {
+    let addr = ptr as *const u8 as usize;
+    let registry = registry();
+    let registry = match registry.as_ref() {
+        Some(registry) => registry,
+        None => return,
+    };
+
+    // statics and other memory that never went through the registry aren't held
+    let origin = match registry.held.get(&(addr, provenance)) {
+        Some(&origin) => origin,
+        None => return,
+    };
+    match registry.live.get(&addr) {
+        Some(&id) if id == origin => {}
+        Some(_) => {
+            crate::events::collided();
+            panic!(
+                "provenance collision: a weak pointer with provenance {:#x} upgraded to {:#x}, \
+                 but only another allocation there has handed out weak pointers with it",
+                provenance, addr
+            )
+        }
+        None => panic!(
+            "a weak pointer upgraded to the freed allocation at {:#x}",
+            addr
+        ),
+    }
+}
+
+/// How many failed upgrades [`failed_upgrades`] remembers
+pub const AUDIT_CAPACITY: usize = 256;
+
+/// A weak pointer that failed to upgrade
+#[derive(Debug, Clone, Copy)]
+pub struct FailedUpgrade {
+    /// Where the upgrade was called from
+    pub location: &'static Location<'static>,
+    /// The provenance id the weak pointer expected
+    pub provenance: Provenance,
+    /// When it happened
+    pub at: SystemTime,
+}
+
+impl fmt::Display for FailedUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{}.{:09} pv={:#x} at {}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos(),
+            self.provenance,
+            self.location
+        )
+    }
+}
+
+// a seqlock per entry: seq is odd while being written, and otherwise says which
+// write the entry holds, so readers can tell torn and overwritten entries apart
+struct AuditEntry {
+    seq: AtomicUsize,
+    location: AtomicPtr<Location<'static>>,
+    provenance: AtomicU64,
+    #[cfg(feature = "provenance-128")]
+    provenance_high: AtomicU64,
+    nanos: AtomicU64,
+}
+
+impl AuditEntry {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: AuditEntry = AuditEntry {
+        seq: AtomicUsize::new(0),
+        location: AtomicPtr::new(ptr::null_mut()),
+        provenance: AtomicU64::new(0),
+        #[cfg(feature = "provenance-128")]
+        provenance_high: AtomicU64::new(0),
+        nanos: AtomicU64::new(0),
+    };
+}
+
+static AUDIT: [AuditEntry; AUDIT_CAPACITY] = [AuditEntry::EMPTY; AUDIT_CAPACITY];
+
+// how many failed upgrades have been recorded, ever
+static AUDITED: AtomicUsize = AtomicUsize::new(0);
+
+pub(crate) fn upgrade_failed(location: &'static Location<'static>, provenance: Provenance) {
+    let n = AUDITED.fetch_add(1, Ordering::SeqCst);
+    let entry = &AUDIT[n % AUDIT_CAPACITY];
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    entry.seq.store(2 * n + 1, Ordering::SeqCst);
+    entry
+        .location
+        .store(location as *const _ as *mut _, Ordering::SeqCst);
+    // already a u64 with wide-provenance on 32-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    entry.provenance.store(provenance as u64, Ordering::SeqCst);
+    #[cfg(feature = "provenance-128")]
+    entry
+        .provenance_high
+        .store((provenance >> 64) as u64, Ordering::SeqCst);
+    entry.nanos.store(nanos, Ordering::SeqCst);
+    entry.seq.store(2 * n + 2, Ordering::SeqCst);
+}
+
+/// Gets the most recent failed upgrades, oldest first.
+///
+/// Doesn't block anything. Entries being overwritten while this runs are skipped.
+pub fn failed_upgrades() -> Vec<FailedUpgrade> {
+    let end = AUDITED.load(Ordering::SeqCst);
+    let start = end.saturating_sub(AUDIT_CAPACITY);
+
+    (start..end)
+        .filter_map(|n| {
+            let entry = &AUDIT[n % AUDIT_CAPACITY];
+            let seq = 2 * n + 2;
+            if entry.seq.load(Ordering::SeqCst) != seq {
+                return None;
+            }
+            let location = entry.location.load(Ordering::SeqCst);
+            let provenance = entry.provenance.load(Ordering::SeqCst) as Provenance;
+            #[cfg(feature = "provenance-128")]
+            let provenance =
+                provenance | (entry.provenance_high.load(Ordering::SeqCst) as Provenance) << 64;
+            let nanos = entry.nanos.load(Ordering::SeqCst);
+            if entry.seq.load(Ordering::SeqCst) != seq {
+                return None;
+            }
+
+            Some(FailedUpgrade {
+                location: unsafe { &*location },
+                provenance,
+                at: UNIX_EPOCH + Duration::from_nanos(nanos),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provenance::ProvenanceToken;
+    use crate::Arc;
+    use std::panic;
+
+    #[test]
+    #[cfg_attr(
+        miri,
+        ignore = "reaches the reused memory through the old pointer on purpose"
+    )]
+    fn collision() {
+        enable();
+        let raw = 0x5eed_0000;
+        let first = Arc::new_with_provenance(1u64, ProvenanceToken::from_raw(raw).unwrap());
+        let weak = Arc::downgrade(&first);
+        let addr = first.addr();
+        drop(first);
+
+        // reusing the id is only a collision if the allocator reuses the memory too
+        let second = Arc::new_with_provenance(2u64, ProvenanceToken::from_raw(raw).unwrap());
+        if second.addr() != addr {
+            return;
+        }
+
+        let result = panic::catch_unwind(|| weak.upgrade().map(|arc| *arc));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn reused_pair_without_stale_weaks() {
+        enable();
+        let raw = 0xf7e5_0000;
+        let first = Arc::new_with_provenance(1u64, ProvenanceToken::from_raw(raw).unwrap());
+        let addr = first.addr();
+        drop(first);
+
+        let second = Arc::new_with_provenance(2u64, ProvenanceToken::from_raw(raw).unwrap());
+        if second.addr() != addr {
+            return;
+        }
+        // the pair has been held twice, but this weak pointer is the second's own
+        let weak = Arc::downgrade(&second);
+        assert_eq!(2, *weak.upgrade().unwrap());
+    }
+
+    #[test]
+    fn env_values() {
+        assert_eq!((false, false), parse_env(None));
+        assert_eq!((false, false), parse_env(Some("0".as_ref())));
+        assert_eq!((true, false), parse_env(Some("track".as_ref())));
+        assert_eq!((false, true), parse_env(Some("log".as_ref())));
+        assert_eq!((true, true), parse_env(Some("1".as_ref())));
+    }
+
+    #[test]
+    fn audit() {
+        enable();
+        let weak = Arc::downgrade(&Arc::new(0u8));
+        let line = line!() + 1;
+        assert!(weak.upgrade().is_none());
+
+        // other tests fail upgrades too, so look for this one
+        let found = failed_upgrades().into_iter().any(|failed| {
+            failed.provenance == weak.provenance()
+                && failed.location.file() == file!()
+                && failed.location.line() == line
+        });
+        assert!(found);
+    }
+}
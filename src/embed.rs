@@ -0,0 +1,131 @@
+//! Arcs over memory the caller owns, for targets without a heap.
+//!
+//! An [`ArcSlot`] is room for an Arc's header and value, which can sit in a static,
+//! an arena, or a field of something bigger. [`Arc::new_in_slot`] builds an Arc in
+//! it, and once the last Arc is dropped, and the value with it, calls a hook with
+//! the slot instead of freeing anything. The provenance id is zeroed first, so weak
+//! pointers to the old value don't upgrade to whatever goes in the slot next:
+//!
+//! ```
+//! use core::alloc::Layout;
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//! use provenant::embed::ArcSlot;
+//! use provenant::Arc;
+//!
+//! static SLOT: ArcSlot<[u8; 64]> = ArcSlot::new();
+//! static IN_USE: AtomicBool = AtomicBool::new(false);
+//!
+//! unsafe fn vacate(_slot: *mut u8, _layout: Layout) {
+//!     IN_USE.store(false, Ordering::Release);
+//! }
+//!
+//! fn take(buffer: [u8; 64]) -> Option<Arc<[u8; 64]>> {
+//!     if IN_USE.swap(true, Ordering::Acquire) {
+//!         return None;
+//!     }
+//!     // the slot's free until vacate is called
+//!     Some(unsafe { Arc::new_in_slot(&SLOT, buffer, vacate) })
+//! }
+//!
+//! let first = take([1; 64]).unwrap();
+//! let weak = Arc::downgrade(&first);
+//! assert!(take([2; 64]).is_none());
+//!
+//! drop(first);
+//! let second = take([2; 64]).unwrap();
+//! assert!(weak.upgrade().is_none());
+//! ```
+
+use crate::{events, random_provenance, Arc, Inner};
+use alloc::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+
+/// Room for an [`Arc`] to a `T`, header and all, in memory the caller owns
+#[repr(transparent)]
+pub struct ArcSlot<T>(UnsafeCell<MaybeUninit<Inner<T>>>);
+
+// only written through by new_in_slot, whose caller makes sure nothing else is using
+// it, and the Arcs it makes are Send and Sync when T is
+unsafe impl<T: Send + Sync> Sync for ArcSlot<T> {}
+
+impl<T> ArcSlot<T> {
+    /// Creates an empty slot
+    pub const fn new() -> Self {
+        ArcSlot(UnsafeCell::new(MaybeUninit::uninit()))
+    }
+
+    /// The address the release hook is called with, for telling slots apart
+    pub fn as_ptr(&self) -> *mut u8 {
+        self.0.get() as *mut u8
+    }
+}
+
+impl<T> Default for ArcSlot<T> {
+    fn default() -> Self {
+        ArcSlot::new()
+    }
+}
+
+impl<T> fmt::Debug for ArcSlot<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("ArcSlot").field(&self.as_ptr()).finish()
+    }
+}
+
+impl<T> Arc<T> {
+    /// Builds an Arc in `slot`, which `release` is called with once the last Arc
+    /// is dropped and the value with it. `release` gets [`ArcSlot::as_ptr`] and
+    /// the layout of an `ArcSlot<T>`, and is only ever called once per Arc made.
+    ///
+    /// # Safety
+    ///
+    /// Until `release` is called, the slot must stay where it is and not be used for
+    /// anything else, including another `new_in_slot`. Afterwards it can be reused,
+    /// as long as it stays readable while there are weak pointers into it.
+    pub unsafe fn new_in_slot(
+        slot: &ArcSlot<T>,
+        val: T,
+        release: unsafe fn(*mut u8, Layout),
+    ) -> Self {
+        let ptr = slot.0.get() as *mut Inner<T>;
+        ptr.write(Inner::new(val, random_provenance(), release));
+        events::allocated(ptr);
+        Arc { ptr }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static RELEASED: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe fn count_release(_slot: *mut u8, layout: Layout) {
+        assert_eq!(Layout::new::<ArcSlot<String>>(), layout);
+        RELEASED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn reused_slot() {
+        let slot = ArcSlot::new();
+        let first = unsafe { Arc::new_in_slot(&slot, String::from("first"), count_release) };
+        let weak = Arc::downgrade(&first);
+        assert_eq!(slot.as_ptr() as usize, weak.addr());
+
+        let other = first.clone();
+        drop(first);
+        assert_eq!("first", *weak.upgrade().unwrap());
+        drop(other);
+        assert_eq!(1, RELEASED.load(Ordering::Relaxed));
+
+        let second = unsafe { Arc::new_in_slot(&slot, String::from("second"), count_release) };
+        assert!(weak.upgrade().is_none());
+        assert_eq!("second", *Arc::downgrade(&second).upgrade().unwrap());
+        drop(second);
+        assert_eq!(2, RELEASED.load(Ordering::Relaxed));
+    }
+}
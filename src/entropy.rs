@@ -0,0 +1,322 @@
+//! Where provenance ids come from.
+//!
+//! By default, ids come from `rand::thread_rng`, which needs `std`. Without it, the
+//! `getrandom` feature takes them from the platform's secure random source instead,
+//! or a [`ProvenanceSource`] can be installed with [`set_provenance_source`], e.g. a
+//! hardware RNG on an embedded target. An installed source takes precedence over
+//! both.
+//!
+//! On `wasm32-unknown-unknown`, where rand has nothing to draw from, std builds
+//! use `getrandom` too. It reaches the browser's or Node's `crypto` once the
+//! application turns on getrandom's `js` feature:
+//!
+//! ```toml
+//! provenant = { version = "0.1", features = ["getrandom", "wide-provenance"] }
+//! getrandom = { version = "0.2", features = ["js"] }
+//! ```
+//!
+//! `wide-provenance` is worth turning on there as well, since without it ids are
+//! only 31 bits on wasm32.
+//!
+//! The `counter-provenance` feature replaces all of those with a [`CounterSource`],
+//! which hands ids out in order. Then no two allocations share an id until the
+//! counter wraps, which on 64-bit targets is never in practice, so a stale weak
+//! pointer can't upgrade to the wrong value at all, rather than just almost
+//! certainly not. Ids chosen explicitly, with
+//! [`ProvenanceToken`](crate::provenance::ProvenanceToken)s, are outside that
+//! guarantee. The ids are predictable, but a handle is no harder to forge than
+//! with the fallback below.
+//!
+//! With none of those, ids come from a counter scrambled by splitmix64. They're
+//! still distinct, which is what upgrades rely on, but predictable, so code that
+//! hands out handles to untrusted code should install a real source.
+//!
+//! Tests, fuzzers and record/replay debuggers that want the same ids on every run
+//! can install a [`SeededSource`], or draw ids from one for particular allocations
+//! with [`ProvenanceToken::from_source`](crate::provenance::ProvenanceToken::from_source):
+//!
+//! ```
+//! use provenant::entropy::{set_provenance_source, SeededSource};
+//!
+//! static SOURCE: SeededSource = SeededSource::new(42);
+//! set_provenance_source(&SOURCE);
+//! ```
+
+use crate::Provenance;
+use alloc::boxed::Box;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+// what the counting sources count with. as wide as the ids, so with wide ids on a
+// 32-bit target they don't wrap and repeat after 2^32 draws
+#[cfg(any(feature = "wide-provenance", feature = "provenance-128"))]
+use core::sync::atomic::AtomicU64 as Counter;
+#[cfg(not(any(feature = "wide-provenance", feature = "provenance-128")))]
+use core::sync::atomic::AtomicUsize as Counter;
+
+// takes the next number from a counter
+// the count is already a u64 with wide ids
+#[allow(clippy::unnecessary_cast)]
+fn count(counter: &Counter) -> u64 {
+    counter.fetch_add(1, Ordering::Relaxed) as u64
+}
+
+/// Something provenance ids can be drawn from
+pub trait ProvenanceSource: Sync {
+    /// Gets a word to make the next id from. Its low bit is ignored, and if the
+    /// rest are all 0, it's asked again
+    fn next_u64(&self) -> u64;
+}
+
+impl ProvenanceSource for fn() -> u64 {
+    fn next_u64(&self) -> u64 {
+        self()
+    }
+}
+
+/// What ids come from when no source is installed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultSource;
+
+impl ProvenanceSource for DefaultSource {
+    fn next_u64(&self) -> u64 {
+        default_source()
+    }
+}
+
+/// A deterministic source: two seeded the same way give the same words in the
+/// same order.
+///
+/// With several threads drawing from it, which thread gets which word depends on
+/// scheduling, so runs are only reproducible if allocations happen in the same
+/// order.
+#[derive(Debug)]
+pub struct SeededSource {
+    seed: u64,
+    drawn: Counter,
+}
+
+impl SeededSource {
+    /// Creates a source from a seed
+    pub const fn new(seed: u64) -> Self {
+        SeededSource {
+            seed,
+            drawn: Counter::new(0),
+        }
+    }
+}
+
+impl ProvenanceSource for SeededSource {
+    fn next_u64(&self) -> u64 {
+        let n = count(&self.drawn);
+        splitmix64(self.seed.wrapping_add(n))
+    }
+}
+
+/// A source that counts up, so every id is new until it's handed out 2^63 of
+/// them and wraps, or 2^31 on 32-bit targets without `wide-provenance`.
+///
+/// Only one should be drawn from, or they'll hand out the same ids, so it's meant
+/// to be installed globally:
+///
+/// ```
+/// use provenant::entropy::{set_provenance_source, CounterSource};
+///
+/// static COUNTER: CounterSource = CounterSource::new();
+/// set_provenance_source(&COUNTER);
+/// ```
+#[derive(Debug, Default)]
+pub struct CounterSource {
+    issued: Counter,
+}
+
+impl CounterSource {
+    /// Creates a source that hasn't handed anything out
+    pub const fn new() -> Self {
+        CounterSource {
+            issued: Counter::new(0),
+        }
+    }
+}
+
+impl ProvenanceSource for CounterSource {
+    fn next_u64(&self) -> u64 {
+        // shifted past the lock bit. after wrapping, 0 comes up once, and is skipped
+        let n = count(&self.issued).wrapping_add(1);
+        n << 1
+    }
+}
+
+// the installed source, or null. each one is leaked, so it can be replaced while
+// another thread is still using the old one
+static SOURCE: AtomicPtr<&'static dyn ProvenanceSource> = AtomicPtr::new(ptr::null_mut());
+
+/// Makes every new provenance id come from `source`.
+///
+/// Should be called before anything is allocated, since ids already handed out
+/// came from whatever was used before.
+pub fn set_provenance_source(source: &'static dyn ProvenanceSource) {
+    SOURCE.store(Box::leak(Box::new(source)), Ordering::Release);
+}
+
+/// Like [`set_provenance_source`], for a plain function
+pub fn set_source(source: fn() -> u64) {
+    set_provenance_source(Box::leak(Box::new(source)));
+}
+
+// a random word for a new provenance id
+pub(crate) fn next() -> u64 {
+    let source = SOURCE.load(Ordering::Acquire);
+    if source.is_null() {
+        return default_source();
+    }
+    unsafe { (*source).next_u64() }
+}
+
+// a provenance id's worth of bits from `draw`, which with `provenance-128` takes two
+// draws, the first for the high half
+#[cfg(not(feature = "provenance-128"))]
+pub(crate) fn widen(mut draw: impl FnMut() -> u64) -> Provenance {
+    draw() as Provenance
+}
+
+#[cfg(feature = "provenance-128")]
+pub(crate) fn widen(mut draw: impl FnMut() -> u64) -> Provenance {
+    let high = draw() as Provenance;
+    high << 64 | draw() as Provenance
+}
+
+#[cfg(feature = "counter-provenance")]
+fn default_source() -> u64 {
+    static COUNTER: CounterSource = CounterSource::new();
+    COUNTER.next_u64()
+}
+
+#[cfg(all(
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown")),
+    not(feature = "counter-provenance")
+))]
+fn default_source() -> u64 {
+    use rand::Rng;
+    crate::primitives::thread_rng().gen()
+}
+
+// the rest are for no_std, and wasm32-unknown-unknown, where rand isn't available
+
+#[cfg(all(
+    any(
+        not(feature = "std"),
+        all(target_arch = "wasm32", target_os = "unknown")
+    ),
+    feature = "getrandom",
+    not(feature = "counter-provenance")
+))]
+fn default_source() -> u64 {
+    let mut bytes = [0; 8];
+    match getrandom::getrandom(&mut bytes) {
+        Ok(()) => u64::from_ne_bytes(bytes),
+        Err(_) => counter(),
+    }
+}
+
+#[cfg(all(
+    any(
+        not(feature = "std"),
+        all(target_arch = "wasm32", target_os = "unknown")
+    ),
+    not(feature = "getrandom"),
+    not(feature = "counter-provenance")
+))]
+fn default_source() -> u64 {
+    counter()
+}
+
+#[cfg(all(
+    any(
+        not(feature = "std"),
+        all(target_arch = "wasm32", target_os = "unknown")
+    ),
+    not(feature = "counter-provenance")
+))]
+fn counter() -> u64 {
+    static COUNTER: Counter = Counter::new(0);
+    splitmix64(count(&COUNTER))
+}
+
+// a bijection, so distinct inputs give distinct outputs
+pub(crate) fn splitmix64(seed: u64) -> u64 {
+    let z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provenance::ProvenanceToken;
+    use crate::Arc;
+    use core::sync::atomic::AtomicUsize;
+
+    const MARK: u64 = 0x5a5a << 48;
+
+    #[test]
+    // the id is already a u64 with wide-provenance on 32-bit targets
+    #[allow(clippy::unnecessary_cast)]
+    fn installed_source() {
+        // other tests allocate meanwhile, so keep the ids distinct
+        fn marked() -> u64 {
+            static N: AtomicUsize = AtomicUsize::new(0);
+            MARK | (N.fetch_add(1, Ordering::Relaxed) as u64) << 1
+        }
+
+        set_source(marked);
+        let arc = Arc::new(1);
+        SOURCE.store(ptr::null_mut(), Ordering::SeqCst);
+        assert_eq!(
+            MARK,
+            Arc::downgrade(&arc).provenance() as u64 & !0xffff_ffff_ffff
+        );
+    }
+
+    #[test]
+    fn counted() {
+        let counter = CounterSource::new();
+        let ids: Vec<_> = (0..3)
+            .map(|_| ProvenanceToken::from_source(&counter).get())
+            .collect();
+        #[cfg(not(feature = "provenance-128"))]
+        assert_eq!(vec![2, 4, 6], ids);
+        // two draws each, high half first
+        #[cfg(feature = "provenance-128")]
+        assert_eq!(vec![2 << 64 | 4, 6 << 64 | 8, 10 << 64 | 12], ids);
+
+        // keeps counting past a 32-bit word, whatever the target
+        #[cfg(any(
+            feature = "wide-provenance",
+            feature = "provenance-128",
+            target_pointer_width = "64"
+        ))]
+        {
+            counter.issued.store(u32::MAX as _, Ordering::Relaxed);
+            assert_eq!(1 << 33, counter.next_u64());
+        }
+    }
+
+    #[test]
+    fn seeded() {
+        let (a, b) = (SeededSource::new(7), SeededSource::new(7));
+        let drawn: Vec<u64> = (0..100).map(|_| a.next_u64()).collect();
+        assert!(drawn.iter().all(|&word| word == b.next_u64()));
+        assert_ne!(drawn[0], SeededSource::new(8).next_u64());
+
+        let (a, b) = (SeededSource::new(7), SeededSource::new(7));
+        let x = Arc::new_with_provenance(1, ProvenanceToken::from_source(&a));
+        let y = Arc::new_with_provenance(2, ProvenanceToken::from_source(&b));
+        assert_eq!(
+            Arc::downgrade(&x).provenance(),
+            Arc::downgrade(&y).provenance()
+        );
+    }
+}
@@ -0,0 +1,282 @@
+//! Shared values whose type has been forgotten.
+//!
+//! [`ErasedArc`] and [`ErasedWeak`] are an [`Arc`] and a [`Weak`] that don't
+//! mention `T`, so handles to all sorts of types can share one table. They remember
+//! which type they point to, and `downcast` gets the typed pointer back:
+//!
+//! ```
+//! use provenant::erased::ErasedWeak;
+//! use provenant::Arc;
+//!
+//! struct Audio;
+//! struct Physics(u32);
+//!
+//! let audio = Arc::new(Audio);
+//! let physics = Arc::new(Physics(60));
+//! let plugins = vec![
+//!     ErasedWeak::new(Arc::downgrade(&audio)),
+//!     ErasedWeak::new(Arc::downgrade(&physics)),
+//! ];
+//!
+//! let rate = plugins.iter().find_map(|plugin| plugin.downcast::<Physics>());
+//! assert_eq!(60, rate.unwrap().upgrade().unwrap().0);
+//! ```
+//!
+//! Unlike [`Weak<dyn Any>`](Weak::downcast), a weak pointer's type is known
+//! without upgrading it, so it can be downcast after the value is gone.
+
+use crate::{Arc, Inner, Provenance, Weak};
+use core::any::{Any, TypeId};
+use core::fmt;
+use core::mem;
+
+// what the handles need to do with the allocation, without knowing its type. the
+// pointer is an Arc's or Weak's, tag and all
+#[derive(Clone, Copy)]
+struct ErasedFns {
+    clone: unsafe fn(*const ()),
+    drop: unsafe fn(*const ()),
+    downgrade: unsafe fn(*const ()) -> Provenance,
+    upgrade: unsafe fn(*const (), Provenance) -> bool,
+    alive: unsafe fn(*const (), Provenance) -> bool,
+}
+
+unsafe fn clone_erased<T>(ptr: *const ()) {
+    let arc = mem::ManuallyDrop::new(Arc::<T> { ptr: ptr as _ });
+    mem::forget(Arc::clone(&arc));
+}
+
+unsafe fn drop_erased<T>(ptr: *const ()) {
+    drop(Arc::<T> { ptr: ptr as _ });
+}
+
+unsafe fn downgrade_erased<T>(ptr: *const ()) -> Provenance {
+    let arc = mem::ManuallyDrop::new(Arc::<T> { ptr: ptr as _ });
+    Arc::downgrade(&arc).provenance
+}
+
+// leaves the strong reference it takes behind, if it takes one
+unsafe fn upgrade_erased<T>(ptr: *const (), provenance: Provenance) -> bool {
+    let weak = Weak::<T> {
+        ptr: ptr as *const Inner<T>,
+        provenance,
+    };
+    weak.upgrade().map(mem::forget).is_some()
+}
+
+unsafe fn alive_erased<T>(ptr: *const (), provenance: Provenance) -> bool {
+    let weak = Weak::<T> {
+        ptr: ptr as *const Inner<T>,
+        provenance,
+    };
+    weak.is_probably_alive()
+}
+
+impl ErasedFns {
+    fn of<T>() -> Self {
+        ErasedFns {
+            clone: clone_erased::<T>,
+            drop: drop_erased::<T>,
+            downgrade: downgrade_erased::<T>,
+            upgrade: upgrade_erased::<T>,
+            alive: alive_erased::<T>,
+        }
+    }
+}
+
+/// An [`Arc`] that has forgotten the type of its value
+pub struct ErasedArc {
+    ptr: *const (),
+    type_id: TypeId,
+    fns: ErasedFns,
+}
+
+// only made from Arcs to values that are Send + Sync
+unsafe impl Send for ErasedArc {}
+unsafe impl Sync for ErasedArc {}
+
+impl ErasedArc {
+    /// Forgets the type of `arc`'s value
+    pub fn new<T: Any + Send + Sync>(arc: Arc<T>) -> Self {
+        let ptr = arc.ptr as *const ();
+        mem::forget(arc);
+        ErasedArc {
+            ptr,
+            type_id: TypeId::of::<T>(),
+            fns: ErasedFns::of::<T>(),
+        }
+    }
+
+    /// The [`TypeId`] of the value
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Whether the value is a `T`
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    /// Gets the typed Arc back, or gives this back if the value isn't a `T`
+    pub fn downcast<T: Any>(self) -> Result<Arc<T>, Self> {
+        if !self.is::<T>() {
+            return Err(self);
+        }
+        let ptr = self.ptr as *const Inner<T>;
+        mem::forget(self);
+        Ok(Arc { ptr })
+    }
+
+    /// Gets a reference to the value, if it's a `T`
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        if !self.is::<T>() {
+            return None;
+        }
+        let arc = mem::ManuallyDrop::new(Arc::<T> { ptr: self.ptr as _ });
+        Some(unsafe { &*(&**arc as *const T) })
+    }
+
+    /// Gets a weak pointer to the value, which also doesn't know its type
+    pub fn downgrade(this: &Self) -> ErasedWeak {
+        ErasedWeak {
+            provenance: unsafe { (this.fns.downgrade)(this.ptr) },
+            ptr: this.ptr,
+            type_id: this.type_id,
+            fns: this.fns,
+        }
+    }
+}
+
+impl<T: Any + Send + Sync> From<Arc<T>> for ErasedArc {
+    fn from(arc: Arc<T>) -> Self {
+        ErasedArc::new(arc)
+    }
+}
+
+impl Clone for ErasedArc {
+    fn clone(&self) -> Self {
+        unsafe { (self.fns.clone)(self.ptr) };
+        ErasedArc { ..*self }
+    }
+}
+
+impl Drop for ErasedArc {
+    fn drop(&mut self) {
+        unsafe { (self.fns.drop)(self.ptr) };
+    }
+}
+
+impl fmt::Debug for ErasedArc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasedArc")
+            .field("type_id", &self.type_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`Weak`] that has forgotten the type of its value
+#[derive(Clone, Copy)]
+pub struct ErasedWeak {
+    provenance: Provenance,
+    ptr: *const (),
+    type_id: TypeId,
+    fns: ErasedFns,
+}
+
+// only made from weak pointers to values that are Send + Sync
+unsafe impl Send for ErasedWeak {}
+unsafe impl Sync for ErasedWeak {}
+
+impl ErasedWeak {
+    /// Forgets the type of `weak`'s value
+    pub fn new<T: Any + Send + Sync>(weak: Weak<T>) -> Self {
+        ErasedWeak {
+            provenance: weak.provenance,
+            ptr: weak.ptr as *const (),
+            type_id: TypeId::of::<T>(),
+            fns: ErasedFns::of::<T>(),
+        }
+    }
+
+    /// The [`TypeId`] of the value
+    pub fn type_id(&self) -> TypeId {
+        self.type_id
+    }
+
+    /// Whether the value is a `T`
+    pub fn is<T: Any>(&self) -> bool {
+        self.type_id == TypeId::of::<T>()
+    }
+
+    /// Gets the typed weak pointer back, if the value is a `T`. Works whether or
+    /// not the value is still alive
+    pub fn downcast<T: Any>(&self) -> Option<Weak<T>> {
+        if !self.is::<T>() {
+            return None;
+        }
+        Some(Weak {
+            provenance: self.provenance,
+            ptr: self.ptr as *const Inner<T>,
+        })
+    }
+
+    /// Attempts to get a strong reference, which also doesn't know its type. See
+    /// [`Weak::upgrade`]
+    pub fn upgrade(&self) -> Option<ErasedArc> {
+        if !unsafe { (self.fns.upgrade)(self.ptr, self.provenance) } {
+            return None;
+        }
+        Some(ErasedArc {
+            ptr: self.ptr,
+            type_id: self.type_id,
+            fns: self.fns,
+        })
+    }
+
+    /// Whether the value still looks alive. See [`Weak::is_probably_alive`]
+    pub fn is_probably_alive(&self) -> bool {
+        unsafe { (self.fns.alive)(self.ptr, self.provenance) }
+    }
+}
+
+impl<T: Any + Send + Sync> From<Weak<T>> for ErasedWeak {
+    fn from(weak: Weak<T>) -> Self {
+        ErasedWeak::new(weak)
+    }
+}
+
+impl fmt::Debug for ErasedWeak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasedWeak")
+            .field("type_id", &self.type_id)
+            .field("alive", &self.is_probably_alive())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn round_trip() {
+        let arc = Arc::new(String::from("plugin"));
+        let erased = ErasedArc::new(arc.clone());
+        assert!(erased.is::<String>() && !erased.is::<u32>());
+        assert_eq!("plugin", erased.downcast_ref::<String>().unwrap());
+        assert_eq!(2, Arc::strong_count(&arc));
+
+        let weak = ErasedArc::downgrade(&erased);
+        let erased = erased.downcast::<u32>().unwrap_err();
+        let typed = erased.downcast::<String>().unwrap();
+        assert!(Arc::ptr_eq(&arc, &typed));
+        assert!(weak.upgrade().unwrap().is::<String>());
+
+        drop((arc, typed));
+        assert!(!weak.is_probably_alive());
+        assert!(weak.upgrade().is_none());
+        // still knows what it was
+        assert!(weak.downcast::<String>().is_some());
+    }
+}
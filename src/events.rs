@@ -0,0 +1,164 @@
+// hooks for the optional instrumentation features.
+// without any of them enabled, these compile to nothing but the check for
+// diagnostics being switched on at runtime
+
+#[cfg(feature = "stats")]
+use crate::stats;
+use crate::{Inner, Provenance};
+
+// a new allocation has been initialized
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn allocated<T: ?Sized>(ptr: *const Inner<T>) {
+    #[cfg(feature = "weak-registry")]
+    crate::live::insert(ptr as *const u8 as usize);
+
+    #[cfg(feature = "profiling")]
+    crate::profile::record_alloc(ptr);
+
+    #[cfg(feature = "track-leaks")]
+    crate::debug::allocated(ptr);
+
+    #[cfg(feature = "stats")]
+    stats::increment(&stats::ALLOCATIONS);
+
+    #[cfg(feature = "std")]
+    if crate::diagnostics::tracking() {
+        crate::diagnostics::allocated(ptr);
+    }
+}
+
+// an allocation has been given a new provenance id
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn rekeyed<T: ?Sized>(ptr: *const Inner<T>) {
+    // the old value is as good as dropped, for anything waiting on it
+    #[cfg(feature = "std")]
+    crate::park::notify(ptr as *const u8 as usize);
+}
+
+// an allocation's data is about to be dropped, or moved out, and its memory released
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn freed<T: ?Sized>(ptr: *const Inner<T>) {
+    #[cfg(feature = "std")]
+    crate::finalize::run(ptr as *const u8 as usize, unsafe {
+        core::ptr::addr_of!((*ptr).data) as *mut u8
+    });
+
+    #[cfg(feature = "weak-registry")]
+    crate::live::remove(ptr as *const u8 as usize);
+
+    #[cfg(feature = "profiling")]
+    crate::profile::record_free(ptr);
+
+    #[cfg(feature = "track-leaks")]
+    crate::debug::freed(ptr as *const u8 as usize);
+
+    #[cfg(feature = "stats")]
+    stats::increment(&stats::DEALLOCATIONS);
+
+    #[cfg(feature = "std")]
+    if crate::diagnostics::tracking() {
+        crate::diagnostics::freed(ptr);
+    }
+
+    #[cfg(feature = "history")]
+    crate::history::record(ptr, crate::history::RefEventKind::Freed, 0, None);
+}
+
+// the allocation handed out a weak pointer expecting `provenance`
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn downgraded<T: ?Sized>(ptr: *const Inner<T>, provenance: Provenance) {
+    #[cfg(feature = "std")]
+    if crate::diagnostics::tracking() {
+        crate::diagnostics::downgraded(ptr, provenance);
+    }
+}
+
+// an Arc was cloned, making the count `ref_count`
+#[allow(unused_variables)]
+#[inline]
+#[track_caller]
+pub(crate) fn cloned<T: ?Sized>(ptr: *const Inner<T>, ref_count: usize) {
+    #[cfg(feature = "history")]
+    crate::history::record(
+        ptr,
+        crate::history::RefEventKind::Clone,
+        ref_count,
+        Some(core::panic::Location::caller()),
+    );
+}
+
+// an Arc other than the last was dropped, leaving the count at `ref_count`
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn dropped<T: ?Sized>(ptr: *const Inner<T>, ref_count: usize) {
+    #[cfg(feature = "history")]
+    crate::history::record(ptr, crate::history::RefEventKind::Drop, ref_count, None);
+}
+
+// a weak pointer tried to upgrade
+#[allow(unused_variables)]
+#[inline]
+pub(crate) fn upgraded(success: bool) {
+    #[cfg(feature = "stats")]
+    stats::increment(if success {
+        &stats::UPGRADES
+    } else {
+        &stats::FAILED_UPGRADES
+    });
+}
+
+// a weak pointer expecting `provenance` upgraded to `ptr`, making the count `ref_count`
+#[allow(unused_variables)]
+#[inline]
+#[track_caller]
+pub(crate) fn matched<T: ?Sized>(ptr: *const Inner<T>, provenance: Provenance, ref_count: usize) {
+    #[cfg(feature = "std")]
+    if crate::diagnostics::tracking() {
+        crate::diagnostics::matched(ptr, provenance);
+    }
+
+    #[cfg(feature = "history")]
+    crate::history::record(
+        ptr,
+        crate::history::RefEventKind::Upgrade,
+        ref_count,
+        Some(core::panic::Location::caller()),
+    );
+}
+
+// a weak pointer expecting `provenance` found its target dead
+#[allow(unused_variables)]
+#[inline]
+#[track_caller]
+pub(crate) fn upgrade_failed(provenance: Provenance) {
+    #[cfg(feature = "std")]
+    if crate::diagnostics::logging() {
+        crate::diagnostics::upgrade_failed(core::panic::Location::caller(), provenance);
+    }
+}
+
+// a weak pointer found its target holding some other provenance id, not zero
+#[inline]
+pub(crate) fn mismatched() {
+    #[cfg(feature = "stats")]
+    stats::increment(&stats::MISMATCHES);
+}
+
+// diagnostics caught an upgrade matching a reused provenance id
+#[cfg(feature = "std")]
+#[inline]
+pub(crate) fn collided() {
+    #[cfg(feature = "stats")]
+    stats::increment(&stats::COLLISIONS);
+}
+
+// the provenance lock was held by someone else, so the CAS has to be retried
+#[inline]
+pub(crate) fn contended() {
+    #[cfg(feature = "stats")]
+    stats::increment(&stats::CONTENDED);
+}
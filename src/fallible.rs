@@ -0,0 +1,73 @@
+//! Allocation that reports failure instead of aborting.
+//!
+//! [`Arc::try_new`] and [`Arc::try_new_uninit`] return an error when the allocator
+//! is out of memory, where [`Arc::new`] and [`Arc::new_uninit`] would abort.
+//!
+//! Only running out of memory is reported this way, and nothing checks the crate
+//! for panic paths. A new Arc's provenance id comes from the entropy source, and
+//! the default one panics if the OS has no randomness to give.
+//! [`Weak::try_upgrade`](crate::Weak::try_upgrade) panics on a provenance
+//! collision when diagnostics are on, and cloning doesn't guard the count against
+//! overflow.
+
+// try_upgrade used to need this feature, and its error was named from here
+pub use crate::upgrade::UpgradeError;
+
+use crate::uninit::try_allocate;
+use crate::{events, random_provenance, release_box, Arc, Inner};
+use alloc::alloc::Layout;
+use core::fmt;
+use core::mem::MaybeUninit;
+
+/// The allocator couldn't provide memory for a new Arc
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+impl core::error::Error for AllocError {}
+
+impl<T> Arc<T> {
+    /// Like [`Arc::new`], but returns an error instead of aborting if allocation fails
+    pub fn try_new(val: T) -> Result<Self, AllocError> {
+        let layout = Layout::new::<Inner<T>>();
+        let ptr = unsafe { alloc::alloc::alloc(layout) } as *mut Inner<T>;
+        if ptr.is_null() {
+            return Err(AllocError);
+        }
+
+        unsafe {
+            ptr.write(Inner::new(val, random_provenance(), release_box));
+        }
+        events::allocated(ptr);
+        Ok(Arc { ptr })
+    }
+
+    /// Like [`Arc::new_uninit`], but returns an error instead of aborting if
+    /// allocation fails
+    pub fn try_new_uninit() -> Result<Arc<MaybeUninit<T>>, AllocError> {
+        let layout = Layout::new::<Inner<MaybeUninit<T>>>();
+        unsafe { try_allocate(layout, false, |base| base as *mut Inner<MaybeUninit<T>>) }
+            .ok_or(AllocError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn try_new() {
+        let arc = Arc::try_new(String::from("a")).unwrap();
+        let weak = Arc::downgrade(&arc);
+        assert_eq!("a", *weak.try_upgrade().unwrap());
+
+        let mut arc = Arc::<[u8; 64]>::try_new_uninit().unwrap();
+        Arc::get_mut(&mut arc).unwrap().write([1; 64]);
+        assert_eq!([1; 64], *unsafe { arc.assume_init() });
+    }
+}
@@ -0,0 +1,245 @@
+//! C functions for holding on to Rust values (`ffi` feature).
+//!
+//! Rust code hands C a [`ProvenantArc`] from [`into_handle`], an owned strong
+//! reference that doesn't say what type it's to. C can clone and drop it, and make
+//! [`ProvenantWeak`] tokens from it: plain structs, an address and a provenance id,
+//! that C copies around freely and never frees. Upgrading a token gives a new
+//! strong handle, or null once the value is gone.
+//!
+//! ```c
+//! ProvenantWeak weak = provenant_arc_downgrade(handle);
+//! provenant_arc_drop(handle);
+//!
+//! ProvenantArc *again = provenant_weak_upgrade(weak);
+//! if (again) {
+//!     use(again);
+//!     provenant_arc_drop(again);
+//! }
+//! ```
+//!
+//! A zeroed `ProvenantWeak` never upgrades, and neither does one whose type
+//! information isn't from a real token. With `salted-handles`, the provenance ids C
+//! sees are salted, as for [`RawWeak`]. Not available with `provenance-128`,
+//! since C has no portable 128-bit integer.
+
+use crate::erased::ErasedArc;
+use crate::raw::RawWeak;
+use crate::{Arc, Provenance, Weak};
+use alloc::boxed::Box;
+use core::any::Any;
+use core::ffi::c_void;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+// what a handle needs to know about its value's type
+struct HandleFns {
+    downgrade: fn(&ErasedArc) -> RawWeak,
+    upgrade: unsafe fn(RawWeak) -> Option<ErasedArc>,
+}
+
+fn downgrade_handle<T: Any + Send + Sync>(arc: &ErasedArc) -> RawWeak {
+    // only ever called with the handle's own type
+    let weak = ErasedArc::downgrade(arc).downcast::<T>().unwrap();
+    weak.into_raw()
+}
+
+unsafe fn upgrade_handle<T: Any + Send + Sync>(raw: RawWeak) -> Option<ErasedArc> {
+    Weak::<T>::from_raw(raw).upgrade().map(ErasedArc::new)
+}
+
+// an associated const, so a reference to it is 'static
+trait HasHandleFns {
+    const FNS: HandleFns;
+}
+
+impl<T: Any + Send + Sync> HasHandleFns for T {
+    const FNS: HandleFns = HandleFns {
+        downgrade: downgrade_handle::<T>,
+        upgrade: upgrade_handle::<T>,
+    };
+}
+
+// every HandleFns a handle has been made with, so the one in a token from C can be
+// checked before anything's called through it. only ever pushed to
+struct Known {
+    fns: &'static HandleFns,
+    next: *mut Known,
+}
+
+static KNOWN: AtomicPtr<Known> = AtomicPtr::new(ptr::null_mut());
+
+fn is_known(fns: *const HandleFns) -> bool {
+    let mut node = KNOWN.load(Ordering::Acquire);
+    while let Some(known) = unsafe { node.as_ref() } {
+        if ptr::eq(known.fns, fns) {
+            return true;
+        }
+        node = known.next;
+    }
+    false
+}
+
+fn register(fns: &'static HandleFns) {
+    // two threads racing here push it twice, which does no harm
+    if is_known(fns) {
+        return;
+    }
+    let node = Box::leak(Box::new(Known {
+        fns,
+        next: KNOWN.load(Ordering::Relaxed),
+    }));
+    while let Err(head) =
+        KNOWN.compare_exchange_weak(node.next, node, Ordering::Release, Ordering::Relaxed)
+    {
+        node.next = head;
+    }
+}
+
+/// A strong reference handed to C. Opaque to C, which only ever holds a pointer
+pub struct ProvenantArc {
+    arc: ErasedArc,
+    fns: &'static HandleFns,
+}
+
+impl ProvenantArc {
+    /// Gets the Arc this holds, which [`ErasedArc::downcast_ref`] gets the value from
+    pub fn erased(&self) -> &ErasedArc {
+        &self.arc
+    }
+}
+
+/// A weak pointer handed to C, which is free to copy it
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProvenantWeak {
+    /// The allocation's address
+    pub addr: usize,
+    /// The provenance id, salted if `salted-handles` is on
+    pub provenance: Provenance,
+    // the HandleFns of the value's type, or null if it never upgrades
+    fns: *const c_void,
+}
+
+/// Turns an Arc into a handle for C, which [`provenant_arc_drop`] frees
+pub fn into_handle<T: Any + Send + Sync>(arc: Arc<T>) -> *mut ProvenantArc {
+    let fns = &T::FNS;
+    register(fns);
+    Box::into_raw(Box::new(ProvenantArc {
+        arc: ErasedArc::new(arc),
+        fns,
+    }))
+}
+
+/// Makes another strong handle to the same value.
+///
+/// # Safety
+///
+/// `handle` must be a live handle from [`into_handle`] or another of these
+/// functions.
+#[no_mangle]
+pub unsafe extern "C" fn provenant_arc_clone(handle: *const ProvenantArc) -> *mut ProvenantArc {
+    let handle = &*handle;
+    Box::into_raw(Box::new(ProvenantArc {
+        arc: handle.arc.clone(),
+        fns: handle.fns,
+    }))
+}
+
+/// Frees a strong handle, dropping the value if it was the last strong reference.
+/// Does nothing with null.
+///
+/// # Safety
+///
+/// `handle` must be null or a live handle, which mustn't be used again.
+#[no_mangle]
+pub unsafe extern "C" fn provenant_arc_drop(handle: *mut ProvenantArc) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Makes a weak token to the handle's value.
+///
+/// # Safety
+///
+/// `handle` must be a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn provenant_arc_downgrade(handle: *const ProvenantArc) -> ProvenantWeak {
+    let handle = &*handle;
+    let raw = (handle.fns.downgrade)(&handle.arc);
+    ProvenantWeak {
+        addr: raw.addr,
+        provenance: raw.provenance,
+        fns: handle.fns as *const HandleFns as *const c_void,
+    }
+}
+
+/// Gets a new strong handle from a weak token, or null if the value is gone.
+///
+/// # Safety
+///
+/// `weak` must be zeroed, or have come from [`provenant_arc_downgrade`]. The
+/// value it was made from doesn't need to be alive. A token with its type
+/// information overwritten is turned away, like a zeroed one.
+#[no_mangle]
+pub unsafe extern "C" fn provenant_weak_upgrade(weak: ProvenantWeak) -> *mut ProvenantArc {
+    let fns = weak.fns as *const HandleFns;
+    if !is_known(fns) {
+        return ptr::null_mut();
+    }
+    let fns = &*fns;
+    let raw = RawWeak {
+        addr: weak.addr,
+        provenance: weak.provenance,
+    };
+    match (fns.upgrade)(raw) {
+        Some(arc) => Box::into_raw(Box::new(ProvenantArc { arc, fns })),
+        None => ptr::null_mut(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::String;
+
+    #[test]
+    fn through_c() {
+        let handle = into_handle(Arc::new(String::from("script object")));
+        unsafe {
+            let weak = provenant_arc_downgrade(handle);
+            let other = provenant_arc_clone(handle);
+            provenant_arc_drop(handle);
+
+            let again = provenant_weak_upgrade(weak);
+            let value = (*again).erased().downcast_ref::<String>();
+            assert_eq!("script object", value.unwrap());
+
+            provenant_arc_drop(again);
+            provenant_arc_drop(other);
+            assert!(provenant_weak_upgrade(weak).is_null());
+            let zeroed = ProvenantWeak {
+                addr: 0,
+                provenance: 0,
+                fns: ptr::null(),
+            };
+            assert!(provenant_weak_upgrade(zeroed).is_null());
+        }
+    }
+
+    #[test]
+    fn forged_type() {
+        let arc = Arc::new(1u32);
+        let handle = into_handle(arc.clone());
+        let fns = [0usize; 4];
+        unsafe {
+            let weak = provenant_arc_downgrade(handle);
+            let forged = ProvenantWeak {
+                fns: fns.as_ptr() as *const c_void,
+                ..weak
+            };
+            assert!(provenant_weak_upgrade(forged).is_null());
+            provenant_arc_drop(handle);
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// finalizers for Arc::new_with_finalizer, kept in a side table keyed by address so
+// allocations without one don't pay for them. the table is split into buckets like
+// the parking lot's, and a drop only locks its bucket if a finalizer might be there
+
+use crate::{events, random_provenance, release_box, Arc, Inner};
+use alloc::boxed::Box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+// called with a pointer to the value
+type Finalizer = Box<dyn FnOnce(*mut u8) + Send>;
+
+struct Bucket {
+    finalizers: Mutex<Vec<(usize, Finalizer)>>,
+    // lets run skip the mutex when the bucket is empty, which is almost always
+    len: AtomicUsize,
+}
+
+impl Bucket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Bucket = Bucket {
+        finalizers: Mutex::new(Vec::new()),
+        len: AtomicUsize::new(0),
+    };
+}
+
+static BUCKETS: [Bucket; 64] = [Bucket::EMPTY; 64];
+
+fn bucket(addr: usize) -> &'static Bucket {
+    &BUCKETS[(addr >> 4) % BUCKETS.len()]
+}
+
+// runs the finalizer for the allocation at `addr`, if it has one. `data` points to
+// its value, which is about to be dropped or moved out
+pub(crate) fn run(addr: usize, data: *mut u8) {
+    let bucket = bucket(addr);
+    // the finalizer was registered before the Arc could be shared, and the thread
+    // freeing it has synchronized with every other one that had it
+    if bucket.len.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    let finalizer = {
+        let mut finalizers = bucket
+            .finalizers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let i = match finalizers.iter().position(|(a, _)| *a == addr) {
+            Some(i) => i,
+            None => return,
+        };
+        bucket.len.fetch_sub(1, Ordering::Relaxed);
+        finalizers.swap_remove(i).1
+    };
+    finalizer(data);
+}
+
+impl<T> Arc<T> {
+    /// Like [`Arc::new`], but `finalize` is called with the value once the last Arc
+    /// lets go of it, just before it's dropped.
+    ///
+    /// It's also called if the value is moved out, by [`Arc::try_unwrap`] or the
+    /// like, so it runs exactly once either way. It runs on whichever thread lets
+    /// go last, and if it panics, the value is leaked.
+    pub fn new_with_finalizer<F>(val: T, finalize: F) -> Self
+    where
+        F: FnOnce(&mut T) + Send + 'static,
+    {
+        let inner = Box::into_raw(Box::new(Inner::new(val, random_provenance(), release_box)));
+        let addr = inner as *const u8 as usize;
+
+        let finalizer: Finalizer =
+            Box::new(move |data| finalize(unsafe { &mut *(data as *mut T) }));
+        let bucket = bucket(addr);
+        bucket
+            .finalizers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push((addr, finalizer));
+        bucket.len.fetch_add(1, Ordering::Relaxed);
+
+        events::allocated(inner);
+        Arc { ptr: inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+    use std::sync::mpsc;
+
+    #[test]
+    fn finalized_once() {
+        let (tx, rx) = mpsc::channel();
+        let arc = Arc::new_with_finalizer(vec![1, 2], move |buffer: &mut Vec<i32>| {
+            tx.send(buffer.drain(..).sum::<i32>()).unwrap();
+        });
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+
+        drop(arc);
+        assert!(rx.try_recv().is_err());
+        drop(other);
+        assert_eq!(3, rx.recv().unwrap());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn finalized_before_unwrap() {
+        let arc = Arc::new_with_finalizer(String::from("log"), |log: &mut String| {
+            log.push_str(" flushed")
+        });
+        assert_eq!("log flushed", Arc::try_unwrap(arc).ok().unwrap());
+    }
+}
@@ -0,0 +1,131 @@
+//! Formatting impls.
+
+use crate::{Arc, Weak};
+use alloc::format;
+use alloc::string::String;
+use core::any::type_name;
+use core::fmt;
+
+impl<T: ?Sized> Weak<T> {
+    fn liveness(&self) -> &'static str {
+        if self.is_probably_alive() {
+            "alive"
+        } else {
+            "dead"
+        }
+    }
+}
+
+/// Shows the target type, whether it looks alive, and the provenance id,
+/// e.g. `Weak<Texture>(alive, pv=0x3f9a0c1e2d4b5a68)`. Never upgrades.
+impl<T: ?Sized> fmt::Debug for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Weak<{}>({}, pv={:#x})",
+            short_type_name::<T>(),
+            self.liveness(),
+            self.provenance
+        )
+    }
+}
+
+/// Like the `Debug` output, but with the provenance id cut short,
+/// e.g. `Weak<Texture>(alive, pv=0x3f9a…)`.
+impl<T: ?Sized> fmt::Display for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pv = format!("{:x}", self.provenance);
+        write!(
+            f,
+            "Weak<{}>({}, pv=0x{}…)",
+            short_type_name::<T>(),
+            self.liveness(),
+            &pv[..pv.len().min(4)]
+        )
+    }
+}
+
+/// The address of the allocation, without the tag
+impl<T: ?Sized> fmt::Pointer for Weak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&(self.addr() as *const ()), f)
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized + fmt::Display> fmt::Display for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&**self, f)
+    }
+}
+
+/// The address of the value, like std's
+impl<T: ?Sized> fmt::Pointer for Arc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Pointer::fmt(&(&**self as *const T), f)
+    }
+}
+
+// type_name without the module paths, so `Vec<alloc::string::String>` becomes `Vec<String>`
+fn short_type_name<T: ?Sized>() -> String {
+    let full = type_name::<T>();
+    let mut short = String::with_capacity(full.len());
+    let mut segment_start = 0;
+
+    for (i, c) in full.char_indices() {
+        if c.is_alphanumeric() || c == '_' || c == ':' {
+            continue;
+        }
+        short.push_str(last_segment(&full[segment_start..i]));
+        short.push(c);
+        segment_start = i + c.len_utf8();
+    }
+    short.push_str(last_segment(&full[segment_start..]));
+    short
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit("::").next().unwrap_or(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+
+    struct Texture;
+
+    #[test]
+    fn weak_debug() {
+        let arc = Arc::new(Texture);
+        let weak = Arc::downgrade(&arc);
+
+        let alive = format!("{:?}", weak);
+        assert!(alive.starts_with("Weak<Texture>(alive, pv=0x"), "{}", alive);
+
+        drop(arc);
+        let dead = format!("{}", weak);
+        assert!(dead.starts_with("Weak<Texture>(dead, pv=0x"), "{}", dead);
+        assert!(dead.ends_with("…)"), "{}", dead);
+    }
+
+    #[test]
+    fn generic_names() {
+        let arc = Arc::new(vec![String::new()]);
+        let weak = Arc::downgrade(&arc);
+        assert!(format!("{:?}", weak).starts_with("Weak<Vec<String>>(alive"));
+    }
+
+    #[test]
+    fn arc() {
+        let arc = Arc::with_tag(Arc::new(String::from("shown")), 1);
+        assert_eq!("\"shown\"", format!("{:?}", arc));
+        assert_eq!("shown", format!("{}", arc));
+        assert_eq!(format!("{:p}", &*arc), format!("{:p}", arc));
+        assert!(format!("{:p}", Arc::downgrade(&arc)).starts_with("0x"));
+    }
+}
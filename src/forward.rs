@@ -0,0 +1,183 @@
+//! Copy-on-write that takes weak pointers along.
+//!
+//! [`Arc::make_mut_forwarding`] gets mutable access to the value, cloning it into a
+//! new allocation if it's shared, like `make_mut` in std. On top of that, it leaves a
+//! forwarding entry from the old allocation to the new one, and
+//! [`Weak::upgrade_forwarded`] follows those entries. So a handle taken before an
+//! edit keeps pointing at the edited value, which is what an undo stack wants.
+//!
+//! Plain [`Weak::upgrade`] ignores forwarding entries. An entry is removed when a
+//! lookup finds both the allocation it forwards from and the one it ends up at dead.
+
+use crate::tag::{tag_of, untagged, with_tag};
+use crate::{Arc, Inner, Provenance, Weak};
+use std::collections::HashMap;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// (address, provenance) of an old allocation -> where its weaks should go instead
+type Table = HashMap<(usize, Provenance), (usize, Provenance)>;
+
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+// how many entries TABLE holds, so lookups can skip the lock when there are none
+static ENTRIES: AtomicUsize = AtomicUsize::new(0);
+
+fn table() -> MutexGuard<'static, Option<Table>> {
+    TABLE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn key<T>(weak: &Weak<T>) -> (usize, Provenance) {
+    (weak.addr(), weak.provenance)
+}
+
+/// Mutable access from [`Arc::make_mut_forwarding`].
+///
+/// Weak pointers to the old allocation start forwarding when this is dropped.
+pub struct Forwarding<'a, T> {
+    arc: &'a mut Arc<T>,
+    from: (usize, Provenance),
+}
+
+impl<T> Deref for Forwarding<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.arc
+    }
+}
+
+impl<T> DerefMut for Forwarding<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // nothing else can reach the allocation until the forwarding entry exists
+        unsafe { &mut (*(untagged(self.arc.ptr) as *mut Inner<T>)).data }
+    }
+}
+
+impl<T> Drop for Forwarding<'_, T> {
+    fn drop(&mut self) {
+        let to = key(&Arc::downgrade(self.arc));
+        let mut table = table();
+        if table
+            .get_or_insert_with(HashMap::new)
+            .insert(self.from, to)
+            .is_none()
+        {
+            ENTRIES.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+}
+
+impl<T: Clone> Arc<T> {
+    /// Gets mutable access to the value, cloning it first if any other Arc shares it.
+    ///
+    /// Either way, existing weak pointers can't upgrade to the value while the
+    /// returned guard is alive. Once it's dropped, [`Weak::upgrade_forwarded`] on
+    /// them reaches the edited value.
+    pub fn make_mut_forwarding(this: &mut Self) -> Forwarding<'_, T> {
+        let from = key(&Arc::downgrade(this));
+        let inner = this.inner();
+
+        // while the lock is held nothing can upgrade, so a count of 1 stays 1
+        if inner.lock(from.1) {
+            if inner.ref_count.load(Ordering::Acquire) == 1 {
+                // unique: keep the allocation but detach its weak pointers
+                inner.unlock(crate::random_provenance());
+                crate::events::rekeyed(inner);
+                return Forwarding { arc: this, from };
+            }
+            inner.unlock(from.1);
+        }
+
+        let copy = Arc::with_tag(Arc::new((**this).clone()), Arc::tag(this));
+        *this = copy;
+        Forwarding { arc: this, from }
+    }
+}
+
+impl<T> Weak<T> {
+    /// Follows forwarding entries left by [`Arc::make_mut_forwarding`]. Returns a copy
+    /// of this weak pointer if there aren't any.
+    pub fn forwarded(&self) -> Weak<T> {
+        if ENTRIES.load(Ordering::SeqCst) == 0 {
+            return *self;
+        }
+
+        let table = table();
+        let table = match table.as_ref() {
+            Some(table) => table,
+            None => return *self,
+        };
+
+        let mut at = key(self);
+        while let Some(&next) = table.get(&at) {
+            at = next;
+        }
+
+        Weak {
+            provenance: at.1,
+            ptr: with_tag(at.0 as *const Inner<T>, tag_of(self.ptr)),
+        }
+    }
+
+    /// Like [`Weak::upgrade`], but follows forwarding entries first
+    pub fn upgrade_forwarded(&self) -> Option<Arc<T>> {
+        let target = self.forwarded();
+        if let Some(arc) = target.upgrade() {
+            return Some(arc);
+        }
+
+        // once both ends are dead the entry can't change the answer any more
+        if key(&target) != key(self) && self.upgrade().is_none() {
+            let mut table = table();
+            if let Some(table) = table.as_mut() {
+                if table.remove(&key(self)).is_some() {
+                    ENTRIES.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shared() {
+        let mut arc = Arc::new(vec![1]);
+        let other = arc.clone();
+        let weak = Arc::downgrade(&arc);
+
+        Arc::make_mut_forwarding(&mut arc).push(2);
+
+        assert_eq!(vec![1], *other);
+        assert_eq!(vec![1], *weak.upgrade().unwrap());
+        assert_eq!(vec![1, 2], *weak.upgrade_forwarded().unwrap());
+
+        // forwarding chains
+        let mut again = arc.clone();
+        Arc::make_mut_forwarding(&mut again).push(3);
+        assert_eq!(vec![1, 2, 3], *weak.upgrade_forwarded().unwrap());
+
+        drop((arc, again));
+        assert!(weak.upgrade_forwarded().is_none());
+    }
+
+    #[test]
+    fn unique() {
+        let mut arc = Arc::new(String::from("a"));
+        let weak = Arc::downgrade(&arc);
+
+        {
+            let mut edit = Arc::make_mut_forwarding(&mut arc);
+            assert!(weak.upgrade().is_none());
+            edit.push('b');
+        }
+
+        assert!(weak.upgrade().is_none());
+        assert_eq!("ab", *weak.upgrade_forwarded().unwrap());
+        assert_eq!(untagged(arc.ptr), untagged(weak.forwarded().ptr));
+    }
+}
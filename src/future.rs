@@ -0,0 +1,316 @@
+//! Futures that wait on shared values (`async` feature).
+//!
+//! These don't depend on any particular executor. Wakers are kept in a side table
+//! keyed by address, so allocations don't grow to make room for them.
+
+use crate::park;
+use crate::upgrade::UpgradeError;
+use crate::{Arc, Weak};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::task::{Context, Poll};
+
+impl<T: ?Sized> Arc<T> {
+    /// Resolves once this is the only strong reference left.
+    ///
+    /// The async version of [`Arc::wait_unique`]. As with that, weak pointers can
+    /// still upgrade afterwards.
+    pub fn when_unique(this: &Self) -> WhenUnique<'_, T> {
+        WhenUnique {
+            arc: this,
+            id: park::waker_id(),
+            registered: false,
+        }
+    }
+}
+
+/// The future returned by [`Arc::when_unique`]
+pub struct WhenUnique<'a, T: ?Sized> {
+    arc: &'a Arc<T>,
+    id: usize,
+    registered: bool,
+}
+
+impl<T: ?Sized> WhenUnique<'_, T> {
+    fn unique(&self) -> bool {
+        self.arc.inner().ref_count.load(Ordering::Acquire) == 1
+    }
+}
+
+impl<T: ?Sized> Future for WhenUnique<'_, T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.unique() {
+            return Poll::Ready(());
+        }
+
+        park::register_waker(self.arc.addr(), self.id, cx.waker());
+        self.registered = true;
+
+        // the last other Arc might have dropped before the waker was registered
+        if self.unique() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized> Drop for WhenUnique<'_, T> {
+    fn drop(&mut self) {
+        if self.registered {
+            park::unregister_waker(self.arc.addr(), self.id);
+        }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Resolves once the value this points to is gone, whether the last Arc was
+    /// dropped or the value was detached with [`Arc::get_mut`].
+    ///
+    /// Like [`Weak::upgrade`], it can be fooled by a new value that happens to get
+    /// the same provenance id at the same address.
+    pub fn dropped(&self) -> Dropped<T> {
+        Dropped {
+            weak: *self,
+            id: park::waker_id(),
+            registered: false,
+        }
+    }
+
+    /// Like [`Weak::upgrade`], but if another thread holds the provenance lock for
+    /// more than a few tries, this yields to the executor and tries again when it's
+    /// next polled, instead of spinning the worker thread until it's released
+    pub fn upgrade_async(&self) -> UpgradeAsync<T> {
+        UpgradeAsync { weak: *self }
+    }
+
+    /// Runs `fut` on behalf of this weak pointer's target, stopping with
+    /// [`Cancelled`] as soon as the target is dropped.
+    ///
+    /// The target is checked every time the future is polled, and dropping it
+    /// wakes the future, so tasks don't outlive the object they work for.
+    pub fn bind_future<F: Future>(&self, fut: F) -> BoundFuture<F, T> {
+        BoundFuture {
+            weak: *self,
+            fut,
+            id: park::waker_id(),
+            registered: false,
+        }
+    }
+}
+
+/// The future returned by [`Weak::bind_future`]
+pub struct BoundFuture<F, T: ?Sized> {
+    weak: Weak<T>,
+    fut: F,
+    id: usize,
+    registered: bool,
+}
+
+/// The future returned by [`Weak::dropped`]
+pub struct Dropped<T: ?Sized> {
+    weak: Weak<T>,
+    id: usize,
+    registered: bool,
+}
+
+impl<T: ?Sized> Future for Dropped<T> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if !self.weak.is_probably_alive() {
+            return Poll::Ready(());
+        }
+
+        park::register_waker(self.weak.addr(), self.id, cx.waker());
+        self.registered = true;
+
+        // the value might have gone before the waker was registered
+        if !self.weak.is_probably_alive() {
+            return Poll::Ready(());
+        }
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized> Drop for Dropped<T> {
+    fn drop(&mut self) {
+        if self.registered {
+            park::unregister_waker(self.weak.addr(), self.id);
+        }
+    }
+}
+
+/// The future returned by [`Weak::upgrade_async`]
+pub struct UpgradeAsync<T: ?Sized> {
+    weak: Weak<T>,
+}
+
+// how many times a poll tries the lock before yielding. it's only held for a couple
+// of atomic operations, so a short wait usually gets it
+const UPGRADE_SPINS: u32 = 16;
+
+impl<T: ?Sized> Future for UpgradeAsync<T> {
+    type Output = Option<Arc<T>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Arc<T>>> {
+        for _ in 0..UPGRADE_SPINS {
+            match self.weak.try_upgrade() {
+                Ok(arc) => return Poll::Ready(Some(arc)),
+                Err(UpgradeError::Contended) => std::hint::spin_loop(),
+                Err(_) => return Poll::Ready(None),
+            }
+        }
+        // nothing wakes us when the lock is released, so ask to be polled again
+        // once the executor has run whatever else is ready
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// The target of a [`Weak::bind_future`] was dropped before the future finished
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the bound value was dropped")
+    }
+}
+
+impl Error for Cancelled {}
+
+impl<F: Future, T: ?Sized> Future for BoundFuture<F, T> {
+    type Output = Result<F::Output, Cancelled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // fut is structurally pinned, and nothing else is
+        let this = unsafe { self.get_unchecked_mut() };
+        let addr = this.weak.addr();
+
+        park::register_waker(addr, this.id, cx.waker());
+        this.registered = true;
+
+        // checked after registering, so a drop in between still wakes us
+        if !this.weak.is_probably_alive() {
+            return Poll::Ready(Err(Cancelled));
+        }
+
+        let fut = unsafe { Pin::new_unchecked(&mut this.fut) };
+        fut.poll(cx).map(Ok)
+    }
+}
+
+impl<F, T: ?Sized> Drop for BoundFuture<F, T> {
+    fn drop(&mut self) {
+        if self.registered {
+            park::unregister_waker(self.weak.addr(), self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Arc as StdArc;
+    use std::task::{Wake, Waker};
+    use std::thread::{self, Thread};
+    use std::time::Duration;
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: StdArc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // just enough of an executor to run one future on the current thread
+    pub(crate) fn block_on<F: Future>(fut: F) -> F::Output {
+        let mut fut = Box::pin(fut);
+        let waker = Waker::from(StdArc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+
+        loop {
+            if let Poll::Ready(out) = fut.as_mut().poll(&mut cx) {
+                return out;
+            }
+            thread::park();
+        }
+    }
+
+    #[test]
+    fn when_unique() {
+        let arc = Arc::new(1);
+        let other = arc.clone();
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(other);
+        });
+
+        block_on(Arc::when_unique(&arc));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn bind_future() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(Ok(5), block_on(weak.bind_future(async { 5 })));
+
+        // never finishes on its own
+        let pending = std::future::pending::<()>();
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(arc);
+        });
+
+        assert_eq!(Err(Cancelled), block_on(weak.bind_future(pending)));
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn dropped() {
+        let arc = Arc::new(String::from("resource"));
+        let weak = Arc::downgrade(&arc);
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(arc);
+        });
+        block_on(weak.dropped());
+        t.join().unwrap();
+
+        let mut arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            *Arc::get_mut(&mut arc).unwrap() += 1;
+            arc
+        });
+        block_on(weak.dropped());
+        assert_eq!(2, *t.join().unwrap());
+    }
+
+    #[test]
+    fn upgrade_async() {
+        let arc = Arc::new(3);
+        let weak = Arc::downgrade(&arc);
+
+        // holds the provenance lock
+        let guard = weak.read().unwrap();
+        let mut upgrade = Box::pin(weak.upgrade_async());
+        let waker = Waker::from(StdArc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        assert!(upgrade.as_mut().poll(&mut cx).is_pending());
+
+        drop(guard);
+        assert_eq!(3, *block_on(upgrade).unwrap());
+        drop(arc);
+        assert!(block_on(weak.upgrade_async()).is_none());
+    }
+}
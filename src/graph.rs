@@ -0,0 +1,273 @@
+//! Building graphs of mutually referencing Arcs in one step.
+//!
+//! Declare the nodes and the edges between them up front, then
+//! [`build`](GraphBuilder::build) turns them into live Arcs. Every node's value is
+//! constructed once, already holding its edges, so there's no need for `Option`
+//! fields that get patched in afterwards.
+//!
+//! Strong edges become [`Arc`]s and weak edges become [`Weak`]s. Weak edges can point
+//! anywhere, including back up the graph, but strong edges can't form a cycle,
+//! since the nodes in it would never be freed.
+//!
+//! ```
+//! use provenant::graph::GraphBuilder;
+//! use provenant::{Arc, Weak};
+//!
+//! struct Node {
+//!     name: &'static str,
+//!     children: Vec<Arc<Node>>,
+//!     parent: Option<Weak<Node>>,
+//! }
+//!
+//! let mut builder = GraphBuilder::new();
+//! let root = builder.add_node("root");
+//! let leaf = builder.add_node("leaf");
+//! builder.strong_edge(root, leaf);
+//! builder.weak_edge(leaf, root);
+//!
+//! let nodes = builder
+//!     .build(|name, links| Node {
+//!         name,
+//!         children: links.strong,
+//!         parent: links.weak.first().copied(),
+//!     })
+//!     .unwrap();
+//!
+//! let leaf = &nodes[leaf.index()];
+//! assert!(leaf.children.is_empty());
+//! assert_eq!("root", leaf.parent.unwrap().upgrade().unwrap().name);
+//! ```
+
+use crate::{Arc, Reserved, Weak};
+use std::error::Error;
+use std::fmt;
+
+/// Identifies a node in a [`GraphBuilder`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    /// Where this node's Arc is in the list returned by [`GraphBuilder::build`]
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// The edges leading out of a node, resolved into pointers
+pub struct Links<T> {
+    /// Targets of strong edges, in the order the edges were added
+    pub strong: Vec<Arc<T>>,
+    /// Targets of weak edges, in the order the edges were added.
+    /// These can't be upgraded until the whole graph is built.
+    pub weak: Vec<Weak<T>>,
+}
+
+/// Declares a graph of nodes and edges, then builds it into Arcs.
+///
+/// Each node has a seed value of type `D`, which is handed back along
+/// with the node's [`Links`] when its value is constructed.
+pub struct GraphBuilder<D> {
+    seeds: Vec<D>,
+    strong: Vec<Vec<NodeId>>,
+    weak: Vec<Vec<NodeId>>,
+}
+
+impl<D> GraphBuilder<D> {
+    /// Creates a builder with no nodes
+    pub fn new() -> Self {
+        GraphBuilder {
+            seeds: Vec::new(),
+            strong: Vec::new(),
+            weak: Vec::new(),
+        }
+    }
+
+    /// Adds a node
+    pub fn add_node(&mut self, seed: D) -> NodeId {
+        self.seeds.push(seed);
+        self.strong.push(Vec::new());
+        self.weak.push(Vec::new());
+        NodeId(self.seeds.len() - 1)
+    }
+
+    /// Adds an edge that keeps `to` alive as long as `from` is
+    ///
+    /// # Panics
+    ///
+    /// If either node isn't from this builder.
+    pub fn strong_edge(&mut self, from: NodeId, to: NodeId) {
+        self.check(to);
+        self.strong[from.0].push(to);
+    }
+
+    /// Adds an edge from `from` to `to` that doesn't keep anything alive
+    ///
+    /// # Panics
+    ///
+    /// If either node isn't from this builder.
+    pub fn weak_edge(&mut self, from: NodeId, to: NodeId) {
+        self.check(to);
+        self.weak[from.0].push(to);
+    }
+
+    fn check(&self, node: NodeId) {
+        assert!(node.0 < self.seeds.len(), "{:?} isn't in this graph", node);
+    }
+
+    /// Constructs every node, returning their Arcs in the order they were added.
+    ///
+    /// Nodes are constructed after everything they have strong edges to, so `node_fn`
+    /// is called in that order rather than the order they were added. Fails without
+    /// calling `node_fn` at all if the strong edges form a cycle.
+    pub fn build<T, F>(self, mut node_fn: F) -> Result<Vec<Arc<T>>, GraphError>
+    where
+        F: FnMut(D, Links<T>) -> T,
+    {
+        let order = self.strong_order()?;
+
+        let mut reserved: Vec<Option<Reserved<T>>> =
+            self.seeds.iter().map(|_| Some(Reserved::new())).collect();
+        let weaks: Vec<Weak<T>> = reserved.iter().flatten().map(Reserved::weak).collect();
+
+        let mut seeds: Vec<Option<D>> = self.seeds.into_iter().map(Some).collect();
+        let mut built: Vec<Option<Arc<T>>> = weaks.iter().map(|_| None).collect();
+
+        for node in order {
+            let links = Links {
+                strong: self.strong[node]
+                    .iter()
+                    .map(|to| built[to.0].clone().unwrap())
+                    .collect(),
+                weak: self.weak[node].iter().map(|to| weaks[to.0]).collect(),
+            };
+
+            let data = node_fn(seeds[node].take().unwrap(), links);
+            built[node] = Some(reserved[node].take().unwrap().init(data));
+        }
+
+        Ok(built.into_iter().map(Option::unwrap).collect())
+    }
+
+    // the nodes ordered so that each one comes after everything it has strong edges to
+    fn strong_order(&self) -> Result<Vec<usize>, GraphError> {
+        let len = self.seeds.len();
+        let mut remaining: Vec<usize> = self.strong.iter().map(Vec::len).collect();
+        let mut incoming: Vec<Vec<usize>> = vec![Vec::new(); len];
+        for (from, targets) in self.strong.iter().enumerate() {
+            for to in targets {
+                incoming[to.0].push(from);
+            }
+        }
+
+        let mut ready: Vec<usize> = (0..len).filter(|&n| remaining[n] == 0).collect();
+        let mut order = Vec::with_capacity(len);
+        while let Some(node) = ready.pop() {
+            order.push(node);
+            for &from in &incoming[node] {
+                remaining[from] -= 1;
+                if remaining[from] == 0 {
+                    ready.push(from);
+                }
+            }
+        }
+
+        if order.len() < len {
+            let nodes = (0..len).filter(|&n| remaining[n] > 0).map(NodeId).collect();
+            return Err(GraphError::StrongCycle { nodes });
+        }
+        Ok(order)
+    }
+}
+
+impl<D> Default for GraphBuilder<D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Why a graph couldn't be built
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GraphError {
+    /// Strong edges form a cycle, which would never be freed
+    StrongCycle {
+        /// The nodes that are in a cycle or have strong edges leading into one
+        nodes: Vec<NodeId>,
+    },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GraphError::StrongCycle { nodes } => {
+                write!(f, "strong edges form a cycle through {:?}", nodes)
+            }
+        }
+    }
+}
+
+impl Error for GraphError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        id: usize,
+        next: Option<Weak<Node>>,
+        owns: Vec<Arc<Node>>,
+    }
+
+    #[test]
+    fn weak_cycle() {
+        let mut builder = GraphBuilder::new();
+        let ids: Vec<_> = (0..3).map(|i| builder.add_node(i)).collect();
+        for i in 0..3 {
+            builder.weak_edge(ids[i], ids[(i + 1) % 3]);
+        }
+        builder.strong_edge(ids[0], ids[1]);
+
+        let nodes = builder
+            .build(|id, links| Node {
+                id,
+                next: links.weak.first().copied(),
+                owns: links.strong,
+            })
+            .unwrap();
+
+        for node in &nodes {
+            let next = node.next.unwrap().upgrade().unwrap();
+            assert_eq!((node.id + 1) % 3, next.id);
+        }
+        assert_eq!(1, nodes[0].owns[0].id);
+
+        let weak = nodes[2].next.unwrap();
+        drop(nodes);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn strong_cycle() {
+        let mut builder = GraphBuilder::new();
+        let a = builder.add_node(0);
+        let b = builder.add_node(1);
+        let c = builder.add_node(2);
+        builder.strong_edge(a, b);
+        builder.strong_edge(b, a);
+        builder.strong_edge(c, b);
+
+        let err = builder
+            .build(|id, _| Node {
+                id,
+                next: None,
+                owns: Vec::new(),
+            })
+            .err()
+            .unwrap();
+        assert_eq!(
+            GraphError::StrongCycle {
+                nodes: vec![a, b, c]
+            },
+            err
+        );
+    }
+}
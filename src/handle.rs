@@ -0,0 +1,127 @@
+//! Weak handles that carry a permission.
+//!
+//! A [`WriteHandle`] upgrades to a full [`Arc`], with everything that allows:
+//! cloning it, [`Arc::make_mut_forwarding`], locking an `Arc<Mutex<_>>` with
+//! [`Arc::lock_arc`], and so on. A [`ReadHandle`] upgrades to a [`ReadRef`], which
+//! only gives out `&T`, and can't be turned back into an Arc or a writable handle.
+//! Code that should only look, like untrusted plugins, gets `ReadHandle`s, and the
+//! type system keeps it from doing anything else.
+//!
+//! Both check that the value is still alive when upgrading, like [`Weak`].
+//! Read-only access goes as deep as `&T` does: a value with interior mutability,
+//! like a `Mutex`, can still be changed through it.
+
+use crate::{Arc, Weak};
+use core::fmt;
+use core::ops::Deref;
+
+/// A weak handle that can only be used to read the value
+///
+/// There's no way to get an [`Arc`] from it:
+///
+/// ```compile_fail
+/// use provenant::Arc;
+///
+/// let reader = Arc::read_handle(&Arc::new(1));
+/// let arc: Arc<i32> = reader.upgrade().unwrap().clone();
+/// ```
+pub struct ReadHandle<T: ?Sized>(Weak<T>);
+
+/// A weak handle with full access to the value
+pub struct WriteHandle<T: ?Sized>(Weak<T>);
+
+impl<T: ?Sized> Copy for ReadHandle<T> {}
+
+impl<T: ?Sized> Clone for ReadHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for WriteHandle<T> {}
+
+impl<T: ?Sized> Clone for WriteHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Gets a handle that can read the value but not change it
+    pub fn read_handle(this: &Self) -> ReadHandle<T> {
+        ReadHandle(Arc::downgrade(this))
+    }
+
+    /// Gets a handle with full access to the value
+    pub fn write_handle(this: &Self) -> WriteHandle<T> {
+        WriteHandle(Arc::downgrade(this))
+    }
+}
+
+impl<T: ?Sized> ReadHandle<T> {
+    /// Gets read access to the value, if it's still alive
+    pub fn upgrade(&self) -> Option<ReadRef<T>> {
+        self.0.upgrade().map(ReadRef)
+    }
+}
+
+impl<T: ?Sized> WriteHandle<T> {
+    /// Gets a strong reference to the value, if it's still alive
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        self.0.upgrade()
+    }
+
+    /// Gets a handle to the same value with only read access
+    pub fn read_only(&self) -> ReadHandle<T> {
+        ReadHandle(self.0)
+    }
+
+    /// Gets the plain weak pointer
+    pub fn weak(&self) -> Weak<T> {
+        self.0
+    }
+}
+
+impl<T: ?Sized> From<WriteHandle<T>> for ReadHandle<T> {
+    fn from(handle: WriteHandle<T>) -> Self {
+        handle.read_only()
+    }
+}
+
+/// Read access from a [`ReadHandle`]. Keeps the value alive, like an Arc.
+pub struct ReadRef<T: ?Sized>(Arc<T>);
+
+impl<T: ?Sized> Deref for ReadRef<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ReadRef<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn permissions() {
+        let state = Arc::new(String::from("config"));
+        let writer = Arc::write_handle(&state);
+        let reader: ReadHandle<String> = writer.into();
+
+        assert_eq!("config", *reader.upgrade().unwrap());
+        let strong = writer.upgrade().unwrap();
+
+        drop(state);
+        assert_eq!(6, reader.upgrade().unwrap().len());
+
+        drop(strong);
+        assert!(reader.upgrade().is_none());
+        assert!(writer.upgrade().is_none());
+    }
+}
@@ -0,0 +1,175 @@
+//! Timelines of what happened to an allocation's ref count (`history` feature).
+//!
+//! When an object's count never gets to zero, the question is who cloned it and
+//! didn't drop it. [`trace`] starts recording every clone, drop and upgrade of one
+//! allocation, with the thread, time and call site, into a [`History`] that can be
+//! read at any point.
+//!
+//! This makes every clone, drop and upgrade check whether its allocation is traced,
+//! so it's for debugging only.
+
+use crate::{Arc, Inner};
+use std::collections::HashMap;
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc as StdArc, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+/// What happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefEventKind {
+    /// An Arc was cloned
+    Clone,
+    /// An Arc was dropped
+    Drop,
+    /// A weak pointer upgraded
+    Upgrade,
+    /// The last Arc was dropped, and the allocation freed
+    Freed,
+}
+
+/// One change to a traced allocation's ref count
+#[derive(Debug, Clone)]
+pub struct RefEvent {
+    /// What happened
+    pub kind: RefEventKind,
+    /// The ref count just afterwards, as seen by the thread that did it
+    pub ref_count: usize,
+    /// The thread that did it
+    pub thread: ThreadId,
+    /// The thread's name, if it has one
+    pub thread_name: Option<String>,
+    /// When it happened
+    pub at: Instant,
+    /// Where it happened. Drops don't know.
+    pub location: Option<&'static Location<'static>>,
+}
+
+impl fmt::Display for RefEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} -> {} on {}",
+            self.kind,
+            self.ref_count,
+            self.thread_name.as_deref().unwrap_or("<unnamed>")
+        )?;
+        if let Some(location) = self.location {
+            write!(f, " at {}", location)?;
+        }
+        Ok(())
+    }
+}
+
+type Log = StdArc<Mutex<Vec<RefEvent>>>;
+
+// how many allocations are traced, so untraced ones can skip the lock
+static TRACED: AtomicUsize = AtomicUsize::new(0);
+
+fn traced() -> MutexGuard<'static, HashMap<usize, Log>> {
+    static MAP: OnceLock<Mutex<HashMap<usize, Log>>> = OnceLock::new();
+    MAP.get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The recorded events for one allocation
+#[derive(Clone)]
+pub struct History {
+    log: Log,
+}
+
+impl History {
+    /// Gets everything recorded so far, oldest first
+    pub fn events(&self) -> Vec<RefEvent> {
+        self.log
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+}
+
+impl fmt::Debug for History {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.events()).finish()
+    }
+}
+
+/// Starts recording what happens to `arc`'s allocation, until it's freed.
+/// Tracing an allocation again gets the same history.
+pub fn trace<T: ?Sized>(arc: &Arc<T>) -> History {
+    let mut traced = traced();
+    let log = traced
+        .entry(arc.addr())
+        .or_insert_with(|| {
+            TRACED.fetch_add(1, Ordering::SeqCst);
+            Log::default()
+        })
+        .clone();
+    History { log }
+}
+
+// the ref count is passed in, since once it's been decremented the allocation
+// might be freed at any moment
+pub(crate) fn record<T: ?Sized>(
+    ptr: *const Inner<T>,
+    kind: RefEventKind,
+    ref_count: usize,
+    location: Option<&'static Location<'static>>,
+) {
+    if TRACED.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    let addr = ptr as *const u8 as usize;
+    let mut traced = traced();
+    let log = match traced.get(&addr) {
+        Some(log) => log.clone(),
+        None => return,
+    };
+    if kind == RefEventKind::Freed {
+        traced.remove(&addr);
+        TRACED.fetch_sub(1, Ordering::SeqCst);
+    }
+    drop(traced);
+
+    let thread = thread::current();
+    let event = RefEvent {
+        kind,
+        ref_count,
+        thread: thread.id(),
+        thread_name: thread.name().map(str::to_owned),
+        at: Instant::now(),
+        location,
+    };
+    log.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(event);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeline() {
+        let arc = Arc::new(1);
+        let history = trace(&arc);
+        let weak = Arc::downgrade(&arc);
+
+        let cloned = arc.clone();
+        let upgraded = weak.upgrade().unwrap();
+        drop((arc, cloned, upgraded));
+
+        let kinds: Vec<RefEventKind> = history.events().iter().map(|e| e.kind).collect();
+        use RefEventKind::*;
+        assert_eq!(vec![Clone, Upgrade, Drop, Drop, Freed], kinds);
+
+        let events = history.events();
+        assert_eq!(2, events[0].ref_count);
+        assert_eq!(file!(), events[0].location.unwrap().file());
+        assert!(events[2].location.is_none());
+    }
+}
@@ -0,0 +1,138 @@
+//! Reading from shared buffers.
+
+use crate::Arc;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+
+/// A [`Read`] + [`Seek`] cursor over a shared byte buffer.
+///
+/// Works like [`std::io::Cursor`], but holds an [`Arc`], so the buffer can be handed
+/// to a parser that wants a reader without copying it or borrowing it.
+/// Anything that's `AsRef<[u8]>` works, like `Vec<u8>`, `[u8]` and `str`.
+pub struct ArcCursor<T: ?Sized> {
+    inner: Arc<T>,
+    pos: u64,
+}
+
+impl<T: ?Sized> Clone for ArcCursor<T> {
+    fn clone(&self) -> Self {
+        ArcCursor {
+            inner: self.inner.clone(),
+            pos: self.pos,
+        }
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> ArcCursor<T> {
+    /// Creates a cursor at the start of the buffer
+    pub fn new(inner: Arc<T>) -> Self {
+        ArcCursor { inner, pos: 0 }
+    }
+
+    /// Gets the buffer back
+    pub fn into_inner(self) -> Arc<T> {
+        self.inner
+    }
+
+    /// Gets a reference to the buffer
+    pub fn get_ref(&self) -> &Arc<T> {
+        &self.inner
+    }
+
+    /// The current position
+    pub fn position(&self) -> u64 {
+        self.pos
+    }
+
+    /// Moves to `pos`, which can be past the end
+    pub fn set_position(&mut self, pos: u64) {
+        self.pos = pos;
+    }
+
+    fn remaining(&self) -> &[u8] {
+        let bytes = (*self.inner).as_ref();
+        let start = self.pos.min(bytes.len() as u64) as usize;
+        &bytes[start..]
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> Read for ArcCursor<T> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.remaining().read(buf)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> BufRead for ArcCursor<T> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        Ok(self.remaining())
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt as u64;
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> Seek for ArcCursor<T> {
+    fn seek(&mut self, style: SeekFrom) -> io::Result<u64> {
+        let (base, offset) = match style {
+            SeekFrom::Start(n) => {
+                self.pos = n;
+                return Ok(n);
+            }
+            SeekFrom::End(n) => ((*self.inner).as_ref().len() as u64, n),
+            SeekFrom::Current(n) => (self.pos, n),
+        };
+
+        match base.checked_add_signed(offset) {
+            Some(n) => {
+                self.pos = n;
+                Ok(n)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl<T: ?Sized + AsRef<[u8]>> From<Arc<T>> for ArcCursor<T> {
+    fn from(inner: Arc<T>) -> Self {
+        ArcCursor::new(inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn read_and_seek() {
+        let buf = Arc::new(b"hello world".to_vec());
+        let mut cursor = ArcCursor::new(buf.clone());
+
+        let mut word = [0; 5];
+        cursor.read_exact(&mut word).unwrap();
+        assert_eq!(b"hello", &word);
+
+        cursor.seek(SeekFrom::End(-5)).unwrap();
+        let mut rest = String::new();
+        cursor.read_to_string(&mut rest).unwrap();
+        assert_eq!("world", rest);
+
+        assert!(cursor.seek(SeekFrom::Current(-100)).is_err());
+        cursor.seek(SeekFrom::Start(100)).unwrap();
+        assert_eq!(0, cursor.read(&mut word).unwrap());
+
+        let back = cursor.into_inner();
+        assert_eq!(buf.as_ptr(), back.as_ptr());
+    }
+
+    #[test]
+    fn buf_read() {
+        let mut cursor = ArcCursor::new(Arc::new("a\nb\n"));
+        let lines: Vec<String> = (&mut cursor).lines().map(Result::unwrap).collect();
+        assert_eq!(vec!["a", "b"], lines);
+    }
+}
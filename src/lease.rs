@@ -0,0 +1,155 @@
+//! Weak pointers that also need the owner's ongoing permission.
+//!
+//! A [`Lease`] is like a [`Weak`], but upgrading it also requires the lease to be
+//! current. The [`LeaseOwner`] keeps leases current by calling
+//! [`renew_leases`](LeaseOwner::renew_leases) before the term runs out, and can cut
+//! them all off at once with [`revoke_leases`](LeaseOwner::revoke_leases). That
+//! separates permission to access a value from the value existing at all.
+
+use crate::{Arc, Weak};
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+/// A value along with the state of the leases on it
+pub struct Leased<T> {
+    epoch: AtomicU64,
+    // nanoseconds since clock_base()
+    expires: AtomicU64,
+    value: T,
+}
+
+impl<T> Deref for Leased<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+fn clock_base() -> Instant {
+    static BASE: OnceLock<Instant> = OnceLock::new();
+    *BASE.get_or_init(Instant::now)
+}
+
+fn now() -> u64 {
+    clock_base().elapsed().as_nanos() as u64
+}
+
+/// Owns a shared value and controls the leases on it
+pub struct LeaseOwner<T> {
+    arc: Arc<Leased<T>>,
+    term: Duration,
+}
+
+impl<T> LeaseOwner<T> {
+    /// Shares `val`, with leases that stay valid for `term` after each renewal.
+    /// Leases start out renewed.
+    pub fn new(val: T, term: Duration) -> Self {
+        let owner = LeaseOwner {
+            arc: Arc::new(Leased {
+                epoch: AtomicU64::new(0),
+                expires: AtomicU64::new(0),
+                value: val,
+            }),
+            term,
+        };
+        owner.renew_leases();
+        owner
+    }
+
+    /// Hands out a lease on the value
+    pub fn lease(&self) -> Lease<T> {
+        Lease {
+            weak: Arc::downgrade(&self.arc),
+            epoch: self.arc.epoch.load(Ordering::SeqCst),
+        }
+    }
+
+    /// Keeps every unrevoked lease upgradeable for another term
+    pub fn renew_leases(&self) {
+        let expires = now().saturating_add(self.term.as_nanos() as u64);
+        self.arc.expires.store(expires, Ordering::SeqCst);
+    }
+
+    /// Makes every lease handed out so far fail to upgrade, even after renewal
+    pub fn revoke_leases(&self) {
+        self.arc.epoch.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Gets a strong reference to the value.
+    /// Strong references aren't affected by leases.
+    pub fn arc(&self) -> &Arc<Leased<T>> {
+        &self.arc
+    }
+}
+
+impl<T> Deref for LeaseOwner<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.arc.value
+    }
+}
+
+/// A weak pointer that only upgrades while its [`LeaseOwner`] keeps it current
+pub struct Lease<T> {
+    weak: Weak<Leased<T>>,
+    epoch: u64,
+}
+
+impl<T> Clone for Lease<T> {
+    fn clone(&self) -> Self {
+        Lease {
+            weak: self.weak,
+            epoch: self.epoch,
+        }
+    }
+}
+
+impl<T> Lease<T> {
+    /// Attempts to get a strong reference. Fails if the value is gone,
+    /// the lease was revoked, or it wasn't renewed in time.
+    pub fn upgrade(&self) -> Option<Arc<Leased<T>>> {
+        let arc = self.weak.upgrade()?;
+        if arc.epoch.load(Ordering::SeqCst) != self.epoch {
+            return None;
+        }
+        if now() >= arc.expires.load(Ordering::SeqCst) {
+            return None;
+        }
+        Some(arc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn revoke() {
+        let owner = LeaseOwner::new(5, Duration::from_secs(60));
+        let lease = owner.lease();
+        assert_eq!(5, **lease.upgrade().unwrap());
+
+        owner.revoke_leases();
+        owner.renew_leases();
+        assert!(lease.upgrade().is_none());
+        assert_eq!(5, **owner.lease().upgrade().unwrap());
+    }
+
+    #[test]
+    fn expire_and_renew() {
+        let owner = LeaseOwner::new("x", Duration::from_millis(10));
+        let lease = owner.lease();
+
+        thread::sleep(Duration::from_millis(20));
+        assert!(lease.upgrade().is_none());
+
+        owner.renew_leases();
+        assert!(lease.upgrade().is_some());
+
+        drop(owner);
+        assert!(lease.upgrade().is_none());
+    }
+}
@@ -1,7 +1,139 @@
-use rand::Rng;
-use std::ops::Deref;
-use std::ptr;
-use std::sync::atomic::{compiler_fence, AtomicUsize, Ordering};
+// without std, only the pointers themselves and the pieces that need nothing
+// but an allocator are built. tests always get std
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "nightly", feature(coerce_unsized, unsize))]
+
+extern crate alloc;
+
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::borrow::Borrow;
+use core::mem::{self, MaybeUninit};
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+use primitives::{fence, AtomicProvenance, AtomicUsize};
+
+mod align;
+pub mod allocator;
+pub mod atomic;
+pub mod borrow;
+pub mod brand;
+pub mod cancel;
+pub mod cast;
+mod cmp;
+#[cfg(feature = "std")]
+pub mod collections;
+mod contention;
+#[cfg(feature = "track-leaks")]
+pub mod debug;
+#[cfg(feature = "std")]
+mod defer;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+pub mod embed;
+pub mod entropy;
+pub mod erased;
+mod events;
+pub mod fallible;
+#[cfg(all(feature = "ffi", not(feature = "provenance-128")))]
+pub mod ffi;
+#[cfg(feature = "std")]
+mod finalize;
+mod fmt;
+#[cfg(feature = "std")]
+pub mod forward;
+#[cfg(feature = "async")]
+pub mod future;
+#[cfg(feature = "std")]
+pub mod graph;
+pub mod handle;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "std")]
+pub mod io;
+#[cfg(feature = "std")]
+pub mod lease;
+pub mod lite;
+mod live;
+#[cfg(feature = "std")]
+pub mod lock;
+#[cfg(feature = "mlua")]
+pub mod lua;
+pub mod offset;
+#[cfg(feature = "rayon")]
+mod par;
+#[cfg(feature = "std")]
+mod park;
+// without std nothing can wait, so there's nobody to wake
+#[cfg(not(feature = "std"))]
+mod park {
+    pub(crate) fn notify(_addr: usize) {}
+}
+pub mod pin;
+pub mod pool;
+mod primitives;
+#[cfg(feature = "profiling")]
+pub mod profile;
+pub mod project;
+#[cfg(feature = "prometheus")]
+pub mod prometheus;
+pub mod provenance;
+#[cfg(feature = "std")]
+pub mod quarantine;
+pub mod raw;
+pub mod rc;
+#[cfg(feature = "std")]
+pub mod registry;
+pub mod ring;
+#[cfg(feature = "std")]
+pub mod rt;
+pub mod scope;
+#[cfg(feature = "serde")]
+mod serialize;
+#[cfg(feature = "std")]
+pub mod shared;
+pub mod slice;
+#[cfg(not(loom))]
+pub mod statics;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "std")]
+pub mod subscribers;
+#[cfg(feature = "std")]
+pub mod sweep;
+pub mod sync;
+pub mod tag;
+#[cfg(feature = "serde")]
+pub mod tagged;
+pub mod task;
+pub mod thin;
+#[cfg(feature = "std")]
+pub mod tracker;
+mod uninit;
+pub mod union;
+pub mod unique;
+pub mod upgrade;
+#[cfg(feature = "std")]
+pub mod veto;
+#[cfg(feature = "std")]
+mod wait;
+#[cfg(feature = "async")]
+pub mod weak_mutex;
+#[cfg(feature = "std")]
+pub mod weak_self;
+mod wipe;
+
+use tag::untagged;
+
+#[cfg(feature = "derive")]
+pub use provenant_derive::ArcProject;
+#[cfg(feature = "stats")]
+pub use stats::{stats, Stats};
+
+// lets derived code name this crate as `::provenant` in its own tests
+#[cfg(all(test, feature = "derive"))]
+extern crate self as provenant;
 
 /// An atomically reference counted shared pointer
 ///
@@ -11,166 +143,832 @@ pub struct Arc<T: ?Sized> {
     ptr: *const Inner<T>,
 }
 
+/// A provenance id, which tells an allocation apart from others that have been at
+/// the same address.
+///
+/// It's a `usize`, except with the `wide-provenance` feature on targets where
+/// that's 32 bits, where it's a `u64`, so that ids are as unlikely to collide
+/// there as anywhere else, and with `provenance-128`, where it's a `u128`.
+#[cfg(not(any(
+    feature = "provenance-128",
+    all(feature = "wide-provenance", target_pointer_width = "32")
+)))]
+pub type Provenance = usize;
+/// A provenance id, which tells an allocation apart from others that have been at
+/// the same address.
+///
+/// It's a `u64` here, because of the `wide-provenance` feature, so that ids are as
+/// unlikely to collide on this 32-bit target as anywhere else. That makes weak
+/// pointers and allocation headers 4 bytes bigger.
+#[cfg(all(
+    not(feature = "provenance-128"),
+    feature = "wide-provenance",
+    target_pointer_width = "32"
+))]
+pub type Provenance = u64;
+/// A provenance id, which tells an allocation apart from others that have been at
+/// the same address.
+///
+/// It's a `u128` here, because of the `provenance-128` feature, for when a weak
+/// pointer upgrading to the wrong value would be a security problem rather than
+/// a bug. A stale weak pointer then only upgrades by matching all 127 random bits.
+/// Allocation headers and weak pointers get bigger, and the second half of the id
+/// is checked while holding the provenance lock, which makes upgrading a little
+/// slower.
+#[cfg(feature = "provenance-128")]
+pub type Provenance = u128;
+
 /// A weak pointer to an atomically reference counted shared pointer
 ///
 /// Can be upgraded to an [`Arc`], and will usually do the right thing.
 /// Does not prevent the pointed-to memory from being dropped or deallocated.
-#[derive(Copy, Clone)]
 pub struct Weak<T: ?Sized> {
-    provenance: usize,
+    provenance: Provenance,
     ptr: *const Inner<T>,
 }
 
+// like std's: the value is shared between threads, and dropped by whichever
+// thread lets go of it last, so it needs to be both Send and Sync
+unsafe impl<T: ?Sized + Send + Sync> Send for Arc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Arc<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Send for Weak<T> {}
+unsafe impl<T: ?Sized + Send + Sync> Sync for Weak<T> {}
+
+// derived impls would require T: Clone, but a Weak is just a pointer and an id
+impl<T: ?Sized> Copy for Weak<T> {}
+
+impl<T: ?Sized> Clone for Weak<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+// repr(C) keeps the header in the same place whatever T is, which weak-registry
+// relies on when a weak pointer looks at a newer allocation at its old address
+//
+// memory orderings, which follow std's Arc where they can:
+//
+// - the provenance lock is a spinlock like any other. taking it is Acquire and
+//   releasing it is Release, so whoever takes it next sees everything done under it.
+//   that's how the last drop sees an upgrade's increment, and vice versa
+// - cloning only needs the count to go up, not to see anything, so it's Relaxed.
+//   so is an upgrade's increment, which the lock already orders
+// - dropping decrements with Release, so the value's last uses come before the
+//   count can reach 0. the thread that frees the value makes an Acquire fence first,
+//   so it sees them
+// - checks that the count is 1 before taking the value load it with Acquire, for
+//   the same reason
+// - reading the provenance to guess what to lock is Relaxed. a stale guess only
+//   makes the lock fail, same as a rekey racing with it would
+#[cfg_attr(feature = "weak-registry", repr(C))]
 struct Inner<T: ?Sized> {
     // the low bit is used to locking, the rest are random provenance id
-    provenance: AtomicUsize,
+    provenance: AtomicProvenance,
 
     // reference count of Arcs. Weak refs are uncounted
     ref_count: AtomicUsize,
 
+    // how many weak pointers have been made. copies and drops aren't seen, so it
+    // only goes up
+    #[cfg(feature = "weak-count")]
+    weak_count: AtomicUsize,
+
+    // gives the memory back once the last Arc is gone and data has been dropped.
+    // it can't mention T, or Inner<T> couldn't be unsized
+    release: Release,
+
     data: T,
 }
 
+// tags live in the low bits of the pointer, and raw and ffi handles assume the
+// header is at least word aligned. with wide-provenance on 32-bit targets the
+// provenance is wider than a word, so the check is against the narrowest field
+const _: () = assert!(core::mem::align_of::<Inner<()>>() >= core::mem::align_of::<usize>());
+
+type Release = unsafe fn(*mut u8, Layout);
+
+// the release for Inners allocated with Box
+#[cfg(not(loom))]
+unsafe fn release_box(ptr: *mut u8, layout: Layout) {
+    #[cfg(feature = "std")]
+    if quarantine::hold(ptr, layout) {
+        return;
+    }
+    alloc::alloc::dealloc(ptr, layout);
+}
+
+// loom can't follow a weak pointer's reads into freed memory, so keep it around.
+// the provenance has still been zeroed, which is what upgrades race against
+#[cfg(loom)]
+unsafe fn release_box(_ptr: *mut u8, _layout: Layout) {}
+
+// never 0, which is what freed memory and Weak::new have
+fn random_provenance() -> Provenance {
+    loop {
+        let provenance = entropy::widen(entropy::next);
+        let provenance = provenance ^ (provenance & 1);
+        if provenance != 0 {
+            return provenance;
+        }
+    }
+}
+
+// fills in the header of an Inner allocated by hand with the global allocator,
+// for a new Arc. the data is up to the caller
+unsafe fn write_header<T: ?Sized>(inner: *mut Inner<T>) {
+    ptr::addr_of_mut!((*inner).provenance).write(AtomicProvenance::new(random_provenance()));
+    ptr::addr_of_mut!((*inner).ref_count).write(AtomicUsize::new(1));
+    #[cfg(feature = "weak-count")]
+    ptr::addr_of_mut!((*inner).weak_count).write(AtomicUsize::new(0));
+    ptr::addr_of_mut!((*inner).release).write(release_box);
+}
+
 impl<T: ?Sized> Drop for Inner<T> {
     fn drop(&mut self) {
-        // using a volatile write followed by a fence should actually zero the memory
-        // and not get optimized out
-        unsafe {
-            ptr::write_volatile(&mut self.provenance, AtomicUsize::default());
-        }
+        // a weak pointer can be loading this concurrently, so it has to be an atomic
+        // store, not a plain write. followed by a fence, it should actually zero the
+        // memory and not get optimized out
+        self.provenance.store(0, Ordering::Relaxed);
         compiler_fence(Ordering::SeqCst);
     }
 }
 
+impl<T> Inner<T> {
+    fn new(data: T, provenance: Provenance, release: Release) -> Self {
+        Inner {
+            provenance: AtomicProvenance::new(provenance),
+            ref_count: AtomicUsize::new(1),
+            #[cfg(feature = "weak-count")]
+            weak_count: AtomicUsize::new(0),
+            release,
+            data,
+        }
+    }
+}
+
 impl<T: ?Sized> Inner<T> {
     fn weak(&self) -> Weak<T> {
         let provenance = self.provenance.load(Ordering::Relaxed);
         let provenance = provenance ^ (provenance & 1); //clear low bit
+        #[cfg(feature = "weak-count")]
+        self.weak_count.fetch_add(1, Ordering::Relaxed);
+        events::downgraded(self, provenance);
         Weak {
-            provenance: provenance,
+            provenance,
             ptr: self as *const Inner<T>,
         }
     }
 
-    fn lock(&self, exp: usize) -> bool {
+    // gives the allocation a new provenance, so every existing weak pointer stops upgrading.
+    // if a thread dropping the last Arc read the old provenance before this, it'll
+    // think someone else freed the allocation and leak it, so callers should hold
+    // a strong reference until every other one is gone
+    fn rekey(&self) {
         loop {
-            match self
-                .provenance
-                .compare_exchange(exp, exp | 1, Ordering::SeqCst, Ordering::SeqCst)
-            {
-                Ok(_) => return true,
-                Err(v) if v == exp | 1 => continue,
-                Err(_) => return false,
+            let exp = self.provenance.load(Ordering::Relaxed);
+            let exp = exp ^ (exp & 1);
+            if self.lock(exp) {
+                self.unlock(random_provenance());
+                events::rekeyed(self);
+                return;
+            }
+        }
+    }
+
+    // takes the lock if the provenance is `exp`, waiting for as long as it's held
+    fn lock(&self, exp: Provenance) -> bool {
+        loop {
+            if let Some(locked) = self.lock_within(exp, u32::MAX) {
+                return locked;
+            }
+        }
+    }
+
+    // like lock, but gives up and returns None if it's still held after `attempts` tries
+    fn lock_within(&self, exp: Provenance, attempts: u32) -> Option<bool> {
+        for attempt in 0..attempts {
+            match self.provenance.compare_exchange(
+                exp,
+                exp | 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(true),
+                Err(v) if v == exp | 1 => {
+                    events::contended();
+                    contention::wait(self.addr(), attempt, || {
+                        self.provenance.load(Ordering::Relaxed) == exp | 1
+                    });
+                }
+                Err(_) => return Some(false),
             }
         }
+        None
+    }
+
+    // releases the lock, leaving `provenance` behind
+    fn unlock(&self, provenance: Provenance) {
+        self.provenance.store(provenance, Ordering::Release);
+        contention::released(self.addr());
+    }
+
+    fn addr(&self) -> usize {
+        self as *const Inner<T> as *const u8 as usize
+    }
+}
+
+impl<T> Weak<T> {
+    /// Creates a weak pointer that never upgrades, without allocating anything
+    pub const fn new() -> Self {
+        Weak {
+            provenance: 0,
+            ptr: ptr::NonNull::dangling().as_ptr(),
+        }
+    }
+}
+
+impl<T> Default for Weak<T> {
+    fn default() -> Self {
+        Weak::new()
     }
 }
 
 impl<T: ?Sized> Weak<T> {
     /// Attempts to get a strong reference to the pointed-to memory. Will probably fail and return None
     /// if there are no strong pointers left.
+    #[track_caller]
     pub fn upgrade(&self) -> Option<Arc<T>> {
+        self.upgrade_with(|weak| Some(weak.lock()))
+    }
+
+    /// Like [`Weak::upgrade`], but gives up and returns None if another thread still
+    /// holds the provenance lock after `attempts` tries, instead of spinning until
+    /// it's released
+    #[track_caller]
+    pub fn try_upgrade_spin(&self, attempts: u32) -> Option<Arc<T>> {
+        self.upgrade_with(|weak| weak.lock_within(attempts))
+    }
+
+    /// How many `Arc`s point at the value, or 0 if it's been dropped.
+    ///
+    /// Like [`Arc::strong_count`], other threads can change it at any moment.
+    pub fn strong_count(&self) -> usize {
+        self.load_count(|inner| &inner.ref_count)
+    }
+
+    /// Whether the value still looks alive, from a single load of its provenance id,
+    /// without taking the lock or touching the count.
+    ///
+    /// Cheaper than upgrading, for deciding what to prune in a sweep over many weak
+    /// pointers. It can go stale as soon as it returns, and is as likely as an
+    /// upgrade to be fooled by reused memory, but once it's false it stays false.
+    pub fn is_probably_alive(&self) -> bool {
+        live::if_live(self.addr(), self.provenance, || {
+            let inner = unsafe { &(*untagged(self.ptr)) };
+            let provenance = inner.provenance.load(Ordering::Relaxed);
+            provenance ^ (provenance & 1) == self.provenance
+        })
+        .unwrap_or(false)
+    }
+
+    /// How many weak pointers have been made to the value, or 0 if it's been
+    /// dropped. See [`Arc::weak_count`]
+    #[cfg(feature = "weak-count")]
+    pub fn weak_count(&self) -> usize {
+        self.load_count(|inner| &inner.weak_count)
+    }
+
+    // reads one of the target's counters, or returns 0 if it isn't the target anymore
+    fn load_count(&self, count: impl FnOnce(&Inner<T>) -> &AtomicUsize) -> usize {
         let exp = self.provenance;
+        live::if_live(self.addr(), exp, || {
+            let inner = unsafe { &(*untagged(self.ptr)) };
+            let matches = || {
+                let provenance = inner.provenance.load(Ordering::Relaxed);
+                provenance ^ (provenance & 1) == exp
+            };
+            if !matches() {
+                return 0;
+            }
+            // the acquire keeps the second check after the count is read, in case
+            // the memory was freed and reused in between
+            let count = count(inner).load(Ordering::Acquire);
+            if matches() {
+                count
+            } else {
+                0
+            }
+        })
+        .unwrap_or(0)
+    }
 
-        let inner = unsafe { &(*self.ptr) };
+    // one try at the target's provenance lock, touching it only while it's live.
+    // holding the lock pins the allocation, so nobody waits on it while the
+    // weak-registry table is held. Err has the provenance found instead, which is 0
+    // if the target isn't live
+    fn try_lock(&self) -> Result<Provenance, Provenance> {
+        let exp = self.provenance;
+        live::if_live(self.addr(), exp, || {
+            let inner = unsafe { &(*untagged(self.ptr)) };
+            inner
+                .provenance
+                .compare_exchange(exp, exp | 1, Ordering::Acquire, Ordering::Relaxed)
+        })
+        .unwrap_or(Err(0))
+    }
 
-        if !inner.lock(exp) {
-            return None;
+    // whether someone holds the target's provenance lock, as try_lock would find it
+    fn is_locked(&self) -> bool {
+        let exp = self.provenance;
+        live::if_live(self.addr(), exp, || {
+            let inner = unsafe { &(*untagged(self.ptr)) };
+            inner.provenance.load(Ordering::Relaxed) == exp | 1
+        })
+        .unwrap_or(false)
+    }
+
+    // like Inner::lock_within, but through try_lock, so it waits between tries
+    // without touching the target
+    fn lock_within(&self, attempts: u32) -> Option<Result<Provenance, Provenance>> {
+        let exp = self.provenance;
+        for attempt in 0..attempts {
+            match self.try_lock() {
+                Err(current) if current == exp | 1 => {
+                    events::contended();
+                    contention::wait(self.addr(), attempt, || self.is_locked());
+                }
+                locked => return Some(locked),
+            }
+        }
+        None
+    }
+
+    // like Inner::lock, through try_lock
+    fn lock(&self) -> Result<Provenance, Provenance> {
+        loop {
+            if let Some(locked) = self.lock_within(u32::MAX) {
+                return locked;
+            }
         }
+    }
+
+    // upgrades once `lock` takes the lock, which returns None if it gave up waiting
+    #[track_caller]
+    fn upgrade_with(
+        &self,
+        lock: impl FnOnce(&Self) -> Option<Result<Provenance, Provenance>>,
+    ) -> Option<Arc<T>> {
+        let exp = self.provenance;
+
+        match lock(self) {
+            Some(Ok(_)) => {}
+            None => {
+                events::upgraded(false);
+                return None;
+            }
+            Some(Err(current)) => {
+                if current != 0 {
+                    events::mismatched();
+                }
+                events::upgraded(false);
+                events::upgrade_failed(exp);
+                return None;
+            }
+        }
+        events::upgraded(true);
+
+        // holding the lock, so it can't be freed now
+        let inner = unsafe { &(*untagged(self.ptr)) };
 
         // increment ref count
-        inner.ref_count.fetch_add(1, Ordering::SeqCst);
+        let count = inner.ref_count.fetch_add(1, Ordering::Relaxed) + 1;
 
         // release the lock
-        inner.provenance.store(exp, Ordering::SeqCst);
+        inner.unlock(exp);
+
+        let arc = Arc { ptr: self.ptr };
+        events::matched(inner, exp, count);
+        Some(arc)
+    }
 
-        Some(Arc { ptr: self.ptr })
+    // the address of the allocation, ignoring tags
+    fn addr(&self) -> usize {
+        untagged(self.ptr) as *const u8 as usize
     }
 }
 
-impl<T: ?Sized> Drop for Arc<T> {
-    fn drop(&mut self) {
-        {
-            let inner = unsafe { &(*self.ptr) };
+impl<T: ?Sized> Arc<T> {
+    // gives up this strong reference. returns true if it was the last one, in which
+    // case the provenance has been zeroed and the caller has to free the allocation
+    fn release_ref(&self) -> bool {
+        let inner = self.inner();
 
-            // we need to load provenance before decrementing ref count.
-            // otherwise, another thread could deallocate before the load happens
-            let exp = inner.provenance.load(Ordering::SeqCst);
-            let exp = exp ^ (exp & 1);
+        // we need to load provenance before decrementing ref count.
+        // otherwise, another thread could deallocate before the load happens
+        let exp = inner.provenance.load(Ordering::Relaxed);
+        let exp = exp ^ (exp & 1);
 
-            if inner.ref_count.fetch_sub(1, Ordering::SeqCst) > 1 {
-                return;
+        let prev = inner.ref_count.fetch_sub(1, Ordering::Release);
+        if prev > 1 {
+            // another thread may free it any moment now, so not through inner
+            events::dropped(untagged(self.ptr), prev - 1);
+            if prev == 2 {
+                // someone might be waiting to become unique
+                park::notify(self.addr());
             }
+            return false;
+        }
 
-            // if the lock fails, another thread must have dropped Inner already
-            // that can happen if this gets interrupted while a weak pointer
-            // upgrades and then drops (hitting 0 again)
-            if !inner.lock(exp) {
-                return;
-            }
+        // if the lock fails, another thread must have dropped Inner already
+        // that can happen if this gets interrupted while a weak pointer
+        // upgrades and then drops (hitting 0 again)
+        if !inner.lock(exp) {
+            return false;
+        }
 
-            // if the ref count isn't 0, a weak pointer managed to upgrade.
-            // it can deal with deallocating when it hits 0 again.
-            if inner.ref_count.load(Ordering::SeqCst) != 0 {
-                inner.provenance.store(exp, Ordering::SeqCst);
-                return;
-            }
+        // if the ref count isn't 0, a weak pointer managed to upgrade.
+        // it can deal with deallocating when it hits 0 again.
+        if inner.ref_count.load(Ordering::Relaxed) != 0 {
+            inner.unlock(exp);
+            return false;
+        }
+
+        // setting provenance to 0 isn't strictly necessary here, since Inner::drop does it
+        inner.unlock(0);
+
+        // see every other Arc's last use of the value before dropping it
+        fence(Ordering::Acquire);
+        true
+    }
+
+    // if this is the only strong reference, replaces the provenance with `provenance`
+    // so that no existing weak pointer can upgrade again. returns whether it was
+    fn claim_unique(&self, provenance: Provenance) -> bool {
+        let inner = self.inner();
+        let exp = inner.provenance.load(Ordering::Relaxed);
+        let exp = exp ^ (exp & 1);
+        if !inner.lock(exp) {
+            return false;
+        }
+
+        // nothing can upgrade while the lock is held, so a count of 1 stays 1
+        if inner.ref_count.load(Ordering::Acquire) != 1 {
+            inner.unlock(exp);
+            return false;
+        }
+        inner.unlock(provenance);
+        true
+    }
+
+    /// Gets a mutable reference to the value, if this is the only strong reference.
+    ///
+    /// Weak pointers aren't counted, so there's no telling whether any exist. To
+    /// make sure none of them can upgrade while the value is being mutated, this
+    /// gives the allocation a new provenance id, and existing weak pointers stop
+    /// upgrading for good, as if the value had been dropped.
+    pub fn get_mut(this: &mut Self) -> Option<&mut T> {
+        if !this.claim_unique(random_provenance()) {
+            return None;
+        }
+        events::rekeyed(this.inner());
+        Some(unsafe { &mut (*(untagged(this.ptr) as *mut Inner<T>)).data })
+    }
+}
 
-            // setting provenance to 0 isn't strictly necessary here, since Inner::drop does it
-            inner.provenance.store(0, Ordering::SeqCst);
+impl<T: ?Sized> Drop for Arc<T> {
+    fn drop(&mut self) {
+        if !self.release_ref() {
+            return;
         }
 
         unsafe {
-            Box::from_raw(self.ptr as *mut Inner<T>);
+            let ptr = untagged(self.ptr);
+            let layout = Layout::for_value(&*ptr);
+            let release = (*ptr).release;
+
+            events::freed(ptr);
+
+            ptr::drop_in_place(ptr as *mut Inner<T>);
+            release(ptr as *mut u8, layout);
         }
+
+        // wake anything waiting for the allocation to die
+        park::notify(self.addr());
     }
 }
 
 impl<T> Arc<T> {
     /// Create a new shared reference
     pub fn new(val: T) -> Self {
-        let mut rng = rand::thread_rng();
-        let provenance: usize = rng.gen();
-        let provenance = provenance ^ (provenance & 1);
-        let inner = Box::new(Inner {
-            provenance: AtomicUsize::new(provenance),
-            ref_count: AtomicUsize::new(1),
-            data: val,
-        });
+        let inner = Box::new(Inner::new(val, random_provenance(), release_box));
 
         let inner = Box::into_raw(inner) as *const Inner<T>;
+        events::allocated(inner);
+
         Arc { ptr: inner }
     }
+
+    /// Creates a new shared reference to a value that's given a weak pointer to itself.
+    ///
+    /// The weak pointer already has its final provenance id, so copies of it stored
+    /// in the value upgrade once this returns. Until then, upgrading it fails. If
+    /// `data_fn` panics, the memory is freed and the weak pointer stays dead.
+    pub fn new_cyclic<F: FnOnce(&Weak<T>) -> T>(data_fn: F) -> Self {
+        let reserved = Reserved::new();
+        let data = data_fn(&reserved.weak());
+        reserved.init(data)
+    }
+}
+
+impl<T> Arc<T> {
+    /// Moves the value into a Box, if this is the only strong reference.
+    /// Otherwise returns the Arc unchanged.
+    ///
+    /// Unlike moving the value out, this copies it straight from one allocation to
+    /// the other, so large values don't pass through the stack. Weak pointers stop
+    /// upgrading.
+    pub fn into_box(this: Self) -> Result<Box<T>, Self> {
+        // allocate first, since the lock shouldn't be held across an allocator abort
+        let mut boxed = Box::<T>::new_uninit();
+        if !this.claim_unique(0) {
+            return Err(this);
+        }
+
+        unsafe {
+            Arc::take_with(this, |data| {
+                ptr::copy_nonoverlapping(data, boxed.as_mut_ptr(), 1);
+            });
+            Ok(boxed.assume_init())
+        }
+    }
+
+    /// Returns the value, if this is the only strong reference.
+    /// Otherwise returns the Arc unchanged.
+    ///
+    /// Weak pointers stop upgrading, and can't upgrade while the value is moved out.
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        if !this.claim_unique(0) {
+            return Err(this);
+        }
+        Ok(unsafe { Arc::take_with(this, |data| ptr::read(data)) })
+    }
+
+    /// Returns the value if this is the only strong reference, and otherwise a clone
+    /// of it, dropping this reference.
+    ///
+    /// Like [`Arc::try_unwrap`], weak pointers stop upgrading once the value is
+    /// moved out, and can't upgrade while it's happening.
+    pub fn unwrap_or_clone(this: Self) -> T
+    where
+        T: Clone,
+    {
+        Arc::try_unwrap(this).unwrap_or_else(|arc| (*arc).clone())
+    }
+
+    /// Returns the value if this is the last strong reference, and drops this
+    /// reference either way.
+    ///
+    /// Unlike [`Arc::try_unwrap`], when several threads call this on the last few
+    /// references at the same time, exactly one of them gets the value. If a weak
+    /// pointer upgrades in the meantime, nobody does, and the value lives on.
+    pub fn into_inner(this: Self) -> Option<T> {
+        if !this.release_ref() {
+            mem::forget(this);
+            return None;
+        }
+        Some(unsafe { Arc::take_with(this, |data| ptr::read(data)) })
+    }
+
+    /// Gets a mutable reference to the value, cloning it into a new allocation first
+    /// if other strong references exist.
+    ///
+    /// Either way, existing weak pointers stop upgrading to this Arc: they're left
+    /// pointing at the old allocation, or detached like with [`Arc::get_mut`].
+    pub fn make_mut(this: &mut Self) -> &mut T
+    where
+        T: Clone,
+    {
+        if this.claim_unique(random_provenance()) {
+            events::rekeyed(this.inner());
+        } else {
+            // a new allocation nothing else can point to
+            *this = Arc::with_tag(Arc::new((**this).clone()), Arc::tag(this));
+        }
+        unsafe { &mut (*(untagged(this.ptr) as *mut Inner<T>)).data }
+    }
+
+    // moves the value out with `take`, then frees the allocation without dropping it.
+    // the provenance has to have been zeroed already
+    unsafe fn take_with<R>(this: Self, take: impl FnOnce(*const T) -> R) -> R {
+        let addr = this.addr();
+        let ptr = untagged(this.ptr) as *mut Inner<T>;
+        mem::forget(this);
+
+        // before taking, so a finalizer sees the value
+        events::freed(ptr);
+        let taken = take(ptr::addr_of!((*ptr).data));
+
+        let layout = Layout::for_value(&*ptr);
+        let release = (*ptr).release;
+        release(ptr as *mut u8, layout);
+        park::notify(addr);
+        taken
+    }
+}
+
+/// Moves the value out of the Box into a new allocation, which works for unsized
+/// values like `Box<dyn Trait>` and `Box<[T]>`, too
+impl<T: ?Sized> From<Box<T>> for Arc<T> {
+    fn from(boxed: Box<T>) -> Self {
+        let value = Layout::for_value(&*boxed);
+        let (layout, offset) = Layout::new::<Inner<()>>()
+            .extend(value)
+            .expect("allocation too large");
+        let layout = layout.pad_to_align();
+
+        unsafe {
+            let base = alloc::alloc::alloc(layout);
+            if base.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+            let data = Box::into_raw(boxed);
+            // the value's metadata, but base's address and provenance. the address is
+            // the first word of a pointer, like std's Rc used to rely on
+            let mut inner = data as *mut Inner<T>;
+            ptr::write(&mut inner as *mut *mut Inner<T> as *mut *mut u8, base);
+            debug_assert_eq!(base.add(offset), ptr::addr_of!((*inner).data) as *mut u8);
+
+            ptr::copy_nonoverlapping(data as *const u8, base.add(offset), value.size());
+            write_header(inner);
+            // the value has moved, so the Box's memory is freed without dropping it
+            if value.size() != 0 {
+                alloc::alloc::dealloc(data as *mut u8, value);
+            }
+
+            events::allocated(inner);
+            Arc { ptr: inner }
+        }
+    }
+}
+
+// an allocation for an Arc whose value isn't there yet.
+// weak pointers to it already have their final provenance, but fail to upgrade
+// until init, since the provenance stays 0 until then.
+// dropping it without calling init frees the memory
+pub(crate) struct Reserved<T> {
+    ptr: *mut Inner<MaybeUninit<T>>,
+    provenance: Provenance,
+}
+
+impl<T> Reserved<T> {
+    pub(crate) fn new() -> Self {
+        let uninit = Box::new(Inner::new(MaybeUninit::<T>::uninit(), 0, release_box));
+        Reserved {
+            ptr: Box::into_raw(uninit),
+            provenance: random_provenance(),
+        }
+    }
+
+    pub(crate) fn weak(&self) -> Weak<T> {
+        #[cfg(feature = "weak-count")]
+        unsafe {
+            (*self.ptr).weak_count.fetch_add(1, Ordering::Relaxed);
+        }
+        Weak {
+            provenance: self.provenance,
+            ptr: self.ptr as *const Inner<T>,
+        }
+    }
+
+    pub(crate) fn init(self, data: T) -> Arc<T> {
+        let ptr = self.ptr as *mut Inner<T>;
+        let provenance = self.provenance;
+        mem::forget(self);
+
+        unsafe {
+            ptr::addr_of_mut!((*ptr).data).write(data);
+            (*ptr).provenance.store(provenance, Ordering::Release);
+        }
+
+        events::allocated(ptr);
+        Arc { ptr }
+    }
+}
+
+impl<T> Drop for Reserved<T> {
+    fn drop(&mut self) {
+        // MaybeUninit means data isn't dropped
+        unsafe { drop(Box::from_raw(self.ptr)) };
+    }
 }
 
 impl<T: ?Sized> Arc<T> {
     /// Gets a weak reference to the same memory
     pub fn downgrade(this: &Self) -> Weak<T> {
-        let inner = unsafe { &(*this.ptr) };
+        // this.ptr has the tag, and unlike a pointer made from a reference to the
+        // Inner, it's allowed to reach the whole allocation
+        Weak {
+            ptr: this.ptr,
+            ..this.inner().weak()
+        }
+    }
+
+    /// How many `Arc`s point at the value.
+    ///
+    /// Other threads can clone or drop theirs at any moment, so by the time this
+    /// returns the count may be out of date. Only a count of 1 can be relied on, and
+    /// only while no weak pointer can upgrade.
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().ref_count.load(Ordering::Relaxed)
+    }
+
+    /// How many weak pointers have been made to the value, with [`Arc::downgrade`]
+    /// or [`Arc::new_cyclic`].
+    ///
+    /// Weak pointers are `Copy`, and dropping one does nothing, so this can't tell
+    /// how many are still around. It counts the ones made, and never goes down.
+    /// Statics count only the ones downgraded from an Arc.
+    #[cfg(feature = "weak-count")]
+    pub fn weak_count(this: &Self) -> usize {
+        this.inner().weak_count.load(Ordering::Relaxed)
+    }
 
-        inner.weak()
+    /// Turns this into a weak reference, giving up the strong one
+    pub fn into_weak(this: Self) -> Weak<T> {
+        // the provenance has to be read while this still counts towards the ref count.
+        // afterwards, the allocation could be freed and reused
+        let weak = Arc::downgrade(&this);
+        drop(this);
+        weak
+    }
+
+    fn inner(&self) -> &Inner<T> {
+        unsafe { &(*untagged(self.ptr)) }
+    }
+
+    // the address of the allocation, ignoring tags
+    fn addr(&self) -> usize {
+        untagged(self.ptr) as *const u8 as usize
+    }
+}
+
+impl<T: ?Sized> From<Arc<T>> for Weak<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Arc::into_weak(arc)
     }
 }
 
 impl<T: ?Sized> Deref for Arc<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
-        let inner = unsafe { &(*self.ptr) };
+        let inner = self.inner();
 
         &inner.data
     }
 }
 
 impl<T: ?Sized> Clone for Arc<T> {
+    #[cfg_attr(feature = "history", track_caller)]
     fn clone(&self) -> Self {
-        let inner = unsafe { &(*self.ptr) };
+        let inner = self.inner();
 
-        inner.ref_count.fetch_add(1, Ordering::SeqCst);
+        let count = inner.ref_count.fetch_add(1, Ordering::Relaxed) + 1;
+        events::cloned(inner, count);
 
         Arc { ptr: self.ptr }
     }
 }
 
+impl<T: ?Sized> Borrow<T> for Arc<T> {
+    fn borrow(&self) -> &T {
+        self
+    }
+}
+
+impl<T: ?Sized> AsRef<T> for Arc<T> {
+    fn as_ref(&self) -> &T {
+        self
+    }
+}
+
+impl<T: Default> Default for Arc<T> {
+    fn default() -> Self {
+        Arc::new(T::default())
+    }
+}
+
+impl<T> From<T> for Arc<T> {
+    fn from(val: T) -> Self {
+        Arc::new(val)
+    }
+}
+
+// the value stays where it is until the last Arc, and clones point at it too
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<T: ?Sized> stable_deref_trait::StableDeref for Arc<T> {}
+#[cfg(feature = "stable_deref_trait")]
+unsafe impl<T: ?Sized> stable_deref_trait::CloneStableDeref for Arc<T> {}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -216,4 +1014,238 @@ mod tests {
 
         assert!(weak.upgrade().is_none());
     }
+
+    #[test]
+    fn bounded_spin() {
+        let arc = Arc::new(4);
+        let weak = Arc::downgrade(&arc);
+
+        // hold the lock the way a concurrent upgrade would
+        assert!(arc.inner().lock(weak.provenance));
+        assert!(weak.try_upgrade_spin(3).is_none());
+        arc.inner().unlock(weak.provenance);
+
+        assert_eq!(4, *weak.try_upgrade_spin(3).unwrap());
+        drop(arc);
+        assert!(weak.try_upgrade_spin(3).is_none());
+    }
+
+    #[test]
+    fn try_unwrap() {
+        let arc = Arc::new(String::from("owned"));
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+
+        let arc = Arc::try_unwrap(arc).unwrap_err();
+        drop(other);
+        assert_eq!("owned", Arc::try_unwrap(arc).ok().unwrap());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn unwrap_or_clone() {
+        let arc = Arc::new(String::from("cow"));
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+
+        assert_eq!("cow", Arc::unwrap_or_clone(arc));
+        assert_eq!(1, weak.strong_count());
+        assert_eq!("cow", Arc::unwrap_or_clone(other));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn into_inner_once() {
+        for _ in 0..100 {
+            let arc = Arc::new(String::from("once"));
+            let other = arc.clone();
+
+            let racer = std::thread::spawn(move || Arc::into_inner(other));
+            let mine = Arc::into_inner(arc);
+            let theirs = racer.join().unwrap();
+            assert_eq!(1, mine.iter().chain(theirs.iter()).count());
+        }
+    }
+
+    #[test]
+    fn dangling() {
+        #[derive(Default)]
+        struct Parent {
+            child: Weak<Parent>,
+        }
+
+        let parent = Parent::default();
+        assert!(parent.child.upgrade().is_none());
+        assert_eq!(0, parent.child.strong_count());
+        assert_ne!(parent.child, Arc::downgrade(&Arc::new(Parent::default())));
+    }
+
+    #[test]
+    fn strong_count() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+        assert_eq!(2, Arc::strong_count(&arc));
+        assert_eq!(2, weak.strong_count());
+
+        drop(other);
+        assert_eq!(1, weak.strong_count());
+        drop(arc);
+        assert_eq!(0, weak.strong_count());
+    }
+
+    #[test]
+    fn probably_alive() {
+        let mut arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        assert!(weak.is_probably_alive());
+
+        // a rekey kills it just as a drop would
+        *Arc::get_mut(&mut arc).unwrap() = 2;
+        assert!(!weak.is_probably_alive());
+        assert!(!Weak::<i32>::new().is_probably_alive());
+    }
+
+    #[cfg(feature = "weak-count")]
+    #[test]
+    fn weak_count() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let _copy = weak;
+        assert_eq!(1, Arc::weak_count(&arc));
+
+        let _other = Arc::downgrade(&arc.clone());
+        assert_eq!(2, weak.weak_count());
+        drop(arc);
+        assert_eq!(0, weak.weak_count());
+    }
+
+    #[test]
+    fn new_cyclic() {
+        struct Node {
+            me: Weak<Node>,
+            value: i32,
+        }
+
+        let arc = Arc::new_cyclic(|me| {
+            assert!(me.upgrade().is_none());
+            Node { me: *me, value: 3 }
+        });
+        assert_eq!(3, arc.me.upgrade().unwrap().value);
+        assert_eq!(Arc::downgrade(&arc).provenance(), arc.me.provenance());
+    }
+
+    #[test]
+    fn get_mut() {
+        let mut arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+        assert!(Arc::get_mut(&mut arc).is_none());
+
+        drop(other);
+        *Arc::get_mut(&mut arc).unwrap() += 1;
+        assert_eq!(2, *arc);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(2, *Arc::downgrade(&arc).upgrade().unwrap());
+    }
+
+    #[test]
+    fn make_mut() {
+        let mut arc = Arc::new(String::from("shared"));
+        let other = arc.clone();
+        Arc::make_mut(&mut arc).push_str(" once");
+        assert_eq!("shared", *other);
+        assert_eq!("shared once", *arc);
+
+        let weak = Arc::downgrade(&arc);
+        Arc::make_mut(&mut arc).push_str(", then unique");
+        assert_eq!("shared once, then unique", *arc);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn from_box() {
+        let arc: Arc<String> = Arc::from(Box::new(String::from("boxed")));
+        assert_eq!("boxed", *arc);
+
+        let slice: Arc<[u16]> = Arc::from(vec![1, 2, 3].into_boxed_slice());
+        assert_eq!([1, 2, 3], *slice);
+
+        let debug: Box<dyn core::fmt::Debug> = Box::new(Some(4u64));
+        let debug: Arc<dyn core::fmt::Debug> = Arc::from(debug);
+        assert_eq!("Some(4)", format!("{:?}", &*debug));
+    }
+
+    #[test]
+    fn conversions() {
+        let mut names = std::collections::HashMap::new();
+        names.insert(Arc::<str>::from("key"), 1);
+        assert_eq!(Some(&1), names.get("key"));
+
+        let arc: Arc<Vec<u8>> = Arc::default();
+        assert!(arc.as_ref().is_empty());
+        assert_eq!(5, *Arc::from(5));
+    }
+
+    #[cfg(feature = "stable_deref_trait")]
+    #[test]
+    fn stable_deref() {
+        fn stable<T: stable_deref_trait::CloneStableDeref>(_: &T) {}
+
+        let arc: Arc<str> = Arc::from("stable");
+        stable(&arc);
+        assert!(ptr::eq(&*arc, &*arc.clone()));
+    }
+
+    #[test]
+    fn into_box() {
+        let arc = Arc::new([7u8; 4096]);
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+
+        let arc = match Arc::into_box(arc) {
+            Ok(_) => panic!("shared Arc turned into a Box"),
+            Err(arc) => arc,
+        };
+        drop(other);
+
+        let boxed = Arc::into_box(arc).ok().unwrap();
+        assert_eq!([7u8; 4096], *boxed);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn into_weak() {
+        let arc = Arc::new(8);
+        let kept = arc.clone();
+
+        let weak: Weak<i32> = arc.into();
+        assert_eq!(8, *weak.upgrade().unwrap());
+
+        assert!(Arc::into_weak(kept).upgrade().is_none());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn across_threads() {
+        fn send_sync<T: Send + Sync>(_: &T) {}
+
+        let arc = Arc::new(std::sync::Mutex::new(0));
+        let weak = Arc::downgrade(&arc);
+        send_sync(&arc);
+        send_sync(&weak);
+
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                let arc = arc.clone();
+                s.spawn(move || *arc.lock().unwrap() += 1);
+                s.spawn(|| *weak.upgrade().unwrap().lock().unwrap() += 1);
+            }
+        });
+        assert_eq!(8, *arc.lock().unwrap());
+
+        // the last Arc can be dropped on another thread
+        std::thread::spawn(move || drop(arc)).join().unwrap();
+        assert!(weak.upgrade().is_none());
+    }
 }
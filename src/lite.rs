@@ -0,0 +1,154 @@
+//! A reference counted pointer without weak pointers.
+//!
+//! [`ArcLite`] has no provenance word and never touches the RNG, so it's a word
+//! smaller and cheaper to create than [`Arc`]. When weak pointers turn out to be
+//! needed, convert it into an `Arc`.
+
+use crate::Arc;
+use alloc::boxed::Box;
+use core::fmt;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{fence, AtomicUsize, Ordering};
+
+/// An atomically reference counted shared pointer that can't be downgraded
+pub struct ArcLite<T> {
+    ptr: *const LiteInner<T>,
+}
+
+struct LiteInner<T> {
+    ref_count: AtomicUsize,
+    data: T,
+}
+
+unsafe impl<T: Send + Sync> Send for ArcLite<T> {}
+unsafe impl<T: Send + Sync> Sync for ArcLite<T> {}
+
+impl<T> ArcLite<T> {
+    /// Create a new shared reference
+    pub fn new(val: T) -> Self {
+        let inner = Box::new(LiteInner {
+            ref_count: AtomicUsize::new(1),
+            data: val,
+        });
+        ArcLite {
+            ptr: Box::into_raw(inner),
+        }
+    }
+
+    /// Returns the value if this is the only reference to it
+    pub fn try_unwrap(this: Self) -> Result<T, Self> {
+        let inner = unsafe { &(*this.ptr) };
+        if inner
+            .ref_count
+            .compare_exchange(1, 0, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            return Err(this);
+        }
+
+        let inner = unsafe { Box::from_raw(this.ptr as *mut LiteInner<T>) };
+        core::mem::forget(this);
+        Ok(inner.data)
+    }
+
+    /// Moves the value into an [`Arc`], if this is the only reference to it.
+    ///
+    /// The value has to move to a new allocation, since an `Arc`'s has room for a
+    /// provenance id and this one doesn't.
+    pub fn try_into_arc(this: Self) -> Result<Arc<T>, Self> {
+        ArcLite::try_unwrap(this).map(Arc::new)
+    }
+
+    /// Converts into an [`Arc`], moving the value if this is the only reference,
+    /// and cloning it otherwise
+    pub fn into_arc(this: Self) -> Arc<T>
+    where
+        T: Clone,
+    {
+        match ArcLite::try_into_arc(this) {
+            Ok(arc) => arc,
+            Err(shared) => Arc::new((*shared).clone()),
+        }
+    }
+
+    /// Returns true if both point to the same allocation
+    pub fn ptr_eq(a: &Self, b: &Self) -> bool {
+        ptr::eq(a.ptr, b.ptr)
+    }
+}
+
+impl<T> Deref for ArcLite<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        let inner = unsafe { &(*self.ptr) };
+
+        &inner.data
+    }
+}
+
+impl<T> Clone for ArcLite<T> {
+    fn clone(&self) -> Self {
+        let inner = unsafe { &(*self.ptr) };
+
+        inner.ref_count.fetch_add(1, Ordering::Relaxed);
+
+        ArcLite { ptr: self.ptr }
+    }
+}
+
+impl<T> Drop for ArcLite<T> {
+    fn drop(&mut self) {
+        let inner = unsafe { &(*self.ptr) };
+
+        // with no weak pointers, there's nobody to race with once this hits 0
+        if inner.ref_count.fetch_sub(1, Ordering::Release) != 1 {
+            return;
+        }
+        fence(Ordering::Acquire);
+
+        unsafe {
+            drop(Box::from_raw(self.ptr as *mut LiteInner<T>));
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArcLite<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: Clone> From<ArcLite<T>> for Arc<T> {
+    fn from(lite: ArcLite<T>) -> Self {
+        ArcLite::into_arc(lite)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::size_of;
+
+    #[test]
+    fn smaller_header() {
+        assert!(size_of::<LiteInner<u8>>() < size_of::<crate::Inner<u8>>());
+    }
+
+    #[test]
+    fn convert_when_weaks_needed() {
+        let lite = ArcLite::new(vec![1, 2]);
+        let shared = lite.clone();
+        assert!(ArcLite::ptr_eq(&lite, &shared));
+
+        let lite = ArcLite::try_into_arc(lite).err().unwrap();
+        drop(shared);
+
+        let arc = ArcLite::try_into_arc(lite).unwrap();
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(vec![1, 2], *weak.upgrade().unwrap());
+
+        let cloned: Arc<Vec<i32>> = ArcLite::new(vec![3]).clone().into();
+        assert_eq!(vec![3], *cloned);
+    }
+}
@@ -0,0 +1,106 @@
+// the `weak-registry` feature's table of live allocations.
+//
+// normally a weak pointer dereferences its target and compares provenance ids,
+// which reads freed memory once the target is gone. that's UB as far as Miri and
+// ASan are concerned, however unlikely it is to matter in practice. with the
+// feature, every allocation's address is recorded here from initialization until
+// it's freed, and weak pointers only touch their target while it's recorded.
+// the provenance check then tells it apart from a newer allocation at the same
+// address, which Inner being repr(C) makes safe to look at whatever its type.
+//
+// without the feature, nothing is recorded and every target is assumed live,
+// except that a provenance of 0 means there's nothing there: either the weak
+// pointer came from Weak::new, or it's looking at zeroed memory
+
+use crate::Provenance;
+#[cfg(feature = "weak-registry")]
+use std::collections::HashSet;
+#[cfg(feature = "weak-registry")]
+use std::sync::{OnceLock, PoisonError, RwLock};
+
+#[cfg(feature = "weak-registry")]
+fn table() -> &'static RwLock<HashSet<usize>> {
+    static TABLE: OnceLock<RwLock<HashSet<usize>>> = OnceLock::new();
+    TABLE.get_or_init(Default::default)
+}
+
+// an allocation at `addr` has been initialized
+#[cfg(feature = "weak-registry")]
+pub(crate) fn insert(addr: usize) {
+    table()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .insert(addr);
+}
+
+// the allocation at `addr` is about to be freed
+#[cfg(feature = "weak-registry")]
+pub(crate) fn remove(addr: usize) {
+    table()
+        .write()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&addr);
+}
+
+// runs `f` if something is allocated at `addr`, which can't be freed until `f`
+// returns. statics aren't recorded, but they're never freed either.
+//
+// nothing can be freed anywhere while `f` runs, so it mustn't wait for anything,
+// like a provenance lock. to hold on to the allocation, `f` should try to take its
+// lock once, and anything waiting for it to be released do so outside this
+#[cfg(feature = "weak-registry")]
+pub(crate) fn if_live<R>(addr: usize, provenance: Provenance, f: impl FnOnce() -> R) -> Option<R> {
+    if provenance == 0 {
+        return None;
+    }
+    if provenance == crate::statics::STATIC_PROVENANCE {
+        return Some(f());
+    }
+    let table = table().read().unwrap_or_else(PoisonError::into_inner);
+    if table.contains(&addr) {
+        Some(f())
+    } else {
+        None
+    }
+}
+
+#[cfg(not(feature = "weak-registry"))]
+#[inline]
+pub(crate) fn if_live<R>(_addr: usize, provenance: Provenance, f: impl FnOnce() -> R) -> Option<R> {
+    if provenance == 0 {
+        return None;
+    }
+    Some(f())
+}
+
+#[cfg(all(test, feature = "weak-registry"))]
+mod tests {
+    use crate::Arc;
+
+    #[test]
+    fn freed_targets_are_not_touched() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        assert!(super::table().read().unwrap().contains(&weak.addr()));
+
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+        assert!(format!("{:?}", weak).contains("dead"));
+    }
+
+    #[test]
+    fn free_while_read() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        let guard = weak.read().unwrap();
+
+        // waits for the guard's lock, which mustn't keep anything else from being freed
+        let upgrader = std::thread::spawn(move || *weak.upgrade().unwrap());
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        drop(Arc::new(6));
+
+        assert_eq!(5, *guard);
+        drop(guard);
+        assert_eq!(5, upgrader.join().unwrap());
+    }
+}
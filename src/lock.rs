@@ -0,0 +1,324 @@
+//! Helpers for shared locks.
+//!
+//! `Arc<Mutex<T>>` and `Arc<RwLock<T>>` can be locked into owned guards, which keep
+//! the Arc alive themselves and so don't borrow from anything.
+//!
+//! Every Arc's value also has the provenance lock that upgrades take, and
+//! [`lock_both`] and [`lock_many`] hold it for several values at once. Two threads
+//! taking the same pair in opposite orders could deadlock, so they always lock in
+//! address order, which every thread agrees on.
+
+use crate::{Arc, Inner, Provenance};
+use std::cmp::Ordering;
+use std::mem::MaybeUninit;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering as MemoryOrdering;
+use std::sync::{LockResult, Mutex, PoisonError, RwLock};
+use std::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+impl<T: ?Sized> Arc<T> {
+    /// Compares where two Arcs point, ignoring tags.
+    ///
+    /// Gives a total order over live allocations that every thread agrees on.
+    pub fn addr_order(a: &Self, b: &Self) -> Ordering {
+        a.addr().cmp(&b.addr())
+    }
+}
+
+/// Holds the provenance lock of an Arc's value until it's dropped. See
+/// [`lock_both`].
+///
+/// Meanwhile weak pointers to the value can't upgrade or read it, and it can't be
+/// rekeyed; they wait until the guard is dropped. So upgrading a weak pointer to the
+/// same value on this thread would never return.
+pub struct ProvenanceGuard<'a, T: ?Sized> {
+    inner: &'a Inner<T>,
+    provenance: Provenance,
+}
+
+impl<T: ?Sized> ProvenanceGuard<'_, T> {
+    fn new(arc: &Arc<T>) -> ProvenanceGuard<'_, T> {
+        let inner = arc.inner();
+        // a rekey can change the provenance between the load and the lock
+        loop {
+            let exp = inner.provenance.load(MemoryOrdering::Relaxed);
+            let exp = exp ^ (exp & 1);
+            if inner.lock(exp) {
+                return ProvenanceGuard {
+                    inner,
+                    provenance: exp,
+                };
+            }
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for ProvenanceGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner.data
+    }
+}
+
+impl<T: ?Sized> Drop for ProvenanceGuard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.unlock(self.provenance);
+    }
+}
+
+/// Takes the provenance locks of two values in address order, returning the guards
+/// in argument order.
+///
+/// # Panics
+///
+/// If `a` and `b` point to the same value, since locking it twice would deadlock.
+pub fn lock_both<'a, A: ?Sized, B: ?Sized>(
+    a: &'a Arc<A>,
+    b: &'a Arc<B>,
+) -> (ProvenanceGuard<'a, A>, ProvenanceGuard<'a, B>) {
+    match a.addr().cmp(&b.addr()) {
+        Ordering::Less => {
+            let a = ProvenanceGuard::new(a);
+            (a, ProvenanceGuard::new(b))
+        }
+        Ordering::Greater => {
+            let b = ProvenanceGuard::new(b);
+            (ProvenanceGuard::new(a), b)
+        }
+        Ordering::Equal => panic!("lock_both called with the same value twice"),
+    }
+}
+
+/// Takes the provenance lock of every value in address order, returning the guards
+/// in argument order.
+///
+/// # Panics
+///
+/// If the same value appears more than once.
+pub fn lock_many<T: ?Sized>(arcs: &[Arc<T>]) -> Vec<ProvenanceGuard<'_, T>> {
+    let mut order: Vec<usize> = (0..arcs.len()).collect();
+    order.sort_by(|&a, &b| Arc::addr_order(&arcs[a], &arcs[b]));
+
+    for pair in order.windows(2) {
+        assert!(
+            Arc::addr_order(&arcs[pair[0]], &arcs[pair[1]]) != Ordering::Equal,
+            "lock_many called with the same value twice"
+        );
+    }
+
+    let mut guards: Vec<Option<ProvenanceGuard<'_, T>>> = arcs.iter().map(|_| None).collect();
+    for i in order {
+        guards[i] = Some(ProvenanceGuard::new(&arcs[i]));
+    }
+
+    guards.into_iter().map(Option::unwrap).collect()
+}
+
+impl<T> Arc<Mutex<T>> {
+    /// Shares a new mutex
+    pub fn new_mutex(val: T) -> Self {
+        Arc::new(Mutex::new(val))
+    }
+}
+
+impl<T> Arc<RwLock<T>> {
+    /// Shares a new reader-writer lock
+    pub fn new_rwlock(val: T) -> Self {
+        Arc::new(RwLock::new(val))
+    }
+}
+
+// the guards borrow from the allocation the Arc next to them keeps alive, so they're
+// made by locking through that Arc, not the caller's, and dropped before it.
+//
+// they're kept in a MaybeUninit so that moving the whole thing, like into
+// mem::drop, doesn't claim the reference inside stays valid for as long as the
+// move, which it doesn't once the Arc is dropped
+macro_rules! owned_guard {
+    ($(#[$doc:meta])* $name:ident, $lock:ident, $guard:ident) => {
+        $(#[$doc])*
+        pub struct $name<T: ?Sized + 'static> {
+            guard: MaybeUninit<$guard<'static, T>>,
+            arc: Arc<$lock<T>>,
+        }
+
+        impl<T: ?Sized + 'static> $name<T> {
+            /// Gets the Arc this guard keeps alive
+            pub fn arc(this: &Self) -> &Arc<$lock<T>> {
+                &this.arc
+            }
+
+            fn new(
+                arc: &Arc<$lock<T>>,
+                lock: impl FnOnce(&'static $lock<T>) -> LockResult<$guard<'static, T>>,
+            ) -> LockResult<Self> {
+                let arc = arc.clone();
+                // outlives the guard, since `arc` can't be swapped out from under
+                // it
+                let shared: &'static $lock<T> = unsafe { &(*crate::untagged(arc.ptr)).data };
+                let result = lock(shared);
+                let extend = |guard| $name {
+                    guard: MaybeUninit::new(guard),
+                    arc,
+                };
+                match result {
+                    Ok(guard) => Ok(extend(guard)),
+                    Err(poisoned) => Err(PoisonError::new(extend(poisoned.into_inner()))),
+                }
+            }
+        }
+
+        impl<T: ?Sized + 'static> Deref for $name<T> {
+            type Target = T;
+            fn deref(&self) -> &T {
+                unsafe { self.guard.assume_init_ref() }
+            }
+        }
+
+        impl<T: ?Sized + 'static> Drop for $name<T> {
+            fn drop(&mut self) {
+                unsafe { self.guard.assume_init_drop() }
+            }
+        }
+    };
+}
+
+owned_guard!(
+    /// A mutex guard that keeps its `Arc<Mutex<T>>` alive. See [`Arc::lock_arc`].
+    ArcMutexGuard,
+    Mutex,
+    MutexGuard
+);
+owned_guard!(
+    /// A read guard that keeps its `Arc<RwLock<T>>` alive. See [`Arc::read_arc`].
+    ArcRwLockReadGuard,
+    RwLock,
+    RwLockReadGuard
+);
+owned_guard!(
+    /// A write guard that keeps its `Arc<RwLock<T>>` alive. See [`Arc::write_arc`].
+    ArcRwLockWriteGuard,
+    RwLock,
+    RwLockWriteGuard
+);
+
+impl<T: ?Sized + 'static> DerefMut for ArcMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.guard.assume_init_mut() }
+    }
+}
+
+impl<T: ?Sized + 'static> DerefMut for ArcRwLockWriteGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { self.guard.assume_init_mut() }
+    }
+}
+
+impl<T: ?Sized + 'static> Arc<Mutex<T>> {
+    /// Locks the mutex, returning a guard that owns a clone of the Arc.
+    ///
+    /// Poisoning works the same as [`Mutex::lock`].
+    pub fn lock_arc(this: &Self) -> LockResult<ArcMutexGuard<T>> {
+        ArcMutexGuard::new(this, Mutex::lock)
+    }
+}
+
+impl<T: ?Sized + 'static> Arc<RwLock<T>> {
+    /// Locks for reading, returning a guard that owns a clone of the Arc.
+    ///
+    /// Poisoning works the same as [`RwLock::read`].
+    pub fn read_arc(this: &Self) -> LockResult<ArcRwLockReadGuard<T>> {
+        ArcRwLockReadGuard::new(this, RwLock::read)
+    }
+
+    /// Locks for writing, returning a guard that owns a clone of the Arc.
+    ///
+    /// Poisoning works the same as [`RwLock::write`].
+    pub fn write_arc(this: &Self) -> LockResult<ArcRwLockWriteGuard<T>> {
+        ArcRwLockWriteGuard::new(this, RwLock::write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::atomic::Ordering::Relaxed;
+    use std::thread;
+
+    #[test]
+    fn opposite_orders() {
+        let a = Arc::new(AtomicUsize::new(0));
+        let b = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..4)
+            .map(|i| {
+                let (x, y) = if i % 2 == 0 {
+                    (a.clone(), b.clone())
+                } else {
+                    (b.clone(), a.clone())
+                };
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        // not atomic, so only the locks keep increments from being lost
+                        let (x, y) = lock_both(&x, &y);
+                        x.store(x.load(Relaxed) + 1, Relaxed);
+                        y.store(y.load(Relaxed) + 1, Relaxed);
+                    }
+                })
+            })
+            .collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(4000, a.load(Relaxed));
+        assert_eq!(4000, b.load(Relaxed));
+    }
+
+    #[test]
+    fn excludes_upgrades() {
+        let a = Arc::new(1);
+        let b = Arc::new(2);
+        let weak = Arc::downgrade(&b);
+
+        let guards = lock_both(&a, &b);
+        assert_eq!((1, 2), (*guards.0, *guards.1));
+        assert!(weak.try_upgrade_spin(3).is_none());
+        drop(guards);
+        assert_eq!(2, *weak.upgrade().unwrap());
+    }
+
+    #[test]
+    fn owned_guards() {
+        let counter = Arc::new_mutex(0);
+        let weak = Arc::downgrade(&counter);
+
+        let mut guard = Arc::lock_arc(&counter).unwrap();
+        drop(counter);
+        *guard += 1;
+        assert!(weak.upgrade().is_some());
+
+        drop(guard);
+        assert!(weak.upgrade().is_none());
+
+        let config = Arc::new_rwlock(String::from("a"));
+        Arc::write_arc(&config).unwrap().push('b');
+        let read = Arc::read_arc(&config).unwrap();
+        let other = Arc::read_arc(&config).unwrap();
+        assert_eq!("ab", &*read);
+        let count = &ArcRwLockReadGuard::arc(&other).inner().ref_count;
+        assert_eq!(3, count.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[test]
+    fn many_in_argument_order() {
+        let arcs: Vec<_> = (0..5).map(Arc::new).collect();
+        let mut reversed = arcs.clone();
+        reversed.reverse();
+
+        let guards = lock_many(&reversed);
+        let values: Vec<i32> = guards.iter().map(|g| **g).collect();
+        assert_eq!(vec![4, 3, 2, 1, 0], values);
+    }
+}
@@ -0,0 +1,156 @@
+//! Letting Lua scripts hold weak handles to Rust objects (`mlua` feature).
+//!
+//! Scripts tend to keep references around for as long as they like, which is
+//! exactly when a weak pointer is wanted. A [`LuaWeak`] is userdata wrapping a
+//! [`Weak`], so a script holding one doesn't keep the object alive:
+//!
+//! - `handle:alive()` says whether the object is still there
+//! - `handle:upgrade()` returns the handle, or `nil` once the object is gone
+//! - `handle.field` and `handle.field = value` go to the object's [`LuaObject`]
+//!   impl, and raise an error once the object is gone
+//!
+//! ```
+//! use mlua::{Lua, Value};
+//! use provenant::lua::{LuaObject, LuaWeak};
+//! use provenant::Arc;
+//!
+//! struct Player {
+//!     name: String,
+//! }
+//!
+//! impl LuaObject for Player {
+//!     fn get(&self, lua: &Lua, key: &str) -> mlua::Result<Value> {
+//!         match key {
+//!             "name" => Ok(Value::String(lua.create_string(&self.name)?)),
+//!             _ => Ok(Value::Nil),
+//!         }
+//!     }
+//! }
+//!
+//! let lua = Lua::new();
+//! let player = Arc::new(Player { name: "ann".into() });
+//! lua.globals().set("player", LuaWeak::new(Arc::downgrade(&player))).unwrap();
+//!
+//! let name: String = lua.load("return player.name").eval().unwrap();
+//! assert_eq!("ann", name);
+//!
+//! drop(player);
+//! let gone: bool = lua.load("return player:upgrade() == nil").eval().unwrap();
+//! assert!(gone);
+//! ```
+
+use crate::{Arc, Weak};
+use mlua::{Lua, MetaMethod, UserData, UserDataMethods, Value};
+
+/// What a Lua script can do with an object through a [`LuaWeak`]
+pub trait LuaObject: 'static {
+    /// Reads a field. Methods named `alive` and `upgrade` are taken by [`LuaWeak`].
+    fn get(&self, lua: &Lua, key: &str) -> mlua::Result<Value>;
+
+    /// Writes a field. Fields are read-only unless this is overridden.
+    fn set(&self, lua: &Lua, key: &str, value: Value) -> mlua::Result<()> {
+        let _ = (lua, value);
+        Err(mlua::Error::runtime(format!(
+            "field '{}' is read-only",
+            key
+        )))
+    }
+}
+
+/// Lua userdata holding a weak pointer to a Rust object
+pub struct LuaWeak<T: ?Sized>(Weak<T>);
+
+impl<T: ?Sized> LuaWeak<T> {
+    /// Wraps a weak pointer for Lua
+    pub fn new(weak: Weak<T>) -> Self {
+        LuaWeak(weak)
+    }
+
+    /// Gets the weak pointer back
+    pub fn weak(&self) -> Weak<T> {
+        self.0
+    }
+
+    fn upgrade(&self) -> mlua::Result<Arc<T>> {
+        self.0
+            .upgrade()
+            .ok_or_else(|| mlua::Error::runtime("the object behind this handle has been dropped"))
+    }
+}
+
+impl<T: ?Sized> Clone for LuaWeak<T> {
+    fn clone(&self) -> Self {
+        LuaWeak(self.0)
+    }
+}
+
+impl<T: ?Sized> From<Weak<T>> for LuaWeak<T> {
+    fn from(weak: Weak<T>) -> Self {
+        LuaWeak(weak)
+    }
+}
+
+impl<T: ?Sized + LuaObject> UserData for LuaWeak<T> {
+    fn add_methods<M: UserDataMethods<Self>>(methods: &mut M) {
+        methods.add_method("alive", |_, this, ()| Ok(this.0.upgrade().is_some()));
+
+        methods.add_method("upgrade", |_, this, ()| {
+            Ok(this.0.upgrade().map(|_| this.clone()))
+        });
+
+        methods.add_meta_method(MetaMethod::Index, |lua, this, key: String| {
+            this.upgrade()?.get(lua, &key)
+        });
+
+        methods.add_meta_method(
+            MetaMethod::NewIndex,
+            |lua, this, (key, value): (String, Value)| this.upgrade()?.set(lua, &key, value),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Counter(Mutex<i64>);
+
+    impl LuaObject for Counter {
+        fn get(&self, _: &Lua, key: &str) -> mlua::Result<Value> {
+            match key {
+                "count" => Ok(Value::Integer(*self.0.lock().unwrap())),
+                _ => Ok(Value::Nil),
+            }
+        }
+
+        fn set(&self, _: &Lua, key: &str, value: Value) -> mlua::Result<()> {
+            match (key, value) {
+                ("count", Value::Integer(n)) => {
+                    *self.0.lock().unwrap() = n;
+                    Ok(())
+                }
+                _ => Err(mlua::Error::runtime("bad field")),
+            }
+        }
+    }
+
+    #[test]
+    fn script_handles() {
+        let lua = Lua::new();
+        let counter = Arc::new(Counter(Mutex::new(1)));
+        lua.globals()
+            .set("counter", LuaWeak::new(Arc::downgrade(&counter)))
+            .unwrap();
+
+        lua.load("counter.count = counter.count + 1")
+            .exec()
+            .unwrap();
+        assert_eq!(2, *counter.0.lock().unwrap());
+        assert!(lua.load("return counter:alive()").eval::<bool>().unwrap());
+
+        drop(counter);
+        assert!(!lua.load("return counter:alive()").eval::<bool>().unwrap());
+        assert!(lua.load("return counter.count").exec().is_err());
+    }
+}
@@ -0,0 +1,130 @@
+//! An Arc that points at its value, for passing through C.
+//!
+//! [`OffsetArc`] is `#[repr(transparent)]` over a non-null pointer to the value,
+//! so `OffsetArc<T>` and `Option<OffsetArc<T>>` have the ABI of `*const T`. The
+//! header sits at a fixed offset in front of the value, which is how it's found
+//! again. Like [`Arc::into_raw`], the tag isn't kept.
+//!
+//! ```
+//! use provenant::offset::OffsetArc;
+//! use provenant::Arc;
+//!
+//! // what a C callback would be handed, and give back
+//! extern "C" fn callback(shared: OffsetArc<u32>) -> OffsetArc<u32> {
+//!     shared
+//! }
+//!
+//! let arc = Arc::new(7);
+//! let back = Arc::from_offset(callback(Arc::into_offset(arc)));
+//! assert_eq!(7, *back);
+//! ```
+
+use crate::Arc;
+use core::fmt;
+use core::mem::{self, ManuallyDrop};
+use core::ops::Deref;
+use core::ptr::NonNull;
+
+/// An Arc that's a single pointer to its value
+#[repr(transparent)]
+pub struct OffsetArc<T> {
+    ptr: NonNull<T>,
+}
+
+unsafe impl<T: Send + Sync> Send for OffsetArc<T> {}
+unsafe impl<T: Send + Sync> Sync for OffsetArc<T> {}
+
+impl<T> Arc<T> {
+    /// Turns this into an [`OffsetArc`], keeping its strong reference
+    pub fn into_offset(this: Self) -> OffsetArc<T> {
+        let ptr = Arc::into_raw(this) as *mut T;
+        OffsetArc {
+            ptr: unsafe { NonNull::new_unchecked(ptr) },
+        }
+    }
+
+    /// Turns an [`OffsetArc`] back into an Arc
+    pub fn from_offset(offset: OffsetArc<T>) -> Self {
+        let ptr = offset.ptr.as_ptr();
+        mem::forget(offset);
+        unsafe { Arc::from_raw(ptr) }
+    }
+}
+
+impl<T> OffsetArc<T> {
+    /// Gets the pointer to the value, which is what C sees
+    pub fn as_ptr(this: &Self) -> *const T {
+        this.ptr.as_ptr()
+    }
+
+    /// Runs `f` with an Arc borrowed from this one, for the Arc methods that take
+    /// `&Arc<T>`
+    pub fn with_arc<R>(this: &Self, f: impl FnOnce(&Arc<T>) -> R) -> R {
+        // never dropped, so it never gives back the reference it doesn't own
+        let arc = ManuallyDrop::new(unsafe { Arc::from_raw(this.ptr.as_ptr()) });
+        f(&arc)
+    }
+}
+
+impl<T> Deref for OffsetArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { self.ptr.as_ref() }
+    }
+}
+
+impl<T> Clone for OffsetArc<T> {
+    fn clone(&self) -> Self {
+        Arc::into_offset(OffsetArc::with_arc(self, Arc::clone))
+    }
+}
+
+impl<T> Drop for OffsetArc<T> {
+    fn drop(&mut self) {
+        drop(unsafe { Arc::from_raw(self.ptr.as_ptr()) });
+    }
+}
+
+impl<T> From<Arc<T>> for OffsetArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Arc::into_offset(arc)
+    }
+}
+
+impl<T> From<OffsetArc<T>> for Arc<T> {
+    fn from(offset: OffsetArc<T>) -> Self {
+        Arc::from_offset(offset)
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for OffsetArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        assert_eq!(
+            mem::size_of::<usize>(),
+            mem::size_of::<Option<OffsetArc<u64>>>()
+        );
+
+        let arc = Arc::new(String::from("offset"));
+        let weak = Arc::downgrade(&arc);
+        let offset = OffsetArc::from(arc);
+        let copy = offset.clone();
+        assert_eq!("offset", *copy);
+        assert_eq!(2, OffsetArc::with_arc(&offset, Arc::strong_count));
+
+        drop(copy);
+        let arc: Arc<String> = Arc::from(offset);
+        assert_eq!(1, Arc::strong_count(&arc));
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,48 @@
+//! Parallel iteration over shared slices (`rayon` feature).
+//!
+//! Splits a shared buffer into owned [`ArcSlice`] chunks that rayon can hand to
+//! its worker threads. Each chunk keeps the buffer alive, so the work doesn't
+//! need to borrow from the caller.
+
+use crate::slice::ArcSlice;
+use rayon::prelude::*;
+
+impl<T, S> ArcSlice<T, S>
+where
+    S: ?Sized + AsRef<[T]> + Send + Sync,
+{
+    /// Splits into chunks of `chunk_size` elements, in parallel.
+    ///
+    /// Same chunks as [`ArcSlice::chunks`].
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is 0.
+    pub fn par_chunks(&self, chunk_size: usize) -> impl IndexedParallelIterator<Item = Self> + '_ {
+        assert!(chunk_size != 0, "chunk size must be nonzero");
+        (0..self.chunk_count(chunk_size))
+            .into_par_iter()
+            .map(move |i| self.chunk(chunk_size, i))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::slice::ArcSlice;
+    use crate::Arc;
+    use rayon::prelude::*;
+
+    #[test]
+    fn parallel_sum() {
+        let data: Vec<u64> = (1..=10_000).collect();
+        let whole = ArcSlice::new(Arc::new(data));
+
+        let sums: Vec<u64> = whole
+            .par_chunks(1000)
+            .map(|chunk| chunk.iter().sum())
+            .collect();
+
+        assert_eq!(10, sums.len());
+        assert_eq!(50_005_000, sums.iter().sum::<u64>());
+    }
+}
@@ -0,0 +1,144 @@
+// a tiny parking lot, so threads can wait for something to happen to an allocation
+// without every allocation paying for a mutex and condvar.
+//
+// waiters and notifiers find each other by hashing the allocation's address into
+// a fixed table of buckets. unrelated allocations sharing a bucket just cause
+// spurious wakeups, which waiters handle by rechecking their condition.
+
+use std::sync::atomic::{fence, AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::task::Waker;
+use std::time::Instant;
+
+struct Bucket {
+    // wakers registered by futures, with the address they're waiting on and their id
+    mutex: Mutex<Vec<(usize, usize, Waker)>>,
+    condvar: Condvar,
+    // lets notify skip the mutex when nobody is waiting, which is almost always
+    waiters: AtomicUsize,
+}
+
+impl Bucket {
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Bucket = Bucket {
+        mutex: Mutex::new(Vec::new()),
+        condvar: Condvar::new(),
+        waiters: AtomicUsize::new(0),
+    };
+}
+
+static BUCKETS: [Bucket; 64] = [Bucket::EMPTY; 64];
+
+fn bucket(addr: usize) -> &'static Bucket {
+    // allocations are at least word aligned, so skip the bits that never change
+    &BUCKETS[(addr >> 4) % BUCKETS.len()]
+}
+
+// blocks until `done` returns true, or the deadline passes. returns the last result of `done`.
+// whatever makes `done` true must be followed by a call to notify with the same address
+pub(crate) fn wait_until(addr: usize, deadline: Option<Instant>, done: impl Fn() -> bool) -> bool {
+    let bucket = bucket(addr);
+    bucket.waiters.fetch_add(1, Ordering::SeqCst);
+    // pairs with the fence in notify, so done() sees the change or notify sees us
+    fence(Ordering::SeqCst);
+
+    let mut guard = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    let result = loop {
+        if done() {
+            break true;
+        }
+
+        match deadline {
+            None => {
+                guard = bucket
+                    .condvar
+                    .wait(guard)
+                    .unwrap_or_else(PoisonError::into_inner);
+            }
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    break false;
+                }
+                guard = bucket
+                    .condvar
+                    .wait_timeout(guard, deadline - now)
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .0;
+            }
+        }
+    };
+    drop(guard);
+
+    bucket.waiters.fetch_sub(1, Ordering::SeqCst);
+    result
+}
+
+// wakes everything waiting on `addr` (and anything else sharing its bucket)
+pub(crate) fn notify(addr: usize) {
+    let bucket = bucket(addr);
+
+    // pairs with the fence after counting a waiter. the caller's change is often
+    // a Release store or fetch_sub, which a SeqCst load alone could be reordered
+    // before. either the waiter is counted here, or it checks its condition after
+    // the caller's change and doesn't sleep
+    fence(Ordering::SeqCst);
+    if bucket.waiters.load(Ordering::SeqCst) == 0 {
+        return;
+    }
+
+    // taking the lock means a waiter can't be between checking and sleeping
+    let mut wakers = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut woken = Vec::new();
+    let mut i = 0;
+    while i < wakers.len() {
+        if wakers[i].0 == addr {
+            woken.push(wakers.swap_remove(i).2);
+        } else {
+            i += 1;
+        }
+    }
+    drop(wakers);
+
+    bucket.waiters.fetch_sub(woken.len(), Ordering::SeqCst);
+    bucket.condvar.notify_all();
+    for waker in woken {
+        waker.wake();
+    }
+}
+
+// gets an id for register_waker, unique for the life of the process
+#[cfg(feature = "async")]
+pub(crate) fn waker_id() -> usize {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+// makes the next notify for `addr` wake `waker`, replacing whatever was registered with `id`.
+// same as wait_until, the caller must check its condition after this
+#[cfg(feature = "async")]
+pub(crate) fn register_waker(addr: usize, id: usize, waker: &Waker) {
+    let bucket = bucket(addr);
+    let mut wakers = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(entry) = wakers.iter_mut().find(|(_, i, _)| *i == id) {
+        entry.2.clone_from(waker);
+    } else {
+        bucket.waiters.fetch_add(1, Ordering::SeqCst);
+        wakers.push((addr, id, waker.clone()));
+    }
+    // pairs with the fence in notify, as in wait_until
+    fence(Ordering::SeqCst);
+}
+
+// removes a waker registered with `id`, if it hasn't been woken yet
+#[cfg(feature = "async")]
+pub(crate) fn unregister_waker(addr: usize, id: usize) {
+    let bucket = bucket(addr);
+    let mut wakers = bucket.mutex.lock().unwrap_or_else(PoisonError::into_inner);
+
+    if let Some(i) = wakers.iter().position(|(_, i, _)| *i == id) {
+        wakers.swap_remove(i);
+        bucket.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+}
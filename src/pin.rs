@@ -0,0 +1,109 @@
+//! Pinned Arcs, and weak pointers that upgrade to them.
+//!
+//! A `Pin<Arc<T>>` promises the value won't move until it's dropped. Any unpinned
+//! Arc to the same value could break that, with [`Arc::try_unwrap`] or
+//! [`Arc::get_mut`], and so could an unpinned [`Weak`] upgrading to one. So pinned
+//! Arcs only come from [`Arc::pin`], or from [`Arc::into_pin`] on a unique Arc,
+//! and their weak pointers are [`PinnedWeak`]s, which upgrade to pinned Arcs.
+//!
+//! ```
+//! use provenant::pin::PinnedWeak;
+//! use provenant::Arc;
+//!
+//! let task = Arc::pin(async { 5 });
+//! let weak = PinnedWeak::new(&task);
+//! assert!(weak.upgrade().is_some());
+//! ```
+
+use crate::{random_provenance, Arc, Weak};
+use core::fmt;
+use core::pin::Pin;
+
+impl<T> Arc<T> {
+    /// Creates a new shared reference to a value that will never move
+    pub fn pin(val: T) -> Pin<Self> {
+        unsafe { Pin::new_unchecked(Arc::new(val)) }
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Pins the value, if this is the only strong reference. Otherwise returns the
+    /// Arc unchanged.
+    ///
+    /// Existing weak pointers could upgrade to unpinned Arcs, so like
+    /// [`Arc::get_mut`], this detaches them.
+    pub fn into_pin(this: Self) -> Result<Pin<Self>, Self> {
+        if !this.claim_unique(random_provenance()) {
+            return Err(this);
+        }
+        crate::events::rekeyed(this.inner());
+        Ok(unsafe { Pin::new_unchecked(this) })
+    }
+}
+
+// Pin is repr(transparent), so this is just a look at the Arc inside
+fn unpinned<T: ?Sized>(pinned: &Pin<Arc<T>>) -> &Arc<T> {
+    unsafe { &*(pinned as *const Pin<Arc<T>> as *const Arc<T>) }
+}
+
+/// A weak pointer to a pinned value, which upgrades to a pinned Arc
+pub struct PinnedWeak<T: ?Sized>(Weak<T>);
+
+impl<T: ?Sized> Copy for PinnedWeak<T> {}
+
+impl<T: ?Sized> Clone for PinnedWeak<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> PinnedWeak<T> {
+    /// Gets a weak pointer to the value in `pinned`
+    pub fn new(pinned: &Pin<Arc<T>>) -> Self {
+        PinnedWeak(Arc::downgrade(unpinned(pinned)))
+    }
+
+    /// Attempts to get a pinned strong reference. Fails like [`Weak::upgrade`]
+    pub fn upgrade(&self) -> Option<Pin<Arc<T>>> {
+        // everything that points at this value is pinned, so it never moved
+        let arc = self.0.upgrade()?;
+        Some(unsafe { Pin::new_unchecked(arc) })
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for PinnedWeak<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::marker::PhantomPinned;
+
+    struct NotUnpin(u32, PhantomPinned);
+
+    #[test]
+    fn upgrade_pinned() {
+        let pinned = Arc::pin(NotUnpin(3, PhantomPinned));
+        let weak = PinnedWeak::new(&pinned);
+        assert_eq!(3, weak.upgrade().unwrap().0);
+
+        drop(pinned);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn into_pin() {
+        let arc = Arc::new(NotUnpin(4, PhantomPinned));
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+
+        let arc = Arc::into_pin(arc).err().unwrap();
+        drop(other);
+        let pinned = Arc::into_pin(arc).ok().unwrap();
+        assert_eq!(4, pinned.0);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,424 @@
+//! Allocating Arcs out of pools of slots, instead of one heap allocation each.
+//!
+//! [`StaticPool`] has a fixed number of slots and never touches the heap, so it's
+//! there without `std`. [`Pool`] grows in chunks, so it never runs out, and values
+//! allocated one after another sit next to each other in memory. It needs `std`.
+//!
+//! [`Arc::new_in_arena`] allocates from a pool shared by every value of the same
+//! type, [`Pool::global`]. Since pools never give memory back, a stale weak pointer
+//! into one always reads mapped memory holding a header, whatever's happened since,
+//! and upgrading it fails because the slot's generation has moved on.
+
+use crate::{Arc, Inner, Provenance};
+use alloc::alloc::Layout;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::Ordering;
+#[cfg(feature = "std")]
+use std::any::{Any, TypeId};
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::ptr::NonNull;
+#[cfg(feature = "std")]
+use std::sync::{Mutex, OnceLock, PoisonError, RwLock};
+
+// core's, not loom's, which can't be made in a const fn. generations count up, so
+// even with `provenance-128` they don't need more than 64 bits
+#[cfg(any(
+    feature = "provenance-128",
+    all(feature = "wide-provenance", target_pointer_width = "32")
+))]
+use core::sync::atomic::AtomicU64 as AtomicGeneration;
+#[cfg(not(any(
+    feature = "provenance-128",
+    all(feature = "wide-provenance", target_pointer_width = "32")
+)))]
+use core::sync::atomic::AtomicUsize as AtomicGeneration;
+
+#[cfg(feature = "provenance-128")]
+type Generation = u64;
+#[cfg(not(feature = "provenance-128"))]
+type Generation = Provenance;
+
+/// A fixed-size array of slots that [`Arc`]s can be allocated from without
+/// touching the heap.
+///
+/// Meant to live in a `static`:
+///
+/// ```
+/// use provenant::pool::StaticPool;
+///
+/// static POOL: StaticPool<u32, 8> = StaticPool::new();
+///
+/// let arc = POOL.alloc(5).unwrap();
+/// assert_eq!(5, *arc);
+/// ```
+///
+/// Each slot keeps a generation that moves on every time it's reused, and that
+/// generation is the provenance of the Arc living in it. So a weak pointer into
+/// a reused slot is guaranteed to fail to upgrade, rather than just very likely.
+pub struct StaticPool<T, const N: usize> {
+    slots: [Slot<T>; N],
+}
+
+#[repr(C)]
+struct Slot<T> {
+    // must be the first field, release_slot finds the slot from the Inner pointer
+    inner: UnsafeCell<MaybeUninit<Inner<T>>>,
+
+    // the low bit is set while the slot is in use,
+    // the rest is the provenance it was last given
+    state: AtomicGeneration,
+}
+
+unsafe impl<T: Send + Sync, const N: usize> Sync for StaticPool<T, N> {}
+unsafe impl<T: Send + Sync, const N: usize> Send for StaticPool<T, N> {}
+
+impl<T> Slot<T> {
+    // only used as an array repeat operand, so each slot gets its own copy
+    #[allow(clippy::declare_interior_mutable_const)]
+    const EMPTY: Self = Slot {
+        inner: UnsafeCell::new(MaybeUninit::uninit()),
+        state: AtomicGeneration::new(0),
+    };
+}
+
+unsafe fn release_slot<T>(ptr: *mut u8, _layout: Layout) {
+    let slot = &*(ptr as *const Slot<T>);
+    slot.state.fetch_and(!1, Ordering::Release);
+}
+
+impl<T, const N: usize> StaticPool<T, N> {
+    /// Creates a pool with every slot free
+    pub const fn new() -> Self {
+        StaticPool {
+            slots: [Slot::EMPTY; N],
+        }
+    }
+
+    /// Moves `val` into a free slot, or gives it back if there isn't one
+    pub fn alloc(&'static self, val: T) -> Result<Arc<T>, PoolExhausted<T>> {
+        for slot in &self.slots {
+            let state = slot.state.load(Ordering::Relaxed);
+            if state & 1 != 0 {
+                continue;
+            }
+
+            let provenance = next_generation(state);
+
+            if slot
+                .state
+                .compare_exchange(state, provenance | 1, Ordering::Acquire, Ordering::Relaxed)
+                .is_err()
+            {
+                continue;
+            }
+
+            let inner = slot.inner.get() as *mut Inner<T>;
+            // only a cast with provenance-128
+            #[allow(clippy::unnecessary_cast)]
+            unsafe {
+                inner.write(Inner::new(val, provenance as Provenance, release_slot::<T>));
+            }
+            crate::events::allocated(inner);
+            return Ok(Arc { ptr: inner });
+        }
+
+        Err(PoolExhausted(val))
+    }
+
+    /// The number of slots, used or not
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    /// The number of slots not currently holding a value
+    pub fn available(&self) -> usize {
+        self.slots
+            .iter()
+            .filter(|slot| slot.state.load(Ordering::Relaxed) & 1 == 0)
+            .count()
+    }
+}
+
+impl<T, const N: usize> Default for StaticPool<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the generation after `generation`, skipping 0
+fn next_generation(generation: Generation) -> Generation {
+    match generation.wrapping_add(2) {
+        0 => 2,
+        p => p,
+    }
+}
+
+#[cfg(feature = "std")]
+/// A pool of slots for [`Arc`]s, which grows in chunks as it fills up.
+///
+/// Like [`StaticPool`], it's meant to live in a `static`, and a weak pointer into
+/// a reused slot is guaranteed to fail to upgrade. Chunks are never given back to
+/// the heap, so the pool only ever grows to the most values alive at once.
+///
+/// ```
+/// use provenant::pool::Pool;
+///
+/// static PARTICLES: Pool<[f32; 3]> = Pool::new();
+///
+/// let particles: Vec<_> = (0..100).map(|i| PARTICLES.alloc([i as f32; 3])).collect();
+/// assert_eq!([5.0; 3], *particles[5]);
+/// assert!(PARTICLES.capacity() >= 100);
+/// ```
+pub struct Pool<T: 'static> {
+    state: Mutex<PoolState<T>>,
+}
+
+#[cfg(feature = "std")]
+struct PoolState<T: 'static> {
+    free: Vec<NonNull<PoolSlot<T>>>,
+    capacity: usize,
+}
+
+#[cfg(feature = "std")]
+#[repr(C)]
+struct PoolSlot<T: 'static> {
+    // must be the first field, release_pooled finds the slot from the Inner pointer
+    inner: UnsafeCell<MaybeUninit<Inner<T>>>,
+    // the provenance it was last given. only touched by whoever took it off the
+    // free list
+    generation: AtomicGeneration,
+    pool: &'static Pool<T>,
+}
+
+#[cfg(feature = "std")]
+unsafe impl<T: Send + Sync> Sync for Pool<T> {}
+#[cfg(feature = "std")]
+unsafe impl<T: Send + Sync> Send for Pool<T> {}
+
+// the first chunk's size. each one after that is as big as all the others together
+#[cfg(feature = "std")]
+const FIRST_CHUNK: usize = 16;
+
+#[cfg(feature = "std")]
+unsafe fn release_pooled<T: 'static>(ptr: *mut u8, _layout: Layout) {
+    let slot = &*(ptr as *const PoolSlot<T>);
+    slot.pool.lock().free.push(NonNull::from(slot));
+}
+
+#[cfg(feature = "std")]
+impl<T> Pool<T> {
+    /// Creates a pool without any slots, which allocates its first chunk when it's
+    /// first used
+    pub const fn new() -> Self {
+        Pool {
+            state: Mutex::new(PoolState {
+                free: Vec::new(),
+                capacity: 0,
+            }),
+        }
+    }
+
+    /// Moves `val` into a free slot, growing the pool if there isn't one
+    pub fn alloc(&'static self, val: T) -> Arc<T> {
+        let slot = {
+            let mut state = self.lock();
+            if state.free.is_empty() {
+                let len = state.capacity.max(FIRST_CHUNK);
+                self.grow(&mut state, len);
+            }
+            state.free.pop().unwrap()
+        };
+
+        let generation = unsafe { &slot.as_ref().generation };
+        let provenance = next_generation(generation.load(Ordering::Relaxed));
+        generation.store(provenance, Ordering::Relaxed);
+
+        // from the pointer to the whole slot, which release_pooled turns back into
+        let inner = slot.as_ptr() as *mut Inner<T>;
+        #[allow(clippy::unnecessary_cast)]
+        unsafe {
+            inner.write(Inner::new(
+                val,
+                provenance as Provenance,
+                release_pooled::<T>,
+            ));
+        }
+        crate::events::allocated(inner);
+        Arc { ptr: inner }
+    }
+
+    /// Makes sure at least `additional` more values fit without growing
+    pub fn reserve(&'static self, additional: usize) {
+        let mut state = self.lock();
+        if let Some(missing) = additional.checked_sub(state.free.len()) {
+            if missing > 0 {
+                self.grow(&mut state, missing);
+            }
+        }
+    }
+
+    /// The number of slots, used or not
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// The number of slots not currently holding a value
+    pub fn available(&self) -> usize {
+        self.lock().free.len()
+    }
+
+    // adds a chunk of `len` free slots, which lives as long as the pool
+    fn grow(&'static self, state: &mut PoolState<T>, len: usize) {
+        let chunk: &'static mut [PoolSlot<T>] = Box::leak(
+            (0..len)
+                .map(|_| PoolSlot {
+                    inner: UnsafeCell::new(MaybeUninit::uninit()),
+                    generation: AtomicGeneration::new(0),
+                    pool: self,
+                })
+                .collect(),
+        );
+        // popped from the end, so the chunk is used front to back
+        state.free.extend(chunk.iter_mut().rev().map(NonNull::from));
+        state.capacity += len;
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PoolState<T>> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// each holds a &'static Pool<T>, keyed by T
+#[cfg(feature = "std")]
+type Globals = HashMap<TypeId, &'static (dyn Any + Send + Sync)>;
+
+#[cfg(feature = "std")]
+impl<T: Send + Sync> Pool<T> {
+    /// The pool for values of type `T` shared by the whole process, created the
+    /// first time it's asked for
+    pub fn global() -> &'static Pool<T> {
+        static GLOBALS: OnceLock<RwLock<Globals>> = OnceLock::new();
+        let globals = GLOBALS.get_or_init(Default::default);
+
+        let id = TypeId::of::<T>();
+        let found = globals
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .get(&id)
+            .copied();
+        let pool = match found {
+            Some(pool) => pool,
+            None => *globals
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .entry(id)
+                .or_insert_with(|| Box::leak(Box::new(Pool::<T>::new()))),
+        };
+        pool.downcast_ref().unwrap()
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: Send + Sync + 'static> Arc<T> {
+    /// Like [`Arc::new`], but allocated from [`Pool::global`], so the memory is
+    /// only ever reused for another `T`, and never given back to the heap
+    pub fn new_in_arena(val: T) -> Self {
+        Pool::global().alloc(val)
+    }
+}
+
+/// Returned when a pool has no free slots. Holds the value that didn't fit.
+pub struct PoolExhausted<T>(pub T);
+
+impl<T> fmt::Debug for PoolExhausted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PoolExhausted(..)")
+    }
+}
+
+impl<T> fmt::Display for PoolExhausted<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("no free slots left in pool")
+    }
+}
+
+impl<T> core::error::Error for PoolExhausted<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhaust_and_reuse() {
+        static POOL: StaticPool<u64, 2> = StaticPool::new();
+
+        let a = POOL.alloc(1).unwrap();
+        let b = POOL.alloc(2).unwrap();
+        match POOL.alloc(3) {
+            Err(PoolExhausted(v)) => assert_eq!(3, v),
+            Ok(_) => panic!("pool should be full"),
+        }
+        assert_eq!(0, POOL.available());
+
+        let weak = Arc::downgrade(&a);
+        drop(a);
+        assert_eq!(1, POOL.available());
+        assert!(weak.upgrade().is_none());
+
+        // lands in the same slot, but with the next generation
+        let c = POOL.alloc(4).unwrap();
+        assert_eq!(weak.ptr, c.ptr);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(2, *b);
+        assert_eq!(4, *Arc::downgrade(&c).upgrade().unwrap());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn grow_and_reuse() {
+        static POOL: Pool<String> = Pool::new();
+
+        let values: Vec<_> = (0..20).map(|i| POOL.alloc(i.to_string())).collect();
+        assert_eq!(32, POOL.capacity());
+        assert_eq!(12, POOL.available());
+
+        // next to each other in the first chunk
+        let step = std::mem::size_of::<PoolSlot<String>>();
+        assert_eq!(values[0].addr() + step, values[1].addr());
+
+        let weak = Arc::downgrade(&values[19]);
+        drop(values);
+        assert!(weak.upgrade().is_none());
+        let again = POOL.alloc(String::from("again"));
+        assert_eq!(weak.addr(), again.addr());
+        assert!(weak.upgrade().is_none());
+
+        POOL.reserve(100);
+        assert_eq!(100, POOL.available());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn arena_per_type() {
+        let arc = Arc::new_in_arena(1u16);
+        let weak = Arc::downgrade(&arc);
+        assert!(Pool::<u16>::global().capacity() > 0);
+        assert_eq!(0, Pool::<i16>::global().capacity());
+
+        drop(arc);
+        let again = Arc::new_in_arena(2u16);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(2, *again);
+    }
+}
@@ -0,0 +1,237 @@
+// what the core algorithms are built on. normally core and rand, but built with
+// `RUSTFLAGS="--cfg shuttle"` they come from shuttle instead, so its schedulers
+// can explore interleavings of the drop and upgrade protocol, and replay failures
+// deterministically.
+//
+// shuttle's primitives only work inside a shuttle test, so with that cfg, run only
+// those: `RUSTFLAGS="--cfg shuttle" cargo test --lib shuttle`
+//
+// `--cfg loom` swaps the atomics for loom's, which checks every interleaving
+// instead of sampling them. same deal, only the loom tests work:
+// `RUSTFLAGS="--cfg loom" cargo test --lib loom --release`. loom's atomics can't be
+// made in a const fn, so statics aren't built with it
+
+#[cfg(not(any(shuttle, loom)))]
+pub(crate) use core::sync::atomic::AtomicUsize;
+#[cfg(all(
+    not(shuttle),
+    feature = "std",
+    not(all(target_arch = "wasm32", target_os = "unknown")),
+    not(feature = "counter-provenance")
+))]
+pub(crate) use rand::thread_rng;
+
+// the provenance word, which is a u64 with `wide-provenance` on 32-bit targets, and
+// two of them with `provenance-128`. loom and shuttle don't get a say there
+#[cfg(not(any(
+    feature = "provenance-128",
+    all(feature = "wide-provenance", target_pointer_width = "32")
+)))]
+pub(crate) type AtomicProvenance = AtomicUsize;
+#[cfg(all(
+    not(feature = "provenance-128"),
+    feature = "wide-provenance",
+    target_pointer_width = "32"
+))]
+pub(crate) use core::sync::atomic::AtomicU64 as AtomicProvenance;
+#[cfg(feature = "provenance-128")]
+pub(crate) use wide::AtomicProvenance;
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::fence;
+
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::fence;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicUsize;
+
+#[cfg(all(shuttle, not(feature = "counter-provenance")))]
+pub(crate) use shuttle::rand::thread_rng;
+#[cfg(shuttle)]
+pub(crate) use shuttle::sync::atomic::AtomicUsize;
+
+// a u128 provenance without 128-bit atomics. the low word works like the usual one,
+// lock bit and all, and the high word is only written while the lock is held, so
+// taking the lock on the low word and then checking the high one is as good as
+// comparing both at once. loads outside the lock can be torn, which at worst makes
+// a guess at what to lock wrong, and the lock fails as if it had been rekeyed
+#[cfg(feature = "provenance-128")]
+mod wide {
+    use core::sync::atomic::{AtomicU64, Ordering};
+
+    pub(crate) struct AtomicProvenance {
+        low: AtomicU64,
+        high: AtomicU64,
+    }
+
+    fn join(high: u64, low: u64) -> u128 {
+        (high as u128) << 64 | low as u128
+    }
+
+    impl AtomicProvenance {
+        pub(crate) const fn new(provenance: u128) -> Self {
+            AtomicProvenance {
+                low: AtomicU64::new(provenance as u64),
+                high: AtomicU64::new((provenance >> 64) as u64),
+            }
+        }
+
+        pub(crate) fn load(&self, order: Ordering) -> u128 {
+            let low = self.low.load(order);
+            join(self.high.load(Ordering::Relaxed), low)
+        }
+
+        // the high word goes first, so whoever sees the low word released sees it too
+        pub(crate) fn store(&self, provenance: u128, order: Ordering) {
+            self.high
+                .store((provenance >> 64) as u64, Ordering::Relaxed);
+            self.low.store(provenance as u64, order);
+        }
+
+        // only ever used to take the lock, so `new` is `current` with the low bit set
+        pub(crate) fn compare_exchange(
+            &self,
+            current: u128,
+            new: u128,
+            success: Ordering,
+            failure: Ordering,
+        ) -> Result<u128, u128> {
+            let high = (current >> 64) as u64;
+            match self
+                .low
+                .compare_exchange(current as u64, new as u64, success, failure)
+            {
+                Err(low) => Err(join(self.high.load(Ordering::Relaxed), low)),
+                Ok(low) => {
+                    // the lock is held, so nothing is writing this
+                    let actual = self.high.load(Ordering::Relaxed);
+                    if actual == high {
+                        return Ok(current);
+                    }
+                    // right low word, wrong allocation. let go without changing anything
+                    self.low.store(low, Ordering::Release);
+                    Err(join(actual, low))
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(test, shuttle))]
+mod shuttle_tests {
+    use crate::{Arc, Weak};
+    use shuttle::thread;
+
+    const ITERATIONS: usize = 1000;
+
+    #[test]
+    fn shuttle_upgrade_races_last_drop() {
+        shuttle::check_random(
+            || {
+                let arc = Arc::new(7);
+                let weak = Arc::downgrade(&arc);
+
+                let upgrader = thread::spawn(move || weak.upgrade().map(|arc| *arc));
+                drop(arc);
+
+                if let Some(value) = upgrader.join().unwrap() {
+                    assert_eq!(7, value);
+                }
+                assert!(weak.upgrade().is_none());
+            },
+            ITERATIONS,
+        );
+    }
+
+    #[test]
+    fn shuttle_clones_and_upgrades() {
+        shuttle::check_random(
+            || {
+                let arc = Arc::new(String::from("x"));
+                let weak: Weak<String> = Arc::downgrade(&arc);
+
+                let threads: Vec<_> = (0..2)
+                    .map(|_| {
+                        let arc = arc.clone();
+                        thread::spawn(move || {
+                            let upgraded = weak.upgrade().unwrap();
+                            drop(arc);
+                            assert_eq!("x", *upgraded);
+                        })
+                    })
+                    .collect();
+                drop(arc);
+
+                for t in threads {
+                    t.join().unwrap();
+                }
+                assert!(weak.upgrade().is_none());
+            },
+            ITERATIONS,
+        );
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use crate::Arc;
+    use loom::thread;
+
+    #[test]
+    fn loom_upgrade_races_last_drop() {
+        loom::model(|| {
+            let arc = Arc::new(7);
+            let weak = Arc::downgrade(&arc);
+
+            let upgrader = thread::spawn(move || weak.upgrade().map(|arc| *arc));
+            drop(arc);
+
+            if let Some(value) = upgrader.join().unwrap() {
+                assert_eq!(7, value);
+            }
+            assert!(weak.upgrade().is_none());
+        });
+    }
+
+    #[test]
+    fn loom_drops_race() {
+        loom::model(|| {
+            let arc = Arc::new(String::from("x"));
+            let weak = Arc::downgrade(&arc);
+            let other = arc.clone();
+
+            let dropper = thread::spawn(move || drop(other));
+            drop(arc);
+            dropper.join().unwrap();
+
+            // exactly one of the drops freed it
+            assert!(weak.upgrade().is_none());
+        });
+    }
+
+    #[test]
+    fn loom_last_drop_sees_last_use() {
+        use loom::cell::UnsafeCell;
+
+        // written by whichever Arc goes first, read when the value is dropped.
+        // loom reports a causality violation if the drop can't see the write
+        struct Slot(UnsafeCell<usize>);
+        unsafe impl Sync for Slot {}
+        impl Drop for Slot {
+            fn drop(&mut self) {
+                self.0.with(|value| assert!(unsafe { *value } <= 1));
+            }
+        }
+
+        loom::model(|| {
+            let arc = Arc::new(Slot(UnsafeCell::new(0)));
+            let other = arc.clone();
+
+            let writer = thread::spawn(move || {
+                other.0.with_mut(|value| unsafe { *value = 1 });
+            });
+            drop(arc);
+            writer.join().unwrap();
+        });
+    }
+}
@@ -0,0 +1,151 @@
+//! Hooks for heap profilers (`profiling` feature).
+//!
+//! Every allocation made by [`Arc`](crate::Arc) comes from the same `Box::new` call,
+//! so profilers that attribute by call site lump them all together. Install an
+//! [`AllocObserver`] to hear about each one with the payload's type name, and
+//! forward that to whatever profiler is in use. [`TypeTally`] is a simple
+//! observer that keeps live counts and bytes per type.
+
+use crate::Inner;
+use std::alloc::Layout;
+use std::any::type_name;
+use std::collections::BTreeMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Describes one shared allocation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocInfo {
+    /// The type of the value being shared, from [`std::any::type_name`]
+    pub type_name: &'static str,
+    /// The whole allocation, including the header
+    pub layout: Layout,
+    /// Where the allocation starts
+    pub addr: usize,
+}
+
+/// Gets told about shared allocations as they come and go
+pub trait AllocObserver: Sync {
+    /// Called right after an allocation is made
+    fn on_alloc(&self, info: &AllocInfo);
+
+    /// Called right before an allocation is given back
+    fn on_free(&self, info: &AllocInfo);
+}
+
+static OBSERVER: OnceLock<&'static dyn AllocObserver> = OnceLock::new();
+
+/// Installs the process-wide observer. There can only be one, and it can't be replaced;
+/// if one is already installed, this returns the one that was passed in.
+pub fn set_observer(
+    observer: &'static dyn AllocObserver,
+) -> Result<(), &'static dyn AllocObserver> {
+    OBSERVER.set(observer)
+}
+
+fn info<T: ?Sized>(ptr: *const Inner<T>) -> AllocInfo {
+    AllocInfo {
+        type_name: type_name::<T>(),
+        layout: Layout::for_value(unsafe { &*ptr }),
+        addr: ptr as *const u8 as usize,
+    }
+}
+
+pub(crate) fn record_alloc<T: ?Sized>(ptr: *const Inner<T>) {
+    if let Some(observer) = OBSERVER.get() {
+        observer.on_alloc(&info(ptr));
+    }
+}
+
+pub(crate) fn record_free<T: ?Sized>(ptr: *const Inner<T>) {
+    if let Some(observer) = OBSERVER.get() {
+        observer.on_free(&info(ptr));
+    }
+}
+
+/// Live allocation counts for one type
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Tally {
+    /// How many allocations are live
+    pub count: usize,
+    /// How many bytes those allocations take up
+    pub bytes: usize,
+}
+
+/// An observer that keeps a [`Tally`] for every type
+///
+/// ```
+/// use provenant::profile::{set_observer, TypeTally};
+///
+/// static TALLY: TypeTally = TypeTally::new();
+/// set_observer(&TALLY).ok();
+/// ```
+pub struct TypeTally {
+    tallies: Mutex<BTreeMap<&'static str, Tally>>,
+}
+
+impl TypeTally {
+    /// Creates an empty tally
+    pub const fn new() -> Self {
+        TypeTally {
+            tallies: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Gets the current tallies, ordered by type name. Types with nothing live are left out.
+    pub fn snapshot(&self) -> Vec<(&'static str, Tally)> {
+        let tallies = self.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        tallies
+            .iter()
+            .filter(|(_, tally)| tally.count > 0)
+            .map(|(name, tally)| (*name, *tally))
+            .collect()
+    }
+}
+
+impl Default for TypeTally {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl AllocObserver for TypeTally {
+    fn on_alloc(&self, info: &AllocInfo) {
+        let mut tallies = self.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        let tally = tallies.entry(info.type_name).or_default();
+        tally.count += 1;
+        tally.bytes += info.layout.size();
+    }
+
+    fn on_free(&self, info: &AllocInfo) {
+        let mut tallies = self.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        let tally = tallies.entry(info.type_name).or_default();
+        tally.count = tally.count.saturating_sub(1);
+        tally.bytes = tally.bytes.saturating_sub(info.layout.size());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    #[test]
+    fn tallies_by_type() {
+        static TALLY: TypeTally = TypeTally::new();
+        assert!(set_observer(&TALLY).is_ok());
+
+        let name = type_name::<[u8; 100]>();
+        let find = || TALLY.snapshot().into_iter().find(|(n, _)| *n == name);
+
+        let a = Arc::new([0u8; 100]);
+        let b = Arc::new([1u8; 100]);
+        let tally = find().unwrap().1;
+        assert_eq!(2, tally.count);
+        assert!(tally.bytes >= 200);
+
+        drop(a);
+        assert_eq!(1, find().unwrap().1.count);
+        drop(b);
+        assert!(find().is_none());
+    }
+}
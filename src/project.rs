@@ -0,0 +1,332 @@
+//! Handles to a part of a shared value.
+//!
+//! [`Arc::map`] turns an Arc into an [`ArcRef`] that derefs to one of the value's
+//! fields, while keeping the whole allocation alive. [`Weak::project`] does the
+//! same for weak pointers, giving a [`WeakRef`] that upgrades to an `ArcRef`, and
+//! [`ArcRef::downgrade`] gets one from an `ArcRef` without having to know the
+//! field's offset.
+//!
+//! ```
+//! use provenant::project::ArcRef;
+//! use provenant::Arc;
+//!
+//! struct Config {
+//!     name: String,
+//!     retries: u32,
+//! }
+//!
+//! fn connect(name: ArcRef<str>) {
+//!     assert_eq!("server", &*name);
+//! }
+//!
+//! let config = Arc::new(Config {
+//!     name: "server".into(),
+//!     retries: 3,
+//! });
+//! // no copy of the name is made, and the config stays alive as long as it's used
+//! connect(Arc::map(config.clone(), |c| c.name.as_str()));
+//! ```
+//!
+//! An Arc converts into an `ArcRef` to its whole value, so code taking an `ArcRef`
+//! can be handed either.
+//!
+//! Neither type mentions the type of the whole value, so code that only cares
+//! about one field doesn't need to know where it lives. `#[derive(ArcProject)]`
+//! (`derive` feature) generates the projections for each field of a struct.
+
+use crate::{Arc, Inner, Provenance, Weak};
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::Deref;
+
+// what an ArcRef or WeakRef needs to do with the allocation, without knowing its type.
+// the pointer is an Arc's or Weak's, tag and all
+#[derive(Clone, Copy)]
+struct OwnerFns {
+    clone: unsafe fn(*const ()),
+    drop: unsafe fn(*const ()),
+    // upgrades a weak pointer, returning the address of the value if it worked
+    upgrade: unsafe fn(*const (), Provenance) -> Option<*const u8>,
+    // makes a weak pointer from an Arc, returning its provenance, and where the
+    // value is and how big
+    downgrade: unsafe fn(*const ()) -> (Provenance, *const u8, usize),
+}
+
+unsafe fn clone_owner<T>(ptr: *const ()) {
+    let arc = mem::ManuallyDrop::new(Arc::<T> { ptr: ptr as _ });
+    mem::forget(Arc::clone(&arc));
+}
+
+unsafe fn drop_owner<T>(ptr: *const ()) {
+    drop(Arc::<T> { ptr: ptr as _ });
+}
+
+unsafe fn upgrade_owner<T>(ptr: *const (), provenance: Provenance) -> Option<*const u8> {
+    let weak = Weak::<T> {
+        ptr: ptr as *const Inner<T>,
+        provenance,
+    };
+    let arc = mem::ManuallyDrop::new(weak.upgrade()?);
+    Some(&**arc as *const T as *const u8)
+}
+
+unsafe fn downgrade_owner<T>(ptr: *const ()) -> (Provenance, *const u8, usize) {
+    let arc = mem::ManuallyDrop::new(Arc::<T> { ptr: ptr as _ });
+    let weak = Arc::downgrade(&arc);
+    (
+        weak.provenance,
+        &**arc as *const T as *const u8,
+        mem::size_of::<T>(),
+    )
+}
+
+impl OwnerFns {
+    fn of<T>() -> Self {
+        OwnerFns {
+            clone: clone_owner::<T>,
+            drop: drop_owner::<T>,
+            upgrade: upgrade_owner::<T>,
+            downgrade: downgrade_owner::<T>,
+        }
+    }
+}
+
+/// A strong handle to part of a shared value. Keeps the whole value alive.
+pub struct ArcRef<U: ?Sized> {
+    owner: *const (),
+    fns: OwnerFns,
+    field: *const U,
+}
+
+// the owner is Send + Sync, which Arc::map requires
+unsafe impl<U: ?Sized + Sync> Send for ArcRef<U> {}
+unsafe impl<U: ?Sized + Sync> Sync for ArcRef<U> {}
+
+impl<T: Send + Sync + 'static> Arc<T> {
+    /// Gets a handle to part of the value, which keeps the whole value alive
+    pub fn map<U: ?Sized, F: FnOnce(&T) -> &U>(this: Self, f: F) -> ArcRef<U> {
+        let field = f(&this) as *const U;
+        let owner = this.ptr as *const ();
+        mem::forget(this);
+        ArcRef {
+            owner,
+            fns: OwnerFns::of::<T>(),
+            field,
+        }
+    }
+}
+
+impl<T: Send + Sync + 'static> From<Arc<T>> for ArcRef<T> {
+    fn from(arc: Arc<T>) -> Self {
+        Arc::map(arc, |value| value)
+    }
+}
+
+impl<U: ?Sized> ArcRef<U> {
+    /// Narrows down to part of this part
+    pub fn map<V: ?Sized, F: FnOnce(&U) -> &V>(this: Self, f: F) -> ArcRef<V> {
+        let field = f(&this) as *const V;
+        let projected = ArcRef {
+            owner: this.owner,
+            fns: this.fns,
+            field,
+        };
+        mem::forget(this);
+        projected
+    }
+}
+
+impl<U> ArcRef<U> {
+    /// Gets a weak handle to the same part of the value, or None if it isn't part
+    /// of the value, like a reference to a static that `map` returned
+    pub fn downgrade(this: &Self) -> Option<WeakRef<U>> {
+        let (provenance, value, size) = unsafe { (this.fns.downgrade)(this.owner) };
+        let offset = (this.field as *const u8 as usize).checked_sub(value as usize)?;
+        if offset + mem::size_of::<U>() > size {
+            return None;
+        }
+        Some(WeakRef {
+            owner: this.owner,
+            provenance,
+            fns: this.fns,
+            offset,
+            _field: PhantomData,
+        })
+    }
+}
+
+impl<U: ?Sized> Deref for ArcRef<U> {
+    type Target = U;
+    fn deref(&self) -> &U {
+        unsafe { &*self.field }
+    }
+}
+
+impl<U: ?Sized> Clone for ArcRef<U> {
+    fn clone(&self) -> Self {
+        unsafe { (self.fns.clone)(self.owner) };
+        ArcRef {
+            owner: self.owner,
+            fns: self.fns,
+            field: self.field,
+        }
+    }
+}
+
+impl<U: ?Sized> Drop for ArcRef<U> {
+    fn drop(&mut self) {
+        unsafe { (self.fns.drop)(self.owner) };
+    }
+}
+
+impl<U: ?Sized + fmt::Debug> fmt::Debug for ArcRef<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A weak handle to part of a shared value, which upgrades to an [`ArcRef`]
+pub struct WeakRef<U> {
+    owner: *const (),
+    provenance: Provenance,
+    fns: OwnerFns,
+    // from the start of the whole value
+    offset: usize,
+    _field: PhantomData<*const U>,
+}
+
+unsafe impl<U: Sync> Send for WeakRef<U> {}
+unsafe impl<U: Sync> Sync for WeakRef<U> {}
+
+impl<U> Copy for WeakRef<U> {}
+
+impl<U> Clone for WeakRef<U> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: Send + Sync + 'static> Weak<T> {
+    /// Gets a weak handle to the field `offset` bytes into the value.
+    ///
+    /// Weak pointers can't look at the value before upgrading, so the field is
+    /// given by offset, usually from [`core::mem::offset_of!`].
+    ///
+    /// # Safety
+    ///
+    /// There must be a `U` at `offset` bytes into every `T`.
+    pub unsafe fn project<U>(&self, offset: usize) -> WeakRef<U> {
+        WeakRef {
+            owner: self.ptr as *const (),
+            provenance: self.provenance,
+            fns: OwnerFns::of::<T>(),
+            offset,
+            _field: PhantomData,
+        }
+    }
+}
+
+impl<U> WeakRef<U> {
+    /// Attempts to get a strong handle to the field
+    pub fn upgrade(&self) -> Option<ArcRef<U>> {
+        let value = unsafe { (self.fns.upgrade)(self.owner, self.provenance)? };
+        Some(ArcRef {
+            owner: self.owner,
+            fns: self.fns,
+            field: value.wrapping_add(self.offset) as *const U,
+        })
+    }
+}
+
+impl<U> fmt::Debug for WeakRef<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WeakRef(pv={:#x}, +{})", self.provenance, self.offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::mem::offset_of;
+
+    struct Config {
+        name: String,
+        limits: Limits,
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct Limits {
+        max: u32,
+    }
+
+    fn config() -> Arc<Config> {
+        Arc::new(Config {
+            name: "server".into(),
+            limits: Limits { max: 10 },
+        })
+    }
+
+    #[test]
+    fn map() {
+        let arc = config();
+        let weak = Arc::downgrade(&arc);
+
+        let name: ArcRef<str> = Arc::map(arc, |c| c.name.as_str());
+        let copy = name.clone();
+        drop(name);
+        assert_eq!("server", &*copy);
+        assert!(weak.upgrade().is_some());
+
+        let first = ArcRef::map(copy, |s| &s[..1]);
+        assert_eq!("s", &*first);
+        drop(first);
+        assert!(weak.upgrade().is_none());
+
+        let whole = ArcRef::from(config());
+        assert_eq!(10, whole.limits.max);
+    }
+
+    #[test]
+    fn project() {
+        let arc = config();
+        let limits: WeakRef<Limits> =
+            unsafe { Arc::downgrade(&arc).project(offset_of!(Config, limits)) };
+
+        assert_eq!(Limits { max: 10 }, *limits.upgrade().unwrap());
+        drop(arc);
+        assert!(limits.upgrade().is_none());
+    }
+
+    #[test]
+    fn downgrade() {
+        static ELSEWHERE: u32 = 3;
+
+        let arc = config();
+        let max = Arc::map(arc.clone(), |c| &c.limits.max);
+        let weak = ArcRef::downgrade(&max).unwrap();
+        drop(max);
+        assert_eq!(10, *weak.upgrade().unwrap());
+
+        let outside = Arc::map(arc.clone(), |_| &ELSEWHERE);
+        assert!(ArcRef::downgrade(&outside).is_none());
+
+        drop((arc, outside));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derived() {
+        #[derive(crate::ArcProject)]
+        struct Pair<T>(T, u8);
+
+        let arc = Arc::new(Pair(String::from("a"), 2));
+        let weak = Arc::downgrade(&arc);
+
+        assert_eq!("a", *arc.project_0());
+        assert_eq!(2, *weak.project_1().upgrade().unwrap());
+        drop(arc);
+        assert!(weak.project_0().upgrade().is_none());
+    }
+}
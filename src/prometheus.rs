@@ -0,0 +1,148 @@
+//! Metrics in Prometheus text format (`prometheus` feature).
+//!
+//! The counters are process-wide and always on while the feature is enabled.
+//! Serve the output of [`render`] from a `/metrics` endpoint, or append it to
+//! the output of whatever registry the service already has.
+//! Allocation and drop rates come from applying `rate()` to the counters.
+
+use crate::stats::{
+    self, ALLOCATIONS, COLLISIONS, CONTENDED, DEALLOCATIONS, FAILED_UPGRADES, MISMATCHES, UPGRADES,
+};
+use std::fmt::{self, Write};
+
+/// Renders every metric in the Prometheus text exposition format
+pub fn render() -> String {
+    let mut out = String::new();
+    write_metrics(&mut out).expect("writing to a String can't fail");
+    out
+}
+
+/// Like [`render`], but appends to `out`
+pub fn write_metrics<W: Write>(out: &mut W) -> fmt::Result {
+    let allocations = stats::get(&ALLOCATIONS);
+    let deallocations = stats::get(&DEALLOCATIONS);
+
+    header(
+        out,
+        "provenant_allocations_total",
+        "counter",
+        "Shared allocations made.",
+    )?;
+    writeln!(out, "provenant_allocations_total {}", allocations)?;
+
+    header(
+        out,
+        "provenant_deallocations_total",
+        "counter",
+        "Shared allocations freed after their last strong reference dropped.",
+    )?;
+    writeln!(out, "provenant_deallocations_total {}", deallocations)?;
+
+    header(
+        out,
+        "provenant_live_objects",
+        "gauge",
+        "Shared allocations currently live.",
+    )?;
+    writeln!(
+        out,
+        "provenant_live_objects {}",
+        allocations.saturating_sub(deallocations)
+    )?;
+
+    header(
+        out,
+        "provenant_upgrades_total",
+        "counter",
+        "Weak pointer upgrade attempts, by result.",
+    )?;
+    writeln!(
+        out,
+        "provenant_upgrades_total{{result=\"success\"}} {}",
+        stats::get(&UPGRADES)
+    )?;
+    writeln!(
+        out,
+        "provenant_upgrades_total{{result=\"failure\"}} {}",
+        stats::get(&FAILED_UPGRADES)
+    )?;
+
+    header(
+        out,
+        "provenant_lock_contention_total",
+        "counter",
+        "Times the provenance lock was found held by another thread and retried.",
+    )?;
+    writeln!(
+        out,
+        "provenant_lock_contention_total {}",
+        stats::get(&CONTENDED)
+    )?;
+
+    header(
+        out,
+        "provenant_provenance_mismatches_total",
+        "counter",
+        "Failed upgrades whose target held another provenance id.",
+    )?;
+    writeln!(
+        out,
+        "provenant_provenance_mismatches_total {}",
+        stats::get(&MISMATCHES)
+    )?;
+
+    header(
+        out,
+        "provenant_provenance_collisions_total",
+        "counter",
+        "Upgrades that diagnostics caught matching a reused provenance id.",
+    )?;
+    writeln!(
+        out,
+        "provenant_provenance_collisions_total {}",
+        stats::get(&COLLISIONS)
+    )
+}
+
+fn header<W: Write>(out: &mut W, name: &str, kind: &str, help: &str) -> fmt::Result {
+    writeln!(out, "# HELP {} {}", name, help)?;
+    writeln!(out, "# TYPE {} {}", name, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    fn value(text: &str, metric: &str) -> usize {
+        text.lines()
+            .find(|line| line.starts_with(metric) && line[metric.len()..].starts_with(' '))
+            .and_then(|line| line.rsplit(' ').next())
+            .unwrap()
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn counts_events() {
+        let before = render();
+
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        drop(weak.upgrade());
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+
+        // other tests run at the same time, so only look for increases
+        let after = render();
+        for metric in &[
+            "provenant_allocations_total",
+            "provenant_deallocations_total",
+            "provenant_upgrades_total{result=\"success\"}",
+            "provenant_upgrades_total{result=\"failure\"}",
+        ] {
+            assert!(value(&after, metric) > value(&before, metric), "{}", metric);
+        }
+        assert!(after.contains("# TYPE provenant_live_objects gauge"));
+    }
+}
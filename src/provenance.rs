@@ -0,0 +1,175 @@
+//! Choosing provenance ids explicitly.
+//!
+//! Normally every allocation gets a random provenance id. Deterministic systems,
+//! like replicated state machines, want every replica to hand out the same ids for
+//! the same allocations, so handles mean the same thing everywhere. A
+//! [`ProvenanceSequence`] seeded identically on every replica issues identical
+//! [`ProvenanceToken`]s, which [`Arc::new_with_provenance`] uses instead of the RNG.
+
+use crate::entropy::{splitmix64, widen, ProvenanceSource};
+use crate::{events, release_box, Arc, Inner, Provenance, Weak};
+use alloc::boxed::Box;
+use core::fmt;
+
+/// A provenance id that's valid to give an allocation.
+///
+/// Tokens are consumed when used, so ids issued by a [`ProvenanceSequence`]
+/// are never given to two allocations.
+pub struct ProvenanceToken(Provenance);
+
+impl ProvenanceToken {
+    /// Wraps a raw id, such as one received from another replica.
+    ///
+    /// Returns None if the id isn't valid: ids must be nonzero, and have the low
+    /// bit clear, since that's used for locking. Nothing stops the same raw id
+    /// being wrapped twice, so uniqueness is up to the caller.
+    pub fn from_raw(raw: Provenance) -> Option<Self> {
+        if raw == 0 || raw & 1 != 0 {
+            return None;
+        }
+        Some(ProvenanceToken(raw))
+    }
+
+    /// Draws an id from `source`, like [`Arc::new`] does from the installed one
+    pub fn from_source(source: &dyn ProvenanceSource) -> Self {
+        loop {
+            let raw = widen(|| source.next_u64());
+            if let Some(token) = ProvenanceToken::from_raw(raw ^ (raw & 1)) {
+                return token;
+            }
+        }
+    }
+
+    /// Gets the raw id
+    pub fn get(&self) -> Provenance {
+        self.0
+    }
+}
+
+impl fmt::Debug for ProvenanceToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProvenanceToken({:#x})", self.0)
+    }
+}
+
+/// Issues a deterministic series of distinct [`ProvenanceToken`]s.
+///
+/// Two sequences with the same seed issue the same tokens in the same order.
+/// Tokens from one sequence don't repeat until it has issued `Provenance::MAX / 2` of them.
+#[derive(Debug, Clone)]
+pub struct ProvenanceSequence {
+    base: Provenance,
+    issued: Provenance,
+}
+
+impl ProvenanceSequence {
+    /// Creates a sequence from a seed
+    pub fn new(seed: u64) -> Self {
+        ProvenanceSequence {
+            base: splitmix64(seed) as Provenance & !1,
+            issued: 0,
+        }
+    }
+
+    /// Issues the next token
+    pub fn next_token(&mut self) -> ProvenanceToken {
+        loop {
+            self.issued = self.issued.wrapping_add(1);
+
+            // xor with a fixed base keeps distinct counts distinct
+            let raw = self.base ^ (self.issued << 1);
+            if let Some(token) = ProvenanceToken::from_raw(raw) {
+                return token;
+            }
+        }
+    }
+}
+
+impl Iterator for ProvenanceSequence {
+    type Item = ProvenanceToken;
+
+    fn next(&mut self) -> Option<ProvenanceToken> {
+        Some(self.next_token())
+    }
+}
+
+impl<T> Arc<T> {
+    /// Create a new shared reference with a chosen provenance id instead of a random one
+    pub fn new_with_provenance(val: T, token: ProvenanceToken) -> Self {
+        let inner = Box::new(Inner::new(val, token.0, release_box));
+
+        let inner = Box::into_raw(inner) as *const Inner<T>;
+        events::allocated(inner);
+        Arc { ptr: inner }
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Gets the provenance id this weak pointer expects to find
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replicas_agree() {
+        let mut a = ProvenanceSequence::new(42);
+        let mut b = ProvenanceSequence::new(42);
+
+        for i in 0..10 {
+            let x = Arc::new_with_provenance(i, a.next_token());
+            let y = Arc::new_with_provenance(i, b.next_token());
+            assert_eq!(
+                Arc::downgrade(&x).provenance(),
+                Arc::downgrade(&y).provenance()
+            );
+            assert_eq!(i, *Arc::downgrade(&x).upgrade().unwrap());
+        }
+    }
+
+    #[test]
+    fn distinct_and_valid() {
+        let tokens: Vec<Provenance> = ProvenanceSequence::new(7)
+            .take(1000)
+            .map(|t| t.get())
+            .collect();
+        let mut sorted = tokens.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(tokens.len(), sorted.len());
+        assert!(tokens.iter().all(|&t| t != 0 && t & 1 == 0));
+
+        assert!(ProvenanceToken::from_raw(3).is_none());
+        assert!(ProvenanceToken::from_raw(0).is_none());
+    }
+
+    #[test]
+    fn width() {
+        let bits = if cfg!(feature = "provenance-128") {
+            128
+        } else if cfg!(feature = "wide-provenance") {
+            64
+        } else {
+            usize::BITS
+        };
+        assert_eq!(bits, Provenance::BITS);
+    }
+
+    #[cfg(feature = "provenance-128")]
+    #[test]
+    fn high_half_checked() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let (addr, provenance) = weak.into_raw_parts();
+        assert_ne!(0, provenance >> 64);
+
+        // the low half matches, so this takes the lock before finding out
+        let forged = unsafe { Weak::<i32>::from_raw_parts(addr, provenance ^ 1 << 100) };
+        assert!(forged.upgrade().is_none());
+        assert_eq!(1, *weak.upgrade().unwrap());
+    }
+}
@@ -0,0 +1,176 @@
+//! Holding on to freed allocations for a while before giving them back.
+//!
+//! A stale weak pointer only upgrades to the wrong value if its memory has been
+//! reused by an allocation that drew the same provenance id. That's already
+//! unlikely, but the sooner memory is reused, the more stale pointers are still
+//! around to hit it. With a quarantine configured, memory freed by the last Arc is
+//! kept, with its provenance zeroed so nothing can upgrade to it, and only handed
+//! back to the allocator once newer frees push it out:
+//!
+//! ```
+//! use provenant::quarantine::{self, Quarantine};
+//!
+//! // the last 1024 allocations freed, or 1 MiB of them, whichever is less
+//! quarantine::configure(Quarantine {
+//!     max_blocks: 1024,
+//!     max_bytes: 1 << 20,
+//! });
+//! ```
+//!
+//! It's off unless configured, and only covers allocations made with the global
+//! allocator; pools and custom allocators reuse memory their own way. While it's
+//! off, freeing costs a relaxed load more than it would otherwise.
+
+use alloc::alloc::Layout;
+use std::collections::VecDeque;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// How much freed memory to hold on to. A freed allocation is given back once
+/// holding it would go over either limit
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quarantine {
+    /// How many allocations to hold
+    pub max_blocks: usize,
+    /// How many bytes to hold, counting the headers
+    pub max_bytes: usize,
+}
+
+impl Quarantine {
+    /// Holds up to `max_blocks` allocations, whatever their size
+    pub fn blocks(max_blocks: usize) -> Self {
+        Quarantine {
+            max_blocks,
+            max_bytes: usize::MAX,
+        }
+    }
+
+    /// Holds up to `max_bytes` bytes, however many allocations that is
+    pub fn bytes(max_bytes: usize) -> Self {
+        Quarantine {
+            max_blocks: usize::MAX,
+            max_bytes,
+        }
+    }
+}
+
+// a freed allocation, which nothing else points to but stale weak pointers
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for Block {}
+
+struct State {
+    limits: Quarantine,
+    // oldest first
+    held: VecDeque<Block>,
+    bytes: usize,
+}
+
+static STATE: Mutex<State> = Mutex::new(State {
+    limits: Quarantine {
+        max_blocks: 0,
+        max_bytes: 0,
+    },
+    held: VecDeque::new(),
+    bytes: 0,
+});
+
+// lets release skip the mutex while there's no quarantine
+static ON: AtomicBool = AtomicBool::new(false);
+
+/// Sets how much freed memory is held, giving back whatever's over the new limits.
+/// `Quarantine::default()` turns it off.
+pub fn configure(limits: Quarantine) {
+    let mut state = lock();
+    state.limits = limits;
+    ON.store(
+        limits.max_blocks > 0 && limits.max_bytes > 0,
+        Ordering::Relaxed,
+    );
+    state.evict();
+}
+
+/// Gives back everything that's held, without changing the limits
+pub fn flush() {
+    let mut state = lock();
+    while let Some(block) = state.held.pop_front() {
+        state.bytes -= block.layout.size();
+        unsafe { alloc::alloc::dealloc(block.ptr.as_ptr(), block.layout) };
+    }
+}
+
+/// How many freed allocations are being held
+pub fn len() -> usize {
+    lock().held.len()
+}
+
+/// How many bytes of freed allocations are being held
+pub fn bytes() -> usize {
+    lock().bytes
+}
+
+// takes memory the last Arc let go of. false if there's no quarantine, and the
+// caller should free it. under loom, nothing is freed
+#[cfg_attr(loom, allow(dead_code))]
+pub(crate) unsafe fn hold(ptr: *mut u8, layout: Layout) -> bool {
+    if !ON.load(Ordering::Relaxed) {
+        return false;
+    }
+    let ptr = match NonNull::new(ptr) {
+        Some(ptr) => ptr,
+        None => return false,
+    };
+
+    let mut state = lock();
+    state.held.push_back(Block { ptr, layout });
+    state.bytes += layout.size();
+    // if it's been turned off meanwhile, this gives the block straight back
+    state.evict();
+    true
+}
+
+impl State {
+    fn evict(&mut self) {
+        while self.held.len() > self.limits.max_blocks || self.bytes > self.limits.max_bytes {
+            let block = match self.held.pop_front() {
+                Some(block) => block,
+                None => return,
+            };
+            self.bytes -= block.layout.size();
+            unsafe { alloc::alloc::dealloc(block.ptr.as_ptr(), block.layout) };
+        }
+    }
+}
+
+fn lock() -> MutexGuard<'static, State> {
+    STATE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    #[test]
+    fn held_while_configured() {
+        configure(Quarantine::blocks(usize::MAX));
+        let arc = Arc::new([7u8; 64]);
+        let weak = Arc::downgrade(&arc);
+        let addr = weak.addr();
+        drop(arc);
+
+        // still there, but dead
+        assert!(lock()
+            .held
+            .iter()
+            .any(|block| block.ptr.as_ptr() as usize == addr));
+        assert!(weak.upgrade().is_none());
+
+        configure(Quarantine::default());
+        assert_eq!((0, 0), (len(), bytes()));
+    }
+}
@@ -0,0 +1,260 @@
+//! Weak pointers as plain integers, and Arcs as raw pointers, for FFI and handle tables.
+//!
+//! [`Weak::into_raw`] splits a weak pointer into an address and a provenance id,
+//! which can be handed across an FFI boundary or stored by code that can't hold
+//! Rust types, and [`Weak::from_raw`] puts it back together. Where only one integer
+//! fits, [`RawWeak::to_bits`] packs both into a `u128`, except with the
+//! `provenance-128` feature, where the id alone takes that much.
+//!
+//! [`Arc::into_raw`] turns an Arc into a pointer to its value, still holding its
+//! strong reference, and [`Arc::from_raw`] turns it back. Like std's, the count can
+//! be adjusted through such a pointer, with [`Arc::increment_strong_count`] and
+//! [`Arc::decrement_strong_count`]. Tags don't survive the trip.
+//!
+//! With the `salted-handles` feature, the provenance id in a [`RawWeak`] is mixed
+//! with a secret chosen once per process, keyed by the address. Untrusted code that
+//! learns an address, or a handle to some other allocation, can't work out what
+//! provenance to pair it with, so a fabricated handle fails to upgrade instead of
+//! reaching the allocation.
+
+use crate::tag::untagged;
+use crate::{Arc, Inner, Provenance, Weak};
+use core::mem::{self, ManuallyDrop};
+use core::ptr;
+
+/// A weak pointer as two integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawWeak {
+    /// The allocation's address
+    pub addr: usize,
+    /// The provenance id, salted if `salted-handles` is on
+    pub provenance: Provenance,
+}
+
+impl RawWeak {
+    /// Packs the address and provenance id into one integer, the address in the
+    /// high half
+    #[cfg(not(feature = "provenance-128"))]
+    pub fn to_bits(self) -> u128 {
+        (self.addr as u128) << 64 | self.provenance as u128
+    }
+
+    /// Unpacks an integer from [`RawWeak::to_bits`]
+    #[cfg(not(feature = "provenance-128"))]
+    pub fn from_bits(bits: u128) -> Self {
+        RawWeak {
+            addr: (bits >> 64) as usize,
+            provenance: bits as u64 as Provenance,
+        }
+    }
+}
+
+impl<T> Weak<T> {
+    /// Turns this into integers that [`Weak::from_raw`] accepts
+    pub fn into_raw(self) -> RawWeak {
+        let addr = self.ptr as usize;
+        RawWeak {
+            addr,
+            provenance: salt(addr, self.provenance),
+        }
+    }
+
+    /// Puts a weak pointer back together from [`Weak::into_raw`]'s output.
+    ///
+    /// # Safety
+    ///
+    /// `raw.addr` must have come from `into_raw` on a `Weak<T>`, though the
+    /// allocation doesn't need to be alive. The provenance can be anything: a
+    /// wrong one makes the weak pointer fail to upgrade.
+    pub unsafe fn from_raw(raw: RawWeak) -> Self {
+        Weak {
+            ptr: raw.addr as *const _,
+            provenance: salt(raw.addr, raw.provenance),
+        }
+    }
+
+    /// Like [`Weak::into_raw`], as an `(address, provenance)` pair
+    pub fn into_raw_parts(self) -> (usize, Provenance) {
+        let raw = self.into_raw();
+        (raw.addr, raw.provenance)
+    }
+
+    /// Like [`Weak::from_raw`], from [`Weak::into_raw_parts`]'s pair
+    ///
+    /// # Safety
+    ///
+    /// The same as for [`Weak::from_raw`].
+    pub unsafe fn from_raw_parts(addr: usize, provenance: Provenance) -> Self {
+        Weak::from_raw(RawWeak { addr, provenance })
+    }
+}
+
+impl<T> Arc<T> {
+    /// Gets a pointer to the value, which stays valid as long as an Arc to it exists
+    pub fn as_ptr(this: &Self) -> *const T {
+        unsafe { ptr::addr_of!((*untagged(this.ptr)).data) }
+    }
+
+    /// Turns this into a pointer to the value, without giving up the strong
+    /// reference. [`Arc::from_raw`] takes it back
+    pub fn into_raw(this: Self) -> *const T {
+        let ptr = Arc::as_ptr(&this);
+        mem::forget(this);
+        ptr
+    }
+
+    /// Takes back a strong reference given up by [`Arc::into_raw`]. The Arc has no tag.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `Arc::<T>::into_raw`, and each pointer it returns
+    /// can be taken back once, plus once more for every
+    /// [`increment_strong_count`](Arc::increment_strong_count) on it.
+    pub unsafe fn from_raw(ptr: *const T) -> Self {
+        Arc {
+            ptr: ptr.wrapping_byte_sub(mem::offset_of!(Inner<T>, data)) as *const Inner<T>,
+        }
+    }
+
+    /// Adds a strong reference to the value behind a pointer from [`Arc::into_raw`],
+    /// for a later [`Arc::from_raw`] to take.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have come from `Arc::<T>::into_raw`, and the strong reference it
+    /// holds must not have been taken back yet.
+    pub unsafe fn increment_strong_count(ptr: *const T) {
+        let arc = ManuallyDrop::new(Arc::from_raw(ptr));
+        mem::forget(Arc::clone(&arc));
+    }
+
+    /// Drops a strong reference to the value behind a pointer from [`Arc::into_raw`].
+    ///
+    /// # Safety
+    ///
+    /// Same as [`Arc::from_raw`]: it takes back one of the strong references.
+    pub unsafe fn decrement_strong_count(ptr: *const T) {
+        drop(Arc::from_raw(ptr));
+    }
+}
+
+// salting is an xor, so this also unsalts
+#[cfg(not(feature = "salted-handles"))]
+fn salt(_addr: usize, provenance: Provenance) -> Provenance {
+    provenance
+}
+
+#[cfg(feature = "salted-handles")]
+fn salt(addr: usize, provenance: Provenance) -> Provenance {
+    use std::sync::OnceLock;
+
+    static SALT: OnceLock<u64> = OnceLock::new();
+    let salt = *SALT.get_or_init(secret);
+
+    let key = mix(salt ^ addr as u64);
+    // both halves of a 128-bit id are salted, the high one with the next output
+    #[cfg(feature = "provenance-128")]
+    let key = (mix(key) as u128) << 64 | key as u128;
+
+    // the low bit stays clear, so salted ids look like any other
+    #[allow(clippy::unnecessary_cast)]
+    let key = key as Provenance & !1;
+    provenance ^ key
+}
+
+#[cfg(all(
+    feature = "salted-handles",
+    not(all(target_arch = "wasm32", target_os = "unknown"))
+))]
+fn secret() -> u64 {
+    use rand::Rng;
+    rand::thread_rng().gen()
+}
+
+// rand isn't available there, so it's whatever provenance ids come from
+#[cfg(all(
+    feature = "salted-handles",
+    all(target_arch = "wasm32", target_os = "unknown")
+))]
+fn secret() -> u64 {
+    crate::entropy::next()
+}
+
+// splitmix64's finalizer
+#[cfg(feature = "salted-handles")]
+fn mix(x: u64) -> u64 {
+    let x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    let x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    #[test]
+    fn round_trip() {
+        let arc = Arc::new(5);
+        let raw = Arc::downgrade(&arc).into_raw();
+        let weak = unsafe { Weak::<i32>::from_raw(raw) };
+        assert_eq!(5, *weak.upgrade().unwrap());
+
+        let forged = RawWeak {
+            provenance: raw.provenance ^ 2,
+            ..raw
+        };
+        assert!(unsafe { Weak::<i32>::from_raw(forged) }.upgrade().is_none());
+    }
+
+    #[test]
+    fn packed() {
+        let arc = Arc::new(5);
+        #[cfg(not(feature = "provenance-128"))]
+        {
+            let raw = Arc::downgrade(&arc).into_raw();
+            assert_eq!(raw, RawWeak::from_bits(raw.to_bits()));
+        }
+
+        let (addr, provenance) = Arc::downgrade(&arc).into_raw_parts();
+        let weak = unsafe { Weak::<i32>::from_raw_parts(addr, provenance) };
+        assert_eq!(5, *weak.upgrade().unwrap());
+    }
+
+    #[test]
+    fn arc_round_trip() {
+        let arc = Arc::with_tag(Arc::new(String::from("raw")), 1);
+        let weak = Arc::downgrade(&arc);
+        let ptr = Arc::into_raw(arc);
+        assert_eq!("raw", unsafe { &*ptr });
+
+        unsafe { Arc::increment_strong_count(ptr) };
+        let arc = unsafe { Arc::from_raw(ptr) };
+        assert_eq!(0, Arc::tag(&arc));
+        assert_eq!(2, Arc::strong_count(&arc));
+        assert_eq!(ptr, Arc::as_ptr(&arc));
+
+        unsafe { Arc::decrement_strong_count(ptr) };
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[cfg(feature = "salted-handles")]
+    #[test]
+    fn salted() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        let raw = weak.into_raw();
+        assert_ne!(weak.provenance(), raw.provenance);
+        #[cfg(feature = "provenance-128")]
+        assert_ne!(weak.provenance() >> 64, raw.provenance >> 64);
+
+        // a handle's provenance doesn't work at another address
+        let other = Arc::new(6);
+        let moved = RawWeak {
+            addr: Arc::downgrade(&other).into_raw().addr,
+            ..raw
+        };
+        assert!(unsafe { Weak::<i32>::from_raw(moved) }.upgrade().is_none());
+    }
+}
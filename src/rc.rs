@@ -0,0 +1,168 @@
+//! Single-threaded reference counting.
+//!
+//! [`Rc`] and [`WeakRc`] work like [`Arc`](crate::Arc) and [`Weak`](crate::Weak),
+//! provenance ids and all, but with plain `Cell` counters. Nothing can race, so
+//! upgrading doesn't need the provenance lock either. They're neither `Send` nor
+//! `Sync`.
+
+use crate::{random_provenance, Provenance};
+use alloc::boxed::Box;
+use core::cell::Cell;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+struct RcInner<T: ?Sized> {
+    provenance: Cell<Provenance>,
+    ref_count: Cell<usize>,
+    data: T,
+}
+
+impl<T: ?Sized> Drop for RcInner<T> {
+    fn drop(&mut self) {
+        // same as Inner: make sure the id is really gone from the freed memory
+        unsafe {
+            ptr::write_volatile(&mut self.provenance, Cell::new(0));
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+/// A single-threaded reference counted pointer
+pub struct Rc<T: ?Sized> {
+    ptr: *const RcInner<T>,
+}
+
+/// A weak pointer to an [`Rc`]
+///
+/// Works like [`Weak`](crate::Weak): doesn't keep anything alive, and will usually
+/// fail to upgrade once the last `Rc` is gone.
+pub struct WeakRc<T: ?Sized> {
+    provenance: Provenance,
+    ptr: *const RcInner<T>,
+    // not Send or Sync, like Rc
+    _marker: PhantomData<*const ()>,
+}
+
+impl<T: ?Sized> Copy for WeakRc<T> {}
+
+impl<T: ?Sized> Clone for WeakRc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Rc<T> {
+    /// Create a new shared reference
+    pub fn new(val: T) -> Self {
+        let inner = Box::new(RcInner {
+            provenance: Cell::new(random_provenance()),
+            ref_count: Cell::new(1),
+            data: val,
+        });
+        Rc {
+            ptr: Box::into_raw(inner),
+        }
+    }
+}
+
+impl<T: ?Sized> Rc<T> {
+    fn inner(&self) -> &RcInner<T> {
+        unsafe { &*self.ptr }
+    }
+
+    /// Gets a weak reference to the same memory
+    pub fn downgrade(this: &Self) -> WeakRc<T> {
+        WeakRc {
+            provenance: this.inner().provenance.get(),
+            ptr: this.ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Turns this into a weak reference, giving up the strong one
+    pub fn into_weak(this: Self) -> WeakRc<T> {
+        Rc::downgrade(&this)
+    }
+
+    /// How many `Rc`s point at the value
+    pub fn strong_count(this: &Self) -> usize {
+        this.inner().ref_count.get()
+    }
+}
+
+impl<T: ?Sized> WeakRc<T> {
+    /// Attempts to get a strong reference to the pointed-to memory. Will probably fail
+    /// and return None if there are no strong pointers left.
+    pub fn upgrade(&self) -> Option<Rc<T>> {
+        let inner = unsafe { &*self.ptr };
+        if inner.provenance.get() != self.provenance {
+            return None;
+        }
+        inner.ref_count.set(inner.ref_count.get() + 1);
+        Some(Rc { ptr: self.ptr })
+    }
+
+    /// Gets the provenance id this weak pointer expects to find
+    pub fn provenance(&self) -> Provenance {
+        self.provenance
+    }
+}
+
+impl<T: ?Sized> Deref for Rc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner().data
+    }
+}
+
+impl<T: ?Sized> Clone for Rc<T> {
+    fn clone(&self) -> Self {
+        let inner = self.inner();
+        inner.ref_count.set(inner.ref_count.get() + 1);
+        Rc { ptr: self.ptr }
+    }
+}
+
+impl<T: ?Sized> Drop for Rc<T> {
+    fn drop(&mut self) {
+        let inner = self.inner();
+        let count = inner.ref_count.get() - 1;
+        inner.ref_count.set(count);
+        if count == 0 {
+            drop(unsafe { Box::from_raw(self.ptr as *mut RcInner<T>) });
+        }
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for Rc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for WeakRc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WeakRc(pv={:#x})", self.provenance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_until_dropped() {
+        let rc = Rc::new(String::from("local"));
+        let weak = Rc::downgrade(&rc);
+        let other = weak.upgrade().unwrap();
+        assert_eq!(2, Rc::strong_count(&rc));
+
+        drop(rc);
+        assert_eq!("local", *weak.upgrade().unwrap());
+        drop(other);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,177 @@
+//! A service locator that doesn't keep services alive.
+//!
+//! A [`Registry`] maps names, or types, to shared values. It only holds weak
+//! pointers, so whoever created a service decides how long it lives: once the last
+//! Arc to it drops, its entry is gone, and lookups return None. Consumers that
+//! mustn't extend a service's lifetime can use [`get_weak`](Registry::get_weak).
+//!
+//! [`Registry::global`] is a process-wide instance, for code that would otherwise
+//! reach for a `lazy_static`.
+
+use crate::sweep::Sweep;
+use crate::{Arc, Weak};
+use std::any::{Any, TypeId};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, OnceLock, PoisonError};
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Name(Cow<'static, str>),
+    Type(TypeId),
+}
+
+trait Slot: Send + Sync {
+    fn alive(&self) -> bool;
+    fn as_any(&self) -> &dyn Any;
+}
+
+impl<T: Send + Sync + 'static> Slot for Weak<T> {
+    fn alive(&self) -> bool {
+        Weak::is_probably_alive(self)
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+}
+
+/// Named and typed shared values, held weakly
+#[derive(Default)]
+pub struct Registry {
+    entries: Mutex<HashMap<Key, Box<dyn Slot>>>,
+}
+
+impl Registry {
+    /// Creates an empty registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gets the process-wide registry
+    pub fn global() -> &'static Registry {
+        static GLOBAL: OnceLock<Registry> = OnceLock::new();
+        GLOBAL.get_or_init(Registry::new)
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<Key, Box<dyn Slot>>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn insert_key<T: Send + Sync + 'static>(&self, key: Key, arc: &Arc<T>) {
+        self.entries().insert(key, Box::new(Arc::downgrade(arc)));
+    }
+
+    fn get_key<T: Send + Sync + 'static>(&self, key: &Key) -> Option<Weak<T>> {
+        let mut entries = self.entries();
+        let slot = entries.get(key)?;
+        if !slot.alive() {
+            entries.remove(key);
+            return None;
+        }
+        slot.as_any().downcast_ref::<Weak<T>>().copied()
+    }
+
+    /// Registers `arc` under `name`, replacing whatever was there
+    pub fn insert<T, N>(&self, name: N, arc: &Arc<T>)
+    where
+        T: Send + Sync + 'static,
+        N: Into<Cow<'static, str>>,
+    {
+        self.insert_key(Key::Name(name.into()), arc);
+    }
+
+    /// Registers `arc` as the value of its type, replacing whatever was there
+    pub fn insert_type<T: Send + Sync + 'static>(&self, arc: &Arc<T>) {
+        self.insert_key(Key::Type(TypeId::of::<T>()), arc);
+    }
+
+    /// Gets the value registered under `name`, if it's alive and a `T`
+    pub fn get<T: Send + Sync + 'static>(&self, name: &str) -> Option<Arc<T>> {
+        self.get_weak(name)?.upgrade()
+    }
+
+    /// Gets a weak pointer to the value registered under `name`, if it's alive and a `T`
+    pub fn get_weak<T: Send + Sync + 'static>(&self, name: &str) -> Option<Weak<T>> {
+        self.get_key(&Key::Name(Cow::Owned(name.to_owned())))
+    }
+
+    /// Gets the value registered for type `T`, if it's alive
+    pub fn get_type<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.get_weak_type()?.upgrade()
+    }
+
+    /// Gets a weak pointer to the value registered for type `T`, if it's alive
+    pub fn get_weak_type<T: Send + Sync + 'static>(&self) -> Option<Weak<T>> {
+        self.get_key(&Key::Type(TypeId::of::<T>()))
+    }
+
+    /// Unregisters whatever is under `name`
+    pub fn remove(&self, name: &str) {
+        self.entries()
+            .remove(&Key::Name(Cow::Owned(name.to_owned())));
+    }
+
+    /// Unregisters whatever is registered for type `T`
+    pub fn remove_type<T: 'static>(&self) {
+        self.entries().remove(&Key::Type(TypeId::of::<T>()));
+    }
+
+    /// How many registered values are still alive
+    pub fn len(&self) -> usize {
+        self.sweep();
+        self.entries().len()
+    }
+
+    /// Whether no registered values are alive
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Sweep for Registry {
+    fn sweep(&self) -> usize {
+        let mut entries = self.entries();
+        let before = entries.len();
+        entries.retain(|_, slot| slot.alive());
+        before - entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Database {
+        url: &'static str,
+    }
+
+    #[test]
+    fn lookups() {
+        let registry = Registry::new();
+        let db = Arc::new(Database { url: "db://" });
+        registry.insert("db", &db);
+        registry.insert_type(&db);
+
+        assert_eq!("db://", registry.get::<Database>("db").unwrap().url);
+        assert!(registry.get::<String>("db").is_none());
+        assert!(registry.get_weak_type::<Database>().is_some());
+        assert_eq!(2, registry.len());
+
+        drop(db);
+        assert!(registry.get::<Database>("db").is_none());
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn global() {
+        let config = Arc::new(String::from("debug"));
+        Registry::global().insert("registry::tests::config", &config);
+        assert_eq!(
+            "debug",
+            *Registry::global()
+                .get::<String>("registry::tests::config")
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,222 @@
+//! A single-producer single-consumer ring of shared values.
+//!
+//! [`ring`] makes a bounded queue of Arcs between two threads. Values go in and
+//! come out as Arcs, so nothing is copied between pipeline stages, and pushing and
+//! popping never wait on the other side.
+//!
+//! Pushing hands back a weak "peek" handle, which the producer (or anything it's
+//! passed to) can use to look at the value while it's queued or being worked on.
+//! Once the consumer lets go of the value, and nothing else holds it, the peek
+//! handle stops upgrading.
+
+use crate::{Arc, Weak};
+use alloc::boxed::Box;
+use core::cell::UnsafeCell;
+use core::fmt;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+struct Ring<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<Arc<T>>>]>,
+    // how many values have ever been popped and pushed. the ones in between are queued
+    head: AtomicUsize,
+    tail: AtomicUsize,
+}
+
+// each slot is only touched by one side at a time, which head and tail arbitrate
+unsafe impl<T: Send + Sync> Send for Ring<T> {}
+unsafe impl<T: Send + Sync> Sync for Ring<T> {}
+
+impl<T> Ring<T> {
+    fn slot(&self, n: usize) -> *mut MaybeUninit<Arc<T>> {
+        self.slots[n % self.slots.len()].get()
+    }
+}
+
+impl<T> Drop for Ring<T> {
+    fn drop(&mut self) {
+        let head = *self.head.get_mut();
+        let tail = *self.tail.get_mut();
+        for n in head..tail {
+            unsafe { (*self.slot(n)).assume_init_drop() };
+        }
+    }
+}
+
+/// Creates a ring with room for `capacity` values.
+///
+/// # Panics
+///
+/// If `capacity` is 0.
+pub fn ring<T>(capacity: usize) -> (Producer<T>, Consumer<T>) {
+    assert!(capacity != 0, "ring capacity must be nonzero");
+    let ring = Arc::new(Ring {
+        slots: (0..capacity)
+            .map(|_| UnsafeCell::new(MaybeUninit::uninit()))
+            .collect(),
+        head: AtomicUsize::new(0),
+        tail: AtomicUsize::new(0),
+    });
+    (
+        Producer {
+            ring: ring.clone(),
+            tail: 0,
+        },
+        Consumer { ring, head: 0 },
+    )
+}
+
+/// The pushing end of a [`ring`]
+pub struct Producer<T> {
+    ring: Arc<Ring<T>>,
+    // a copy of ring.tail, which only this side changes
+    tail: usize,
+}
+
+impl<T> Producer<T> {
+    /// Queues an Arc, returning a peek handle to it, or gives it back if the ring is full
+    pub fn push_arc(&mut self, arc: Arc<T>) -> Result<Weak<T>, Arc<T>> {
+        if self.is_full() {
+            return Err(arc);
+        }
+        Ok(self.write(arc))
+    }
+
+    /// Shares a value and queues it, returning a peek handle to it,
+    /// or gives it back if the ring is full
+    pub fn push(&mut self, value: T) -> Result<Weak<T>, T> {
+        if self.is_full() {
+            return Err(value);
+        }
+        Ok(self.write(Arc::new(value)))
+    }
+
+    // only this side adds values, so once there's room, there stays room
+    fn write(&mut self, arc: Arc<T>) -> Weak<T> {
+        let peek = Arc::downgrade(&arc);
+        unsafe { (*self.ring.slot(self.tail)).write(arc) };
+        self.tail += 1;
+        self.ring.tail.store(self.tail, Ordering::Release);
+        peek
+    }
+
+    /// Whether there's no room to push
+    pub fn is_full(&self) -> bool {
+        self.tail - self.ring.head.load(Ordering::Acquire) == self.ring.slots.len()
+    }
+
+    /// How many values the ring holds at most
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+}
+
+/// The popping end of a [`ring`]
+pub struct Consumer<T> {
+    ring: Arc<Ring<T>>,
+    // a copy of ring.head, which only this side changes
+    head: usize,
+}
+
+impl<T> Consumer<T> {
+    /// Takes the oldest queued value, if there is one
+    pub fn pop(&mut self) -> Option<Arc<T>> {
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if self.head == tail {
+            return None;
+        }
+
+        let arc = unsafe { (*self.ring.slot(self.head)).assume_init_read() };
+        self.head += 1;
+        self.ring.head.store(self.head, Ordering::Release);
+        Some(arc)
+    }
+
+    /// Gets a peek handle to the oldest queued value, without taking it
+    pub fn peek(&self) -> Option<Weak<T>> {
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        if self.head == tail {
+            return None;
+        }
+        Some(Arc::downgrade(unsafe {
+            (*self.ring.slot(self.head)).assume_init_ref()
+        }))
+    }
+
+    /// How many values are queued
+    pub fn len(&self) -> usize {
+        self.ring.tail.load(Ordering::Acquire) - self.head
+    }
+
+    /// Whether nothing is queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> fmt::Debug for Producer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Producer")
+            .field("capacity", &self.capacity())
+            .finish()
+    }
+}
+
+impl<T> fmt::Debug for Consumer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Consumer")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn peek_handles() {
+        let (mut tx, mut rx) = ring(2);
+        let first = tx.push(String::from("a")).unwrap();
+        tx.push("b".into()).unwrap();
+        assert_eq!(Err(String::from("c")), tx.push("c".into()));
+
+        assert_eq!("a", *rx.peek().unwrap().upgrade().unwrap());
+        let popped = rx.pop().unwrap();
+        assert_eq!("a", *first.upgrade().unwrap());
+        drop(popped);
+        assert!(first.upgrade().is_none());
+
+        // queued values are dropped along with the ring
+        let second = rx.peek().unwrap();
+        drop((tx, rx));
+        assert!(second.upgrade().is_none());
+    }
+
+    #[test]
+    fn across_threads() {
+        let (mut tx, mut rx) = ring(4);
+        let producer = thread::spawn(move || {
+            for i in 0..1000 {
+                let mut value = i;
+                while let Err(back) = tx.push(value) {
+                    value = back;
+                    thread::yield_now();
+                }
+            }
+        });
+
+        let mut next = 0;
+        while next < 1000 {
+            match rx.pop() {
+                Some(value) => {
+                    assert_eq!(next, *value);
+                    next += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        producer.join().unwrap();
+    }
+}
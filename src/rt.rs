@@ -0,0 +1,181 @@
+//! Real-time friendly hand-off of shared values between threads.
+//!
+//! [`TripleBuffer`] lets one writer thread publish [`Arc`]s that a single
+//! reader thread (an audio callback, a render loop) picks up.
+//!
+//! The reader side makes these guarantees:
+//! - wait-free: [`Reader::read`] is one atomic load and at most one atomic swap, no loops
+//! - lock-free: it never takes the provenance lock or any other lock
+//! - allocation-free: it never allocates, and never frees either. stale values are
+//!   handed back to the writer, so the last `Arc` to a payload is always dropped
+//!   on the writer's thread
+//!
+//! The writer side is also wait-free, but it drops whatever it replaces.
+
+use crate::Arc;
+use std::cell::UnsafeCell;
+use std::mem;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+// the shared state holds the index of the middle slot, plus a flag saying
+// the writer put something there that the reader hasn't seen yet
+const INDEX_MASK: u8 = 0b011;
+const DIRTY: u8 = 0b100;
+
+/// A wait-free single-producer single-consumer swap slot for [`Arc`]s
+///
+/// Construct one with [`TripleBuffer::new`], which hands back the two ends.
+pub struct TripleBuffer<T> {
+    slots: [UnsafeCell<Arc<T>>; 3],
+    state: AtomicU8,
+}
+
+/// The publishing end of a [`TripleBuffer`]
+pub struct Writer<T> {
+    shared: Arc<TripleBuffer<T>>,
+    back: u8,
+}
+
+/// The consuming end of a [`TripleBuffer`]
+pub struct Reader<T> {
+    shared: Arc<TripleBuffer<T>>,
+    front: u8,
+}
+
+// each slot is only ever touched by whichever end currently owns its index,
+// so the ends can move between threads as long as the payload can
+unsafe impl<T: Send + Sync> Send for Writer<T> {}
+unsafe impl<T: Send + Sync> Send for Reader<T> {}
+
+impl<T> TripleBuffer<T> {
+    /// Creates a triple buffer whose reader initially sees `initial`
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(initial: Arc<T>) -> (Writer<T>, Reader<T>) {
+        let shared = Arc::new(TripleBuffer {
+            slots: [
+                UnsafeCell::new(initial.clone()),
+                UnsafeCell::new(initial.clone()),
+                UnsafeCell::new(initial),
+            ],
+            state: AtomicU8::new(1),
+        });
+
+        let writer = Writer {
+            shared: shared.clone(),
+            back: 2,
+        };
+        let reader = Reader { shared, front: 0 };
+        (writer, reader)
+    }
+}
+
+impl<T> Writer<T> {
+    /// Makes `value` the latest value for the reader.
+    ///
+    /// Returns whatever the writer's slot held before, which is either a value the
+    /// reader has already moved past or one it never saw. Dropping it here keeps
+    /// deallocation off the reader's thread.
+    pub fn publish(&mut self, value: Arc<T>) -> Arc<T> {
+        let slot = unsafe { &mut *self.shared.slots[self.back as usize].get() };
+        let old = mem::replace(slot, value);
+
+        let prev = self.shared.state.swap(self.back | DIRTY, Ordering::AcqRel);
+        self.back = prev & INDEX_MASK;
+
+        old
+    }
+}
+
+impl<T> Reader<T> {
+    /// Gets the most recently published value.
+    ///
+    /// Wait-free, lock-free, and never allocates or deallocates.
+    pub fn read(&mut self) -> &T {
+        self.read_arc()
+    }
+
+    /// Like [`read`](Reader::read), but gives access to the `Arc` so it can be cloned.
+    ///
+    /// Cloning is a single `fetch_add`, but dropping the clone on this thread may
+    /// deallocate, so real-time code should send clones elsewhere to be dropped.
+    pub fn read_arc(&mut self) -> &Arc<T> {
+        if self.shared.state.load(Ordering::Relaxed) & DIRTY != 0 {
+            let prev = self.shared.state.swap(self.front, Ordering::AcqRel);
+            self.front = prev & INDEX_MASK;
+        }
+
+        unsafe { &*self.shared.slots[self.front as usize].get() }
+    }
+
+    /// Returns true if the writer has published something the reader hasn't seen yet
+    pub fn has_update(&self) -> bool {
+        self.shared.state.load(Ordering::Relaxed) & DIRTY != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocator::{ArcAllocator, Global};
+    use std::alloc::Layout;
+    use std::cell::Cell;
+    use std::ptr::NonNull;
+    use std::thread;
+
+    // counts the payloads' allocations and frees on each thread
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl ArcAllocator for CountingAlloc {
+        fn allocate(&self, layout: Layout) -> Option<NonNull<u8>> {
+            ALLOCS.with(|a| a.set(a.get() + 1));
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            ALLOCS.with(|a| a.set(a.get() + 1));
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    fn heap_ops() -> usize {
+        ALLOCS.with(|a| a.get())
+    }
+
+    #[test]
+    fn sees_latest() {
+        let (mut writer, mut reader) = TripleBuffer::new(Arc::new(1));
+        assert_eq!(1, *reader.read());
+        assert!(!reader.has_update());
+
+        writer.publish(Arc::new(2));
+        writer.publish(Arc::new(3));
+        assert!(reader.has_update());
+        assert_eq!(3, *reader.read());
+        assert_eq!(3, *reader.read());
+    }
+
+    #[test]
+    fn reader_never_touches_heap() {
+        let (mut writer, mut reader) = TripleBuffer::new(Arc::new_in(0usize, CountingAlloc));
+
+        let handle = thread::spawn(move || {
+            let before = heap_ops();
+            let mut last = 0;
+            while last < 1000 {
+                let v = *reader.read();
+                assert!(v >= last);
+                last = v;
+            }
+            assert_eq!(before, heap_ops());
+        });
+
+        for i in 1..=1000 {
+            drop(writer.publish(Arc::new_in(i, CountingAlloc)));
+        }
+        handle.join().unwrap();
+    }
+}
@@ -0,0 +1,111 @@
+//! Sharing an Arc within a scope without touching the ref count.
+//!
+//! Cloning an [`Arc`] is an atomic increment, and dropping one is an atomic
+//! decrement, which adds up when a value is passed around a lot. Inside
+//! [`Arc::scope`], the value can be shared through [`ScopedArc`] handles instead.
+//! They're `Copy`, cost nothing to pass around, and can't leave the scope, which
+//! keeps one strong reference alive for all of them. A handle that needs to outlive
+//! the scope is turned into a real Arc with [`ScopedArc::to_arc`].
+
+use crate::{Arc, Weak};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+/// A handle to an Arc's value that can't outlive the [`Arc::scope`] it came from
+pub struct ScopedArc<'s, T: ?Sized> {
+    arc: &'s Arc<T>,
+    // invariant, so one scope's handles can't be passed off as another's
+    _scope: PhantomData<fn(&'s ()) -> &'s ()>,
+}
+
+impl<T: ?Sized> Copy for ScopedArc<'_, T> {}
+
+impl<T: ?Sized> Clone for ScopedArc<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'s, T: ?Sized> ScopedArc<'s, T> {
+    /// Gets a real Arc, which can outlive the scope
+    pub fn to_arc(self) -> Arc<T> {
+        self.arc.clone()
+    }
+
+    /// Gets a weak reference to the value
+    pub fn downgrade(self) -> Weak<T> {
+        Arc::downgrade(self.arc)
+    }
+}
+
+impl<T: ?Sized> Deref for ScopedArc<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.arc
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for ScopedArc<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Runs `f` with a [`ScopedArc`] to this value, which can be copied freely
+    /// inside `f` without changing the ref count.
+    ///
+    /// ```
+    /// use provenant::Arc;
+    ///
+    /// let data = Arc::new(vec![1, 2, 3]);
+    /// let total: i32 = Arc::scope(&data, |scoped| {
+    ///     std::thread::scope(|s| {
+    ///         let a = s.spawn(move || scoped[0] + scoped[1]);
+    ///         let b = s.spawn(move || scoped[2]);
+    ///         a.join().unwrap() + b.join().unwrap()
+    ///     })
+    /// });
+    /// assert_eq!(6, total);
+    /// ```
+    ///
+    /// Handles can't escape the scope:
+    ///
+    /// ```compile_fail
+    /// use provenant::Arc;
+    ///
+    /// let data = Arc::new(1);
+    /// let escaped = Arc::scope(&data, |scoped| scoped);
+    /// ```
+    pub fn scope<R, F>(this: &Self, f: F) -> R
+    where
+        F: for<'s> FnOnce(ScopedArc<'s, T>) -> R,
+    {
+        f(ScopedArc {
+            arc: this,
+            _scope: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn no_ref_count_traffic() {
+        let arc = Arc::new(String::from("shared"));
+
+        let escaped = Arc::scope(&arc, |scoped| {
+            let copies = [scoped; 8];
+            assert_eq!(1, arc.inner().ref_count.load(Ordering::SeqCst));
+            assert!(copies.iter().all(|c| **c == "shared"));
+            scoped.to_arc()
+        });
+
+        assert_eq!(2, arc.inner().ref_count.load(Ordering::SeqCst));
+        assert_eq!("shared", *escaped);
+    }
+}
@@ -0,0 +1,71 @@
+//! Serde impls (`serde` feature).
+//!
+//! An Arc serializes as its value, and deserializes into a new allocation. Like
+//! serde's own impls for `std::sync::Arc`, every Arc is written out in full, so two
+//! Arcs to one value come back as two values.
+//!
+//! A weak pointer serializes as an `Option` of its value: `Some` if it upgrades,
+//! `None` if the value is gone. It can't be deserialized, since there would be no
+//! Arc to keep the value alive.
+
+use crate::{Arc, Weak};
+use alloc::boxed::Box;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+impl<T: ?Sized + Serialize> Serialize for Arc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        (**self).serialize(serializer)
+    }
+}
+
+// through a Box, so that str and slices work too
+impl<'de, T: ?Sized> Deserialize<'de> for Arc<T>
+where
+    Box<T>: Deserialize<'de>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Box::deserialize(deserializer).map(Arc::from)
+    }
+}
+
+impl<T: ?Sized + Serialize> Serialize for Weak<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.upgrade().serialize(serializer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Arc, Weak};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Serialize, Deserialize)]
+    struct Config {
+        name: Arc<str>,
+        ports: Arc<Vec<u16>>,
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            name: Arc::from("server"),
+            ports: Arc::new(vec![80, 443]),
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(r#"{"name":"server","ports":[80,443]}"#, json);
+
+        let config: Config = serde_json::from_str(&json).unwrap();
+        assert_eq!("server", &*config.name);
+        assert_eq!(vec![80, 443], *config.ports);
+    }
+
+    #[test]
+    fn weak() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        assert_eq!("5", serde_json::to_string(&weak).unwrap());
+        drop(arc);
+        assert_eq!("null", serde_json::to_string(&weak).unwrap());
+        assert_eq!("null", serde_json::to_string(&Weak::<u8>::new()).unwrap());
+    }
+}
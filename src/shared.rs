@@ -0,0 +1,141 @@
+//! Traits for code that wants to be generic over the flavor of shared pointer.
+//!
+//! Both this crate's [`Arc`](crate::Arc)/[`Weak`](crate::Weak) and
+//! [`std::sync::Arc`]/[`std::sync::Weak`] implement them, so a library can take
+//! `P: SharedPtr<Target = Foo>` and leave the choice to its users.
+//!
+//! For code that has to hand one flavor to something expecting the other,
+//! [`Arc::from_std`](crate::Arc::from_std) and [`Arc::into_std`](crate::Arc::into_std)
+//! convert between them. The two lay out their allocations differently, so a
+//! conversion always makes a new allocation: it moves the value if the pointer
+//! was the only strong reference, and clones it otherwise.
+
+use std::ops::Deref;
+
+/// A reference counted pointer that can be downgraded to a weak pointer
+pub trait SharedPtr: Clone + Deref {
+    /// The weak pointer that goes with this shared pointer
+    type Weak: SharedWeak<Strong = Self>;
+
+    /// Moves `val` into a new shared allocation
+    fn new(val: Self::Target) -> Self
+    where
+        Self::Target: Sized;
+
+    /// Gets a weak pointer to the same allocation
+    fn downgrade(this: &Self) -> Self::Weak;
+}
+
+/// A weak pointer that can be upgraded back to its [`SharedPtr`]
+pub trait SharedWeak: Clone {
+    /// The shared pointer this upgrades to
+    type Strong: SharedPtr<Weak = Self>;
+
+    /// Attempts to get a strong pointer, returning None if the value is gone
+    fn upgrade(&self) -> Option<Self::Strong>;
+}
+
+impl<T: ?Sized> SharedPtr for crate::Arc<T> {
+    type Weak = crate::Weak<T>;
+
+    fn new(val: T) -> Self
+    where
+        T: Sized,
+    {
+        crate::Arc::new(val)
+    }
+
+    fn downgrade(this: &Self) -> Self::Weak {
+        crate::Arc::downgrade(this)
+    }
+}
+
+impl<T: ?Sized> SharedWeak for crate::Weak<T> {
+    type Strong = crate::Arc<T>;
+
+    fn upgrade(&self) -> Option<Self::Strong> {
+        crate::Weak::upgrade(self)
+    }
+}
+
+impl<T: ?Sized> SharedPtr for std::sync::Arc<T> {
+    type Weak = std::sync::Weak<T>;
+
+    fn new(val: T) -> Self
+    where
+        T: Sized,
+    {
+        std::sync::Arc::new(val)
+    }
+
+    fn downgrade(this: &Self) -> Self::Weak {
+        std::sync::Arc::downgrade(this)
+    }
+}
+
+impl<T: ?Sized> SharedWeak for std::sync::Weak<T> {
+    type Strong = std::sync::Arc<T>;
+
+    fn upgrade(&self) -> Option<Self::Strong> {
+        std::sync::Weak::upgrade(self)
+    }
+}
+
+impl<T: Clone> crate::Arc<T> {
+    /// Converts from a [`std::sync::Arc`], moving the value into a new allocation if
+    /// that was its only strong reference, and cloning it otherwise.
+    ///
+    /// Either way, the result is a separate allocation, so it doesn't see later
+    /// changes made through the other Arcs, and `std`'s weak pointers don't
+    /// upgrade to it.
+    pub fn from_std(arc: std::sync::Arc<T>) -> Self {
+        let val = std::sync::Arc::try_unwrap(arc).unwrap_or_else(|arc| (*arc).clone());
+        crate::Arc::new(val)
+    }
+
+    /// Converts into a [`std::sync::Arc`], moving the value into a new allocation if
+    /// this was its only strong reference, and cloning it otherwise
+    pub fn into_std(this: Self) -> std::sync::Arc<T> {
+        std::sync::Arc::new(crate::Arc::unwrap_or_clone(this))
+    }
+
+    /// Clones the value into a new [`std::sync::Arc`], leaving this one as it is
+    pub fn to_std(&self) -> std::sync::Arc<T> {
+        std::sync::Arc::new((**self).clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<P: SharedPtr<Target = i32>>() {
+        let strong = P::new(7);
+        let weak = P::downgrade(&strong);
+        assert_eq!(7, *weak.upgrade().unwrap());
+
+        drop(strong);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn both_flavors() {
+        round_trip::<crate::Arc<i32>>();
+        round_trip::<std::sync::Arc<i32>>();
+    }
+
+    #[test]
+    fn std_conversions() {
+        let std = std::sync::Arc::new(String::from("moved"));
+        let moved = crate::Arc::from_std(std);
+        let weak = crate::Arc::downgrade(&moved);
+        assert_eq!("moved", *crate::Arc::into_std(moved));
+        assert!(weak.upgrade().is_none());
+
+        let std = std::sync::Arc::new(vec![1, 2]);
+        let cloned = crate::Arc::from_std(std.clone());
+        assert_eq!(*std, *cloned);
+        assert!(!std::sync::Arc::ptr_eq(&std, &cloned.to_std()));
+        assert_eq!(*cloned, *cloned.to_std());
+    }
+}
@@ -0,0 +1,443 @@
+//! Shared slices and strings: building them in place, and owned views into them.
+
+use crate::{events, write_header, Arc, Inner};
+use alloc::alloc::Layout;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::iter::FromIterator;
+use core::marker::PhantomData;
+use core::mem;
+use core::ops::{Bound, Deref, Range, RangeBounds};
+use core::ptr::{self, NonNull};
+
+/// A range of a shared slice that keeps the whole buffer alive.
+///
+/// `S` is whatever the [`Arc`] holds, as long as it's `AsRef<[T]>`: `[T]`, `Vec<T>`,
+/// an array, and so on. Views can be split up and sent to other threads without
+/// copying anything.
+pub struct ArcSlice<T, S: ?Sized = [T]> {
+    arc: Arc<S>,
+    range: Range<usize>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T, S: ?Sized + AsRef<[T]>> ArcSlice<T, S> {
+    /// Creates a view of the whole slice
+    pub fn new(arc: Arc<S>) -> Self {
+        let len = (*arc).as_ref().len();
+        ArcSlice {
+            arc,
+            range: 0..len,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a view of part of the slice
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds.
+    pub fn with_range(arc: Arc<S>, range: Range<usize>) -> Self {
+        let len = (*arc).as_ref().len();
+        assert!(
+            range.start <= range.end && range.end <= len,
+            "range {:?} out of bounds for slice of length {}",
+            range,
+            len
+        );
+        ArcSlice {
+            arc,
+            range,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Gets the Arc holding the whole slice
+    pub fn arc(&self) -> &Arc<S> {
+        &self.arc
+    }
+
+    /// The part of the whole slice this views
+    pub fn range(&self) -> Range<usize> {
+        self.range.clone()
+    }
+
+    /// Gets a view of part of this one, without copying. `range` is relative to
+    /// this view, like indexing the slice it derefs to
+    ///
+    /// # Panics
+    ///
+    /// If `range` is out of bounds.
+    pub fn slice(&self, range: impl RangeBounds<usize>) -> Self {
+        let len = self.range.len();
+        // None if the bound is past usize::MAX, which is out of bounds too
+        let start = match range.start_bound() {
+            Bound::Included(&start) => Some(start),
+            Bound::Excluded(&start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1),
+            Bound::Excluded(&end) => Some(end),
+            Bound::Unbounded => Some(len),
+        };
+        let (start, end) = match (start, end) {
+            (Some(start), Some(end)) if start <= end && end <= len => (start, end),
+            _ => panic!(
+                "range {:?} out of bounds for slice of length {}",
+                (range.start_bound(), range.end_bound()),
+                len
+            ),
+        };
+        ArcSlice {
+            arc: self.arc.clone(),
+            range: self.range.start + start..self.range.start + end,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Splits into views of `chunk_size` elements each, except the last which may be shorter
+    ///
+    /// # Panics
+    ///
+    /// If `chunk_size` is 0.
+    pub fn chunks(&self, chunk_size: usize) -> impl ExactSizeIterator<Item = Self> + '_ {
+        assert!(chunk_size != 0, "chunk size must be nonzero");
+        (0..self.chunk_count(chunk_size)).map(move |i| self.chunk(chunk_size, i))
+    }
+
+    pub(crate) fn chunk_count(&self, chunk_size: usize) -> usize {
+        self.range.len().div_ceil(chunk_size)
+    }
+
+    pub(crate) fn chunk(&self, chunk_size: usize, i: usize) -> Self {
+        let start = self.range.start + i * chunk_size;
+        let end = (start + chunk_size).min(self.range.end);
+        ArcSlice {
+            arc: self.arc.clone(),
+            range: start..end,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S: ?Sized + AsRef<[T]>> Deref for ArcSlice<T, S> {
+    type Target = [T];
+    fn deref(&self) -> &[T] {
+        &(*self.arc).as_ref()[self.range.clone()]
+    }
+}
+
+impl<T, S: ?Sized> Clone for ArcSlice<T, S> {
+    fn clone(&self) -> Self {
+        ArcSlice {
+            arc: self.arc.clone(),
+            range: self.range.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: fmt::Debug, S: ?Sized + AsRef<[T]>> fmt::Debug for ArcSlice<T, S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Builds an `Arc<[T]>` in its final allocation, instead of collecting into a `Vec`
+/// and copying.
+///
+/// Elements go straight into space after the Arc's header. If they outgrow the
+/// capacity, the allocation is grown in place where the allocator allows it, and
+/// [`finish`](ArcSliceBuilder::finish) trims any spare capacity before handing
+/// the allocation over to the Arc.
+pub struct ArcSliceBuilder<T> {
+    // the allocation the Inner will live in, if anything's been reserved
+    ptr: Option<NonNull<u8>>,
+    cap: usize,
+    len: usize,
+    _marker: PhantomData<T>,
+}
+
+unsafe impl<T: Send> Send for ArcSliceBuilder<T> {}
+unsafe impl<T: Sync> Sync for ArcSliceBuilder<T> {}
+
+// the allocation for an Inner<[T]> of `len` elements, and where the elements start
+pub(crate) fn slice_layout<T>(len: usize) -> (Layout, usize) {
+    let header = Layout::new::<Inner<[T; 0]>>();
+    let elements = Layout::array::<T>(len).expect("capacity overflow");
+    let (layout, offset) = header.extend(elements).expect("capacity overflow");
+    (layout.pad_to_align(), offset)
+}
+
+impl<T> Default for ArcSliceBuilder<T> {
+    fn default() -> Self {
+        ArcSliceBuilder::new()
+    }
+}
+
+impl<T> ArcSliceBuilder<T> {
+    /// Creates a builder that allocates once something is pushed
+    pub fn new() -> Self {
+        ArcSliceBuilder {
+            ptr: None,
+            cap: 0,
+            len: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a builder with room for `cap` elements
+    pub fn with_capacity(cap: usize) -> Self {
+        let mut builder = ArcSliceBuilder::new();
+        builder.reserve(cap);
+        builder
+    }
+
+    /// How many elements have been pushed
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether nothing has been pushed
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// How many elements fit without growing
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    /// Makes room for at least `additional` more elements
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = self.len.checked_add(additional).expect("capacity overflow");
+        if needed > self.cap {
+            self.grow_to(needed.max(self.cap.saturating_mul(2)).max(4));
+        }
+    }
+
+    fn grow_to(&mut self, cap: usize) {
+        let (layout, _) = slice_layout::<T>(cap);
+        let ptr = unsafe {
+            match self.ptr {
+                Some(ptr) => alloc::alloc::realloc(
+                    ptr.as_ptr(),
+                    slice_layout::<T>(self.cap).0,
+                    layout.size(),
+                ),
+                None => alloc::alloc::alloc(layout),
+            }
+        };
+        self.ptr =
+            Some(NonNull::new(ptr).unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout)));
+        self.cap = cap;
+    }
+
+    fn elements(&self) -> *mut T {
+        match self.ptr {
+            Some(ptr) => unsafe { ptr.as_ptr().add(slice_layout::<T>(0).1) as *mut T },
+            None => NonNull::dangling().as_ptr(),
+        }
+    }
+
+    /// Adds an element to the end
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+        unsafe { self.elements().add(self.len).write(value) };
+        self.len += 1;
+    }
+
+    /// Turns what's been pushed into a shared slice, without copying it
+    pub fn finish(mut self) -> Arc<[T]> {
+        let (layout, _) = slice_layout::<T>(self.len);
+        let base = unsafe {
+            match self.ptr.take() {
+                Some(ptr) if self.cap == self.len => ptr.as_ptr(),
+                Some(ptr) => alloc::alloc::realloc(
+                    ptr.as_ptr(),
+                    slice_layout::<T>(self.cap).0,
+                    layout.size(),
+                ),
+                None => alloc::alloc::alloc(layout),
+            }
+        };
+        if base.is_null() {
+            alloc::alloc::handle_alloc_error(layout);
+        }
+        let len = mem::replace(&mut self.len, 0);
+
+        let inner = ptr::slice_from_raw_parts(base as *const T, len) as *mut Inner<[T]>;
+        unsafe { write_header(inner) };
+        events::allocated(inner);
+        Arc { ptr: inner }
+    }
+}
+
+impl<T> Extend<T> for ArcSliceBuilder<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        let iter = iter.into_iter();
+        self.reserve(iter.size_hint().0);
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T> Drop for ArcSliceBuilder<T> {
+    fn drop(&mut self) {
+        if let Some(ptr) = self.ptr {
+            unsafe {
+                ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.elements(), self.len));
+                alloc::alloc::dealloc(ptr.as_ptr(), slice_layout::<T>(self.cap).0);
+            }
+        }
+    }
+}
+
+impl<T> FromIterator<T> for Arc<[T]> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut builder = ArcSliceBuilder::new();
+        builder.extend(iter);
+        builder.finish()
+    }
+}
+
+impl<T: Clone> From<&[T]> for Arc<[T]> {
+    fn from(slice: &[T]) -> Self {
+        slice.iter().cloned().collect()
+    }
+}
+
+// the iterator knows its length, so this allocates once and moves each element
+impl<T> From<Vec<T>> for Arc<[T]> {
+    fn from(vec: Vec<T>) -> Self {
+        vec.into_iter().collect()
+    }
+}
+
+impl From<&str> for Arc<str> {
+    fn from(s: &str) -> Self {
+        let bytes = Arc::<[u8]>::from(s.as_bytes());
+        unsafe { Arc::from_utf8_unchecked(bytes) }
+    }
+}
+
+impl From<String> for Arc<str> {
+    fn from(s: String) -> Self {
+        let bytes = Arc::<[u8]>::from(s.into_bytes());
+        unsafe { Arc::from_utf8_unchecked(bytes) }
+    }
+}
+
+impl From<Arc<str>> for Arc<[u8]> {
+    fn from(s: Arc<str>) -> Self {
+        let ptr = s.ptr as *const Inner<[u8]>;
+        mem::forget(s);
+        Arc { ptr }
+    }
+}
+
+impl Arc<str> {
+    // str and [u8] have the same layout and metadata, so only the type changes.
+    // the tag is kept
+    unsafe fn from_utf8_unchecked(bytes: Arc<[u8]>) -> Self {
+        let ptr = bytes.ptr as *const Inner<str>;
+        mem::forget(bytes);
+        Arc { ptr }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunks() {
+        let whole = ArcSlice::new(Arc::new(vec![1, 2, 3, 4, 5]));
+        let chunks: Vec<ArcSlice<i32, Vec<i32>>> = whole.chunks(2).collect();
+        assert_eq!(3, chunks.len());
+        assert_eq!([5], *chunks[2]);
+
+        let inner = ArcSlice::with_range(whole.arc().clone(), 1..4);
+        let parts: Vec<Vec<i32>> = inner.chunks(2).map(|c| c.to_vec()).collect();
+        assert_eq!(vec![vec![2, 3], vec![4]], parts);
+    }
+
+    #[test]
+    fn subslices() {
+        let whole: ArcSlice<u8> = ArcSlice::new(Arc::from(&b"GET /index.html"[..]));
+        let path = whole.slice(4..);
+        assert_eq!(b"/index.html", &*path);
+        let name = path.slice(1..=5);
+        assert_eq!(b"index", &*name);
+        assert_eq!(5..10, name.range());
+        assert!(Arc::ptr_eq(whole.arc(), name.arc()));
+
+        // a cache keyed on the buffer forgets it once the last view is gone
+        let weak = Arc::downgrade(name.arc());
+        drop((whole, path));
+        assert!(weak.upgrade().is_some());
+        drop(name);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn build() {
+        let mut builder = ArcSliceBuilder::with_capacity(2);
+        builder.extend((0..10).map(|i| i.to_string()));
+        builder.push("last".into());
+        assert!(builder.capacity() > 11);
+
+        let arc: Arc<[String]> = builder.finish();
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(11, arc.len());
+        assert_eq!("last", arc[10]);
+        drop(arc);
+        assert!(weak.upgrade().is_none());
+
+        let empty: Arc<[u64]> = std::iter::empty().collect();
+        assert!(empty.is_empty());
+        let mut unfinished = ArcSliceBuilder::new();
+        unfinished.push(Arc::new(1));
+    }
+
+    #[test]
+    fn conversions() {
+        let words = ["a".to_string(), "b".to_string()];
+        let cloned: Arc<[String]> = Arc::from(&words[..]);
+        assert_eq!(words, *cloned);
+
+        let moved: Arc<[String]> = Arc::from(words.to_vec());
+        assert_eq!(words, *moved);
+        assert!(Arc::<[u8]>::from(Vec::new()).is_empty());
+    }
+
+    #[test]
+    fn strings() {
+        let borrowed: Arc<str> = Arc::from("shared");
+        let owned: Arc<str> = Arc::from(String::from("shared"));
+        assert_eq!(&*borrowed, &*owned);
+
+        let weak = Arc::downgrade(&owned);
+        let bytes: Arc<[u8]> = Arc::from(owned);
+        assert_eq!(b"shared", &*bytes);
+        assert!(weak.upgrade().is_some());
+    }
+
+    #[test]
+    #[should_panic]
+    fn out_of_range() {
+        ArcSlice::<u8, _>::with_range(Arc::new([0u8; 4]), 2..5);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of bounds")]
+    fn slice_to_max() {
+        let whole: ArcSlice<u8> = ArcSlice::new(Arc::from(&[0u8; 4][..]));
+        whole.slice(2..=usize::MAX);
+    }
+}
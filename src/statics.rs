@@ -0,0 +1,169 @@
+//! Arcs that live in statics.
+//!
+//! [`Arc::leak`] does the same for a value that's already in an Arc, at the cost
+//! of its allocation never being freed.
+
+use crate::primitives::{AtomicProvenance, AtomicUsize};
+use crate::{untagged, Arc, Inner, Provenance, Weak};
+use alloc::alloc::Layout;
+use core::mem;
+
+// all static Inners share this provenance. they're never freed,
+// so it doesn't need to be unique
+pub(crate) const STATIC_PROVENANCE: Provenance = Provenance::MAX - 1;
+
+// far enough from zero that no amount of dropping clones gets there
+const STATIC_REF_COUNT: usize = usize::MAX / 2;
+
+/// Storage for an [`Arc`] that's never deallocated, constructible in a const context.
+///
+/// Usually declared through [`static_arc!`](crate::static_arc), but can be used directly:
+///
+/// ```
+/// use provenant::{statics::StaticInner, Arc};
+///
+/// static INNER: StaticInner<&str> = StaticInner::new("hello");
+/// static GREETING: Arc<&str> = Arc::from_static(&INNER);
+///
+/// assert_eq!("hello", *GREETING.clone());
+/// ```
+pub struct StaticInner<T>(Inner<T>);
+
+unsafe impl<T: Send + Sync> Sync for StaticInner<T> {}
+
+// static memory isn't ours to give back.
+// the ref count should never get here anyway
+unsafe fn release_static(_ptr: *mut u8, _layout: Layout) {}
+
+impl<T> StaticInner<T> {
+    /// Wraps `val` so that [`Arc::from_static`] can point at it
+    pub const fn new(val: T) -> Self {
+        StaticInner(Inner {
+            provenance: AtomicProvenance::new(STATIC_PROVENANCE),
+            ref_count: AtomicUsize::new(STATIC_REF_COUNT),
+            #[cfg(feature = "weak-count")]
+            weak_count: AtomicUsize::new(0),
+            release: release_static,
+            data: val,
+        })
+    }
+}
+
+impl<T> Arc<T> {
+    /// Creates an Arc pointing into static storage.
+    ///
+    /// It doesn't need an allocation or any randomness, so it works in const contexts.
+    /// Dropping every clone of it does nothing, and weak pointers to it always upgrade.
+    pub const fn from_static(inner: &'static StaticInner<T>) -> Self {
+        Arc { ptr: &inner.0 }
+    }
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Gives up this strong reference without ever dropping it, so the value lives
+    /// for the rest of the program.
+    ///
+    /// Other Arcs and weak pointers to it keep working, and weak pointers always
+    /// upgrade from then on.
+    pub fn leak<'a>(this: Self) -> &'a T
+    where
+        T: 'a,
+    {
+        let this = mem::ManuallyDrop::new(this);
+        unsafe { &(*untagged(this.ptr)).data }
+    }
+}
+
+impl<T> Weak<T> {
+    /// Creates a weak pointer into static storage, which always upgrades.
+    ///
+    /// Handy as a fallback where an API takes a [`Weak`], without leaking a real
+    /// allocation. The value has to live in a [`StaticInner`], since the Arcs it
+    /// upgrades to need the header in front of it.
+    ///
+    /// ```
+    /// use provenant::{statics::StaticInner, Weak};
+    ///
+    /// static DEFAULT: StaticInner<u32> = StaticInner::new(7);
+    ///
+    /// let weak = Weak::from_static(&DEFAULT);
+    /// assert_eq!(7, *weak.upgrade().unwrap());
+    /// ```
+    pub const fn from_static(inner: &'static StaticInner<T>) -> Self {
+        Weak {
+            provenance: STATIC_PROVENANCE,
+            ptr: &inner.0,
+        }
+    }
+}
+
+/// Declares a `static` [`Arc`].
+///
+/// ```
+/// use provenant::{static_arc, Arc};
+///
+/// struct Config {
+///     retries: u32,
+/// }
+///
+/// static_arc! {
+///     pub static CONFIG: Arc<Config> = Config { retries: 3 };
+/// }
+///
+/// assert_eq!(3, CONFIG.retries);
+/// ```
+#[macro_export]
+macro_rules! static_arc {
+    ($(#[$attr:meta])* $vis:vis static $name:ident: Arc<$t:ty> = $init:expr;) => {
+        $(#[$attr])*
+        $vis static $name: $crate::Arc<$t> = {
+            static INNER: $crate::statics::StaticInner<$t> = $crate::statics::StaticInner::new($init);
+            $crate::Arc::from_static(&INNER)
+        };
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StaticInner;
+    use crate::{Arc, Weak};
+
+    static_arc! {
+        static NUMBER: Arc<u32> = 12;
+    }
+
+    #[test]
+    fn never_freed() {
+        let weak = Arc::downgrade(&NUMBER);
+        let cloned = NUMBER.clone();
+        drop(cloned);
+
+        let upgraded = weak.upgrade().unwrap();
+        assert_eq!(12, *upgraded);
+        drop(upgraded);
+
+        assert_eq!(12, *weak.upgrade().unwrap());
+    }
+
+    #[test]
+    fn static_weak() {
+        static INNER: StaticInner<&str> = StaticInner::new("fallback");
+        static WEAK: Weak<&str> = Weak::from_static(&INNER);
+
+        for _ in 0..3 {
+            assert_eq!("fallback", *WEAK.upgrade().unwrap());
+        }
+    }
+
+    #[test]
+    fn leaked() {
+        let arc = Arc::new(String::from("forever"));
+        let weak = Arc::downgrade(&arc);
+        let leaked: &'static String = Arc::leak(arc.clone());
+        drop(arc);
+
+        assert_eq!("forever", leaked);
+        assert_eq!(1, weak.strong_count());
+        assert_eq!("forever", *weak.upgrade().unwrap());
+    }
+}
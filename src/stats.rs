@@ -0,0 +1,85 @@
+// process-wide counters of what shared pointers are doing (`stats` feature)
+
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+pub(crate) static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static UPGRADES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static FAILED_UPGRADES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static CONTENDED: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static MISMATCHES: AtomicUsize = AtomicUsize::new(0);
+pub(crate) static COLLISIONS: AtomicUsize = AtomicUsize::new(0);
+
+// counters are only ever read for reporting, so they don't order anything
+pub(crate) fn increment(counter: &AtomicUsize) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+pub(crate) fn get(counter: &AtomicUsize) -> usize {
+    counter.load(Ordering::Relaxed)
+}
+
+/// What every shared pointer in the process has done so far, from [`stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Stats {
+    /// Shared allocations made
+    pub allocations: usize,
+    /// Shared allocations freed after their last strong reference dropped
+    pub deallocations: usize,
+    /// Weak pointers upgraded
+    pub upgrades: usize,
+    /// Weak pointers that failed to upgrade
+    pub failed_upgrades: usize,
+    /// Times the provenance lock was found held by another thread and retried
+    pub contended: usize,
+    /// Failed upgrades whose target held a different provenance id, because it
+    /// was rekeyed, or its memory reused, rather than just freed
+    pub mismatches: usize,
+    /// Upgrades that diagnostics caught matching an id that more than one
+    /// allocation has held at the same address. Only counted while diagnostics are
+    /// on, and they panic right after
+    pub collisions: usize,
+}
+
+impl Stats {
+    /// Allocations that haven't been freed yet
+    pub fn live(&self) -> usize {
+        self.allocations.saturating_sub(self.deallocations)
+    }
+}
+
+/// Reads every counter. They're read one at a time, so with other threads busy,
+/// they can be slightly out of step with each other
+pub fn stats() -> Stats {
+    Stats {
+        allocations: get(&ALLOCATIONS),
+        deallocations: get(&DEALLOCATIONS),
+        upgrades: get(&UPGRADES),
+        failed_upgrades: get(&FAILED_UPGRADES),
+        contended: get(&CONTENDED),
+        mismatches: get(&MISMATCHES),
+        collisions: get(&COLLISIONS),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Arc;
+
+    #[test]
+    fn counts_mismatches() {
+        let before = stats();
+
+        let mut arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        *Arc::get_mut(&mut arc).unwrap() = 2;
+        assert!(weak.upgrade().is_none());
+
+        // other tests run at the same time, so only look for increases
+        let after = stats();
+        assert!(after.allocations > before.allocations);
+        assert!(after.failed_upgrades > before.failed_upgrades);
+        assert!(after.mismatches > before.mismatches);
+    }
+}
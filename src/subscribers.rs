@@ -0,0 +1,144 @@
+//! Broadcasting events to listeners that are held weakly.
+//!
+//! A listener stays subscribed for as long as its Arc is alive, so unsubscribing is
+//! just dropping it, and a [`Subscribers`] list never keeps anything alive:
+//!
+//! ```
+//! use provenant::subscribers::Subscribers;
+//! use provenant::Arc;
+//!
+//! let on_resize = Subscribers::new();
+//! let listener = Arc::new(|size: &(u32, u32)| println!("resized to {:?}", size));
+//! on_resize.subscribe(&listener);
+//! assert_eq!(1, on_resize.notify(&(640, 480)));
+//!
+//! drop(listener);
+//! assert_eq!(0, on_resize.notify(&(800, 600)));
+//! assert!(on_resize.is_empty());
+//! ```
+
+use crate::{Arc, Weak};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// What [`Subscribers`] calls with each event
+pub type Listener<E> = dyn Fn(&E) + Send + Sync;
+
+/// A list of listeners for events of type `E`, held weakly
+pub struct Subscribers<E> {
+    listeners: Mutex<Vec<Weak<Listener<E>>>>,
+}
+
+impl<E> Default for Subscribers<E> {
+    fn default() -> Self {
+        Subscribers {
+            listeners: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<E> Subscribers<E> {
+    /// Creates a list without any listeners
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `listener`, which is called with every event until it's dropped
+    pub fn subscribe<F: Fn(&E) + Send + Sync + 'static>(&self, listener: &Arc<F>) {
+        self.subscribe_weak(crate::unsize!(Arc::downgrade(listener) => Listener<E>));
+    }
+
+    /// Adds a listener that's already a weak pointer to a trait object
+    pub fn subscribe_weak(&self, listener: Weak<Listener<E>>) {
+        self.lock().push(listener);
+    }
+
+    /// Calls every live listener with `event`, in the order they subscribed, and
+    /// drops the dead ones. Returns how many were called.
+    ///
+    /// The list isn't locked while listeners run, so they can subscribe others, or
+    /// notify this list again. Listeners subscribed meanwhile only hear later events.
+    pub fn notify(&self, event: &E) -> usize {
+        let live: Vec<Arc<Listener<E>>> = {
+            let mut listeners = self.lock();
+            let mut live = Vec::with_capacity(listeners.len());
+            listeners.retain(|weak| match weak.upgrade() {
+                Some(listener) => {
+                    live.push(listener);
+                    true
+                }
+                None => false,
+            });
+            live
+        };
+
+        for listener in &live {
+            listener(event);
+        }
+        live.len()
+    }
+
+    /// How many listeners there are, including dead ones not yet dropped
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Whether there are no listeners, dead or alive
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<Weak<Listener<E>>>> {
+        self.listeners
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<E> fmt::Debug for Subscribers<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscribers")
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn listener_subscribes_another() {
+        let subscribers = Arc::new(Subscribers::<usize>::new());
+        let total = Arc::new(AtomicUsize::new(0));
+
+        let adder = {
+            let total = total.clone();
+            Arc::new(move |n: &usize| {
+                total.fetch_add(*n, Ordering::Relaxed);
+            })
+        };
+        let added = Arc::new(Mutex::new(None));
+        let subscriber = {
+            let (subscribers, added) = (subscribers.clone(), added.clone());
+            Arc::new(move |_: &usize| {
+                let mut added = added.lock().unwrap();
+                if added.is_none() {
+                    subscribers.subscribe(&adder);
+                    *added = Some(adder.clone());
+                }
+            })
+        };
+        subscribers.subscribe(&subscriber);
+
+        assert_eq!(1, subscribers.notify(&1));
+        assert_eq!(2, subscribers.notify(&10));
+        assert_eq!(10, total.load(Ordering::Relaxed));
+
+        drop(subscriber);
+        assert_eq!(1, subscribers.notify(&100));
+        assert_eq!(110, total.load(Ordering::Relaxed));
+        assert_eq!(1, subscribers.len());
+    }
+}
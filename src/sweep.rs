@@ -0,0 +1,243 @@
+//! Periodically clearing dead entries out of weak collections.
+//!
+//! Collections of weak pointers only notice dead entries when they're accessed, so
+//! rarely touched ones fill up with them. Collections that implement [`Sweep`] can be
+//! registered with a [`Sweeper`], which compacts all of them on demand, or every so
+//! often from a background thread started by [`Sweeper::spawn`].
+//!
+//! The sweeper only keeps weak pointers to registered collections, so dropping a
+//! collection unregisters it.
+
+use crate::{Arc, Weak};
+use std::fmt;
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A collection that can drop its dead entries
+pub trait Sweep: Send + Sync {
+    /// Removes dead entries, returning how many there were
+    fn sweep(&self) -> usize;
+}
+
+/// What a [`Sweeper`] has done so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SweepStats {
+    /// How many times every collection was swept
+    pub passes: u64,
+    /// Dead entries removed, in total
+    pub reclaimed: u64,
+    /// Collections registered and still alive, as of the last pass
+    pub collections: usize,
+}
+
+impl fmt::Display for SweepStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} passes over {} collections reclaimed {} entries",
+            self.passes, self.collections, self.reclaimed
+        )
+    }
+}
+
+// sweeps one registered collection, or returns None if it's gone
+type Entry = Box<dyn Fn() -> Option<usize> + Send>;
+
+struct Shared {
+    entries: Mutex<Vec<Entry>>,
+    stats: Mutex<SweepStats>,
+    stopped: Mutex<bool>,
+    stop: Condvar,
+}
+
+/// Sweeps registered collections. Clones share the same registrations.
+#[derive(Clone)]
+pub struct Sweeper {
+    shared: Arc<Shared>,
+}
+
+impl Default for Sweeper {
+    fn default() -> Self {
+        Sweeper::new()
+    }
+}
+
+impl Sweeper {
+    /// Creates a sweeper with nothing registered
+    pub fn new() -> Self {
+        Sweeper {
+            shared: Arc::new(Shared {
+                entries: Mutex::new(Vec::new()),
+                stats: Mutex::new(SweepStats::default()),
+                stopped: Mutex::new(false),
+                stop: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Adds a collection to every future pass, until it's dropped
+    pub fn register<C: Sweep + 'static>(&self, collection: &Arc<C>) {
+        let weak: Weak<C> = Arc::downgrade(collection);
+        let entry: Entry = Box::new(move || weak.upgrade().map(|c| c.sweep()));
+        self.shared
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(entry);
+    }
+
+    /// Sweeps every registered collection now, returning how many entries were removed
+    pub fn sweep_now(&self) -> usize {
+        let mut entries = self
+            .shared
+            .entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut reclaimed = 0;
+        entries.retain(|entry| match entry() {
+            Some(n) => {
+                reclaimed += n;
+                true
+            }
+            None => false,
+        });
+
+        let mut stats = self
+            .shared
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        stats.passes += 1;
+        stats.reclaimed += reclaimed as u64;
+        stats.collections = entries.len();
+        reclaimed
+    }
+
+    /// Gets the totals so far
+    pub fn stats(&self) -> SweepStats {
+        *self
+            .shared
+            .stats
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Starts a background thread that sweeps every `interval`, until the
+    /// returned handle is dropped
+    pub fn spawn(&self, interval: Duration) -> SweepThread {
+        *self
+            .shared
+            .stopped
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = false;
+
+        let sweeper = self.clone();
+        let thread = thread::Builder::new()
+            .name("provenant-sweeper".into())
+            .spawn(move || loop {
+                let stopped = sweeper
+                    .shared
+                    .stopped
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner);
+                let (stopped, _) = sweeper
+                    .shared
+                    .stop
+                    .wait_timeout_while(stopped, interval, |stopped| !*stopped)
+                    .unwrap_or_else(PoisonError::into_inner);
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+
+                sweeper.sweep_now();
+            })
+            .expect("failed to spawn sweeper thread");
+
+        SweepThread {
+            sweeper: self.clone(),
+            thread: Some(thread),
+        }
+    }
+}
+
+/// A background thread started by [`Sweeper::spawn`]. Dropping it stops the thread.
+pub struct SweepThread {
+    sweeper: Sweeper,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for SweepThread {
+    fn drop(&mut self) {
+        let shared = &self.sweeper.shared;
+        *shared
+            .stopped
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = true;
+        shared.stop.notify_all();
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Observers(Mutex<Vec<Weak<u32>>>);
+
+    impl Sweep for Observers {
+        fn sweep(&self) -> usize {
+            let mut list = self.0.lock().unwrap();
+            let before = list.len();
+            list.retain(|weak| weak.upgrade().is_some());
+            before - list.len()
+        }
+    }
+
+    #[test]
+    fn sweep_now() {
+        let sweeper = Sweeper::new();
+        let alive = Arc::new(1);
+        let dead = Arc::new(2);
+        let observers = Arc::new(Observers(Mutex::new(vec![
+            Arc::downgrade(&alive),
+            Arc::downgrade(&dead),
+        ])));
+        sweeper.register(&observers);
+
+        drop(dead);
+        assert_eq!(1, sweeper.sweep_now());
+        assert_eq!(1, observers.0.lock().unwrap().len());
+
+        drop(observers);
+        sweeper.sweep_now();
+        assert_eq!(
+            SweepStats {
+                passes: 2,
+                reclaimed: 1,
+                collections: 0
+            },
+            sweeper.stats()
+        );
+    }
+
+    #[test]
+    fn background() {
+        let sweeper = Sweeper::new();
+        let observers = Arc::new(Observers(Mutex::new(vec![Arc::downgrade(&Arc::new(3))])));
+        sweeper.register(&observers);
+
+        let thread = sweeper.spawn(Duration::from_millis(5));
+        while sweeper.stats().reclaimed == 0 {
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(thread);
+
+        assert!(observers.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+//! A stand-in for the `Arc`/`Weak` half of [`std::sync`].
+//!
+//! Migrating from std should mostly be a matter of replacing
+//! `use std::sync::{Arc, Weak};` with `use provenant::sync::{Arc, Weak};`.
+//! Methods that exist on both take the same arguments and return the same types,
+//! and are called the same way (`Arc::downgrade(&a)`, not `a.downgrade()`).
+//!
+//! # Differences from std
+//!
+//! These can't be papered over, because they're the point of the crate:
+//!
+//! - **Memory is freed when the last `Arc` drops.** std keeps the allocation around
+//!   until the last `Weak` is gone too; here weaks don't keep anything alive.
+//! - **[`Weak::upgrade`] is probabilistic.** If the freed memory is reused and happens
+//!   to contain the same provenance id at the same place, a dead weak can upgrade.
+//! - **`Weak` is `Copy`.** Code that clones weaks keeps working, it just doesn't need to.
+//! - **Weaks are not counted**, so there is no `Arc::weak_count` or `Weak::weak_count`.
+//!   The `weak-count` feature adds both, but they count the weaks made, not the
+//!   ones still around.
+//!
+//! On stable Rust, `Arc<dyn Trait>` can't be made by implicit coercion. It's made
+//! with [`unsize!`](crate::unsize) or from a `Box<dyn Trait>` instead, unless the
+//! `nightly` feature is on.
+
+pub use crate::{Arc, Weak};
+
+#[cfg(test)]
+mod tests {
+    // written against std, with only the `use` changed
+    use super::{Arc, Weak};
+
+    struct Node {
+        value: u32,
+    }
+
+    fn parent_of(weak: &Weak<Node>) -> Option<Arc<Node>> {
+        weak.upgrade()
+    }
+
+    #[test]
+    fn std_style_code() {
+        let node = Arc::new(Node { value: 3 });
+        let weak: Weak<Node> = Arc::downgrade(&node);
+        let other = Arc::clone(&node);
+
+        assert_eq!(3, parent_of(&weak).unwrap().value);
+        drop(node);
+        drop(other);
+        assert!(parent_of(&weak).is_none());
+    }
+}
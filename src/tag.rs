@@ -0,0 +1,123 @@
+//! A few user bits stored in the handle itself.
+//!
+//! The shared allocation is always at least as aligned as a `usize`, so the bottom
+//! bits of an [`Arc`]'s pointer are free: two on 32-bit targets, three on 64-bit
+//! ones. They can hold a small tag that travels with the handle: it's kept by
+//! `clone`, carried over to weak pointers made with [`Arc::downgrade`], and
+//! restored by [`Weak::upgrade`].
+//!
+//! The tag belongs to the handle, not the allocation. Two Arcs to the same value can
+//! carry different tags.
+
+use crate::{Arc, Inner, Weak};
+
+/// The bits available for tags. Tags must fit in this mask, which is `0b11` on
+/// 32-bit targets and `0b111` on 64-bit ones
+pub const TAG_MASK: u8 = (core::mem::align_of::<usize>() - 1) as u8;
+
+pub(crate) fn tag_of<T: ?Sized>(ptr: *const Inner<T>) -> u8 {
+    (ptr as *const u8 as usize & TAG_MASK as usize) as u8
+}
+
+// byte offsets keep the metadata of unsized pointers intact
+pub(crate) fn untagged<T: ?Sized>(ptr: *const Inner<T>) -> *const Inner<T> {
+    ptr.wrapping_byte_sub(tag_of(ptr) as usize)
+}
+
+pub(crate) fn with_tag<T: ?Sized>(ptr: *const Inner<T>, tag: u8) -> *const Inner<T> {
+    untagged(ptr).wrapping_byte_add(tag as usize)
+}
+
+impl<T: ?Sized> Arc<T> {
+    /// Replaces the tag on this handle.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in [`TAG_MASK`].
+    pub fn with_tag(mut this: Self, tag: u8) -> Self {
+        Arc::set_tag(&mut this, tag);
+        this
+    }
+
+    /// Replaces the tag on this handle in place.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in [`TAG_MASK`].
+    pub fn set_tag(this: &mut Self, tag: u8) {
+        check(tag);
+        this.ptr = with_tag(this.ptr, tag);
+    }
+
+    /// Gets the tag on this handle. It's 0 unless set with [`Arc::with_tag`].
+    pub fn tag(this: &Self) -> u8 {
+        tag_of(this.ptr)
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Gets the tag of the Arc this was downgraded from
+    pub fn tag(&self) -> u8 {
+        tag_of(self.ptr)
+    }
+
+    /// Replaces the tag, which the Arcs this upgrades to will carry.
+    ///
+    /// # Panics
+    ///
+    /// If `tag` doesn't fit in [`TAG_MASK`].
+    pub fn set_tag(&mut self, tag: u8) {
+        check(tag);
+        self.ptr = with_tag(self.ptr, tag);
+    }
+}
+
+fn check(tag: u8) {
+    assert!(
+        tag & !TAG_MASK == 0,
+        "tag {} doesn't fit in the tag bits",
+        tag
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_travels() {
+        let arc = Arc::with_tag(Arc::new(String::from("x")), 3);
+        assert_eq!(3, Arc::tag(&arc));
+        assert_eq!("x", *arc);
+
+        let cloned = arc.clone();
+        assert_eq!(3, Arc::tag(&cloned));
+
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(3, weak.tag());
+        assert_eq!(3, Arc::tag(&weak.upgrade().unwrap()));
+
+        let retagged = Arc::with_tag(cloned, 1);
+        assert_eq!(1, Arc::tag(&retagged));
+        assert_eq!(3, Arc::tag(&arc));
+
+        drop(arc);
+        drop(retagged);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn set_in_place() {
+        let mut arc = Arc::new(1);
+        Arc::set_tag(&mut arc, TAG_MASK);
+        assert_eq!(TAG_MASK, Arc::tag(&arc));
+
+        let mut weak = Arc::downgrade(&arc);
+        weak.set_tag(2);
+        assert_eq!(2, Arc::tag(&weak.upgrade().unwrap()));
+        assert_eq!(1, *weak.upgrade().unwrap());
+
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(0b111, TAG_MASK);
+    }
+}
@@ -0,0 +1,299 @@
+//! Serializing trait object Arcs with a type tag (`serde` feature).
+//!
+//! An `Arc<dyn Trait>` serializes as a map from the concrete type's tag to its
+//! value, like `{"circle": {"radius": 1.0}}`, and deserializes back into the same
+//! concrete type. Each concrete type is registered, under its tag, with the trait
+//! objects it can be read back as, using [`register_tagged!`](crate::register_tagged).
+//!
+//! Fields can use [`TaggedArc`], or `#[serde(with = "provenant::tagged")]` on an
+//! `Arc<dyn Trait>`. The trait has to extend [`SerializeTagged`], which every
+//! `Serialize + 'static` type implements:
+//!
+//! ```
+//! use provenant::tagged::{SerializeTagged, TaggedArc};
+//! use provenant::{register_tagged, unsize, Arc};
+//! use serde::{Deserialize, Serialize};
+//!
+//! trait Shape: SerializeTagged {
+//!     fn area(&self) -> f64;
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Square {
+//!     side: f64,
+//! }
+//!
+//! impl Shape for Square {
+//!     fn area(&self) -> f64 {
+//!         self.side * self.side
+//!     }
+//! }
+//!
+//! register_tagged!(dyn Shape: Square = "square");
+//!
+//! let shapes: Vec<TaggedArc<dyn Shape>> =
+//!     vec![TaggedArc(unsize!(Arc::new(Square { side: 2.0 }) => dyn Shape))];
+//! let json = serde_json::to_string(&shapes).unwrap();
+//! assert_eq!(r#"[{"square":{"side":2.0}}]"#, json);
+//!
+//! let shapes: Vec<TaggedArc<dyn Shape>> = serde_json::from_str(&json).unwrap();
+//! assert_eq!(4.0, shapes[0].area());
+//! ```
+//!
+//! Every Arc is written out in full, so two Arcs to one value come back as two values.
+
+use crate::cast::CastFrom;
+use crate::Arc;
+use serde::de::{self, DeserializeSeed, Deserializer, MapAccess, Visitor};
+use serde::ser::{self, SerializeMap, Serializer};
+use serde::Serialize;
+use std::any::{type_name, Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::Deref;
+use std::sync::{Arc as StdArc, OnceLock, PoisonError, RwLock};
+
+/// Values that can be serialized behind a trait object. Implemented for every
+/// `Serialize + 'static` type.
+pub trait SerializeTagged: CastFrom {
+    /// Gets this as an object-safe `Serialize`
+    fn as_serialize(&self) -> &dyn erased_serde::Serialize;
+}
+
+impl<T: Serialize + 'static> SerializeTagged for T {
+    fn as_serialize(&self) -> &dyn erased_serde::Serialize {
+        self
+    }
+}
+
+type ReadFn<T> = StdArc<
+    dyn Fn(&mut dyn erased_serde::Deserializer) -> erased_serde::Result<Arc<T>> + Send + Sync,
+>;
+
+#[derive(Default)]
+struct Tags {
+    // concrete type -> tag
+    names: HashMap<TypeId, &'static str>,
+    // (trait object type, tag) -> a ReadFn for that trait object
+    readers: HashMap<(TypeId, &'static str), Box<dyn Any + Send + Sync>>,
+}
+
+fn tags() -> &'static RwLock<Tags> {
+    static TAGS: OnceLock<RwLock<Tags>> = OnceLock::new();
+    TAGS.get_or_init(Default::default)
+}
+
+/// Registers `T` under `tag`, to be serialized behind, and deserialized as, `U`.
+/// [`register_tagged!`](crate::register_tagged) calls this safely.
+///
+/// # Safety
+///
+/// `coerce` must return its argument, coerced.
+pub unsafe fn register<T, U>(tag: &'static str, coerce: fn(*const T) -> *const U)
+where
+    T: for<'de> serde::Deserialize<'de> + 'static,
+    U: ?Sized + 'static,
+{
+    let reader: ReadFn<U> = StdArc::new(move |deserializer| {
+        let value: T = erased_serde::deserialize(deserializer)?;
+        Ok(Arc::unsize(Arc::new(value), coerce))
+    });
+
+    let mut tags = tags().write().unwrap_or_else(PoisonError::into_inner);
+    tags.names.insert(TypeId::of::<T>(), tag);
+    tags.readers
+        .insert((TypeId::of::<U>(), tag), Box::new(reader));
+}
+
+/// Registers concrete types under tags, for a trait object type.
+///
+/// ```
+/// use provenant::register_tagged;
+/// use std::fmt::Debug;
+///
+/// register_tagged!(dyn Debug: u32 = "u32", String = "string");
+/// ```
+#[macro_export]
+macro_rules! register_tagged {
+    ($target:ty: $($concrete:ty = $tag:expr),+ $(,)?) => {
+        $({
+            let tag: &'static str = $tag;
+            // the closure only coerces, which is what register needs
+            unsafe { $crate::tagged::register::<$concrete, $target>(tag, |ptr| -> *const $target { ptr }) };
+        })+
+    };
+}
+
+/// Serializes an Arc to a [`SerializeTagged`] trait object, tagged with its concrete
+/// type. For `#[serde(with = "provenant::tagged")]`.
+pub fn serialize<T, S>(arc: &Arc<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: ?Sized + SerializeTagged,
+    S: Serializer,
+{
+    let concrete = (**arc).concrete_type();
+    let tag = tags()
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .names
+        .get(&concrete)
+        .copied();
+    let tag = tag.ok_or_else(|| {
+        ser::Error::custom(format_args!(
+            "no tag registered for a value behind {}",
+            type_name::<T>()
+        ))
+    })?;
+
+    let mut map = serializer.serialize_map(Some(1))?;
+    map.serialize_entry(tag, arc.as_serialize())?;
+    map.end()
+}
+
+/// Deserializes an Arc to a trait object from a tagged value, as the type registered
+/// under the tag. For `#[serde(with = "provenant::tagged")]`.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<Arc<T>, D::Error>
+where
+    T: ?Sized + 'static,
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_map(TaggedVisitor(PhantomData))
+}
+
+struct TaggedVisitor<T: ?Sized>(PhantomData<fn() -> Arc<T>>);
+
+impl<'de, T: ?Sized + 'static> Visitor<'de> for TaggedVisitor<T> {
+    type Value = Arc<T>;
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "a tagged {}", type_name::<T>())
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Arc<T>, A::Error> {
+        let tag: String = map
+            .next_key()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+        let reader = {
+            let tags = tags().read().unwrap_or_else(PoisonError::into_inner);
+            tags.readers
+                .get(&(TypeId::of::<T>(), tag.as_str()))
+                .and_then(|reader| reader.downcast_ref::<ReadFn<T>>())
+                .cloned()
+        };
+        let reader = reader.ok_or_else(|| {
+            de::Error::custom(format_args!(
+                "unknown tag `{}` for {}",
+                tag,
+                type_name::<T>()
+            ))
+        })?;
+
+        map.next_value_seed(Reader(reader))
+    }
+}
+
+struct Reader<T: ?Sized>(ReadFn<T>);
+
+impl<'de, T: ?Sized> DeserializeSeed<'de> for Reader<T> {
+    type Value = Arc<T>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Arc<T>, D::Error> {
+        let mut erased = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0)(&mut erased).map_err(de::Error::custom)
+    }
+}
+
+/// An Arc to a trait object that serializes tagged with its concrete type
+pub struct TaggedArc<T: ?Sized>(pub Arc<T>);
+
+impl<T: ?Sized> Deref for TaggedArc<T> {
+    type Target = Arc<T>;
+    fn deref(&self) -> &Arc<T> {
+        &self.0
+    }
+}
+
+impl<T: ?Sized> Clone for TaggedArc<T> {
+    fn clone(&self) -> Self {
+        TaggedArc(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> From<Arc<T>> for TaggedArc<T> {
+    fn from(arc: Arc<T>) -> Self {
+        TaggedArc(arc)
+    }
+}
+
+impl<T: ?Sized + SerializeTagged> Serialize for TaggedArc<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize(&self.0, serializer)
+    }
+}
+
+impl<'de, T: ?Sized + 'static> serde::Deserialize<'de> for TaggedArc<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize(deserializer).map(TaggedArc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    trait Animal: SerializeTagged {
+        fn speak(&self) -> String;
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Dog;
+
+    #[derive(Serialize, Deserialize)]
+    struct Cat {
+        lives: u8,
+    }
+
+    impl Animal for Dog {
+        fn speak(&self) -> String {
+            "woof".into()
+        }
+    }
+
+    impl Animal for Cat {
+        fn speak(&self) -> String {
+            format!("meow x{}", self.lives)
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Zoo {
+        #[serde(with = "crate::tagged")]
+        star: Arc<dyn Animal>,
+        others: Vec<TaggedArc<dyn Animal>>,
+    }
+
+    #[test]
+    fn round_trip() {
+        crate::register_tagged!(dyn Animal: Dog = "dog", Cat = "cat");
+
+        let zoo = Zoo {
+            star: crate::unsize!(Arc::new(Cat { lives: 9 }) => dyn Animal),
+            others: vec![TaggedArc(crate::unsize!(Arc::new(Dog) => dyn Animal))],
+        };
+        let json = serde_json::to_string(&zoo).unwrap();
+        assert_eq!(
+            r#"{"star":{"cat":{"lives":9}},"others":[{"dog":null}]}"#,
+            json
+        );
+
+        let zoo: Zoo = serde_json::from_str(&json).unwrap();
+        assert_eq!("meow x9", zoo.star.speak());
+        assert_eq!("woof", zoo.others[0].speak());
+
+        let unknown = serde_json::from_str::<TaggedArc<dyn Animal>>(r#"{"cow":null}"#);
+        assert!(unknown.is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Wakers backed by an Arc.
+//!
+//! std's `Wake` trait only works with `std::sync::Arc`, so tasks implement
+//! [`Wake`] from here instead, and an `Arc<W>` turns into a `Waker` with
+//! [`waker`] or `Waker::from`. The waker holds a strong reference, like std's.
+//!
+//! An executor can keep weak pointers to its tasks, which don't keep finished
+//! tasks alive, and upgrade one to build a waker when it polls:
+//!
+//! ```
+//! use core::sync::atomic::{AtomicBool, Ordering};
+//! use provenant::task::{waker, Wake};
+//! use provenant::Arc;
+//!
+//! struct Task {
+//!     woken: AtomicBool,
+//! }
+//!
+//! impl Wake for Task {
+//!     fn wake(this: Arc<Self>) {
+//!         this.woken.store(true, Ordering::Relaxed);
+//!     }
+//! }
+//!
+//! let task = Arc::new(Task { woken: AtomicBool::new(false) });
+//! let slab = vec![Arc::downgrade(&task)];
+//!
+//! waker(slab[0].upgrade().unwrap()).wake();
+//! assert!(task.woken.load(Ordering::Relaxed));
+//! ```
+
+use crate::Arc;
+use core::mem::ManuallyDrop;
+use core::task::{RawWaker, RawWakerVTable, Waker};
+
+/// Something an Arc can wake, like std's `Wake`
+pub trait Wake {
+    /// Wakes the task, using up the Arc
+    fn wake(this: Arc<Self>);
+
+    /// Wakes the task without using up the Arc
+    fn wake_by_ref(this: &Arc<Self>) {
+        Wake::wake(this.clone());
+    }
+}
+
+/// Turns an Arc into a waker that calls its [`Wake`] impl
+pub fn waker<W: Wake + Send + Sync + 'static>(arc: Arc<W>) -> Waker {
+    unsafe { Waker::from_raw(raw_waker(Arc::into_raw(arc))) }
+}
+
+impl<W: Wake + Send + Sync + 'static> From<Arc<W>> for Waker {
+    fn from(arc: Arc<W>) -> Self {
+        waker(arc)
+    }
+}
+
+// the data pointer is from Arc::into_raw, and holds one strong reference
+fn raw_waker<W: Wake + Send + Sync + 'static>(ptr: *const W) -> RawWaker {
+    RawWaker::new(
+        ptr as *const (),
+        &RawWakerVTable::new(clone::<W>, wake::<W>, wake_by_ref::<W>, drop::<W>),
+    )
+}
+
+unsafe fn clone<W: Wake + Send + Sync + 'static>(ptr: *const ()) -> RawWaker {
+    Arc::increment_strong_count(ptr as *const W);
+    raw_waker(ptr as *const W)
+}
+
+unsafe fn wake<W: Wake + Send + Sync + 'static>(ptr: *const ()) {
+    W::wake(Arc::from_raw(ptr as *const W));
+}
+
+unsafe fn wake_by_ref<W: Wake + Send + Sync + 'static>(ptr: *const ()) {
+    let arc = ManuallyDrop::new(Arc::from_raw(ptr as *const W));
+    W::wake_by_ref(&arc);
+}
+
+unsafe fn drop<W: Wake + Send + Sync + 'static>(ptr: *const ()) {
+    Arc::decrement_strong_count(ptr as *const W);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    struct Count(AtomicUsize);
+
+    impl Wake for Count {
+        fn wake(this: Arc<Self>) {
+            this.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn counted() {
+        let count = Arc::new(Count(AtomicUsize::new(0)));
+        let weak = Arc::downgrade(&count);
+        let waker = Waker::from(count.clone());
+        let other = waker.clone();
+        assert_eq!(3, Arc::strong_count(&count));
+
+        waker.wake_by_ref();
+        waker.wake();
+        assert_eq!(2, count.0.load(Ordering::Relaxed));
+        assert_eq!(2, Arc::strong_count(&count));
+
+        core::mem::drop((other, count));
+        assert!(weak.upgrade().is_none());
+    }
+}
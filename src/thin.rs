@@ -0,0 +1,261 @@
+//! Shared slices behind a single-word handle.
+//!
+//! An `Arc<[T]>` is two words wide, since the length travels with the pointer. A
+//! [`ThinArc`] keeps the length in the allocation instead, next to a header, so
+//! the handle is one pointer and can go through FFI or be packed densely.
+//!
+//! ```
+//! use provenant::thin::ThinArc;
+//!
+//! let thin = ThinArc::from_header_and_iter("primes", [2, 3, 5, 7]);
+//! assert_eq!(std::mem::size_of::<usize>(), std::mem::size_of_val(&thin));
+//! assert_eq!("primes", thin.header);
+//! assert_eq!([2, 3, 5, 7], thin.slice);
+//! ```
+//!
+//! It converts to and from an `Arc<HeaderSlice<H, [T]>>` without copying, and its
+//! weak pointers, [`ThinWeak`], don't carry the length either.
+
+use crate::tag::untagged;
+use crate::{events, write_header, Arc, Inner, Weak};
+use alloc::alloc::Layout;
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem::{self, ManuallyDrop};
+use core::ops::Deref;
+use core::ptr;
+
+/// A header followed by a slice, in one allocation
+#[repr(C)]
+pub struct HeaderSlice<H, T: ?Sized> {
+    /// The header
+    pub header: H,
+    // the slice's length, so a thin pointer can find it
+    len: usize,
+    /// The slice
+    pub slice: T,
+}
+
+// the sized stand-in a thin pointer points at. its header is laid out like the
+// real thing's, and the slice starts in the same place
+type Thin<H, T> = Inner<HeaderSlice<H, [T; 0]>>;
+
+type Fat<H, T> = Inner<HeaderSlice<H, [T]>>;
+
+// the allocation for `len` elements
+fn layout<H, T>(len: usize) -> Layout {
+    let start = mem::offset_of!(Thin<H, T>, data) + mem::offset_of!(HeaderSlice<H, [T; 0]>, slice);
+    let slice = Layout::array::<T>(len).expect("capacity overflow");
+    let size = start.checked_add(slice.size()).expect("capacity overflow");
+    Layout::from_size_align(size, mem::align_of::<Thin<H, T>>())
+        .expect("capacity overflow")
+        .pad_to_align()
+}
+
+// gives a thin pointer the length it points at. keeps the tag
+unsafe fn fat<H, T>(thin: *const Thin<H, T>) -> *const Fat<H, T> {
+    let len = (*untagged(thin)).data.len;
+    ptr::slice_from_raw_parts(thin as *const T, len) as *const Fat<H, T>
+}
+
+/// An Arc to a [`HeaderSlice`], one pointer wide
+pub struct ThinArc<H, T> {
+    ptr: *const Thin<H, T>,
+    _marker: PhantomData<Arc<HeaderSlice<H, [T]>>>,
+}
+
+unsafe impl<H: Send + Sync, T: Send + Sync> Send for ThinArc<H, T> {}
+unsafe impl<H: Send + Sync, T: Send + Sync> Sync for ThinArc<H, T> {}
+
+impl<H, T> ThinArc<H, T> {
+    /// Allocates a header and the elements of `items` together.
+    ///
+    /// # Panics
+    ///
+    /// If `items` yields a different number of elements than its `len` said.
+    pub fn from_header_and_iter<I>(header: H, items: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+        I::IntoIter: ExactSizeIterator,
+    {
+        let mut items = items.into_iter();
+        let len = items.len();
+        let layout = layout::<H, T>(len);
+
+        // if an element panics, the allocation leaks, along with what's written so far
+        unsafe {
+            let base = alloc::alloc::alloc(layout);
+            if base.is_null() {
+                alloc::alloc::handle_alloc_error(layout);
+            }
+            let inner = ptr::slice_from_raw_parts_mut(base as *mut T, len) as *mut Fat<H, T>;
+            let data = ptr::addr_of_mut!((*inner).data);
+            ptr::addr_of_mut!((*data).header).write(header);
+            ptr::addr_of_mut!((*data).len).write(len);
+
+            let slice = ptr::addr_of_mut!((*data).slice) as *mut T;
+            for i in 0..len {
+                match items.next() {
+                    Some(item) => slice.add(i).write(item),
+                    None => {
+                        ptr::drop_in_place(ptr::addr_of_mut!((*data).header));
+                        ptr::drop_in_place(ptr::slice_from_raw_parts_mut(slice, i));
+                        alloc::alloc::dealloc(base, layout);
+                        panic!("iterator was shorter than its length");
+                    }
+                }
+            }
+            let overran = items.next().is_some();
+
+            write_header(inner);
+            events::allocated(inner);
+            let thin = ThinArc::from_arc(Arc { ptr: inner });
+            assert!(!overran, "iterator was longer than its length");
+            thin
+        }
+    }
+
+    /// Turns an Arc to a header and slice into a thin one, without copying
+    pub fn from_arc(arc: Arc<HeaderSlice<H, [T]>>) -> Self {
+        let ptr = arc.ptr as *const Thin<H, T>;
+        mem::forget(arc);
+        ThinArc {
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Turns this into an ordinary Arc, without copying
+    pub fn into_arc(this: Self) -> Arc<HeaderSlice<H, [T]>> {
+        let ptr = unsafe { fat(this.ptr) };
+        mem::forget(this);
+        Arc { ptr }
+    }
+
+    /// Gets a weak pointer, which is one pointer and a provenance id wide
+    pub fn downgrade(this: &Self) -> ThinWeak<H, T> {
+        ThinWeak {
+            weak: Weak {
+                ptr: this.ptr,
+                provenance: Arc::downgrade(&this.as_arc()).provenance,
+            },
+        }
+    }
+
+    // an Arc to look through, which mustn't be dropped
+    fn as_arc(&self) -> ManuallyDrop<Arc<HeaderSlice<H, [T]>>> {
+        ManuallyDrop::new(Arc {
+            ptr: unsafe { fat(self.ptr) },
+        })
+    }
+}
+
+impl<H, T> Deref for ThinArc<H, T> {
+    type Target = HeaderSlice<H, [T]>;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &(*untagged(fat(self.ptr))).data }
+    }
+}
+
+impl<H, T> Clone for ThinArc<H, T> {
+    fn clone(&self) -> Self {
+        ThinArc::from_arc(Arc::clone(&self.as_arc()))
+    }
+}
+
+impl<H, T> Drop for ThinArc<H, T> {
+    fn drop(&mut self) {
+        drop(ManuallyDrop::into_inner(self.as_arc()));
+    }
+}
+
+impl<H, T> From<Arc<HeaderSlice<H, [T]>>> for ThinArc<H, T> {
+    fn from(arc: Arc<HeaderSlice<H, [T]>>) -> Self {
+        ThinArc::from_arc(arc)
+    }
+}
+
+impl<H, T> From<ThinArc<H, T>> for Arc<HeaderSlice<H, [T]>> {
+    fn from(thin: ThinArc<H, T>) -> Self {
+        ThinArc::into_arc(thin)
+    }
+}
+
+impl<H: fmt::Debug, T: fmt::Debug> fmt::Debug for ThinArc<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThinArc")
+            .field("header", &self.header)
+            .field("slice", &&self.slice)
+            .finish()
+    }
+}
+
+/// A weak pointer to a [`HeaderSlice`], which upgrades to a [`ThinArc`]
+pub struct ThinWeak<H, T> {
+    // the length is only read once upgrading has made sure the value is alive
+    weak: Weak<HeaderSlice<H, [T; 0]>>,
+}
+
+unsafe impl<H: Send + Sync, T: Send + Sync> Send for ThinWeak<H, T> {}
+unsafe impl<H: Send + Sync, T: Send + Sync> Sync for ThinWeak<H, T> {}
+
+impl<H, T> Copy for ThinWeak<H, T> {}
+
+impl<H, T> Clone for ThinWeak<H, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<H, T> ThinWeak<H, T> {
+    /// Attempts to get a strong reference, like [`Weak::upgrade`]
+    pub fn upgrade(&self) -> Option<ThinArc<H, T>> {
+        // the Arc this gives points at the stand-in type, whose layout is wrong for
+        // dropping, so it's turned straight into a ThinArc
+        let arc = self.weak.upgrade()?;
+        let ptr = arc.ptr;
+        mem::forget(arc);
+        Some(ThinArc {
+            ptr,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<H, T> fmt::Debug for ThinWeak<H, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.weak, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let thin =
+            ThinArc::from_header_and_iter(String::from("names"), ["a", "b"].map(String::from));
+        let weak = ThinArc::downgrade(&thin);
+        let copy = thin.clone();
+
+        let arc: Arc<HeaderSlice<String, [String]>> = thin.into();
+        assert_eq!("names", arc.header);
+        assert_eq!(["a", "b"], arc.slice);
+
+        let thin = ThinArc::from(arc);
+        assert_eq!(2, weak.upgrade().unwrap().slice.len());
+        drop((thin, copy));
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn aligned_header() {
+        let thin = ThinArc::from_header_and_iter(u128::MAX, 0..3u8);
+        assert_eq!(u128::MAX, thin.header);
+        assert_eq!([0, 1, 2], thin.slice);
+
+        let empty = ThinArc::<(), u64>::from_header_and_iter((), []);
+        assert!(empty.slice.is_empty());
+    }
+}
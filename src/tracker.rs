@@ -0,0 +1,203 @@
+//! Graceful shutdown of shared resources.
+//!
+//! Register the resources a server owns with a [`ResourceTracker`], then call
+//! [`shutdown`](ResourceTracker::shutdown) when it's time to stop. That cuts off
+//! every weak pointer to them, waits for the other strong holders to let go, and
+//! reports whichever ones didn't in time, along with where they were registered.
+
+use crate::Arc;
+use std::any::type_name;
+use std::fmt;
+use std::panic::Location;
+use std::sync::atomic::Ordering;
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+trait Tracked: Send + Sync {
+    fn invalidate(&self);
+    fn wait_unique(&self, deadline: Instant) -> bool;
+    fn others(&self) -> usize;
+    fn type_name(&self) -> &'static str;
+}
+
+impl<T: ?Sized + Send + Sync> Tracked for Arc<T> {
+    fn invalidate(&self) {
+        self.inner().rekey();
+    }
+
+    fn wait_unique(&self, deadline: Instant) -> bool {
+        Arc::wait_unique(self, deadline.saturating_duration_since(Instant::now()))
+    }
+
+    fn others(&self) -> usize {
+        self.inner()
+            .ref_count
+            .load(Ordering::SeqCst)
+            .saturating_sub(1)
+    }
+
+    fn type_name(&self) -> &'static str {
+        type_name::<T>()
+    }
+}
+
+struct Entry {
+    resource: Box<dyn Tracked>,
+    registered_at: &'static Location<'static>,
+}
+
+/// Keeps track of shared resources so they can be shut down together
+///
+/// The tracker holds a strong reference to everything registered with it,
+/// so resources stay alive until shutdown.
+#[derive(Default)]
+pub struct ResourceTracker {
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl ResourceTracker {
+    /// Creates a tracker with nothing registered
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts tracking `resource`, remembering the caller's location for the shutdown report
+    #[track_caller]
+    pub fn register<T: ?Sized + Send + Sync + 'static>(&self, resource: &Arc<T>) {
+        let entry = Entry {
+            resource: Box::new(resource.clone()),
+            registered_at: Location::caller(),
+        };
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(entry);
+    }
+
+    /// The number of resources registered
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Returns true if nothing is registered
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Shuts down every registered resource.
+    ///
+    /// First every weak pointer to them is invalidated, so nothing new can get hold of
+    /// them. Then this waits, until `deadline` at the latest, for the other strong
+    /// references to be dropped. The tracker's own references are dropped at the end,
+    /// which frees whatever isn't held anywhere else.
+    pub fn shutdown(self, deadline: Instant) -> ShutdownReport {
+        let entries = self
+            .entries
+            .into_inner()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        for entry in &entries {
+            entry.resource.invalidate();
+        }
+
+        let mut stragglers = Vec::new();
+        for entry in &entries {
+            if !entry.resource.wait_unique(deadline) {
+                stragglers.push(Straggler {
+                    type_name: entry.resource.type_name(),
+                    registered_at: entry.registered_at,
+                    strong_count: entry.resource.others(),
+                });
+            }
+        }
+
+        ShutdownReport { stragglers }
+    }
+}
+
+/// What happened during [`ResourceTracker::shutdown`]
+#[derive(Debug)]
+pub struct ShutdownReport {
+    /// Resources that were still held elsewhere when the deadline passed
+    pub stragglers: Vec<Straggler>,
+}
+
+impl ShutdownReport {
+    /// Returns true if every resource was released in time
+    pub fn is_clean(&self) -> bool {
+        self.stragglers.is_empty()
+    }
+}
+
+/// A resource that was still held elsewhere at the shutdown deadline
+#[derive(Debug, Clone)]
+pub struct Straggler {
+    /// The type of the resource
+    pub type_name: &'static str,
+    /// Where it was registered with the tracker
+    pub registered_at: &'static Location<'static>,
+    /// How many strong references there were besides the tracker's
+    pub strong_count: usize,
+}
+
+impl fmt::Display for Straggler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} registered at {} still has {} strong reference(s)",
+            self.type_name, self.registered_at, self.strong_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn clean_shutdown() {
+        let tracker = ResourceTracker::new();
+        let db = Arc::new(String::from("db"));
+        tracker.register(&db);
+
+        let weak = Arc::downgrade(&db);
+        let user = db.clone();
+        drop(db);
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(user);
+        });
+
+        let report = tracker.shutdown(Instant::now() + Duration::from_secs(10));
+        assert!(report.is_clean());
+        assert!(weak.upgrade().is_none());
+        t.join().unwrap();
+    }
+
+    #[test]
+    fn reports_stragglers() {
+        let tracker = ResourceTracker::new();
+        let cache = Arc::new(vec![1, 2, 3]);
+        tracker.register(&cache);
+        let line = line!() - 1;
+
+        let weak = Arc::downgrade(&cache);
+        let report = tracker.shutdown(Instant::now() + Duration::from_millis(10));
+
+        assert_eq!(1, report.stragglers.len());
+        let straggler = &report.stragglers[0];
+        assert_eq!(1, straggler.strong_count);
+        assert_eq!(line, straggler.registered_at.line());
+        assert!(straggler.type_name.contains("Vec<i32>"));
+
+        // the straggler still works, but can't be reached through old weaks
+        assert_eq!(3, cache.len());
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,202 @@
+//! Arcs allocated before their value exists, so large values can be filled in
+//! place instead of being built on the stack and copied in.
+
+use crate::slice::slice_layout;
+use crate::{events, write_header, Arc, Inner};
+use alloc::alloc::Layout;
+use core::mem::MaybeUninit;
+use core::ptr;
+
+// allocates an Inner of `layout` and fills in its header. `zeroed` zeroes the data.
+// returns None if the allocator is out of memory
+pub(crate) unsafe fn try_allocate<T: ?Sized>(
+    layout: Layout,
+    zeroed: bool,
+    inner: impl FnOnce(*mut u8) -> *mut Inner<T>,
+) -> Option<Arc<T>> {
+    let base = if zeroed {
+        alloc::alloc::alloc_zeroed(layout)
+    } else {
+        alloc::alloc::alloc(layout)
+    };
+    if base.is_null() {
+        return None;
+    }
+    let inner = inner(base);
+    write_header(inner);
+    events::allocated(inner);
+    Some(Arc { ptr: inner })
+}
+
+unsafe fn allocate<T: ?Sized>(
+    layout: Layout,
+    zeroed: bool,
+    inner: impl FnOnce(*mut u8) -> *mut Inner<T>,
+) -> Arc<T> {
+    try_allocate(layout, zeroed, inner).unwrap_or_else(|| alloc::alloc::handle_alloc_error(layout))
+}
+
+impl<T> Arc<T> {
+    /// Allocates room for a value without initializing it. Fill it in through
+    /// [`Arc::get_mut`], then call `assume_init`
+    pub fn new_uninit() -> Arc<MaybeUninit<T>> {
+        let layout = Layout::new::<Inner<MaybeUninit<T>>>();
+        unsafe { allocate(layout, false, |base| base as *mut Inner<MaybeUninit<T>>) }
+    }
+
+    /// Allocates room for a value, with every byte of it zeroed
+    pub fn new_zeroed() -> Arc<MaybeUninit<T>> {
+        let layout = Layout::new::<Inner<MaybeUninit<T>>>();
+        unsafe { allocate(layout, true, |base| base as *mut Inner<MaybeUninit<T>>) }
+    }
+
+    /// Allocates room for a value, then has `init` write it straight into the
+    /// allocation, instead of it being built on the stack and moved in.
+    ///
+    /// `init` returns a reference to the value it wrote, the one
+    /// [`MaybeUninit::write`] or [`MaybeUninit::assume_init_mut`] gives back.
+    /// `write` can still build its argument on the stack first, which the optimizer
+    /// usually, but not always, avoids. Writing through the pointer never does:
+    ///
+    /// ```
+    /// use provenant::Arc;
+    ///
+    /// struct Table {
+    ///     rows: [[u64; 512]; 1024],
+    /// }
+    ///
+    /// let table = Arc::<Table>::new_with(|slot| unsafe {
+    ///     // all zeroes is a valid Table
+    ///     slot.as_mut_ptr().write_bytes(0, 1);
+    ///     slot.assume_init_mut()
+    /// });
+    /// assert_eq!(0, table.rows[1023][511]);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If `init` returns a reference to anything else, like a leaked one. The
+    /// allocation is freed, and if a value was written, it's leaked. If `init`
+    /// panics, the allocation is freed too.
+    pub fn new_with<F>(init: F) -> Self
+    where
+        F: FnOnce(&mut MaybeUninit<T>) -> &mut T,
+    {
+        let uninit = Arc::<T>::new_uninit();
+        // nothing else can see it yet
+        let slot = unsafe { &mut (*(uninit.ptr as *mut Inner<MaybeUninit<T>>)).data };
+        let expected = slot.as_ptr();
+        let written: *const T = init(slot);
+        assert!(
+            ptr::eq(expected, written),
+            "new_with's closure has to return the reference MaybeUninit::write gave it"
+        );
+        unsafe { uninit.assume_init() }
+    }
+}
+
+impl<T> Arc<[T]> {
+    /// Allocates room for `len` elements without initializing them
+    pub fn new_uninit_slice(len: usize) -> Arc<[MaybeUninit<T>]> {
+        Arc::<[T]>::uninit_slice(len, false)
+    }
+
+    /// Allocates room for `len` elements, with every byte of them zeroed
+    pub fn new_zeroed_slice(len: usize) -> Arc<[MaybeUninit<T>]> {
+        Arc::<[T]>::uninit_slice(len, true)
+    }
+
+    fn uninit_slice(len: usize, zeroed: bool) -> Arc<[MaybeUninit<T>]> {
+        let (layout, _) = slice_layout::<T>(len);
+        unsafe {
+            allocate(layout, zeroed, |base| {
+                ptr::slice_from_raw_parts_mut(base as *mut MaybeUninit<T>, len)
+                    as *mut Inner<[MaybeUninit<T>]>
+            })
+        }
+    }
+}
+
+impl<T> Arc<MaybeUninit<T>> {
+    /// Treats the value as initialized. Keeps the tag.
+    ///
+    /// Unlike most methods here, this takes `self`: `Arc<MaybeUninit<T>>` and
+    /// `Arc<[MaybeUninit<T>]>` both have one, so `Arc::assume_init(arc)` would be
+    /// ambiguous.
+    ///
+    /// # Safety
+    ///
+    /// The value must have been fully initialized, as for [`MaybeUninit::assume_init`].
+    pub unsafe fn assume_init(self) -> Arc<T> {
+        let ptr = self.ptr as *const Inner<T>;
+        core::mem::forget(self);
+        Arc { ptr }
+    }
+}
+
+impl<T> Arc<[MaybeUninit<T>]> {
+    /// Treats every element as initialized. Keeps the tag.
+    ///
+    /// # Safety
+    ///
+    /// Every element must have been fully initialized, as for
+    /// [`MaybeUninit::assume_init`].
+    pub unsafe fn assume_init(self) -> Arc<[T]> {
+        let ptr = self.ptr as *const Inner<[T]>;
+        core::mem::forget(self);
+        Arc { ptr }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+
+    #[test]
+    fn fill_in_place() {
+        let mut arc = Arc::<[u64; 1024]>::new_uninit();
+        Arc::get_mut(&mut arc).unwrap().write([7; 1024]);
+        let arc = unsafe { arc.assume_init() };
+        assert!(arc.iter().all(|&n| n == 7));
+
+        let zeroed = unsafe { Arc::<u32>::new_zeroed().assume_init() };
+        assert_eq!(0, *zeroed);
+    }
+
+    #[test]
+    fn new_with() {
+        let arc = Arc::new_with(|slot| slot.write(String::from("in place")));
+        assert_eq!("in place", *arc);
+
+        // on a thread with a stack too small to build it on
+        let big = std::thread::Builder::new()
+            .stack_size(64 << 10)
+            .spawn(|| {
+                let arc = Arc::<[u8; 1 << 20]>::new_with(|slot| unsafe {
+                    slot.as_mut_ptr().write_bytes(1, 1);
+                    slot.assume_init_mut()
+                });
+                arc[12345]
+            })
+            .unwrap();
+        assert_eq!(1, big.join().unwrap());
+
+        let wrong = std::panic::catch_unwind(|| {
+            Arc::new_with(|_| Box::leak(Box::new(String::new())));
+        });
+        assert!(wrong.is_err());
+    }
+
+    #[test]
+    fn slices() {
+        let mut arc = Arc::<[String]>::new_uninit_slice(3);
+        for (i, slot) in Arc::get_mut(&mut arc).unwrap().iter_mut().enumerate() {
+            slot.write(i.to_string());
+        }
+        let arc = unsafe { arc.assume_init() };
+        assert_eq!(["0", "1", "2"], *arc);
+
+        let zeroed = unsafe { Arc::<[u8]>::new_zeroed_slice(5).assume_init() };
+        assert_eq!([0; 5], *zeroed);
+    }
+}
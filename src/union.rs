@@ -0,0 +1,177 @@
+//! One of two kinds of Arc in a single word.
+//!
+//! An enum of `Arc<A>` and `Arc<B>` needs a word for the pointer and another for
+//! the discriminant. [`ArcUnion`] keeps which one it is in a tag bit of the pointer
+//! instead, so it's the size of one Arc:
+//!
+//! ```
+//! use provenant::union::{ArcUnion, ArcUnionBorrow};
+//! use provenant::Arc;
+//!
+//! struct Leaf(u32);
+//! struct Pair(Node, Node);
+//! type Node = ArcUnion<Leaf, Pair>;
+//!
+//! fn sum(node: &Node) -> u32 {
+//!     match node.borrow() {
+//!         ArcUnionBorrow::First(leaf) => leaf.0,
+//!         ArcUnionBorrow::Second(pair) => sum(&pair.0) + sum(&pair.1),
+//!     }
+//! }
+//!
+//! let leaf = || ArcUnion::from_first(Arc::new(Leaf(1)));
+//! let tree = ArcUnion::from_second(Arc::new(Pair(leaf(), leaf())));
+//! assert_eq!(2, sum(&tree));
+//! assert_eq!(std::mem::size_of::<usize>(), std::mem::size_of::<Node>());
+//! ```
+//!
+//! The union uses the tag bits itself, so tags on the Arcs put in it are dropped.
+
+use crate::borrow::ArcBorrow;
+use crate::tag::{untagged, with_tag};
+use crate::{Arc, Inner};
+use core::fmt;
+use core::marker::PhantomData;
+use core::mem;
+
+// the tag that marks a B
+const SECOND: u8 = 1;
+
+/// Either an `Arc<A>` or an `Arc<B>`, in one word
+pub struct ArcUnion<A, B> {
+    ptr: *const (),
+    _marker: PhantomData<(Arc<A>, Arc<B>)>,
+}
+
+unsafe impl<A: Send + Sync, B: Send + Sync> Send for ArcUnion<A, B> {}
+unsafe impl<A: Send + Sync, B: Send + Sync> Sync for ArcUnion<A, B> {}
+
+/// A borrow of what's in an [`ArcUnion`], from [`ArcUnion::borrow`]
+#[derive(Debug)]
+pub enum ArcUnionBorrow<'a, A, B> {
+    /// It holds an `Arc<A>`
+    First(ArcBorrow<'a, A>),
+    /// It holds an `Arc<B>`
+    Second(ArcBorrow<'a, B>),
+}
+
+impl<A, B> ArcUnion<A, B> {
+    /// Holds an `Arc<A>`
+    pub fn from_first(arc: Arc<A>) -> Self {
+        Self::from_ptr(untagged(arc.ptr) as *const (), arc)
+    }
+
+    /// Holds an `Arc<B>`
+    pub fn from_second(arc: Arc<B>) -> Self {
+        Self::from_ptr(with_tag(arc.ptr, SECOND) as *const (), arc)
+    }
+
+    // takes over the reference `arc` holds
+    fn from_ptr<T>(ptr: *const (), arc: Arc<T>) -> Self {
+        mem::forget(arc);
+        ArcUnion {
+            ptr,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Whether it holds an `Arc<A>`
+    pub fn is_first(&self) -> bool {
+        !self.is_second()
+    }
+
+    /// Whether it holds an `Arc<B>`
+    pub fn is_second(&self) -> bool {
+        crate::tag::tag_of(self.ptr as *const Inner<()>) == SECOND
+    }
+
+    /// Borrows the `Arc<A>`, if that's what it holds
+    pub fn as_first(&self) -> Option<ArcBorrow<'_, A>> {
+        match self.borrow() {
+            ArcUnionBorrow::First(first) => Some(first),
+            ArcUnionBorrow::Second(_) => None,
+        }
+    }
+
+    /// Borrows the `Arc<B>`, if that's what it holds
+    pub fn as_second(&self) -> Option<ArcBorrow<'_, B>> {
+        match self.borrow() {
+            ArcUnionBorrow::First(_) => None,
+            ArcUnionBorrow::Second(second) => Some(second),
+        }
+    }
+
+    /// Borrows whichever Arc it holds. The borrows have no tag
+    pub fn borrow(&self) -> ArcUnionBorrow<'_, A, B> {
+        unsafe {
+            if self.is_second() {
+                ArcUnionBorrow::Second(ArcBorrow::from_ptr(untagged(self.ptr as *const Inner<B>)))
+            } else {
+                ArcUnionBorrow::First(ArcBorrow::from_ptr(self.ptr as *const Inner<A>))
+            }
+        }
+    }
+
+    /// Returns true if both hold the same allocation
+    pub fn ptr_eq(this: &Self, other: &Self) -> bool {
+        this.ptr == other.ptr
+    }
+}
+
+impl<A, B> Clone for ArcUnion<A, B> {
+    fn clone(&self) -> Self {
+        match self.borrow() {
+            ArcUnionBorrow::First(first) => ArcUnion::from_first(first.clone_arc()),
+            ArcUnionBorrow::Second(second) => ArcUnion::from_second(second.clone_arc()),
+        }
+    }
+}
+
+impl<A, B> Drop for ArcUnion<A, B> {
+    fn drop(&mut self) {
+        if self.is_second() {
+            drop(Arc {
+                ptr: untagged(self.ptr as *const Inner<B>),
+            });
+        } else {
+            drop(Arc {
+                ptr: self.ptr as *const Inner<A>,
+            });
+        }
+    }
+}
+
+impl<A: fmt::Debug, B: fmt::Debug> fmt::Debug for ArcUnion<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.borrow() {
+            ArcUnionBorrow::First(first) => f.debug_tuple("First").field(&first).finish(),
+            ArcUnionBorrow::Second(second) => f.debug_tuple("Second").field(&second).finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn either_arc() {
+        let text = Arc::with_tag(Arc::new(String::from("text")), 3);
+        let weak = Arc::downgrade(&text);
+        let union: ArcUnion<u64, String> = ArcUnion::from_second(text);
+        assert!(union.is_second());
+        assert!(union.as_first().is_none());
+        assert_eq!("text", *union.as_second().unwrap());
+        assert_eq!(0, union.as_second().unwrap().with_arc(Arc::tag));
+
+        let copy = union.clone();
+        assert!(ArcUnion::ptr_eq(&union, &copy));
+        assert_eq!(2, weak.strong_count());
+        drop((union, copy));
+        assert!(weak.upgrade().is_none());
+
+        let number: ArcUnion<u64, String> = ArcUnion::from_first(Arc::new(5));
+        assert_eq!(5, *number.as_first().unwrap());
+        assert_eq!("First(5)", format!("{:?}", number));
+    }
+}
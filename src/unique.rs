@@ -0,0 +1,206 @@
+//! Arcs that are known to be the only one, so the value can be mutated freely
+//! before it's shared.
+//!
+//! A [`UniqueArc`] hands out weak pointers that already have their final
+//! provenance id, but don't upgrade until it's turned into an [`Arc`] with
+//! [`shareable`](UniqueArc::shareable). That makes two-phase construction of
+//! cyclic structures safe: build the nodes, link them up with weak pointers and
+//! `&mut` access, then share them.
+//!
+//! ```
+//! use provenant::unique::UniqueArc;
+//! use provenant::Weak;
+//!
+//! struct Node {
+//!     parent: Weak<Node>,
+//!     children: Vec<Weak<Node>>,
+//! }
+//!
+//! let mut parent = UniqueArc::new(Node {
+//!     parent: Weak::new(),
+//!     children: vec![],
+//! });
+//! let child = UniqueArc::new(Node {
+//!     parent: UniqueArc::downgrade(&parent),
+//!     children: vec![],
+//! });
+//! parent.children.push(UniqueArc::downgrade(&child));
+//! assert!(child.parent.upgrade().is_none());
+//!
+//! let (parent, child) = (parent.shareable(), child.shareable());
+//! assert!(child.parent.upgrade().is_some());
+//! # drop(parent);
+//! ```
+//!
+//! [`Arc::try_into_unique`] goes the other way, for an Arc that's the only strong
+//! reference left. Weak pointers to it stop upgrading until it's shared again, and
+//! then see the changes.
+
+use crate::{events, park, random_provenance, release_box, untagged, Arc, Inner, Provenance, Weak};
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::fmt;
+use core::mem;
+use core::ops::{Deref, DerefMut};
+use core::ptr;
+use core::sync::atomic::Ordering;
+
+/// An Arc that's the only one to its value, so it can be mutated
+pub struct UniqueArc<T> {
+    // the provenance in the header stays 0 until shareable, so nothing upgrades
+    ptr: *mut Inner<T>,
+    provenance: Provenance,
+    // made from an Arc, so it's already been reported allocated
+    thawed: bool,
+}
+
+// a weak pointer made before sending can upgrade on another thread once this is
+// shareable, so this needs what an Arc does
+unsafe impl<T: Send + Sync> Send for UniqueArc<T> {}
+unsafe impl<T: Send + Sync> Sync for UniqueArc<T> {}
+
+impl<T> UniqueArc<T> {
+    /// Moves `val` into a new allocation nothing else can reach yet
+    pub fn new(val: T) -> Self {
+        let inner = Box::new(Inner::new(val, 0, release_box));
+        UniqueArc {
+            ptr: Box::into_raw(inner),
+            provenance: random_provenance(),
+            thawed: false,
+        }
+    }
+
+    /// Gets a weak pointer that fails to upgrade until this is made shareable
+    pub fn downgrade(this: &Self) -> Weak<T> {
+        #[cfg(feature = "weak-count")]
+        this.inner().weak_count.fetch_add(1, Ordering::Relaxed);
+        Weak {
+            provenance: this.provenance,
+            ptr: this.ptr,
+        }
+    }
+
+    /// Turns this into an Arc, which weak pointers from
+    /// [`downgrade`](UniqueArc::downgrade) can upgrade to
+    pub fn shareable(self) -> Arc<T> {
+        let ptr = self.ptr;
+        // publishes the value along with the provenance, like Reserved::init
+        self.inner()
+            .provenance
+            .store(self.provenance, Ordering::Release);
+        if !self.thawed {
+            events::allocated(ptr);
+        }
+        mem::forget(self);
+
+        Arc { ptr }
+    }
+
+    fn inner(&self) -> &Inner<T> {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> Deref for UniqueArc<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.inner().data
+    }
+}
+
+impl<T> DerefMut for UniqueArc<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut (*self.ptr).data }
+    }
+}
+
+impl<T> Arc<T> {
+    /// Turns this into a [`UniqueArc`] if it's the only strong reference, so the
+    /// value can be mutated freely, or gives it back.
+    ///
+    /// Weak pointers fail to upgrade for as long as it's unique, and upgrade again,
+    /// to the changed value, once it's made [`shareable`](UniqueArc::shareable).
+    /// Unlike [`Arc::get_mut`], nothing is given up for good.
+    pub fn try_into_unique(this: Self) -> Result<UniqueArc<T>, Self> {
+        // only this Arc could change it, if it's the only one
+        let provenance = this.inner().provenance.load(Ordering::Relaxed);
+        let provenance = provenance ^ (provenance & 1);
+        if !this.claim_unique(0) {
+            return Err(this);
+        }
+
+        let ptr = untagged(this.ptr) as *mut Inner<T>;
+        mem::forget(this);
+        Ok(UniqueArc {
+            ptr,
+            provenance,
+            thawed: true,
+        })
+    }
+}
+
+impl<T> Drop for UniqueArc<T> {
+    fn drop(&mut self) {
+        unsafe {
+            let layout = Layout::for_value(&*self.ptr);
+            let release = (*self.ptr).release;
+            if self.thawed {
+                events::freed(self.ptr);
+            }
+            ptr::drop_in_place(self.ptr);
+            release(self.ptr as *mut u8, layout);
+        }
+        if self.thawed {
+            park::notify(self.ptr as *const u8 as usize);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for UniqueArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mutate_then_share() {
+        let mut unique = UniqueArc::new(vec![1]);
+        let weak = UniqueArc::downgrade(&unique);
+        unique.push(2);
+        assert!(weak.upgrade().is_none());
+
+        let arc = unique.shareable();
+        assert_eq!(vec![1, 2], *weak.upgrade().unwrap());
+        assert_eq!(weak, Arc::downgrade(&arc));
+    }
+
+    #[test]
+    fn thawed() {
+        let arc = Arc::new(vec![1]);
+        let weak = Arc::downgrade(&arc);
+        let other = arc.clone();
+        let arc = Arc::try_into_unique(arc).unwrap_err();
+
+        drop(other);
+        let mut unique = Arc::try_into_unique(arc).unwrap();
+        assert!(weak.upgrade().is_none());
+        unique.push(2);
+
+        let arc = unique.shareable();
+        assert_eq!(vec![1, 2], *weak.upgrade().unwrap());
+        drop(Arc::try_into_unique(arc).unwrap());
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn dropped_unshared() {
+        let unique = UniqueArc::new(String::from("never shared"));
+        let weak = UniqueArc::downgrade(&unique);
+        drop(unique);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -0,0 +1,333 @@
+//! Upgrades that say why they failed.
+//!
+//! [`Weak::upgrade`] returns None whether the value was dropped, its memory now
+//! holds something else, or another thread held the provenance lock for too long.
+//! [`Weak::try_upgrade`] makes a single attempt at the lock and tells those apart,
+//! and [`Weak::upgrade_timeout`] keeps trying for a bounded time.
+//! [`Weak::upgrade_many`] upgrades a batch of weak pointers at once, and
+//! [`Weak::read`] and [`Weak::with`] read through a weak pointer without upgrading
+//! it at all.
+
+use crate::{events, Arc, Inner, Provenance, Weak};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Deref;
+use core::ptr;
+use core::sync::atomic::Ordering;
+#[cfg(feature = "std")]
+use std::time::{Duration, Instant};
+
+/// Why [`Weak::try_upgrade`] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpgradeError {
+    /// The value has been dropped. Its memory was zeroed and hasn't been written since
+    Dead,
+    /// The memory holds a different provenance id. The value was given a new id,
+    /// which makes old weak pointers dead, or it was dropped and its memory has been
+    /// reused, possibly by the allocator itself
+    ProvenanceMismatch,
+    /// Another thread held the provenance lock, so the value might still be alive
+    Contended,
+}
+
+impl fmt::Display for UpgradeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UpgradeError::Dead => f.write_str("the value has been dropped"),
+            UpgradeError::ProvenanceMismatch => {
+                f.write_str("the memory holds a different provenance id")
+            }
+            UpgradeError::Contended => f.write_str("the provenance lock was held"),
+        }
+    }
+}
+
+impl core::error::Error for UpgradeError {}
+
+impl<T: ?Sized> Weak<T> {
+    /// Like [`Weak::upgrade`], but gives up instead of waiting if another thread
+    /// holds the provenance lock, and says why it failed
+    #[track_caller]
+    pub fn try_upgrade(&self) -> Result<Arc<T>, UpgradeError> {
+        self.upgrade_or_explain(Weak::try_lock)
+    }
+
+    /// Like [`Weak::try_upgrade`], but waits for the provenance lock to be released
+    /// for up to `timeout`, instead of giving up straight away.
+    ///
+    /// The deadline is checked between attempts, so this can run over by however
+    /// long one wait between them takes: next to nothing while spinning, which is
+    /// the default, a time slice with `lock-yield`, or up to a millisecond with
+    /// `lock-park` or `lock-std`.
+    #[cfg(feature = "std")]
+    #[track_caller]
+    pub fn upgrade_timeout(&self, timeout: Duration) -> Result<Arc<T>, UpgradeError> {
+        let start = Instant::now();
+        self.upgrade_or_explain(|weak| {
+            let mut attempt = 0;
+            loop {
+                match weak.try_lock() {
+                    Err(current) if current == weak.provenance | 1 && start.elapsed() < timeout => {
+                        events::contended();
+                        crate::contention::wait(weak.addr(), attempt, || weak.is_locked());
+                        attempt = attempt.saturating_add(1);
+                    }
+                    locked => return locked,
+                }
+            }
+        })
+    }
+
+    // upgrades if `lock` takes the lock, and otherwise says why from what it found
+    // in the provenance instead
+    #[track_caller]
+    fn upgrade_or_explain(
+        &self,
+        lock: impl FnOnce(&Self) -> Result<Provenance, Provenance>,
+    ) -> Result<Arc<T>, UpgradeError> {
+        let exp = self.provenance;
+
+        // freed memory is zeroed, and so is memory the registry says was freed
+        if let Err(current) = lock(self) {
+            events::upgraded(false);
+            if current == exp | 1 {
+                events::contended();
+                return Err(UpgradeError::Contended);
+            }
+            events::upgrade_failed(exp);
+            if current == 0 {
+                return Err(UpgradeError::Dead);
+            }
+            events::mismatched();
+            return Err(UpgradeError::ProvenanceMismatch);
+        }
+        events::upgraded(true);
+
+        let inner = unsafe { &(*crate::untagged(self.ptr)) };
+        let count = inner.ref_count.fetch_add(1, Ordering::Relaxed) + 1;
+        inner.unlock(exp);
+
+        let arc = Arc { ptr: self.ptr };
+        events::matched(inner, exp, count);
+        Ok(arc)
+    }
+
+    /// Upgrades every weak pointer in `weaks`, returning the Arcs for the ones that
+    /// upgraded, in order.
+    ///
+    /// A weak pointer that's the same as the one before it, tag included, is
+    /// upgraded by cloning the Arc just made, without taking the provenance lock
+    /// again. So sorting a batch first, by address, saves a lock for every
+    /// duplicate.
+    #[track_caller]
+    pub fn upgrade_many<'a>(weaks: impl IntoIterator<Item = &'a Weak<T>>) -> Vec<Arc<T>>
+    where
+        T: 'a,
+    {
+        let weaks = weaks.into_iter();
+        let mut upgraded: Vec<Arc<T>> = Vec::with_capacity(weaks.size_hint().0);
+        // the weak pointer the last Arc came from
+        let mut last: Option<&Weak<T>> = None;
+        for weak in weaks {
+            let arc = match (last, upgraded.last()) {
+                // it can't be rekeyed or freed while the last Arc is held
+                (Some(last), Some(arc))
+                    if last.provenance == weak.provenance && ptr::eq(last.ptr, weak.ptr) =>
+                {
+                    arc.clone()
+                }
+                _ => match weak.upgrade() {
+                    Some(arc) => arc,
+                    None => continue,
+                },
+            };
+            last = Some(weak);
+            upgraded.push(arc);
+        }
+        upgraded
+    }
+
+    /// Runs `f` on the value if it's still alive, without upgrading.
+    ///
+    /// Like [`Weak::read`], for when the value is only needed for a moment. The
+    /// same goes for what `f` mustn't do.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> Option<R> {
+        self.read().map(|guard| f(&guard))
+    }
+
+    /// Gets read access to the value if it's still alive, without upgrading.
+    ///
+    /// Rather than taking a strong reference, the guard holds the provenance lock
+    /// until it's dropped, which saves the two atomic read-modify-writes of bumping
+    /// the ref count and dropping it again. Holding the lock stops other threads
+    /// upgrading to the value, reading it, or dropping its last Arc: they spin, or
+    /// whatever the lock strategy does, until the guard is dropped. So the guard
+    /// should be short-lived, and not held across anything slow.
+    ///
+    /// While it's held, this thread mustn't take the lock itself, by upgrading a
+    /// weak pointer to the same value, reading it again, dropping its last Arc or
+    /// calling [`Arc::get_mut`] on it, which would never return. Allocating and
+    /// freeing other values is fine, `weak-registry` or not.
+    pub fn read(&self) -> Option<WeakReadGuard<'_, T>> {
+        // the lock is what keeps the value from being freed, so the guard doesn't
+        // hold anything else, like the weak-registry table
+        self.lock().ok()?;
+        Some(WeakReadGuard {
+            inner: unsafe { &(*crate::untagged(self.ptr)) },
+            provenance: self.provenance,
+        })
+    }
+}
+
+/// Read access to a value through a weak pointer, from [`Weak::read`]. Holds the
+/// provenance lock until it's dropped
+pub struct WeakReadGuard<'a, T: ?Sized> {
+    inner: &'a Inner<T>,
+    provenance: Provenance,
+}
+
+impl<T: ?Sized> Deref for WeakReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.inner.data
+    }
+}
+
+impl<T: ?Sized> Drop for WeakReadGuard<'_, T> {
+    fn drop(&mut self) {
+        self.inner.unlock(self.provenance);
+    }
+}
+
+impl<T: ?Sized + fmt::Debug> fmt::Debug for WeakReadGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reasons() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+
+        let inner = arc.inner();
+        assert!(inner.lock(weak.provenance));
+        assert_eq!(UpgradeError::Contended, weak.try_upgrade().err().unwrap());
+        inner.unlock(weak.provenance);
+        assert_eq!(1, *weak.try_upgrade().unwrap());
+
+        inner.rekey();
+        assert_eq!(
+            UpgradeError::ProvenanceMismatch,
+            weak.try_upgrade().err().unwrap()
+        );
+
+        // the allocator is free to write to freed memory, so it might not read as zero
+        let weak = Arc::downgrade(&arc);
+        drop(arc);
+        assert_ne!(UpgradeError::Contended, weak.try_upgrade().err().unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn timeout() {
+        use std::time::Duration;
+
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let inner = arc.inner();
+        assert!(inner.lock(weak.provenance));
+        assert_eq!(
+            UpgradeError::Contended,
+            weak.upgrade_timeout(Duration::from_millis(5))
+                .err()
+                .unwrap()
+        );
+
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                std::thread::sleep(Duration::from_millis(5));
+                inner.unlock(weak.provenance);
+            });
+            assert_eq!(1, *weak.upgrade_timeout(Duration::from_secs(10)).unwrap());
+        });
+    }
+
+    #[test]
+    fn many() {
+        let (a, b) = (Arc::new(1), Arc::new(2));
+        let dead = Arc::downgrade(&Arc::new(3));
+        let weaks = [
+            Arc::downgrade(&a),
+            Arc::downgrade(&a),
+            dead,
+            Arc::downgrade(&b),
+            Arc::downgrade(&a),
+        ];
+
+        let arcs = Weak::upgrade_many(&weaks);
+        let values: Vec<i32> = arcs.iter().map(|arc| **arc).collect();
+        assert_eq!(vec![1, 1, 2, 1], values);
+        assert_eq!(4, Arc::strong_count(&a));
+    }
+
+    #[test]
+    fn with() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        assert_eq!(Some((1, 1)), weak.with(|v| (*v, Arc::strong_count(&arc))));
+        // the lock was let go
+        assert!(weak.upgrade().is_some());
+
+        drop(arc);
+        assert_eq!(None, weak.with(|v| *v));
+    }
+
+    #[test]
+    fn read() {
+        let arc = Arc::new(String::from("read"));
+        let weak = Arc::downgrade(&arc);
+
+        let guard = weak.read().unwrap();
+        assert_eq!("read", &*guard);
+        assert_eq!(UpgradeError::Contended, weak.try_upgrade().err().unwrap());
+        drop(guard);
+        assert!(weak.try_upgrade().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn read_across_frees() {
+        let arc = Arc::new(1);
+        let weak = Arc::downgrade(&arc);
+        let guard = weak.read().unwrap();
+
+        let other = std::thread::spawn(move || {
+            // only the last Arc needs the lock to be dropped
+            drop(arc.clone());
+            *weak.upgrade().unwrap()
+        });
+        let unrelated: Vec<_> = (0..100).map(Arc::new).collect();
+        drop(unrelated);
+
+        assert_eq!(1, *guard);
+        drop(guard);
+        assert_eq!(1, other.join().unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn dead() {
+        // pool slots aren't given back to the allocator, so they stay zeroed
+        static POOL: crate::pool::StaticPool<u32, 1> = crate::pool::StaticPool::new();
+        let arc = POOL.alloc(1).unwrap();
+        let weak = Arc::downgrade(&arc);
+        drop(arc);
+        assert_eq!(UpgradeError::Dead, weak.try_upgrade().err().unwrap());
+    }
+}
@@ -0,0 +1,184 @@
+//! Letting the owners of a value refuse upgrades.
+//!
+//! A [`Vetoable`] value carries a policy that's consulted whenever a [`VetoWeak`]
+//! to it upgrades. Owners can install one to turn upgrades away, for example once
+//! shutdown has started or a quota has run out, without giving up the value
+//! themselves.
+
+use crate::{events, Arc, Weak};
+use std::error::Error;
+use std::fmt;
+use std::ops::Deref;
+use std::sync::atomic::Ordering;
+use std::sync::{PoisonError, RwLock};
+
+type Policy<T> = Box<dyn Fn(&T) -> bool + Send + Sync>;
+
+/// A value with a policy deciding which weak pointers may upgrade to it
+pub struct Vetoable<T> {
+    policy: RwLock<Option<Policy<T>>>,
+    value: T,
+}
+
+impl<T> Vetoable<T> {
+    /// Wraps `value` with no policy, so every upgrade is admitted
+    pub fn new(value: T) -> Self {
+        Vetoable {
+            policy: RwLock::new(None),
+            value,
+        }
+    }
+
+    /// Installs a policy, replacing any previous one. Upgrades are admitted
+    /// while it returns true.
+    ///
+    /// The policy runs under the value's provenance lock, so it mustn't upgrade,
+    /// read or drop the last Arc of the same value, which would never return.
+    pub fn set_policy<F>(&self, policy: F)
+    where
+        F: Fn(&T) -> bool + Send + Sync + 'static,
+    {
+        *self.policy.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(policy));
+    }
+
+    /// Removes the policy, admitting every upgrade again
+    pub fn clear_policy(&self) {
+        *self.policy.write().unwrap_or_else(PoisonError::into_inner) = None;
+    }
+
+    /// Makes a weak pointer to `this` that only upgrades if the policy admits it
+    pub fn downgrade(this: &Arc<Vetoable<T>>) -> VetoWeak<T> {
+        VetoWeak {
+            weak: Arc::downgrade(this),
+        }
+    }
+
+    fn admits(&self) -> bool {
+        match &*self.policy.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(policy) => policy(&self.value),
+            None => true,
+        }
+    }
+}
+
+impl<T> Deref for Vetoable<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Why [`VetoWeak::upgrade`] failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refused {
+    /// The value has been dropped
+    Dead,
+    /// The value is alive, but its policy turned the upgrade away
+    Vetoed,
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Refused::Dead => f.write_str("the value has been dropped"),
+            Refused::Vetoed => f.write_str("the upgrade was vetoed by the value's policy"),
+        }
+    }
+}
+
+impl Error for Refused {}
+
+/// A weak pointer to a [`Vetoable`] value, from [`Vetoable::downgrade`]. Every
+/// upgrade asks the value's policy
+pub struct VetoWeak<T> {
+    weak: Weak<Vetoable<T>>,
+}
+
+impl<T> Clone for VetoWeak<T> {
+    fn clone(&self) -> Self {
+        VetoWeak { weak: self.weak }
+    }
+}
+
+impl<T> VetoWeak<T> {
+    /// Like [`Weak::upgrade`], but fails if the value's policy turns it away
+    pub fn upgrade(&self) -> Result<Arc<Vetoable<T>>, Refused> {
+        // the guard holds the provenance lock, so the value can't be freed while
+        // the policy is asked, and unlocks even if the policy panics
+        let guard = match self.weak.read() {
+            Some(guard) => guard,
+            None => {
+                events::upgraded(false);
+                return Err(Refused::Dead);
+            }
+        };
+        // asked before the count goes up, so a refused caller never holds a
+        // reference, and can't end up being the one to drop the value
+        if !guard.admits() {
+            events::upgraded(false);
+            return Err(Refused::Vetoed);
+        }
+        events::upgraded(true);
+
+        let inner = unsafe { &(*crate::untagged(self.weak.ptr)) };
+        let count = inner.ref_count.fetch_add(1, Ordering::Relaxed) + 1;
+        drop(guard);
+
+        let arc = Arc { ptr: self.weak.ptr };
+        events::matched(inner, self.weak.provenance, count);
+        Ok(arc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Barrier;
+    use std::thread::{self, ThreadId};
+    use std::time::Duration;
+
+    #[test]
+    fn shutdown() {
+        let arc = Arc::new(Vetoable::new(AtomicBool::new(false)));
+        let weak = Vetoable::downgrade(&arc);
+        arc.set_policy(|shutting_down| !shutting_down.load(Ordering::SeqCst));
+
+        assert!(weak.upgrade().is_ok());
+
+        arc.store(true, Ordering::SeqCst);
+        assert_eq!(Refused::Vetoed, weak.upgrade().err().unwrap());
+
+        arc.clear_policy();
+        assert!(weak.upgrade().is_ok());
+
+        drop(arc);
+        assert_eq!(Refused::Dead, weak.upgrade().err().unwrap());
+    }
+
+    #[test]
+    fn vetoed_never_drops() {
+        // panics if it's dropped anywhere but the thread that made it
+        struct Owned(ThreadId);
+        impl Drop for Owned {
+            fn drop(&mut self) {
+                assert_eq!(self.0, thread::current().id());
+            }
+        }
+
+        let arc = Arc::new(Vetoable::new(Owned(thread::current().id())));
+        let asked = std::sync::Arc::new(Barrier::new(2));
+        let barrier = asked.clone();
+        // drops the last Arc while the policy is still deciding
+        arc.set_policy(move |_| {
+            barrier.wait();
+            thread::sleep(Duration::from_millis(10));
+            false
+        });
+        let weak = Vetoable::downgrade(&arc);
+        let vetoed = thread::spawn(move || weak.upgrade().err());
+        asked.wait();
+        drop(arc);
+        assert_eq!(Some(Refused::Vetoed), vetoed.join().unwrap());
+    }
+}
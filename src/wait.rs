@@ -0,0 +1,81 @@
+//! Blocking until something happens to an allocation.
+
+use crate::park;
+use crate::{Arc, Weak};
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+impl<T: ?Sized> Arc<T> {
+    /// Blocks until this is the only strong reference left, or `timeout` passes.
+    ///
+    /// Returns true if this became the only strong reference. Weak pointers can
+    /// still upgrade afterwards, so that's a snapshot, not a promise.
+    pub fn wait_unique(this: &Self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        let inner = this.inner();
+
+        park::wait_until(this.addr(), deadline, || {
+            inner.ref_count.load(Ordering::Acquire) == 1
+        })
+    }
+}
+
+impl<T: ?Sized> Weak<T> {
+    /// Blocks until the value this points to is gone, or `timeout` passes.
+    ///
+    /// Returns true if it's gone: the last Arc was dropped, or the value was
+    /// detached with [`Arc::get_mut`]. The blocking version of `Weak::dropped`.
+    pub fn wait_dropped(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now().checked_add(timeout);
+        park::wait_until(self.addr(), deadline, || !self.is_probably_alive())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn wait_for_others() {
+        let arc = Arc::new(5);
+        let others: Vec<_> = (0..3)
+            .map(|i| {
+                let arc = arc.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(10 * i));
+                    drop(arc);
+                })
+            })
+            .collect();
+
+        assert!(Arc::wait_unique(&arc, Duration::from_secs(10)));
+        for t in others {
+            t.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn times_out() {
+        let arc = Arc::new(5);
+        let other = arc.clone();
+        assert!(!Arc::wait_unique(&arc, Duration::from_millis(10)));
+        drop(other);
+        assert!(Arc::wait_unique(&arc, Duration::from_millis(10)));
+    }
+
+    #[test]
+    fn wait_dropped() {
+        let arc = Arc::new(5);
+        let weak = Arc::downgrade(&arc);
+        assert!(!weak.wait_dropped(Duration::from_millis(10)));
+
+        let t = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            drop(arc);
+        });
+        assert!(weak.wait_dropped(Duration::from_secs(10)));
+        t.join().unwrap();
+    }
+}
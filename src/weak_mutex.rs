@@ -0,0 +1,234 @@
+//! An async mutex that's reached through a weak pointer (`async` feature).
+//!
+//! Background workers often need to lock some shared state, but shouldn't keep it
+//! alive: if everyone else is done with it, the work is pointless. A [`WeakMutex`]
+//! does both. Its [`lock`](WeakMutex::lock) future waits for the lock without
+//! holding a strong reference, and resolves to [`Dead`] instead if the state is
+//! dropped in the meantime.
+//!
+//! Waiting uses the same parking table as [`Arc::when_unique`], woken by unlocks
+//! and by the value being freed.
+
+use crate::park;
+use crate::{Arc, Weak};
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::task::{Context, Poll};
+
+/// A mutex meant to be shared with [`WeakMutex`] handles
+pub struct AsyncMutex<T: ?Sized> {
+    locked: AtomicBool,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for AsyncMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for AsyncMutex<T> {}
+
+impl<T> AsyncMutex<T> {
+    /// Creates an unlocked mutex
+    pub fn new(value: T) -> Self {
+        AsyncMutex {
+            locked: AtomicBool::new(false),
+            value: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> AsyncMutex<T> {
+    fn try_acquire(&self) -> bool {
+        self.locked
+            .compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst)
+            .is_ok()
+    }
+}
+
+/// A weak pointer to an [`AsyncMutex`], which can lock it while it's alive
+pub struct WeakMutex<T: ?Sized>(Weak<AsyncMutex<T>>);
+
+impl<T: ?Sized> Copy for WeakMutex<T> {}
+
+impl<T: ?Sized> Clone for WeakMutex<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+/// The [`AsyncMutex`] behind a [`WeakMutex`] was dropped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dead;
+
+impl fmt::Display for Dead {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the mutex has been dropped")
+    }
+}
+
+impl Error for Dead {}
+
+impl<T: ?Sized> WeakMutex<T> {
+    /// Gets a handle to the mutex in `arc`
+    pub fn new(arc: &Arc<AsyncMutex<T>>) -> Self {
+        WeakMutex(Arc::downgrade(arc))
+    }
+
+    /// Waits for the lock, unless the mutex is dropped first
+    pub fn lock(&self) -> Lock<T> {
+        Lock {
+            weak: self.0,
+            id: park::waker_id(),
+            registered: false,
+        }
+    }
+
+    /// Takes the lock if it's free right now
+    pub fn try_lock(&self) -> Result<Option<WeakMutexGuard<T>>, Dead> {
+        let arc = self.0.upgrade().ok_or(Dead)?;
+        if arc.try_acquire() {
+            Ok(Some(WeakMutexGuard::new(arc)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+/// The future returned by [`WeakMutex::lock`]
+pub struct Lock<T: ?Sized> {
+    weak: Weak<AsyncMutex<T>>,
+    id: usize,
+    registered: bool,
+}
+
+impl<T: ?Sized> Future for Lock<T> {
+    type Output = Result<WeakMutexGuard<T>, Dead>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let arc = match self.weak.upgrade() {
+            Some(arc) => arc,
+            None => return Poll::Ready(Err(Dead)),
+        };
+        if arc.try_acquire() {
+            return Poll::Ready(Ok(WeakMutexGuard::new(arc)));
+        }
+
+        park::register_waker(arc.addr(), self.id, cx.waker());
+        self.registered = true;
+
+        // the lock might have been released before the waker was registered
+        if arc.try_acquire() {
+            return Poll::Ready(Ok(WeakMutexGuard::new(arc)));
+        }
+
+        // waiting mustn't keep the mutex alive. if this is the last Arc,
+        // dropping it wakes us up to return Dead
+        drop(arc);
+        Poll::Pending
+    }
+}
+
+impl<T: ?Sized> Drop for Lock<T> {
+    fn drop(&mut self) {
+        if self.registered {
+            park::unregister_waker(self.weak.addr(), self.id);
+        }
+    }
+}
+
+/// Holds the lock on an [`AsyncMutex`], and keeps it alive.
+///
+/// Sharing the guard shares the value, so it's only `Sync` when the value is:
+///
+/// ```compile_fail
+/// use provenant::weak_mutex::{AsyncMutex, WeakMutex, WeakMutexGuard};
+/// use provenant::Arc;
+/// use std::cell::Cell;
+///
+/// fn shared<T: Sync>(_: &T) {}
+///
+/// let arc = Arc::new(AsyncMutex::new(Cell::new(0)));
+/// let guard = WeakMutex::new(&arc).try_lock().unwrap().unwrap();
+/// shared(&guard);
+/// ```
+pub struct WeakMutexGuard<T: ?Sized> {
+    arc: Arc<AsyncMutex<T>>,
+    // AsyncMutex is Sync for any T: Send, which the guard mustn't inherit
+    _not_sync: PhantomData<*const ()>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for WeakMutexGuard<T> {}
+unsafe impl<T: ?Sized + Sync> Sync for WeakMutexGuard<T> {}
+
+impl<T: ?Sized> WeakMutexGuard<T> {
+    fn new(arc: Arc<AsyncMutex<T>>) -> Self {
+        WeakMutexGuard {
+            arc,
+            _not_sync: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized> Deref for WeakMutexGuard<T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        unsafe { &*self.arc.value.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for WeakMutexGuard<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.arc.value.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for WeakMutexGuard<T> {
+    fn drop(&mut self) {
+        self.arc.locked.store(false, Ordering::SeqCst);
+        park::notify(self.arc.addr());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::future::tests::block_on;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn lock_and_wait() {
+        let arc = Arc::new(AsyncMutex::new(0));
+        let mutex = WeakMutex::new(&arc);
+
+        let mut guard = block_on(mutex.lock()).unwrap();
+        *guard += 1;
+        assert!(mutex.try_lock().unwrap().is_none());
+
+        let t = thread::spawn(move || {
+            *block_on(mutex.lock()).unwrap() += 1;
+        });
+        thread::sleep(Duration::from_millis(20));
+        drop(guard);
+        t.join().unwrap();
+
+        assert_eq!(2, *mutex.try_lock().unwrap().unwrap());
+    }
+
+    #[test]
+    fn dead_while_waiting() {
+        let arc = Arc::new(AsyncMutex::new(()));
+        let mutex = WeakMutex::new(&arc);
+        let guard = mutex.try_lock().unwrap().unwrap();
+
+        let t = thread::spawn(move || block_on(mutex.lock()).err());
+        thread::sleep(Duration::from_millis(20));
+        drop((guard, arc));
+
+        assert_eq!(Some(Dead), t.join().unwrap());
+        assert_eq!(Dead, mutex.try_lock().err().unwrap());
+    }
+}
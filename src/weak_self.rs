@@ -0,0 +1,114 @@
+//! Values that can hand out pointers to themselves.
+//!
+//! Observers, actors and tree nodes often need to give out handles to themselves
+//! from inside their own methods. Give the type a [`WeakSelf`] field, implement
+//! [`HasWeakSelf`] to say where it is, and build it with [`WeakSelf::new_arc`]:
+//!
+//! ```
+//! use provenant::weak_self::{HasWeakSelf, WeakSelf};
+//! use provenant::{Arc, Weak};
+//!
+//! struct Actor {
+//!     me: WeakSelf<Actor>,
+//!     name: String,
+//! }
+//!
+//! impl HasWeakSelf for Actor {
+//!     fn weak_self_field(&self) -> &WeakSelf<Self> {
+//!         &self.me
+//!     }
+//! }
+//!
+//! let actor = WeakSelf::new_arc(|me| Actor { me, name: "bob".into() });
+//! let handle: Weak<Actor> = actor.weak_self();
+//! assert_eq!("bob", handle.upgrade().unwrap().name);
+//! ```
+
+use crate::{Arc, Weak};
+use std::fmt;
+
+/// A weak pointer a value keeps to its own allocation
+pub struct WeakSelf<T>(Weak<T>);
+
+impl<T> WeakSelf<T> {
+    /// Builds a value that holds a weak pointer to itself.
+    ///
+    /// `data_fn` gets the `WeakSelf` to store in the value. It can't be upgraded
+    /// until `data_fn` returns.
+    pub fn new_arc<F: FnOnce(WeakSelf<T>) -> T>(data_fn: F) -> Arc<T> {
+        Arc::new_cyclic(|weak| data_fn(WeakSelf(*weak)))
+    }
+
+    /// Gets the weak pointer
+    pub fn get(&self) -> Weak<T> {
+        self.0
+    }
+
+    /// Tries to get a strong pointer
+    pub fn upgrade(&self) -> Option<Arc<T>> {
+        self.0.upgrade()
+    }
+}
+
+impl<T> fmt::Debug for WeakSelf<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.0, f)
+    }
+}
+
+/// Implemented by types with a [`WeakSelf`] field, to give them `weak_self` and `arc_self`
+pub trait HasWeakSelf: Sized {
+    /// Gets the `WeakSelf` field
+    fn weak_self_field(&self) -> &WeakSelf<Self>;
+
+    /// Gets a weak pointer to this value
+    fn weak_self(&self) -> Weak<Self> {
+        self.weak_self_field().get()
+    }
+
+    /// Gets a strong pointer to this value, if it's still shared
+    fn arc_self(&self) -> Option<Arc<Self>> {
+        self.weak_self_field().upgrade()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Node {
+        me: WeakSelf<Node>,
+        upgraded_during_construction: bool,
+    }
+
+    impl HasWeakSelf for Node {
+        fn weak_self_field(&self) -> &WeakSelf<Self> {
+            &self.me
+        }
+    }
+
+    #[test]
+    fn points_at_itself() {
+        let node = WeakSelf::new_arc(|me| Node {
+            upgraded_during_construction: me.upgrade().is_some(),
+            me,
+        });
+        assert!(!node.upgraded_during_construction);
+
+        let again = node.arc_self().unwrap();
+        assert_eq!(node.ptr, again.ptr);
+
+        let weak = node.weak_self();
+        drop(again);
+        drop(node);
+        assert!(weak.upgrade().is_none());
+    }
+
+    #[test]
+    fn panicking_constructor() {
+        let result = std::panic::catch_unwind(|| {
+            WeakSelf::<Node>::new_arc(|_| panic!("construction failed"))
+        });
+        assert!(result.is_err());
+    }
+}
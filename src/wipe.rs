@@ -0,0 +1,60 @@
+// Arc::new_zeroizing, for values like keys that shouldn't be left behind in freed
+// memory. the wiping is done by the allocation's release function, which runs once
+// the value has been dropped or moved out, just before the memory goes back
+
+use crate::{events, random_provenance, release_box, Arc, Inner};
+use alloc::alloc::Layout;
+use alloc::boxed::Box;
+use core::mem;
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering};
+
+// a weak pointer can still be reading the header, so only data is touched
+unsafe fn release_wiped<T>(ptr: *mut u8, layout: Layout) {
+    wipe_data(ptr as *mut Inner<T>);
+    release_box(ptr, layout);
+}
+
+unsafe fn wipe_data<T>(inner: *mut Inner<T>) {
+    let data = ptr::addr_of_mut!((*inner).data) as *mut u8;
+    for i in 0..mem::size_of::<T>() {
+        // volatile, so it isn't optimized out for being written to memory that's
+        // about to be freed
+        ptr::write_volatile(data.add(i), 0);
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+impl<T> Arc<T> {
+    /// Like [`Arc::new`], but the memory the value lived in is overwritten with
+    /// zeroes once it's been dropped or moved out, before it's freed.
+    ///
+    /// Only the value's own bytes are wiped, not anything it owns elsewhere, so a
+    /// key should be held inline, like `[u8; 32]`, rather than in a `Vec`.
+    pub fn new_zeroizing(val: T) -> Self {
+        let inner = Box::new(Inner::new(val, random_provenance(), release_wiped::<T>));
+
+        let inner = Box::into_raw(inner) as *const Inner<T>;
+        events::allocated(inner);
+
+        Arc { ptr: inner }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wiped() {
+        let mut inner = Inner::new([0xa5u8; 32], random_provenance(), release_wiped::<[u8; 32]>);
+        unsafe { wipe_data(&mut inner) };
+        assert_eq!([0; 32], inner.data);
+
+        let arc = Arc::new_zeroizing([0xa5u8; 32]);
+        let weak = Arc::downgrade(&arc);
+        assert_eq!([0xa5; 32], *arc);
+        assert_eq!([0xa5; 32], Arc::try_unwrap(arc).ok().unwrap());
+        assert!(weak.upgrade().is_none());
+    }
+}